@@ -0,0 +1,80 @@
+use async_std::task::block_on;
+use connect::{ConnectDatagram, ConnectionReader, StreamExt};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use futures::io::Cursor;
+
+fn serialized(count: usize, size: usize) -> Vec<u8> {
+    (0..count)
+        .flat_map(|i| {
+            ConnectDatagram::with_tag(i as u16, vec![7; size])
+                .unwrap()
+                .into_bytes()
+        })
+        .collect()
+}
+
+fn read_all(bytes: Vec<u8>) -> usize {
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let reader = ConnectionReader::new(addr, addr, Box::pin(Cursor::new(bytes)));
+
+    block_on(reader.count())
+}
+
+fn read_all_generic(bytes: Vec<u8>) -> usize {
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let reader = ConnectionReader::from_stream(addr, addr, Cursor::new(bytes));
+
+    block_on(reader.count())
+}
+
+fn read_all_into(bytes: Vec<u8>) -> usize {
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let mut reader = ConnectionReader::new(addr, addr, Box::pin(Cursor::new(bytes)));
+    let mut buf = Vec::new();
+
+    block_on(async {
+        let mut count = 0;
+        while reader.read_into(&mut buf).await.is_some() {
+            count += 1;
+        }
+        count
+    })
+}
+
+fn bench_reader(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reader");
+
+    for (count, size) in [(10_000, 32), (10_000, 1024), (10, 1024 * 1024)] {
+        let bytes = serialized(count, size);
+
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_function(format!("{}x{}B", count, size), |b| {
+            b.iter_batched(
+                || bytes.clone(),
+                |bytes| assert_eq!(count, read_all(bytes)),
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_function(format!("generic/{}x{}B", count, size), |b| {
+            b.iter_batched(
+                || bytes.clone(),
+                |bytes| assert_eq!(count, read_all_generic(bytes)),
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_function(format!("read_into/{}x{}B", count, size), |b| {
+            b.iter_batched(
+                || bytes.clone(),
+                |bytes| assert_eq!(count, read_all_into(bytes)),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_reader);
+criterion_main!(benches);
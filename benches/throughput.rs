@@ -0,0 +1,28 @@
+use async_std::task::block_on;
+use connect::bench::throughput;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+/// Payload sizes from framing-bound small datagrams to copy-bound large ones, with the number of
+/// datagrams sent of each.
+const CASES: [(usize, usize); 3] = [(16, 10_000), (1024, 10_000), (64 * 1024, 100)];
+
+fn bench_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("throughput");
+
+    for (size, count) in CASES {
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_function(format!("datagrams/{}x{}B", count, size), |b| {
+            b.iter(|| block_on(throughput(size, count)).unwrap())
+        });
+
+        group.throughput(Throughput::Bytes((count * size) as u64));
+        group.bench_function(format!("bytes/{}x{}B", count, size), |b| {
+            b.iter(|| block_on(throughput(size, count)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_throughput);
+criterion_main!(benches);
@@ -0,0 +1,142 @@
+use async_std::task::block_on;
+use connect::{Bytes, ConnectDatagram, ConnectionWriter, SegmentedDatagram, SinkExt};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use futures::io::{sink, AsyncWrite};
+
+fn write_all(datagrams: Vec<ConnectDatagram>) {
+    let addr = "127.0.0.1:0".parse().unwrap();
+    write_to(
+        ConnectionWriter::new(addr, addr, Box::pin(sink())),
+        datagrams,
+    )
+}
+
+fn write_all_generic(datagrams: Vec<ConnectDatagram>) {
+    let addr = "127.0.0.1:0".parse().unwrap();
+    write_to(ConnectionWriter::from_stream(addr, addr, sink()), datagrams)
+}
+
+fn write_to<W: AsyncWrite + Unpin>(
+    mut writer: ConnectionWriter<W>,
+    datagrams: Vec<ConnectDatagram>,
+) {
+    block_on(async {
+        for datagram in datagrams {
+            writer.feed(datagram).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+    })
+}
+
+fn bench_writer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("writer");
+
+    for (count, size) in [(10_000, 32), (10_000, 1024), (10, 1024 * 1024)] {
+        group.throughput(Throughput::Elements(count as u64));
+
+        group.bench_function(format!("with_tag/{}x{}B", count, size), |b| {
+            b.iter_batched(
+                || vec![vec![7; size]; count],
+                |payloads| {
+                    write_all(
+                        payloads
+                            .into_iter()
+                            .map(|data| ConnectDatagram::with_tag(1, data).unwrap())
+                            .collect(),
+                    )
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_function(format!("generic/with_tag/{}x{}B", count, size), |b| {
+            b.iter_batched(
+                || vec![vec![7; size]; count],
+                |payloads| {
+                    write_all_generic(
+                        payloads
+                            .into_iter()
+                            .map(|data| ConnectDatagram::with_tag(1, data).unwrap())
+                            .collect(),
+                    )
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_function(format!("from_reserved/{}x{}B", count, size), |b| {
+            b.iter_batched(
+                || {
+                    let mut buffer = ConnectDatagram::reserved_buffer(size);
+                    buffer.resize(buffer.len() + size, 7);
+                    vec![buffer; count]
+                },
+                |buffers| {
+                    write_all(
+                        buffers
+                            .into_iter()
+                            .map(|buffer| ConnectDatagram::from_reserved(1, buffer).unwrap())
+                            .collect(),
+                    )
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_segmented(c: &mut Criterion) {
+    let mut group = c.benchmark_group("segmented");
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let count = 1_000;
+
+    for size in [1024, 64 * 1024, 1024 * 1024] {
+        let segments = vec![
+            Bytes::from(vec![1; 64]),
+            Bytes::from(vec![2; size]),
+            Bytes::from(vec![3; 16]),
+        ];
+
+        group.throughput(Throughput::Elements(count as u64));
+
+        group.bench_function(format!("concatenated/{}x{}B", count, size), |b| {
+            let mut writer = ConnectionWriter::from_stream(addr, addr, sink());
+
+            b.iter(|| {
+                block_on(async {
+                    for _ in 0..count {
+                        let mut data = Vec::with_capacity(segments.iter().map(Bytes::len).sum());
+                        for segment in segments.iter() {
+                            data.extend_from_slice(segment);
+                        }
+
+                        writer
+                            .send(ConnectDatagram::with_tag(1, data).unwrap())
+                            .await
+                            .unwrap();
+                    }
+                })
+            })
+        });
+
+        group.bench_function(format!("segmented/{}x{}B", count, size), |b| {
+            let mut writer = ConnectionWriter::from_stream(addr, addr, sink());
+
+            b.iter(|| {
+                block_on(async {
+                    for _ in 0..count {
+                        let datagram = SegmentedDatagram::with_tag(1, segments.clone()).unwrap();
+                        writer.send_segmented(datagram).await.unwrap();
+                    }
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_writer, bench_segmented);
+criterion_main!(benches);
@@ -0,0 +1,61 @@
+use async_std::fs::File;
+use async_std::{io, task};
+use connect::tcp::TcpListener;
+use connect::{Connection, StreamExt};
+use log::*;
+use std::env;
+
+/// Tag of the datagrams that carry the file.
+const FILE_TAG: u16 = 1;
+
+/// Size of each chunk of the file sent over the network.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[async_std::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    // Get the source and destination paths from cmd line args
+    let args: Vec<String> = env::args().collect();
+    let (source, destination) = match (args.get(1), args.get(2)) {
+        (Some(source), Some(destination)) => (source.clone(), destination.clone()),
+        _ => {
+            error!("Need to pass the file to send and its destination as command line arguments");
+            panic!();
+        }
+    };
+
+    // bind a server to a local port
+    let server_addr = "127.0.0.1:5691";
+    let mut server = TcpListener::bind(server_addr).await?;
+    info!("Listening on {}", server_addr);
+
+    // stream the file from a client connection to the server
+    let client = task::spawn(async move {
+        let mut conn = Connection::tcp_client(server_addr).await?;
+        let file = File::open(&source).await?;
+
+        let bytes_sent = conn
+            .writer()
+            .send_stream(FILE_TAG, file, CHUNK_SIZE)
+            .await?;
+        info!("Client sent {} bytes from {}", bytes_sent, source);
+
+        anyhow::Ok(())
+    });
+
+    // accept the client connection on the server
+    let mut conn = match server.next().await {
+        Some(conn) => conn,
+        None => anyhow::bail!("Server closed before accepting a connection"),
+    };
+
+    // reassemble the chunks into the destination file, holding only one chunk in memory at a time
+    let mut file = File::create(&destination).await?;
+    let bytes_received = io::copy(conn.reader().recv_stream(FILE_TAG), &mut file).await?;
+    info!("Server wrote {} bytes to {}", bytes_received, destination);
+
+    client.await?;
+
+    Ok(())
+}
@@ -28,12 +28,10 @@ async fn main() -> anyhow::Result<()> {
         let mut prev: Option<Number> = None;
 
         while let Some(reply) = reader.next().await {
-            let mut payload = reply.data().to_vec();
+            let payload = reply.data();
 
             let mut data_bytes: [u8; 2] = [0; 2];
-            for i in 0..payload.len() {
-                data_bytes[i] = payload.remove(0);
-            }
+            data_bytes[..payload.len()].copy_from_slice(payload);
 
             let data = Number::from_be_bytes(data_bytes);
 
@@ -2,6 +2,7 @@ use connect::{ConnectDatagram, Connection, SinkExt, StreamExt};
 use log::*;
 use rustls::ClientConfig;
 use std::env;
+use std::sync::Arc;
 
 #[async_std::main]
 async fn main() -> anyhow::Result<()> {
@@ -14,7 +15,7 @@ async fn main() -> anyhow::Result<()> {
     let client_config = create_client_config(cafile_path)?;
 
     // create a client connection to the server
-    let mut conn = Connection::tls_client(ip_addr, &domain, client_config.into()).await?;
+    let mut conn = Connection::tls_client(ip_addr, &domain, Arc::new(client_config).into()).await?;
 
     // send a message to the server
     let msg = String::from("Hello world");
@@ -1,41 +1,64 @@
 use async_std::{io, task};
 use connect::{tls::TlsListener, ConnectDatagram, SinkExt, StreamExt};
 use log::*;
-use rustls::{Certificate, NoClientAuth, PrivateKey, ServerConfig};
+use rustls::{
+    AllowAnyAuthenticatedClient, Certificate, NoClientAuth, PrivateKey, RootCertStore, ServerConfig,
+};
 use rustls_pemfile::{certs, rsa_private_keys};
 use std::env;
 use std::fs::File;
 use std::io::BufReader;
+use std::sync::Arc;
 
 #[async_std::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
 
     // Get ip address from cmd line args
-    let (ip_address, cert_path, key_path) = parse_args();
+    let (ip_address, cert_path, key_path, client_ca_path) = parse_args();
 
     let certs = certs(&mut BufReader::new(File::open(cert_path)?))
         .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid cert"))?;
-    assert!(certs.len() > 0);
+    assert!(!certs.is_empty());
 
-    let rustls_certs: Vec<Certificate> = certs.into_iter().map(|v| Certificate(v)).collect();
-    assert!(rustls_certs.len() > 0);
+    let rustls_certs: Vec<Certificate> = certs.into_iter().map(Certificate).collect();
+    assert!(!rustls_certs.is_empty());
 
     let mut keys = rsa_private_keys(&mut BufReader::new(File::open(key_path)?))
         .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid key"))?;
 
-    let mut config = ServerConfig::new(NoClientAuth::new());
+    // require clients to authenticate with a certificate if a client CA file is provided
+    let client_auth = match client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            roots
+                .add_pem_file(&mut BufReader::new(File::open(path)?))
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid client CA"))?;
+
+            AllowAnyAuthenticatedClient::new(roots)
+        }
+
+        None => NoClientAuth::new(),
+    };
+
+    let mut config = ServerConfig::new(client_auth);
     config
         .set_single_cert(rustls_certs, PrivateKey(keys.remove(0)))
         .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
 
     // create a server
-    let mut server = TlsListener::bind(ip_address, config.into()).await?;
+    let mut server = TlsListener::bind(ip_address, Arc::new(config).into()).await?;
 
     // handle server connections
     // wait for a connection to come in and be accepted
     while let Some(mut conn) = server.next().await {
         info!("Handling connection from {}", conn.peer_addr());
+        if let Some(certs) = conn.peer_certificates() {
+            info!(
+                "Client authenticated with a chain of {} certificate(s)",
+                certs.len()
+            );
+        }
 
         task::spawn(async move {
             while let Some(envelope) = conn.reader().next().await {
@@ -76,7 +99,7 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-fn parse_args() -> (String, String, String) {
+fn parse_args() -> (String, String, String, Option<String>) {
     let args: Vec<String> = env::args().collect();
 
     let ip_address = match args.get(1) {
@@ -103,9 +126,12 @@ fn parse_args() -> (String, String, String) {
         }
     };
 
+    let client_ca_path = args.get(4).cloned();
+
     (
         ip_address.to_string(),
         cert_path.to_string(),
         key_path.to_string(),
+        client_ca_path,
     )
 }
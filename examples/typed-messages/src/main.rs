@@ -0,0 +1,65 @@
+use connect::tcp::TcpListener;
+use connect::typed::{JsonCodec, TypedConnection};
+use connect::{Connection, StreamExt};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Order {
+    id: u32,
+    item: String,
+    quantity: u16,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Receipt {
+    order_id: u32,
+    accepted: bool,
+}
+
+#[async_std::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    // bind a server to a local port
+    let server_addr = "127.0.0.1:5690";
+    let mut server = TcpListener::bind(server_addr).await?;
+    info!("Listening on {}", server_addr);
+
+    // create a client connection to the server
+    let mut client = TypedConnection::new(Connection::tcp_client(server_addr).await?, JsonCodec);
+
+    // accept the client connection on the server
+    let mut conn = match server.next().await {
+        Some(conn) => TypedConnection::new(conn, JsonCodec),
+        None => anyhow::bail!("Server closed before accepting a connection"),
+    };
+
+    // send a struct to the server
+    let order = Order {
+        id: 1,
+        item: String::from("widget"),
+        quantity: 3,
+    };
+    info!("Client sending: {:?}", order);
+    client.send(&order).await?;
+
+    // receive and deserialize the struct on the server, then reply
+    if let Some(order) = conn.recv::<Order>().await {
+        let order = order?;
+        info!("Server received: {:?}", order);
+
+        conn.send(&Receipt {
+            order_id: order.id,
+            accepted: order.quantity > 0,
+        })
+        .await?;
+    }
+
+    // wait for the server to reply with a receipt
+    if let Some(receipt) = client.recv::<Receipt>().await {
+        info!("Client received: {:?}", receipt?);
+    }
+
+    Ok(())
+}
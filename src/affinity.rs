@@ -0,0 +1,54 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+
+/// Strategy used by a listener to assign an affinity hint to each accepted
+/// [`Connection`](`crate::Connection`).
+///
+/// The affinity hint is a worker index in `0..workers`, intended for sharded servers that dispatch
+/// each connection to a fixed worker or core. The hint is advisory and the crate does not act on
+/// it.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AffinityStrategy {
+    /// Assigns accepted connections to workers in turn.
+    RoundRobin {
+        /// Number of workers to distribute connections across.
+        workers: usize,
+    },
+
+    /// Assigns accepted connections to workers by hashing the peer IP address, so that
+    /// connections from the same host are handled by the same worker.
+    PeerHash {
+        /// Number of workers to distribute connections across.
+        workers: usize,
+    },
+}
+
+pub(crate) struct AffinityAssigner {
+    strategy: AffinityStrategy,
+    next: usize,
+}
+
+impl AffinityAssigner {
+    pub(crate) fn new(strategy: AffinityStrategy) -> Self {
+        Self { strategy, next: 0 }
+    }
+
+    /// Gets the affinity hint for the next accepted connection from `peer_addr`.
+    pub(crate) fn assign(&mut self, peer_addr: &SocketAddr) -> usize {
+        match self.strategy {
+            AffinityStrategy::RoundRobin { workers } => {
+                let hint = self.next % workers.max(1);
+                self.next = self.next.wrapping_add(1);
+                hint
+            }
+
+            AffinityStrategy::PeerHash { workers } => {
+                let mut hasher = DefaultHasher::new();
+                peer_addr.ip().hash(&mut hasher);
+                (hasher.finish() % workers.max(1) as u64) as usize
+            }
+        }
+    }
+}
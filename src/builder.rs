@@ -0,0 +1,450 @@
+use crate::tcp::listener::AcceptFilter;
+use crate::tcp::{TcpConnectOptions, TcpListener};
+use crate::{AffinityStrategy, ByteOrder, Connection, ConnectionObserver, HeartbeatConfig};
+use async_std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "tls")]
+use crate::tls::{TlsAcceptor, TlsConnector, TlsListener};
+
+/// Accumulates the options of a [`Connection`] before establishing it, as an alternative to the
+/// transport-specific constructors such as [`Connection::tcp_client`].
+///
+/// Options that are not set keep the defaults of the plain constructors. The same builder can be
+/// cloned or reused to establish several connections with the same options, and
+/// [`configure`](ConnectionBuilder::configure) applies the connection-level options to a
+/// [`Connection`] that was established in any other way.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let mut conn = ConnectionBuilder::new()
+///     .with_connect_timeout(Duration::from_secs(2))
+///     .with_keepalive(Duration::from_secs(60))
+///     .with_idle_timeout(Duration::from_secs(300))
+///     .connect_tcp("127.0.0.1:3456")
+///     .await?;
+/// ```
+#[derive(Clone, Default)]
+pub struct ConnectionBuilder {
+    socket_options: TcpConnectOptions,
+    idle_timeout: Option<Duration>,
+    heartbeat: Option<HeartbeatConfig>,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+    max_buffered_bytes: Option<usize>,
+    resync_on_error: bool,
+    write_buffer_limit: Option<usize>,
+    write_rate_limit: Option<u64>,
+    flush_delay: Option<Duration>,
+    auto_flush: bool,
+    sequencing: bool,
+    byte_order: ByteOrder,
+}
+
+impl ConnectionBuilder {
+    /// Creates a [`ConnectionBuilder`] with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces all socket options at once. See [`TcpConnectOptions`].
+    pub fn with_socket_options(mut self, options: TcpConnectOptions) -> Self {
+        self.socket_options = options;
+        self
+    }
+
+    /// Sets whether to disable Nagle's algorithm with `TCP_NODELAY`. Defaults to `true`.
+    pub fn with_nodelay(mut self, nodelay: bool) -> Self {
+        self.socket_options.nodelay = nodelay;
+        self
+    }
+
+    /// Enables `SO_KEEPALIVE`, using `interval` as both the idle time before the first probe and
+    /// the interval between probes.
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        self.socket_options.keepalive = Some(interval);
+        self
+    }
+
+    /// Fails with a [`TimedOut`](`std::io::ErrorKind::TimedOut`) error if the connection, including
+    /// any TLS handshake, is not established within `timeout`.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.socket_options.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the size of the socket send buffer with `SO_SNDBUF`.
+    pub fn with_send_buffer_size(mut self, size: usize) -> Self {
+        self.socket_options.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets the size of the socket receive buffer with `SO_RCVBUF`.
+    pub fn with_recv_buffer_size(mut self, size: usize) -> Self {
+        self.socket_options.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Closes the connection once it has been idle for longer than `timeout`. See
+    /// [`Connection::set_idle_timeout`].
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Enables heartbeats on the connection. See [`Connection::enable_heartbeat`].
+    pub fn with_heartbeat(mut self, config: HeartbeatConfig) -> Self {
+        self.heartbeat = Some(config);
+        self
+    }
+
+    /// Attaches an observer to the connection. See [`Connection::with_observer`].
+    pub fn with_observer(mut self, observer: Arc<dyn ConnectionObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Caps the bytes buffered by the reader. See
+    /// [`ConnectionReader::set_max_buffered_bytes`](`crate::ConnectionReader::set_max_buffered_bytes`).
+    pub fn with_max_buffered_bytes(mut self, max: usize) -> Self {
+        self.max_buffered_bytes = Some(max);
+        self
+    }
+
+    /// Sets whether the reader resynchronizes after receiving garbage. See
+    /// [`ConnectionReader::set_resync_on_error`](`crate::ConnectionReader::set_resync_on_error`).
+    pub fn with_resync_on_error(mut self, enabled: bool) -> Self {
+        self.resync_on_error = enabled;
+        self
+    }
+
+    /// Sets the number of bytes the writer buffers before applying backpressure. See
+    /// [`ConnectionWriter::set_buffer_limit`](`crate::ConnectionWriter::set_buffer_limit`).
+    pub fn with_write_buffer_limit(mut self, bytes: usize) -> Self {
+        self.write_buffer_limit = Some(bytes);
+        self
+    }
+
+    /// Limits the rate at which the writer sends bytes. See
+    /// [`ConnectionWriter::set_rate_limit`](`crate::ConnectionWriter::set_rate_limit`).
+    pub fn with_write_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.write_rate_limit = Some(bytes_per_sec);
+        self
+    }
+
+    /// Delays flushes to coalesce writes. See
+    /// [`ConnectionWriter::set_flush_delay`](`crate::ConnectionWriter::set_flush_delay`).
+    pub fn with_flush_delay(mut self, delay: Duration) -> Self {
+        self.flush_delay = Some(delay);
+        self
+    }
+
+    /// Writes every datagram as soon as it is accepted. See
+    /// [`ConnectionWriter::set_auto_flush`](`crate::ConnectionWriter::set_auto_flush`).
+    pub fn with_auto_flush(mut self, enabled: bool) -> Self {
+        self.auto_flush = enabled;
+        self
+    }
+
+    /// Sets whether datagrams are sequenced end-to-end. See [`Connection::enable_sequencing`].
+    pub fn with_sequencing(mut self, enabled: bool) -> Self {
+        self.sequencing = enabled;
+        self
+    }
+
+    /// Sets the byte order of the datagram headers. See [`Connection::set_byte_order`].
+    pub fn with_byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    /// Creates a [`Connection`] that uses a TCP transport with the configured options.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut conn = ConnectionBuilder::new()
+    ///     .with_nodelay(false)
+    ///     .connect_tcp("127.0.0.1:3456")
+    ///     .await?;
+    /// ```
+    pub async fn connect_tcp<A: ToSocketAddrs + std::fmt::Display>(
+        &self,
+        ip_addrs: A,
+    ) -> anyhow::Result<Connection> {
+        let stream = self.socket_options.connect(ip_addrs).await?;
+        Ok(self.configure(Connection::from(stream)))
+    }
+
+    /// Creates a [`Connection`] that uses a TLS transport with the configured options, where the
+    /// connect timeout covers both the TCP connection and the TLS handshake.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut conn = ConnectionBuilder::new()
+    ///     .with_connect_timeout(Duration::from_secs(2))
+    ///     .connect_tls("127.0.0.1:3456", "localhost", Arc::new(client_config).into())
+    ///     .await?;
+    /// ```
+    #[cfg(feature = "tls")]
+    pub async fn connect_tls<A: ToSocketAddrs + std::fmt::Display>(
+        &self,
+        ip_addrs: A,
+        domain: &str,
+        connector: TlsConnector,
+    ) -> anyhow::Result<Connection> {
+        let options = TcpConnectOptions {
+            connect_timeout: None,
+            ..self.socket_options
+        };
+
+        let connect = async {
+            let stream = options.connect(ip_addrs).await?;
+            Connection::tls_handshake(stream, domain, connector).await
+        };
+
+        let conn = match self.socket_options.connect_timeout {
+            Some(timeout) => match async_std::future::timeout(timeout, connect).await {
+                Ok(res) => res?,
+                Err(_) => return Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into()),
+            },
+
+            None => connect.await?,
+        };
+
+        Ok(self.configure(conn))
+    }
+
+    /// Applies the connection-level options, such as the idle timeout and the reader and writer
+    /// limits, to an established [`Connection`]. Socket options are ignored.
+    pub fn configure(&self, mut conn: Connection) -> Connection {
+        if let Some(timeout) = self.idle_timeout {
+            conn.set_idle_timeout(timeout);
+        }
+
+        if let Some(config) = self.heartbeat {
+            conn.enable_heartbeat(config);
+        }
+
+        if let Some(observer) = self.observer.clone() {
+            conn = conn.with_observer(observer);
+        }
+
+        if self.sequencing {
+            conn.enable_sequencing();
+        }
+
+        conn.set_byte_order(self.byte_order);
+
+        let (reader, writer) = conn.split_mut();
+
+        if let Some(max) = self.max_buffered_bytes {
+            reader.set_max_buffered_bytes(max);
+        }
+        reader.set_resync_on_error(self.resync_on_error);
+
+        if let Some(bytes) = self.write_buffer_limit {
+            writer.set_buffer_limit(bytes);
+        }
+
+        if let Some(bytes_per_sec) = self.write_rate_limit {
+            writer.set_rate_limit(bytes_per_sec);
+        }
+
+        if let Some(delay) = self.flush_delay {
+            writer.set_flush_delay(delay);
+        }
+        writer.set_auto_flush(self.auto_flush);
+
+        conn
+    }
+}
+
+/// Accumulates the options of a listener before binding it, as an alternative to configuring a
+/// bound [`TcpListener`] or `TlsListener`.
+///
+/// TLS listeners only support the maximum number of connections and the accept filter, so binding
+/// one fails if any of the other options are set.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let mut server = ListenerBuilder::new()
+///     .with_max_connections(10_000)
+///     .with_idle_timeout(Duration::from_secs(300))
+///     .bind_tcp("127.0.0.1:3456")
+///     .await?;
+/// ```
+#[derive(Default)]
+pub struct ListenerBuilder {
+    socket_options: Option<TcpConnectOptions>,
+    idle_timeout: Option<Duration>,
+    affinity: Option<AffinityStrategy>,
+    accept_rate_limit: Option<u32>,
+    max_connections: Option<usize>,
+    accept_filter: Option<AcceptFilter>,
+}
+
+impl ListenerBuilder {
+    /// Creates a [`ListenerBuilder`] with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies socket options to each accepted connection. See
+    /// [`TcpListener::with_socket_options`].
+    pub fn with_socket_options(mut self, options: TcpConnectOptions) -> Self {
+        self.socket_options = Some(options);
+        self
+    }
+
+    /// Closes each accepted connection once it has been idle for longer than `timeout`. See
+    /// [`TcpListener::with_idle_timeout`].
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Assigns an affinity hint to each accepted connection. See
+    /// [`TcpListener::with_affinity`].
+    pub fn with_affinity(mut self, strategy: AffinityStrategy) -> Self {
+        self.affinity = Some(strategy);
+        self
+    }
+
+    /// Caps the rate at which connections are accepted. See
+    /// [`TcpListener::set_accept_rate_limit`].
+    pub fn with_accept_rate_limit(mut self, per_sec: u32) -> Self {
+        self.accept_rate_limit = Some(per_sec);
+        self
+    }
+
+    /// Caps the number of live accepted connections. See
+    /// [`TcpListener::with_max_connections`].
+    pub fn with_max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Drops incoming connections from rejected peer addresses. See
+    /// [`TcpListener::with_accept_filter`].
+    pub fn with_accept_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        self.accept_filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Creates a [`TcpListener`] bound to an IP address and port with the configured options.
+    pub async fn bind_tcp<A: ToSocketAddrs + std::fmt::Display>(
+        self,
+        ip_addrs: A,
+    ) -> anyhow::Result<TcpListener> {
+        let mut listener = TcpListener::bind(ip_addrs).await?;
+
+        if let Some(options) = self.socket_options {
+            listener = listener.with_socket_options(options);
+        }
+
+        if let Some(timeout) = self.idle_timeout {
+            listener = listener.with_idle_timeout(timeout);
+        }
+
+        if let Some(strategy) = self.affinity {
+            listener = listener.with_affinity(strategy);
+        }
+
+        if let Some(per_sec) = self.accept_rate_limit {
+            listener.set_accept_rate_limit(per_sec);
+        }
+
+        if let Some(max) = self.max_connections {
+            listener = listener.with_max_connections(max);
+        }
+
+        if let Some(filter) = self.accept_filter {
+            listener = listener.with_accept_filter(filter);
+        }
+
+        Ok(listener)
+    }
+
+    /// Creates a `TlsListener` bound to an IP address and port with the configured options.
+    ///
+    /// Fails if any options that TLS listeners do not support are set.
+    #[cfg(feature = "tls")]
+    pub async fn bind_tls<A: ToSocketAddrs + std::fmt::Display>(
+        self,
+        ip_addrs: A,
+        acceptor: TlsAcceptor,
+    ) -> anyhow::Result<TlsListener> {
+        if self.socket_options.is_some()
+            || self.idle_timeout.is_some()
+            || self.affinity.is_some()
+            || self.accept_rate_limit.is_some()
+        {
+            return Err(anyhow::anyhow!(
+                "TLS listeners only support the max connections and accept filter options"
+            ));
+        }
+
+        let mut listener = TlsListener::bind(ip_addrs, acceptor).await?;
+
+        if let Some(max) = self.max_connections {
+            listener = listener.with_max_connections(max);
+        }
+
+        if let Some(filter) = self.accept_filter {
+            listener = listener.with_accept_filter(filter);
+        }
+
+        Ok(listener)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionBuilder, ListenerBuilder};
+    use crate::{ConnectDatagram, SinkExt, StreamExt};
+    use std::time::{Duration, Instant};
+
+    #[async_std::test]
+    async fn builds_configured_connections() -> anyhow::Result<()> {
+        let mut server = ListenerBuilder::new()
+            .with_idle_timeout(Duration::from_millis(100))
+            .bind_tcp("127.0.0.1:0")
+            .await?;
+        let server_addr = server.local_addr();
+
+        let mut client = ConnectionBuilder::new()
+            .with_connect_timeout(Duration::from_secs(5))
+            .with_flush_delay(Duration::from_millis(50))
+            .connect_tcp(server_addr)
+            .await?;
+        let mut conn = server.next().await.expect("listener closed unexpectedly");
+
+        let start = Instant::now();
+        client
+            .writer()
+            .send(ConnectDatagram::with_tag(1, vec![1])?)
+            .await?;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(1, conn.reader().next().await.expect("closed").tag());
+
+        // the accepted connection closes once idle
+        assert!(conn.reader().next().await.is_none());
+
+        Ok(())
+    }
+}
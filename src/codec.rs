@@ -0,0 +1,114 @@
+use crate::logging::*;
+use crate::{ConnectDatagram, SIZE_PREFIX_BYTE_SIZE};
+use bytes::BytesMut;
+use std::convert::TryInto;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// A [`tokio_util`] codec that frames [`ConnectDatagram`]s with the same size-prefixed wire
+/// format used by [`ConnectionReader`](`crate::ConnectionReader`) and
+/// [`ConnectionWriter`](`crate::ConnectionWriter`).
+///
+/// This allows datagrams to be exchanged over any transport wrapped in a
+/// [`Framed`](`tokio_util::codec::Framed`), including with peers that use a [`Connection`](`crate::Connection`).
+/// Like the [`ConnectionReader`](`crate::ConnectionReader`), datagrams that cannot be
+/// deserialized are logged and skipped.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let mut framed = Framed::new(stream, ConnectDatagramCodec);
+///
+/// framed.send(ConnectDatagram::with_tag(1, b"hello".to_vec())?).await?;
+/// while let Some(msg) = framed.next().await {
+///     // handle the received message
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectDatagramCodec;
+
+impl Encoder<ConnectDatagram> for ConnectDatagramCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: ConnectDatagram, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        dst.extend_from_slice(&item.into_bytes());
+        Ok(())
+    }
+}
+
+impl Decoder for ConnectDatagramCodec {
+    type Item = ConnectDatagram;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            if src.len() < SIZE_PREFIX_BYTE_SIZE {
+                return Ok(None);
+            }
+
+            let size = u32::from_be_bytes(
+                src[..SIZE_PREFIX_BYTE_SIZE]
+                    .try_into()
+                    .expect("could not parse bytes into u32"),
+            ) as usize;
+            let datagram_size = SIZE_PREFIX_BYTE_SIZE + size;
+
+            if src.len() < datagram_size {
+                src.reserve(datagram_size - src.len());
+                return Ok(None);
+            }
+
+            let buffer = src.split_to(datagram_size);
+            match ConnectDatagram::from_bytes(&buffer) {
+                Ok(datagram) => return Ok(Some(datagram)),
+                Err(err) => warn!("Could not deserialize datagram: {}", err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConnectDatagramCodec;
+    use crate::ConnectDatagram;
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    #[test]
+    fn encode_and_decode() -> anyhow::Result<()> {
+        let datagrams = vec![
+            ConnectDatagram::with_tag(1, vec![1])?,
+            ConnectDatagram::with_tag(2, vec![2; 1000])?,
+            ConnectDatagram::with_tag(3, vec![3; 100_000])?,
+        ];
+
+        let mut codec = ConnectDatagramCodec;
+        let mut buffer = BytesMut::new();
+        for datagram in datagrams.iter() {
+            codec.encode(datagram.clone(), &mut buffer)?;
+        }
+
+        // the encoding is the same wire format written by a `ConnectionWriter`
+        let expected: Vec<u8> = datagrams
+            .iter()
+            .flat_map(|d| d.clone().into_bytes())
+            .collect();
+        assert_eq!(expected, buffer.to_vec());
+
+        // feed the bytes in small chunks to exercise partial frames
+        let mut input = BytesMut::new();
+        let mut decoded = Vec::new();
+        for chunk in buffer.chunks(777) {
+            input.extend_from_slice(chunk);
+            while let Some(datagram) = codec.decode(&mut input)? {
+                decoded.push(datagram);
+            }
+        }
+
+        assert_eq!(datagrams, decoded);
+        assert!(input.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+// connections are only limited by listeners, which are not available on wasm32
+#![cfg_attr(target_arch = "wasm32", allow(dead_code))]
+
+use futures::task::{AtomicWaker, Context, Poll};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+struct LimiterState {
+    max: AtomicUsize,
+    live: AtomicUsize,
+    waker: AtomicWaker,
+    drain_waker: AtomicWaker,
+    slots: Mutex<Vec<Weak<SlotState>>>,
+}
+
+/// Tracks the number of live [`Connection`](`crate::Connection`)s handed out by a listener, so
+/// that accepting can be paused while a maximum number of connections are outstanding, and so
+/// that the live connections can be drained or closed.
+///
+#[derive(Clone)]
+pub(crate) struct ConnectionLimiter {
+    state: Arc<LimiterState>,
+}
+
+impl ConnectionLimiter {
+    /// Creates a [`ConnectionLimiter`] that allows up to `max` live connections.
+    ///
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            state: Arc::new(LimiterState {
+                max: AtomicUsize::new(max.max(1)),
+                live: AtomicUsize::new(0),
+                waker: AtomicWaker::new(),
+                drain_waker: AtomicWaker::new(),
+                slots: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Creates a [`ConnectionLimiter`] that only tracks live connections, without a maximum.
+    ///
+    pub(crate) fn unlimited() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    /// Sets the maximum number of live connections.
+    ///
+    pub(crate) fn set_max(&self, max: usize) {
+        self.state.max.store(max.max(1), Ordering::SeqCst);
+        self.state.waker.wake();
+    }
+
+    /// Checks whether another connection can be accepted, registering the current task to be
+    /// woken once a live connection is dropped if not.
+    ///
+    pub(crate) fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.state.waker.register(cx.waker());
+
+        if self.live() < self.state.max.load(Ordering::SeqCst) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Counts a newly accepted connection, which is released when the returned
+    /// [`ConnectionSlot`] is dropped.
+    ///
+    pub(crate) fn acquire(&self) -> ConnectionSlot {
+        self.state.live.fetch_add(1, Ordering::SeqCst);
+
+        let slot = Arc::new(SlotState::default());
+        let mut slots = self
+            .state
+            .slots
+            .lock()
+            .expect("connection slots lock is poisoned");
+        slots.retain(|slot| slot.strong_count() > 0);
+        slots.push(Arc::downgrade(&slot));
+
+        ConnectionSlot {
+            state: self.state.clone(),
+            slot,
+        }
+    }
+
+    /// Gets the number of live connections.
+    ///
+    pub(crate) fn live(&self) -> usize {
+        self.state.live.load(Ordering::SeqCst)
+    }
+
+    /// Checks whether every connection has been dropped, registering the current task to be woken
+    /// once a live connection is dropped if not.
+    ///
+    pub(crate) fn poll_drained(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.state.drain_waker.register(cx.waker());
+
+        if self.live() == 0 {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Closes every live connection, ending their reader streams and failing their writers.
+    ///
+    pub(crate) fn close_all(&self) {
+        let slots = self
+            .state
+            .slots
+            .lock()
+            .expect("connection slots lock is poisoned");
+
+        for slot in slots.iter().filter_map(Weak::upgrade) {
+            slot.closed.store(true, Ordering::SeqCst);
+            slot.reader_waker.wake();
+            slot.writer_waker.wake();
+        }
+    }
+}
+
+#[derive(Default)]
+struct SlotState {
+    closed: AtomicBool,
+    reader_waker: AtomicWaker,
+    writer_waker: AtomicWaker,
+}
+
+/// Held by an accepted connection for as long as it is alive, shared by its reading and writing
+/// halves.
+///
+pub(crate) struct ConnectionSlot {
+    state: Arc<LimiterState>,
+    slot: Arc<SlotState>,
+}
+
+impl ConnectionSlot {
+    /// Checks whether the listener has closed the connection.
+    ///
+    pub(crate) fn is_closed(&self) -> bool {
+        self.slot.closed.load(Ordering::SeqCst)
+    }
+
+    /// Checks whether the listener has closed the connection, registering the reading half to be
+    /// woken once it does.
+    ///
+    pub(crate) fn poll_reader_closed(&self, cx: &mut Context<'_>) -> bool {
+        self.slot.reader_waker.register(cx.waker());
+        self.is_closed()
+    }
+
+    /// Checks whether the listener has closed the connection, registering the writing half to be
+    /// woken once it does.
+    ///
+    pub(crate) fn poll_writer_closed(&self, cx: &mut Context<'_>) -> bool {
+        self.slot.writer_waker.register(cx.waker());
+        self.is_closed()
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.state.live.fetch_sub(1, Ordering::SeqCst);
+        self.state.waker.wake();
+        self.state.drain_waker.wake();
+    }
+}
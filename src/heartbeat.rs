@@ -0,0 +1,227 @@
+use crate::rt::Timer;
+use crate::ConnectDatagram;
+use futures::task::{AtomicWaker, Context};
+use futures::{Future, StreamExt};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Message body of a heartbeat ping, which the peer answers with a pong.
+const PING: u8 = 0;
+
+/// Message body of a heartbeat pong.
+const PONG: u8 = 1;
+
+/// Configures the opt-in application-level heartbeat of a [`Connection`](`crate::Connection`).
+///
+/// Heartbeats are sent as datagrams with the reserved `tag` and a single-byte message body that
+/// marks them as either a ping or a pong. The writer sends a ping every `interval` and answers
+/// every ping received by the reader with a pong. Heartbeat datagrams are never yielded by the
+/// reader, so `tag` must not be used for application messages.
+///
+/// If nothing is received from the peer for longer than `timeout`, the connection is considered
+/// dead: the reader stream ends and the writer refuses new messages.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// conn.enable_heartbeat(HeartbeatConfig {
+///     tag: u16::MAX,
+///     interval: Duration::from_secs(5),
+///     timeout: Duration::from_secs(15),
+/// });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    /// Tag reserved for heartbeat datagrams.
+    pub tag: u16,
+
+    /// Interval at which pings are sent to the peer.
+    pub interval: Duration,
+
+    /// Time without receiving anything from the peer after which the connection is considered
+    /// dead.
+    pub timeout: Duration,
+}
+
+/// Heartbeat state shared between the reading and writing halves of a connection.
+struct HeartbeatState {
+    config: HeartbeatConfig,
+    pongs_owed: AtomicUsize,
+    dead: AtomicBool,
+    writer_waker: AtomicWaker,
+}
+
+/// Creates the reading and writing halves of a heartbeat with the provided configuration.
+pub(crate) fn heartbeat(config: HeartbeatConfig) -> (ReaderHeartbeat, WriterHeartbeat) {
+    let state = Arc::new(HeartbeatState {
+        config,
+        pongs_owed: AtomicUsize::new(0),
+        dead: AtomicBool::new(false),
+        writer_waker: AtomicWaker::new(),
+    });
+
+    (
+        ReaderHeartbeat {
+            state: state.clone(),
+            deadline: Timer::after(config.timeout),
+        },
+        WriterHeartbeat {
+            state,
+            interval: Timer::interval(config.interval),
+        },
+    )
+}
+
+/// Tracks liveness of the peer and answers its pings from the reading half of a connection.
+pub(crate) struct ReaderHeartbeat {
+    state: Arc<HeartbeatState>,
+    deadline: Timer,
+}
+
+impl ReaderHeartbeat {
+    /// Get the tag of the heartbeat datagrams.
+    pub(crate) fn tag(&self) -> u16 {
+        self.state.config.tag
+    }
+
+    /// Records that bytes were received from the peer, pushing back the deadline.
+    pub(crate) fn received(&mut self) {
+        self.deadline.set_after(self.state.config.timeout);
+    }
+
+    /// Handles a received datagram, returning whether it was a heartbeat that must not be yielded
+    /// to the user.
+    pub(crate) fn handle(&mut self, datagram: &ConnectDatagram) -> bool {
+        if datagram.tag() != self.state.config.tag {
+            return false;
+        }
+
+        if datagram.data() == [PING] {
+            self.state.pongs_owed.fetch_add(1, Ordering::SeqCst);
+            self.state.writer_waker.wake();
+        }
+
+        true
+    }
+
+    /// Checks whether the peer has been silent for longer than the timeout, marking the
+    /// connection as dead if so.
+    pub(crate) fn poll_expired(&mut self, cx: &mut Context<'_>) -> bool {
+        if Pin::new(&mut self.deadline).poll(cx).is_ready() {
+            self.state.dead.store(true, Ordering::SeqCst);
+            self.state.writer_waker.wake();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Emits pings and owed pongs from the writing half of a connection.
+pub(crate) struct WriterHeartbeat {
+    state: Arc<HeartbeatState>,
+    interval: Timer,
+}
+
+impl WriterHeartbeat {
+    /// Checks whether the reading half has considered the connection dead.
+    pub(crate) fn is_dead(&self) -> bool {
+        self.state.dead.load(Ordering::SeqCst)
+    }
+
+    /// Collects the heartbeat datagrams that are due to be sent, registering the current task to be
+    /// woken when more are due.
+    pub(crate) fn poll_due(&mut self, cx: &mut Context<'_>) -> Vec<ConnectDatagram> {
+        self.state.writer_waker.register(cx.waker());
+
+        let tag = self.state.config.tag;
+        let mut due = Vec::new();
+
+        if self.interval.poll_next_unpin(cx).is_ready() {
+            due.push(
+                ConnectDatagram::with_tag(tag, vec![PING]).expect("heartbeat is never too large"),
+            );
+        }
+
+        for _ in 0..self.state.pongs_owed.swap(0, Ordering::SeqCst) {
+            due.push(
+                ConnectDatagram::with_tag(tag, vec![PONG]).expect("heartbeat is never too large"),
+            );
+        }
+
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeartbeatConfig;
+    use crate::{ConnectDatagram, Connection};
+    use async_std::net::{TcpListener, TcpStream};
+    use futures::{SinkExt, StreamExt};
+    use std::time::Duration;
+
+    const CONFIG: HeartbeatConfig = HeartbeatConfig {
+        tag: u16::MAX,
+        interval: Duration::from_millis(20),
+        timeout: Duration::from_millis(100),
+    };
+
+    async fn connection_pair() -> anyhow::Result<(Connection, Connection)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let client = TcpStream::connect(listener.local_addr()?).await?;
+        let (server, _) = listener.accept().await?;
+
+        let mut client = Connection::from(client);
+        let mut server = Connection::from(server);
+        client.enable_heartbeat(CONFIG);
+        server.enable_heartbeat(CONFIG);
+
+        Ok((client, server))
+    }
+
+    #[async_std::test]
+    async fn heartbeat_keeps_idle_connection_alive() -> anyhow::Result<()> {
+        let (client, server) = connection_pair().await?;
+        let (mut client_reader, mut client_writer) = client.split();
+        let (mut server_reader, mut server_writer) = server.split();
+
+        async_std::task::spawn(async move { while client_writer.heartbeat().await.is_ok() {} });
+        async_std::task::spawn(async move {
+            server_writer
+                .send(ConnectDatagram::with_tag(1, b"hello".to_vec())?)
+                .await?;
+            while server_writer.heartbeat().await.is_ok() {}
+            anyhow::Ok(())
+        });
+        async_std::task::spawn(async move { while server_reader.next().await.is_some() {} });
+
+        // heartbeats are not yielded, only the application message is
+        let msg = client_reader.next().await.expect("connection closed");
+        assert_eq!(1, msg.tag());
+
+        let next =
+            async_std::future::timeout(Duration::from_millis(300), client_reader.next()).await;
+        assert!(next.is_err());
+        assert!(!client_reader.is_closed());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn heartbeat_timeout_closes_connection() -> anyhow::Result<()> {
+        let (client, _server) = connection_pair().await?;
+        let (mut client_reader, client_writer) = client.split();
+
+        // the server never sends heartbeats, so the client gives up on it
+        let next = async_std::future::timeout(Duration::from_secs(5), client_reader.next()).await?;
+        assert!(next.is_none());
+        assert!(client_writer.is_closed());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use crate::rt::{Instant, Timer};
+use futures::task::Context;
+use futures::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Idle timeout state shared between the reading and writing halves of a connection.
+struct IdleState {
+    timeout: Duration,
+    start: Instant,
+    last_activity_nanos: AtomicU64,
+    expired: AtomicBool,
+}
+
+impl IdleState {
+    /// Records that a datagram was read or written, pushing back the deadline.
+    fn touch(&self) {
+        let nanos = self.start.elapsed().as_nanos() as u64;
+        self.last_activity_nanos.store(nanos, Ordering::SeqCst);
+    }
+
+    /// Gets the instant at which the connection expires unless more activity is recorded.
+    fn deadline(&self) -> Instant {
+        let last_activity = Duration::from_nanos(self.last_activity_nanos.load(Ordering::SeqCst));
+        self.start + last_activity + self.timeout
+    }
+
+    /// Checks whether the connection has been idle for longer than the timeout, marking it as
+    /// expired if so.
+    fn is_expired(&self) -> bool {
+        if self.expired.load(Ordering::SeqCst) {
+            return true;
+        }
+
+        if Instant::now() >= self.deadline() {
+            self.expired.store(true, Ordering::SeqCst);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Creates the reading and writing halves of an idle timeout.
+pub(crate) fn idle_timeout(timeout: Duration) -> (ReaderIdle, WriterIdle) {
+    let state = Arc::new(IdleState {
+        timeout,
+        start: Instant::now(),
+        last_activity_nanos: AtomicU64::new(0),
+        expired: AtomicBool::new(false),
+    });
+
+    (
+        ReaderIdle {
+            state: state.clone(),
+            timer: Timer::after(timeout),
+        },
+        WriterIdle { state },
+    )
+}
+
+/// Tracks the idle timeout from the reading half of a connection.
+pub(crate) struct ReaderIdle {
+    state: Arc<IdleState>,
+    timer: Timer,
+}
+
+impl ReaderIdle {
+    /// Records that a datagram was read.
+    pub(crate) fn touch(&self) {
+        self.state.touch();
+    }
+
+    /// Checks whether the connection has been idle for longer than the timeout, registering the
+    /// current task to be woken at the deadline otherwise.
+    pub(crate) fn poll_expired(&mut self, cx: &mut Context<'_>) -> bool {
+        loop {
+            if self.state.is_expired() {
+                return true;
+            }
+
+            self.timer.set_at(self.state.deadline());
+            if Pin::new(&mut self.timer).poll(cx).is_pending() {
+                return false;
+            }
+        }
+    }
+}
+
+/// Tracks the idle timeout from the writing half of a connection.
+pub(crate) struct WriterIdle {
+    state: Arc<IdleState>,
+}
+
+impl WriterIdle {
+    /// Records that a datagram was written.
+    pub(crate) fn touch(&self) {
+        self.state.touch();
+    }
+
+    /// Checks whether the connection has been idle for longer than the timeout.
+    pub(crate) fn is_expired(&self) -> bool {
+        self.state.is_expired()
+    }
+}
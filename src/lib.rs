@@ -10,7 +10,7 @@
 //!
 //! // construct a new message
 //! let msg = String::from("Hello world!");
-//! let envelope: ConnectDatagram = ConnectDatagram::new(65535, msg.into_bytes())?;
+//! let envelope: ConnectDatagram = ConnectDatagram::with_tag(65535, msg.into_bytes())?;
 //!
 //! // send a message to the server
 //! conn.writer().send(envelope).await?;
@@ -33,9 +33,12 @@
 //! - TCP
 //!     - [TCP Echo Server](https://github.com/sachanganesh/connect-rs/tree/main/examples/tcp-echo-server)
 //!     - [TCP Client](https://github.com/sachanganesh/connect-rs/tree/main/examples/tcp-client)
+//!     - [File Transfer](https://github.com/sachanganesh/connect-rs/tree/main/examples/file-transfer)
 //! - TLS (enable `tls` feature flag)
 //!     - [TLS Echo Server](https://github.com/sachanganesh/connect-rs/tree/main/examples/tls-echo-server)
 //!     - [TLS Client](https://github.com/sachanganesh/connect-rs/tree/main/examples/tls-client)
+//! - Typed Messages (enable `json` feature flag)
+//!     - [Typed Messages](https://github.com/sachanganesh/connect-rs/tree/main/examples/typed-messages)
 //!
 //! # Why?
 //!
@@ -66,13 +69,66 @@
 //! # Feature Flags
 //!
 //! - `tls`: enables usage of tls transport functionality
+//! - `compression`: enables zstd compression of datagram message bodies
+//! - `checksum`: enables CRC32C integrity checks of datagram message bodies
+//! - `serde`: implements `Serialize` and `Deserialize` for `ConnectDatagram`, and enables the
+//!   [`typed`] message layer
+//! - `json`: enables the JSON codec for the [`typed`] message layer
+//! - `bincode`: enables the bincode codec for the [`typed`] message layer
+//! - `websocket`: enables usage of WebSocket transport functionality
+//! - `tokio-util`: enables the [`ConnectDatagramCodec`] for use with `tokio_util::codec::Framed`
+//! - `quic`: enables usage of QUIC transport functionality, with TLS provided by QUIC itself
+//! - `noise`: enables [`noise`] protocol encryption of TCP connections with static keypairs
+//! - `tracing`: emits events with `tracing` instead of `log`, within a `connection` span that
+//!   records the local and peer addresses of each connection
+//!
+//! Log statements on the hot paths of reading and writing are at the `trace` level, so they can be
+//! compiled out with the `max_level_*` and `release_max_level_*` features of `log` or `tracing`.
+//!
+//! # WebAssembly
+//!
+//! The crate compiles for `wasm32-unknown-unknown` to run in the browser, where timers and
+//! background tasks are provided by the browser instead of `async-std`. The TCP and UDP transports
+//! and the listeners are not available there, nor are the `tls`, `quic` and `noise` features.
+//! With the `websocket` feature, [`Connection::ws_client`] connects with the browser's `WebSocket`
+//! API instead, so code handling messages can be shared between a native server and a browser
+//! client.
 //!
 
 // #![feature(doc_cfg)]
 
-mod protocol;
+#[cfg(not(target_arch = "wasm32"))]
+mod affinity;
+pub mod bench;
+#[cfg(not(target_arch = "wasm32"))]
+mod builder;
+#[cfg(feature = "tokio-util")]
+mod codec;
+mod conn_limit;
+mod heartbeat;
+mod idle;
+mod logging;
+mod observer;
+pub mod protocol;
+mod proxy;
+mod rate_limit;
 mod reader;
+pub mod rpc;
+mod rt;
+mod sequence;
+mod shared;
+#[cfg(not(target_arch = "wasm32"))]
+mod shutdown;
+mod stats;
+#[cfg(not(target_arch = "wasm32"))]
+mod stdio;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod tcp;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod transport;
+#[cfg(feature = "serde")]
+pub mod typed;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod udp;
 mod writer;
 
@@ -80,14 +136,58 @@ mod writer;
 // #[doc(cfg(feature = "tls"))]
 pub mod tls;
 
-use async_std::{net::SocketAddr, pin::Pin};
-use futures::{AsyncRead, AsyncWrite, Sink, Stream};
+#[cfg(feature = "websocket")]
+pub mod ws;
+
+#[cfg(feature = "quic")]
+pub mod quic;
+
+#[cfg(feature = "noise")]
+pub mod noise;
 
+#[cfg(all(
+    target_arch = "wasm32",
+    any(feature = "tls", feature = "quic", feature = "noise")
+))]
+compile_error!("the tls, quic and noise features are not supported on wasm32");
+
+use crate::conn_limit::ConnectionSlot;
+use crate::protocol::SharedByteOrder;
+use futures::{AsyncRead, AsyncWrite};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::affinity::AffinityStrategy;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::builder::{ConnectionBuilder, ListenerBuilder};
+#[cfg(feature = "tokio-util")]
+pub use crate::codec::ConnectDatagramCodec;
+pub use crate::heartbeat::HeartbeatConfig;
+pub use crate::observer::ConnectionObserver;
+#[cfg(feature = "compression")]
+pub use crate::protocol::Compression;
 pub use crate::protocol::{
-    ConnectDatagram, DatagramError, DATAGRAM_HEADER_BYTE_SIZE, SIZE_PREFIX_BYTE_SIZE,
+    ByteOrder, ConnectDatagram, DatagramError, SegmentedDatagram, DATAGRAM_HEADER_BYTE_SIZE,
+    SIZE_PREFIX_BYTE_SIZE,
+};
+pub use crate::proxy::proxy;
+pub use crate::reader::{
+    BoxedReadStream, ChunkReader, CloseReason, ConnectionReader, DatagramReceiver, NextPayload,
+    NextResult, PayloadReader, TagRouter, TagSubscriber, TeeReader, DEFAULT_POLL_BUDGET_BYTES,
+    DEFAULT_POLL_BUDGET_DATAGRAMS,
 };
-pub use crate::reader::ConnectionReader;
-pub use crate::writer::{ConnectionWriteError, ConnectionWriter};
+pub use crate::sequence::{SequenceGap, SEQUENCE_EXTENSION_KEY};
+pub use crate::shared::{SharedWriter, SHARED_WRITER_CAPACITY};
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::shutdown::{ListenerHandle, ShutdownHandle};
+pub use crate::stats::ConnectionStats;
+pub use crate::writer::{
+    broadcast, BoxedWriteStream, ConnectionWriteError, ConnectionWriter, TaggedSinkError,
+};
+pub use bytes::Bytes;
 pub use futures::{SinkExt, StreamExt};
 
 /// Wrapper around a [`ConnectionReader`] and [`ConnectionWriter`] to read and write on a network
@@ -95,8 +195,17 @@ pub use futures::{SinkExt, StreamExt};
 pub struct Connection {
     local_addr: SocketAddr,
     peer_addr: SocketAddr,
-    reader: Box<dyn Stream<Item = ConnectDatagram> + Unpin + Send + Sync>,
-    writer: Box<dyn Sink<ConnectDatagram, Error = ConnectionWriteError> + Unpin + Send + Sync>,
+    affinity_hint: usize,
+    #[cfg(feature = "tls")]
+    peer_certificates: Option<Vec<tls::Certificate>>,
+    #[cfg(feature = "tls")]
+    sni_hostname: Option<String>,
+    #[cfg(feature = "tls")]
+    alpn_protocol: Option<Vec<u8>>,
+    #[cfg(feature = "noise")]
+    noise_remote_static: Option<Vec<u8>>,
+    reader: ConnectionReader,
+    writer: ConnectionWriter,
 }
 
 #[allow(dead_code)]
@@ -110,56 +219,383 @@ impl Connection {
         Self {
             local_addr,
             peer_addr,
-            reader: Box::new(ConnectionReader::new(local_addr, peer_addr, read_stream)),
-            writer: Box::new(ConnectionWriter::new(local_addr, peer_addr, write_stream)),
+            affinity_hint: 0,
+            #[cfg(feature = "tls")]
+            peer_certificates: None,
+            #[cfg(feature = "tls")]
+            sni_hostname: None,
+            #[cfg(feature = "tls")]
+            alpn_protocol: None,
+            #[cfg(feature = "noise")]
+            noise_remote_static: None,
+            reader: ConnectionReader::new(local_addr, peer_addr, read_stream),
+            writer: ConnectionWriter::new(local_addr, peer_addr, write_stream),
         }
     }
 
+    /// Shares the byte orders of the reader and writer with the read and write streams of a
+    /// transport that frames datagrams itself.
+    pub(crate) fn with_framing_byte_order(
+        mut self,
+        read: SharedByteOrder,
+        write: SharedByteOrder,
+    ) -> Self {
+        self.reader.share_byte_order(read);
+        self.writer.share_byte_order(write);
+        self
+    }
+
+    /// Creates a [`Connection`] over any established bidirectional byte stream, such as a session
+    /// of a TLS or SSH library set up elsewhere, with the provided addresses reported as its local
+    /// and peer socket addresses.
+    ///
+    /// No handshake of any kind is performed, the datagram framing is simply layered on top of the
+    /// stream.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let tunnel = ssh_session.direct_tcpip("localhost", 3456).await?;
+    /// let mut conn = Connection::from_io(local_addr, peer_addr, tunnel);
+    /// ```
+    pub fn from_io<S>(local_addr: SocketAddr, peer_addr: SocketAddr, stream: S) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + Sync + 'static,
+    {
+        let (read_stream, write_stream) = futures::AsyncReadExt::split(stream);
+
+        Self::new(
+            local_addr,
+            peer_addr,
+            Box::pin(read_stream),
+            Box::pin(write_stream),
+        )
+    }
+
     /// Get the local IP address and port.
     pub fn local_addr(&self) -> SocketAddr {
-        self.local_addr.clone()
+        self.local_addr
     }
 
     /// Get the peer IP address and port.
     pub fn peer_addr(&self) -> SocketAddr {
-        self.peer_addr.clone()
+        self.peer_addr
+    }
+
+    /// Labels the connection, such as with the authenticated user or a request ID, so that the log
+    /// lines of its [`ConnectionReader`] and [`ConnectionWriter`] identify it as
+    /// `label (peer_addr)` instead of by the peer address alone, which may be shared by many
+    /// connections behind a NAT or on loopback.
+    ///
+    /// This includes the lines logged when the connection is closed by its listener. With the
+    /// `tracing` feature, the label is also recorded as the `label` field of the `connection` span.
+    /// Connections have no label by default.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let user = authenticate(&mut conn).await?;
+    /// conn.set_label(user.name.clone());
+    /// ```
+    pub fn set_label(&mut self, label: String) {
+        self.reader.set_label(label.clone());
+        self.writer.set_label(label);
+    }
+
+    /// Get the label of the connection set with [`set_label`](Connection::set_label), if any.
+    pub fn label(&self) -> Option<&str> {
+        self.reader.label()
+    }
+
+    /// Get the affinity hint assigned by the listener that accepted the connection.
+    ///
+    /// This is `0` unless the listener was configured with an [`AffinityStrategy`].
+    pub fn affinity_hint(&self) -> usize {
+        self.affinity_hint
+    }
+
+    /// Get the certificate chain presented by the peer during the TLS handshake.
+    ///
+    /// On a client connection this is the server's certificate chain. On a connection accepted by
+    /// a [`TlsListener`](`tls::TlsListener`) this is the verified client certificate chain, which
+    /// is only present when the listener's [`rustls::ServerConfig`] is configured to request client
+    /// certificates (e.g. with [`rustls::AllowAnyAuthenticatedClient`]). Returns `None` for
+    /// connections that do not use a TLS transport.
+    #[cfg(feature = "tls")]
+    pub fn peer_certificates(&self) -> Option<&[tls::Certificate]> {
+        self.peer_certificates.as_deref()
+    }
+
+    /// Get the server name that the client requested with SNI during the TLS handshake.
+    ///
+    /// This is only present on a connection accepted by a [`TlsListener`](`tls::TlsListener`)
+    /// from a client that sent the SNI extension, and can be used to route the connection by
+    /// virtual host. Returns `None` for client connections and connections that do not use a TLS
+    /// transport.
+    #[cfg(feature = "tls")]
+    pub fn sni_hostname(&self) -> Option<&str> {
+        self.sni_hostname.as_deref()
+    }
+
+    /// Get the application protocol agreed on with ALPN during the TLS handshake.
+    ///
+    /// This is only present when both peers configured their protocols with `set_protocols` on
+    /// the [`rustls::ClientConfig`] and [`rustls::ServerConfig`], and they have a protocol in
+    /// common. Returns `None` for connections that do not use a TLS transport.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// match conn.negotiated_alpn() {
+    ///     Some(b"chat/2") => handle_chat_v2(conn).await,
+    ///     _ => handle_chat_v1(conn).await,
+    /// }
+    /// ```
+    #[cfg(feature = "tls")]
+    pub fn negotiated_alpn(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// Get the static public key that the peer authenticated with during the Noise handshake.
+    ///
+    /// This is only set for connections created with [`Connection::noise_client`] or
+    /// [`Connection::noise_server`], and servers should check it against the keys of the clients
+    /// they trust.
+    #[cfg(feature = "noise")]
+    pub fn noise_remote_static(&self) -> Option<&[u8]> {
+        self.noise_remote_static.as_deref()
+    }
+
+    /// Get a snapshot of the messages and bytes read and written on the connection so far.
+    ///
+    /// The counters are accumulated without locking by the [`ConnectionReader`] and
+    /// [`ConnectionWriter`], and only start again from zero when a new connection is created. Once
+    /// the connection is split, each half reports its own counters through
+    /// [`ConnectionReader::stats`] and [`ConnectionWriter::stats`].
+    pub fn stats(&self) -> ConnectionStats {
+        let read = self.reader.stats();
+        let written = self.writer.stats();
+
+        ConnectionStats {
+            messages_read: read.messages_read,
+            messages_written: written.messages_written,
+            bytes_read: read.bytes_read,
+            bytes_written: written.bytes_written,
+        }
+    }
+
+    pub(crate) fn set_affinity_hint(&mut self, affinity_hint: usize) {
+        self.affinity_hint = affinity_hint;
+    }
+
+    pub(crate) fn set_slot(&mut self, slot: ConnectionSlot) {
+        let slot = Arc::new(slot);
+        self.reader.set_slot(slot.clone());
+        self.writer.set_slot(slot);
+    }
+
+    /// Enables the opt-in application-level heartbeat with the provided configuration, which both
+    /// peers must enable with the same reserved tag.
+    ///
+    /// See [`HeartbeatConfig`] for how heartbeats are exchanged, and
+    /// [`ConnectionWriter::heartbeat`] for sending heartbeats while no messages are being sent.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// conn.enable_heartbeat(HeartbeatConfig {
+    ///     tag: u16::MAX,
+    ///     interval: Duration::from_secs(5),
+    ///     timeout: Duration::from_secs(15),
+    /// });
+    /// ```
+    pub fn enable_heartbeat(&mut self, config: HeartbeatConfig) {
+        let (reader, writer) = heartbeat::heartbeat(config);
+        self.reader.set_heartbeat(reader);
+        self.writer.set_heartbeat(writer);
+    }
+
+    /// Closes the connection once no datagram has been read or written for longer than `timeout`:
+    /// the reader stream ends and the writer refuses new messages, as if the peer had gone away.
+    ///
+    /// Reading or writing any datagram, including heartbeats, resets the timer. The timeout is
+    /// checked while the reader is polled and whenever the writer is used, without spawning any
+    /// background tasks.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// conn.set_idle_timeout(Duration::from_secs(300));
+    /// ```
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        let (reader, writer) = idle::idle_timeout(timeout);
+        self.reader.set_idle(reader);
+        self.writer.set_idle(writer);
+    }
+
+    /// Enables end-to-end sequencing of the datagrams sent and received on the connection, starting
+    /// from sequence number `0` in both directions, which both peers must enable.
+    ///
+    /// The writer stamps each datagram with the next sequence number, and the reader reports a
+    /// [`SequenceGap`] to the connection's [`ConnectionObserver`] whenever a received sequence
+    /// number skips ahead or goes back. Unlike the ordering guaranteed by the transport, this
+    /// detects datagrams lost at the application layer, such as those that were pending when a
+    /// previous connection failed. To resume from a checkpoint instead of starting over, see
+    /// [`ConnectionWriter::set_next_sequence`] and [`ConnectionReader::set_expected_sequence`].
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut conn = Connection::tcp_client(ip_address).await?.with_observer(gap_alerts);
+    /// conn.enable_sequencing();
+    /// ```
+    pub fn enable_sequencing(&mut self) {
+        self.reader.set_expected_sequence(0);
+        self.writer.set_next_sequence(0);
+    }
+
+    /// Sets the byte order of the header fields of the datagrams sent and received on the
+    /// connection, such as to talk to a legacy peer that frames its datagrams in little-endian.
+    ///
+    /// See [`ConnectionReader::set_byte_order`] and [`ConnectionWriter::set_byte_order`].
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut conn = Connection::tcp_client(legacy_address).await?;
+    /// conn.set_byte_order(ByteOrder::LittleEndian);
+    /// ```
+    pub fn set_byte_order(&mut self, byte_order: ByteOrder) {
+        self.reader.set_byte_order(byte_order);
+        self.writer.set_byte_order(byte_order);
+    }
+
+    /// Checks without waiting whether the connection is still usable, such as before handing out
+    /// an idle connection from a pool.
+    ///
+    /// The probe reads whatever the network stream has ready without blocking, which detects a
+    /// peer that has closed or reset the connection, as well as a heartbeat or idle timeout that
+    /// has expired. Any datagrams read by the probe are kept and yielded by the
+    /// [`ConnectionReader`] as usual, and no application data is sent.
+    ///
+    /// The probe can only detect what the transport has already reported: a peer that vanished
+    /// without closing the connection, such as after a crash or a network partition, leaves the
+    /// connection half-open and still appears alive until TCP keepalive or the heartbeat notices.
+    /// How soon a reset is reported also varies by platform, and connectionless transports like
+    /// UDP never report the peer going away. Enable [`enable_heartbeat`](Connection::enable_heartbeat)
+    /// to detect dead peers reliably.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// while let Some(mut conn) = pool.pop() {
+    ///     if conn.is_alive() {
+    ///         return Ok(conn);
+    ///     }
+    /// }
+    /// ```
+    pub fn is_alive(&mut self) -> bool {
+        !self.writer.is_closed() && !self.reader.probe_closed()
+    }
+
+    /// Attaches an observer that is notified of the datagrams read and written on the connection,
+    /// and of any errors reading from or writing to the network stream.
+    ///
+    /// The observer is shared by the reading and writing halves and stays attached after the
+    /// connection is split. See [`ConnectionObserver`] for when each callback is invoked.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let metrics = Arc::new(Metrics::default());
+    /// let conn = Connection::tcp_client(ip_address).await?.with_observer(metrics.clone());
+    /// ```
+    pub fn with_observer(mut self, observer: Arc<dyn ConnectionObserver>) -> Self {
+        self.reader.set_observer(observer.clone());
+        self.writer.set_observer(observer);
+        self
     }
 
     /// Consume the [`Connection`] to split into separate [`ConnectionReader`] and
     /// [`ConnectionWriter`] halves.
     ///
-    /// [`Connection`]s are split when reading and writing must be concurrent operations.
-    pub fn split(
-        self,
-    ) -> (
-        impl Stream<Item = ConnectDatagram> + Send + Sync,
-        impl Sink<ConnectDatagram, Error = ConnectionWriteError> + Send + Sync,
-    ) {
+    /// [`Connection`]s are split when reading and writing must be concurrent operations. To write
+    /// from several tasks at once, use [`split_shared`](Connection::split_shared) instead.
+    ///
+    /// If the connection was accepted by a listener with a maximum connection limit, it keeps
+    /// counting towards that limit until both halves are dropped.
+    pub fn split(self) -> (ConnectionReader, ConnectionWriter) {
         (self.reader, self.writer)
     }
 
+    /// Borrow the [`ConnectionReader`] and [`ConnectionWriter`] halves at the same time, so that
+    /// reading and writing can be concurrent operations without consuming the [`Connection`].
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let (reader, writer) = conn.split_mut();
+    ///
+    /// futures::select! {
+    ///     msg = reader.next() => handle(msg),
+    ///     res = writer.send(outgoing).fuse() => res?,
+    /// }
+    /// ```
+    pub fn split_mut(&mut self) -> (&mut ConnectionReader, &mut ConnectionWriter) {
+        (&mut self.reader, &mut self.writer)
+    }
+
     /// Re-wrap the [`ConnectionReader`] and [`ConnectionWriter`] halves into a [`Connection`].
     pub fn join(
         local_addr: SocketAddr,
         peer_addr: SocketAddr,
-        reader: impl Stream<Item = ConnectDatagram> + Unpin + Send + Sync + 'static,
-        writer: impl Sink<ConnectDatagram, Error = ConnectionWriteError> + Unpin + Send + Sync + 'static,
+        reader: ConnectionReader,
+        writer: ConnectionWriter,
     ) -> Self {
         Self {
             local_addr,
             peer_addr,
-            reader: Box::new(reader),
-            writer: Box::new(writer),
+            affinity_hint: 0,
+            #[cfg(feature = "tls")]
+            peer_certificates: None,
+            #[cfg(feature = "tls")]
+            sni_hostname: None,
+            #[cfg(feature = "tls")]
+            alpn_protocol: None,
+            #[cfg(feature = "noise")]
+            noise_remote_static: None,
+            reader,
+            writer,
         }
     }
 
     /// Get mutable access to the underlying [`ConnectionReader`].
-    pub fn reader(&mut self) -> &mut impl Stream<Item = ConnectDatagram> {
+    pub fn reader(&mut self) -> &mut ConnectionReader {
         &mut self.reader
     }
 
     /// Get mutable access to the underlying [`ConnectionWriter`].
-    pub fn writer(&mut self) -> &mut impl Sink<ConnectDatagram, Error = ConnectionWriteError> {
+    pub fn writer(&mut self) -> &mut ConnectionWriter {
         &mut self.writer
     }
 
@@ -173,9 +609,223 @@ impl Connection {
         // writer.close().await;
         drop(writer);
 
-        return peer_addr;
+        peer_addr
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use crate::{ConnectDatagram, Connection, ConnectionObserver, ConnectionStats, SequenceGap};
+    use async_std::net::{TcpListener, TcpStream};
+    use futures::{SinkExt, StreamExt};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    #[async_std::test]
+    async fn stats() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = Connection::from(TcpStream::connect(listener.local_addr()?).await?);
+        let mut server = Connection::from(listener.accept().await?.0);
+
+        let mut size = 0;
+        for tag in 0..3 {
+            let datagram = ConnectDatagram::with_tag(tag, vec![1, 2, 3])?;
+            size += datagram.serialized_size() as u64;
+            client.writer().feed(datagram).await?;
+        }
+        client.writer().flush().await?;
+
+        for _ in 0..3 {
+            server.reader().next().await.expect("connection closed");
+        }
+
+        assert_eq!(
+            ConnectionStats {
+                messages_read: 0,
+                messages_written: 3,
+                bytes_read: 0,
+                bytes_written: size,
+            },
+            client.stats()
+        );
+        assert_eq!(
+            ConnectionStats {
+                messages_read: 3,
+                messages_written: 0,
+                bytes_read: size,
+                bytes_written: 0,
+            },
+            server.stats()
+        );
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn is_alive() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = Connection::from(TcpStream::connect(listener.local_addr()?).await?);
+        let mut server = Connection::from(listener.accept().await?.0);
+        assert!(client.is_alive());
+
+        server
+            .writer()
+            .send(ConnectDatagram::with_tag(1, vec![1])?)
+            .await?;
+        drop(server);
+
+        let start = Instant::now();
+        while client.is_alive() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        }
+
+        // the datagram read by the probe is still yielded
+        assert_eq!(
+            1,
+            client.reader().next().await.expect("datagram lost").tag()
+        );
+        assert!(client.reader().next().await.is_none());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn split_mut() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = Connection::from(TcpStream::connect(listener.local_addr()?).await?);
+        let mut server = Connection::from(listener.accept().await?.0);
+
+        // echo a datagram back while reading the reply through the same connection
+        let (reader, writer) = client.split_mut();
+        let (sent, reply) = futures::join!(
+            async {
+                writer.send(ConnectDatagram::with_tag(1, vec![1])?).await?;
+                anyhow::Ok(())
+            },
+            async {
+                let msg = server.reader().next().await.expect("connection closed");
+                server.writer().send(msg).await?;
+                anyhow::Ok(reader.next().await)
+            }
+        );
+
+        sent?;
+        assert_eq!(1, reply?.expect("connection closed").tag());
+        assert_eq!(server.peer_addr(), client.local_addr());
+
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct CountingObserver {
+        read: AtomicUsize,
+        written: AtomicUsize,
+        bytes: AtomicUsize,
+    }
+
+    impl ConnectionObserver for CountingObserver {
+        fn on_message_read(&self, _tag: u16, bytes: usize) {
+            self.read.fetch_add(1, Ordering::SeqCst);
+            self.bytes.fetch_add(bytes, Ordering::SeqCst);
+        }
+
+        fn on_message_written(&self, _tag: u16, bytes: usize) {
+            self.written.fetch_add(1, Ordering::SeqCst);
+            self.bytes.fetch_add(bytes, Ordering::SeqCst);
+        }
+    }
+
+    #[async_std::test]
+    async fn observer() -> anyhow::Result<()> {
+        let observer = Arc::new(CountingObserver::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = Connection::from(TcpStream::connect(listener.local_addr()?).await?)
+            .with_observer(observer.clone());
+        let mut server =
+            Connection::from(listener.accept().await?.0).with_observer(observer.clone());
+
+        let datagram = ConnectDatagram::with_tag(1, vec![1, 2, 3])?;
+        let size = datagram.serialized_size();
+        client.writer().send(datagram).await?;
+        server.reader().next().await.expect("connection closed");
+
+        assert_eq!(1, observer.read.load(Ordering::SeqCst));
+        assert_eq!(1, observer.written.load(Ordering::SeqCst));
+        assert_eq!(2 * size, observer.bytes.load(Ordering::SeqCst));
+
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct GapObserver {
+        gaps: Mutex<Vec<SequenceGap>>,
+    }
+
+    impl ConnectionObserver for GapObserver {
+        fn on_sequence_gap(&self, gap: SequenceGap) {
+            self.gaps.lock().unwrap().push(gap);
+        }
+    }
+
+    #[async_std::test]
+    async fn labels() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut conn = Connection::from(TcpStream::connect(listener.local_addr()?).await?);
+        assert_eq!(None, conn.label());
+
+        conn.set_label("alice".to_string());
+        assert_eq!(Some("alice"), conn.label());
+
+        let (reader, writer) = conn.split();
+        assert_eq!(Some("alice"), reader.label());
+        assert_eq!(Some("alice"), writer.label());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn sequencing() -> anyhow::Result<()> {
+        let observer = Arc::new(GapObserver::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = Connection::from(TcpStream::connect(listener.local_addr()?).await?);
+        let mut server =
+            Connection::from(listener.accept().await?.0).with_observer(observer.clone());
+        client.enable_sequencing();
+        server.enable_sequencing();
+
+        for tag in 0..2 {
+            client
+                .writer()
+                .send(ConnectDatagram::with_tag(tag, vec![1])?)
+                .await?;
+        }
+
+        // skip ahead, as if datagrams 2 to 4 were lost
+        client.writer().set_next_sequence(5);
+        client
+            .writer()
+            .send(ConnectDatagram::with_tag(2, vec![1])?)
+            .await?;
+        assert_eq!(Some(6), client.writer().next_sequence());
+
+        let mut sequences = Vec::new();
+        for _ in 0..3 {
+            let datagram = server.reader().next().await.expect("connection closed");
+            sequences.push(datagram.sequence().expect("datagram not sequenced"));
+        }
+
+        assert_eq!(vec![0, 1, 5], sequences);
+        assert_eq!(
+            vec![SequenceGap {
+                expected: 2,
+                got: 5
+            }],
+            *observer.gaps.lock().unwrap()
+        );
+        assert_eq!(Some(6), server.reader().expected_sequence());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+//! Selects the logging macros used throughout the crate: those of `log` by default, or those of
+//! `tracing` when the `tracing` feature is enabled.
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use log::{debug, error, info, trace, warn};
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{debug, error, info, trace, warn};
+
+use std::fmt::Display;
+use std::net::SocketAddr;
+
+/// Displays the peer address of a connection in log lines, preceded by the label of the connection
+/// if it has one, such as `alice (10.0.0.7:51234)`.
+pub(crate) struct Peer<'a> {
+    pub(crate) label: Option<&'a str>,
+    pub(crate) addr: SocketAddr,
+}
+
+impl Display for Peer<'_> {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.label {
+            Some(label) => write!(formatter, "{} ({})", label, self.addr),
+            None => Display::fmt(&self.addr, formatter),
+        }
+    }
+}
+
+/// Creates the span that the events of a connection's reader and writer are recorded in, with a
+/// `label` field that is recorded once the connection is labeled.
+#[cfg(feature = "tracing")]
+pub(crate) fn connection_span(local_addr: SocketAddr, peer_addr: SocketAddr) -> tracing::Span {
+    tracing::debug_span!(
+        "connection",
+        %local_addr,
+        %peer_addr,
+        label = tracing::field::Empty
+    )
+}
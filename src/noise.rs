@@ -0,0 +1,459 @@
+//! Noise protocol encryption for TCP connections, as a lightweight alternative to TLS.
+//!
+//! <br/>
+//!
+//! This module exposes the [`Connection::noise_client`] and [`Connection::noise_server`]
+//! constructors, which perform a [Noise](https://noiseprotocol.org/noise.html) handshake over an
+//! established [`TcpStream`] and then encrypt everything written on it, authenticating both peers
+//! with static keypairs instead of certificates.
+//!
+//! # Handshake pattern
+//!
+//! Connections use the `IK` pattern, as `Noise_IK_25519_ChaChaPoly_BLAKE2s`:
+//!
+//! - The client must know the static public key of the server in advance, such as from
+//!   configuration. The handshake fails if the server does not hold the matching private key.
+//! - The client sends its own static public key encrypted during the handshake, and the server
+//!   reads it with [`Connection::noise_remote_static`] to decide whether to trust the client.
+//! - The handshake completes in a single round trip, after which both directions are encrypted
+//!   with ChaCha20-Poly1305 and forward secrecy.
+//!
+//! # Framing
+//!
+//! The serialized datagrams are written as a plain byte stream inside the encrypted channel, which
+//! is sent as Noise transport messages of up to 64KB, each prefixed by its size as a big-endian
+//! `u16`. A message that fails to decrypt, such as one tampered with in transit, fails the read
+//! and ends the stream of the [`ConnectionReader`](crate::ConnectionReader).
+
+use crate::logging::*;
+use crate::Connection;
+use async_std::net::TcpStream;
+use async_std::pin::Pin;
+use async_std::task::{Context, Poll};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use snow::{Builder, HandshakeState, StatelessTransportState};
+use std::sync::Arc;
+
+pub use snow;
+pub use snow::Keypair;
+
+/// The Noise protocol name used for every connection.
+pub const NOISE_PATTERN: &str = "Noise_IK_25519_ChaChaPoly_BLAKE2s";
+
+/// The maximum size of a Noise message, including its authentication tag.
+const MAX_MESSAGE_BYTE_SIZE: usize = 65535;
+
+/// The size of the authentication tag appended to every encrypted Noise message.
+const TAG_BYTE_SIZE: usize = 16;
+
+/// The maximum number of plaintext bytes encrypted into a single Noise message.
+const MAX_PLAINTEXT_BYTE_SIZE: usize = MAX_MESSAGE_BYTE_SIZE - TAG_BYTE_SIZE;
+
+/// The size of the big-endian `u16` prefixing every Noise message on the wire.
+const LENGTH_PREFIX_BYTE_SIZE: usize = 2;
+
+/// Generates a new static keypair for use with [`Connection::noise_client`] or
+/// [`Connection::noise_server`].
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let keypair = connect::noise::generate_keypair()?;
+/// publish(&keypair.public);
+/// ```
+pub fn generate_keypair() -> anyhow::Result<Keypair> {
+    Ok(Builder::new(NOISE_PATTERN.parse()?).generate_keypair()?)
+}
+
+impl Connection {
+    /// Creates a [`Connection`] encrypted with the Noise protocol, by performing the handshake as
+    /// the client over an established [`TcpStream`].
+    ///
+    /// The handshake authenticates the server against `remote_public`, its static public key, and
+    /// sends the public key of `keypair` to the server. Please see the
+    /// [module documentation](crate::noise) for details of the handshake pattern.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let stream = TcpStream::connect("127.0.0.1:3456").await?;
+    /// let mut conn = Connection::noise_client(stream, &keypair, &server_public_key).await?;
+    /// ```
+    pub async fn noise_client(
+        mut stream: TcpStream,
+        keypair: &Keypair,
+        remote_public: &[u8],
+    ) -> anyhow::Result<Self> {
+        let mut handshake = Builder::new(NOISE_PATTERN.parse()?)
+            .local_private_key(&keypair.private)
+            .remote_public_key(remote_public)
+            .build_initiator()?;
+
+        write_handshake_message(&mut stream, &mut handshake).await?;
+        read_handshake_message(&mut stream, &mut handshake).await?;
+        info!(
+            "Completed Noise handshake with server {}",
+            stream.peer_addr()?
+        );
+
+        Self::from_noise(stream, handshake)
+    }
+
+    /// Creates a [`Connection`] encrypted with the Noise protocol, by performing the handshake as
+    /// the server over an accepted [`TcpStream`].
+    ///
+    /// Any client that knows the public key of `keypair` can complete the handshake, so check the
+    /// static public key of the client with [`Connection::noise_remote_static`] before trusting
+    /// it.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let (stream, _) = listener.accept().await?;
+    /// let mut conn = Connection::noise_server(stream, &keypair).await?;
+    ///
+    /// if !authorized_keys.contains(conn.noise_remote_static().unwrap()) {
+    ///     return Err(anyhow!("unknown client"));
+    /// }
+    /// ```
+    pub async fn noise_server(mut stream: TcpStream, keypair: &Keypair) -> anyhow::Result<Self> {
+        let mut handshake = Builder::new(NOISE_PATTERN.parse()?)
+            .local_private_key(&keypair.private)
+            .build_responder()?;
+
+        read_handshake_message(&mut stream, &mut handshake).await?;
+        write_handshake_message(&mut stream, &mut handshake).await?;
+        info!(
+            "Completed Noise handshake with client {}",
+            stream.peer_addr()?
+        );
+
+        Self::from_noise(stream, handshake)
+    }
+
+    /// Creates a [`Connection`] over a TCP stream whose Noise handshake has completed.
+    fn from_noise(stream: TcpStream, handshake: HandshakeState) -> anyhow::Result<Self> {
+        let local_addr = stream.local_addr()?;
+        let peer_addr = stream.peer_addr()?;
+
+        let state = Arc::new(handshake.into_stateless_transport_mode()?);
+        let remote_static = state.get_remote_static().map(|key| key.to_vec());
+
+        let read_stream = NoiseReadStream {
+            stream: stream.clone(),
+            state: state.clone(),
+            nonce: 0,
+            message: Vec::new(),
+            filled: 0,
+            reading_message: false,
+            plaintext: Vec::new(),
+            offset: 0,
+        };
+
+        let write_stream = NoiseWriteStream {
+            stream,
+            state,
+            nonce: 0,
+            plaintext: Vec::new(),
+            message: Vec::new(),
+            offset: 0,
+        };
+
+        let mut conn = Self::new(
+            local_addr,
+            peer_addr,
+            Box::pin(read_stream),
+            Box::pin(write_stream),
+        );
+        conn.noise_remote_static = remote_static;
+
+        Ok(conn)
+    }
+}
+
+async fn write_handshake_message(
+    stream: &mut TcpStream,
+    handshake: &mut HandshakeState,
+) -> anyhow::Result<()> {
+    let mut message = vec![0; MAX_MESSAGE_BYTE_SIZE];
+    let len = handshake.write_message(&[], &mut message)?;
+
+    stream.write_all(&(len as u16).to_be_bytes()).await?;
+    stream.write_all(&message[..len]).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+async fn read_handshake_message(
+    stream: &mut TcpStream,
+    handshake: &mut HandshakeState,
+) -> anyhow::Result<()> {
+    let mut prefix = [0; LENGTH_PREFIX_BYTE_SIZE];
+    stream.read_exact(&mut prefix).await?;
+
+    let mut message = vec![0; u16::from_be_bytes(prefix) as usize];
+    stream.read_exact(&mut message).await?;
+
+    let mut payload = vec![0; MAX_MESSAGE_BYTE_SIZE];
+    handshake.read_message(&message, &mut payload)?;
+
+    Ok(())
+}
+
+/// Decrypts the Noise messages received on a TCP stream into the byte stream of serialized
+/// datagrams, so that they can be read by a [`ConnectionReader`](crate::ConnectionReader).
+struct NoiseReadStream {
+    stream: TcpStream,
+    state: Arc<StatelessTransportState>,
+    nonce: u64,
+    message: Vec<u8>,
+    filled: usize,
+    reading_message: bool,
+    plaintext: Vec<u8>,
+    offset: usize,
+}
+
+impl NoiseReadStream {
+    /// Reads from the TCP stream until `message` is filled, returning `false` if the stream ended
+    /// before any byte of it was read.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<bool>> {
+        while self.filled < self.message.len() {
+            let filled = self.filled;
+            let n = futures::ready!(
+                Pin::new(&mut self.stream).poll_read(cx, &mut self.message[filled..])
+            )?;
+
+            if n == 0 {
+                if self.filled == 0 {
+                    return Poll::Ready(Ok(false));
+                }
+
+                return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+
+            self.filled += n;
+        }
+
+        Poll::Ready(Ok(true))
+    }
+}
+
+impl AsyncRead for NoiseReadStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        loop {
+            if self.offset < self.plaintext.len() {
+                let offset = self.offset;
+                let len = buf.len().min(self.plaintext.len() - offset);
+
+                buf[..len].copy_from_slice(&self.plaintext[offset..offset + len]);
+                self.offset += len;
+
+                return Poll::Ready(Ok(len));
+            }
+
+            if !self.reading_message {
+                if self.message.is_empty() {
+                    self.message.resize(LENGTH_PREFIX_BYTE_SIZE, 0);
+                    self.filled = 0;
+                }
+
+                if !futures::ready!(self.poll_fill(cx))? {
+                    return Poll::Ready(Ok(0));
+                }
+
+                let size = u16::from_be_bytes([self.message[0], self.message[1]]) as usize;
+                if size < TAG_BYTE_SIZE {
+                    return Poll::Ready(Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Noise message is too short",
+                    )));
+                }
+
+                self.message = vec![0; size];
+                self.filled = 0;
+                self.reading_message = true;
+            }
+
+            if !futures::ready!(self.poll_fill(cx))? {
+                return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+
+            self.reading_message = false;
+            let message = std::mem::take(&mut self.message);
+            let mut plaintext = vec![0; message.len()];
+            let len = self
+                .state
+                .read_message(self.nonce, &message, &mut plaintext)
+                .map_err(|err| {
+                    warn!("Could not decrypt Noise message: {}", err);
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+                })?;
+            plaintext.truncate(len);
+
+            self.nonce += 1;
+            self.plaintext = plaintext;
+            self.offset = 0;
+        }
+    }
+}
+
+/// Encrypts the byte stream written by a [`ConnectionWriter`](crate::ConnectionWriter) into Noise
+/// messages sent on a TCP stream, encrypting the buffered bytes whenever it is flushed.
+struct NoiseWriteStream {
+    stream: TcpStream,
+    state: Arc<StatelessTransportState>,
+    nonce: u64,
+    plaintext: Vec<u8>,
+    message: Vec<u8>,
+    offset: usize,
+}
+
+impl NoiseWriteStream {
+    /// Encrypts the next chunk of buffered bytes into a length-prefixed Noise message to be
+    /// written, returning `false` if there are no buffered bytes.
+    fn encrypt_next_message(&mut self) -> std::io::Result<bool> {
+        if self.plaintext.is_empty() {
+            return Ok(false);
+        }
+
+        let len = self.plaintext.len().min(MAX_PLAINTEXT_BYTE_SIZE);
+        let mut message = vec![0; LENGTH_PREFIX_BYTE_SIZE + len + TAG_BYTE_SIZE];
+        let size = self
+            .state
+            .write_message(
+                self.nonce,
+                &self.plaintext[..len],
+                &mut message[LENGTH_PREFIX_BYTE_SIZE..],
+            )
+            .map_err(std::io::Error::other)?;
+        message[..LENGTH_PREFIX_BYTE_SIZE].copy_from_slice(&(size as u16).to_be_bytes());
+        message.truncate(LENGTH_PREFIX_BYTE_SIZE + size);
+
+        self.nonce += 1;
+        self.plaintext.drain(..len);
+        self.message = message;
+        self.offset = 0;
+
+        Ok(true)
+    }
+}
+
+impl AsyncWrite for NoiseWriteStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.plaintext.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        loop {
+            while self.offset < self.message.len() {
+                let this = &mut *self;
+                let n = futures::ready!(
+                    Pin::new(&mut this.stream).poll_write(cx, &this.message[this.offset..])
+                )?;
+
+                if n == 0 {
+                    return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+                }
+
+                self.offset += n;
+            }
+
+            if !self.encrypt_next_message()? {
+                return Pin::new(&mut self.stream).poll_flush(cx);
+            }
+        }
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        futures::ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.stream).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate_keypair;
+    use crate::{ConnectDatagram, Connection};
+    use async_std::net::{TcpListener, TcpStream};
+    use futures::{SinkExt, StreamExt};
+
+    #[async_std::test]
+    async fn rejects_wrong_server_key() -> anyhow::Result<()> {
+        let server_keys = generate_keypair()?;
+        let client_keys = generate_keypair()?;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let server = async_std::task::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            Connection::noise_server(stream, &server_keys).await
+        });
+
+        let stream = TcpStream::connect(addr).await?;
+        let wrong_key = generate_keypair()?.public;
+        let res = Connection::noise_client(stream, &client_keys, &wrong_key).await;
+
+        assert!(res.is_err());
+        assert!(server.await.is_err());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn large_datagrams_span_messages() -> anyhow::Result<()> {
+        let server_keys = generate_keypair()?;
+        let server_public = server_keys.public.clone();
+        let client_keys = generate_keypair()?;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        async_std::task::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("could not accept");
+            let mut conn = Connection::noise_server(stream, &server_keys)
+                .await
+                .expect("could not complete handshake");
+
+            while let Some(msg) = conn.reader().next().await {
+                conn.writer().send(msg).await.expect("could not echo");
+            }
+        });
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut conn = Connection::noise_client(stream, &client_keys, &server_public).await?;
+        assert_eq!(Some(server_public.as_slice()), conn.noise_remote_static());
+
+        let data: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+        conn.writer()
+            .send(ConnectDatagram::with_tag(1, vec![1])?)
+            .await?;
+        conn.writer()
+            .send(ConnectDatagram::with_tag(2, data.clone())?)
+            .await?;
+
+        let reply = conn.reader().next().await.expect("connection closed");
+        assert_eq!(1, reply.tag());
+        let reply = conn.reader().next().await.expect("connection closed");
+        assert_eq!(2, reply.tag());
+        assert_eq!(data.as_slice(), reply.data());
+
+        Ok(())
+    }
+}
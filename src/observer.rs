@@ -0,0 +1,49 @@
+use crate::sequence::SequenceGap;
+use crate::writer::ConnectionWriteError;
+
+/// Callbacks invoked as a connection reads and writes datagrams, to feed metrics backends such as
+/// Prometheus or statsd without the crate depending on any of them.
+///
+/// Every method has a default no-op implementation, so only the events of interest need to be
+/// implemented. The callbacks are invoked from within the poll loops of the
+/// [`ConnectionReader`](`crate::ConnectionReader`) and
+/// [`ConnectionWriter`](`crate::ConnectionWriter`), so they should only update counters or
+/// otherwise return quickly. Heartbeat datagrams are reported like any other datagram.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// struct Metrics {
+///     messages_read: AtomicU64,
+/// }
+///
+/// impl ConnectionObserver for Metrics {
+///     fn on_message_read(&self, _tag: u16, _bytes: usize) {
+///         self.messages_read.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let conn = conn.with_observer(metrics.clone());
+/// ```
+pub trait ConnectionObserver: Send + Sync {
+    /// Called when a datagram with the given tag has been read and deserialized, where `bytes` is
+    /// its serialized size including the datagram header.
+    fn on_message_read(&self, _tag: u16, _bytes: usize) {}
+
+    /// Called when a datagram with the given tag has been completely written to the network
+    /// stream, where `bytes` is its serialized size including the datagram header.
+    fn on_message_written(&self, _tag: u16, _bytes: usize) {}
+
+    /// Called when reading from the network stream fails, or when a received datagram cannot be
+    /// deserialized and is skipped.
+    fn on_read_error(&self, _err: &(dyn std::error::Error + 'static)) {}
+
+    /// Called when writing to or flushing the network stream fails.
+    fn on_write_error(&self, _err: &ConnectionWriteError) {}
+
+    /// Called when the sequence number of a received datagram is not the expected one, see
+    /// [`ConnectionReader::set_expected_sequence`](`crate::ConnectionReader::set_expected_sequence`).
+    fn on_sequence_gap(&self, _gap: SequenceGap) {}
+}
@@ -1,15 +1,115 @@
+//! The wire format of a [`ConnectDatagram`] and the constants needed to frame and deframe it.
+//!
+//! <br/>
+//!
+//! Every serialized datagram starts with a fixed-size header, in network byte order:
+//!
+//! | Offset             | Size                      | Field                                 |
+//! |--------------------|---------------------------|---------------------------------------|
+//! | `0`                | [`SIZE_PREFIX_BYTE_SIZE`] | byte-size of the rest of the datagram |
+//! | [`VERSION_OFFSET`] | [`VERSION_BYTE_SIZE`]     | protocol [`VERSION`] and header flags |
+//! | [`TAG_OFFSET`]     | [`TAG_BYTE_SIZE`]         | recipient tag                         |
+//!
+//! The message body follows the header, preceded by a checksum and extension fields when the
+//! corresponding header flags are set. A transport that frames datagrams itself only needs to read
+//! the size-prefix to know how many more bytes belong to the datagram, and must reject
+//! size-prefixes that would exceed [`MAX_SERIALIZED_BYTE_SIZE`].
+//!
+//! Every integer field is big-endian. Only for peers that cannot be changed, the three header
+//! fields above can instead be framed in little-endian by setting the [`ByteOrder`] of both the
+//! reader and the writer of a connection, while any checksum and extension fields stay big-endian.
+//!
+//! # Reserved extension keys
+//!
+//! Extension keys from `0xff00` up are reserved for the extension fields that this crate attaches
+//! to datagrams itself, so that applications can use any key below `0xff00` without clashing with
+//! them:
+//!
+//! | Key      | Constant                                                   | Carries                          |
+//! |----------|------------------------------------------------------------|----------------------------------|
+//! | `0xfffd` | [`SEQUENCE_EXTENSION_KEY`](`crate::SEQUENCE_EXTENSION_KEY`) | sequence number of the datagram  |
+//! | `0xfffe` | [`REQUEST_ID_EXTENSION`](`crate::rpc::REQUEST_ID_EXTENSION`) | correlation id of an RPC request |
+//! | `0xffff` | [`REPLY_ID_EXTENSION`](`crate::rpc::REPLY_ID_EXTENSION`)     | correlation id of an RPC reply   |
+
+use crate::sequence::SEQUENCE_EXTENSION_KEY;
+use bytes::Bytes;
 use std::array::TryFromSliceError;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Current version of the datagram protocol, written to the version field of every datagram.
+pub const VERSION: u16 = 1;
+
+/// Bits of the version field that are reserved for header flags rather than the version number.
+pub const FLAGS_MASK: u16 = 0xf000;
+
+/// Header flag set when the message body has been compressed.
+const COMPRESSED_FLAG: u16 = 0x8000;
+
+/// Header flag set when a checksum of the message body follows the tag field.
+const CHECKSUM_FLAG: u16 = 0x4000;
+
+/// Header flag set when a block of extension fields follows the tag field and any checksum.
+const EXTENSIONS_FLAG: u16 = 0x2000;
+
+/// Header flag set when the datagram is a chunk of a stream sent with
+/// [`ConnectionWriter::send_stream`](`crate::ConnectionWriter::send_stream`).
+const STREAM_CHUNK_FLAG: u16 = 0x1000;
+
+const CHECKSUM_BYTE_SIZE: usize = 4;
 
-const VERSION: u16 = 1;
+/// Size of the length field that starts the extensions block.
+const EXTENSIONS_LENGTH_BYTE_SIZE: usize = 2;
 
+/// Size of the key and value length fields that start each extension field.
+const EXTENSION_HEADER_BYTE_SIZE: usize = 4;
+
+/// Maximum size of the extension fields of a datagram, excluding the extensions block length.
+const MAX_EXTENSIONS_BYTE_SIZE: usize = u16::MAX as usize;
+
+/// Maximum byte-size of the message body of a datagram, 100MB.
+pub const MAX_DATA_BYTE_SIZE: usize = 100_000_000;
+
+/// Maximum size of a serialized datagram, including the size-prefix, leaving room for the overhead
+/// of compressing an incompressible message body.
+pub const MAX_SERIALIZED_BYTE_SIZE: usize = DATAGRAM_HEADER_BYTE_SIZE
+    + CHECKSUM_BYTE_SIZE
+    + EXTENSIONS_LENGTH_BYTE_SIZE
+    + MAX_EXTENSIONS_BYTE_SIZE
+    + MAX_DATA_BYTE_SIZE
+    + MAX_DATA_BYTE_SIZE / 64;
+
+/// Maximum size of a message body whose datagram, with every optional field at its largest, still
+/// has a serialized size that fits in the size-prefix.
+const MAX_PREFIXED_DATA_BYTE_SIZE: usize =
+    u32::MAX as usize - (MAX_SERIALIZED_BYTE_SIZE - SIZE_PREFIX_BYTE_SIZE - MAX_DATA_BYTE_SIZE);
+
+/// Number of message body bytes shown when formatting a [`ConnectDatagram`] with `Debug`.
+const DEBUG_DATA_BYTE_SIZE: usize = 16;
+
+/// Size of the size-prefix field that starts every serialized datagram.
 pub const SIZE_PREFIX_BYTE_SIZE: usize = 4;
-const VERSION_BYTE_SIZE: usize = 2;
-const TAG_BYTE_SIZE: usize = 2;
 
-pub const DATAGRAM_HEADER_BYTE_SIZE: usize =
-    SIZE_PREFIX_BYTE_SIZE + VERSION_BYTE_SIZE + TAG_BYTE_SIZE;
+/// Size of the version field, which also carries the header flags.
+pub const VERSION_BYTE_SIZE: usize = 2;
+
+/// Size of the tag field.
+pub const TAG_BYTE_SIZE: usize = 2;
+
+/// Offset of the version field in a serialized datagram.
+pub const VERSION_OFFSET: usize = SIZE_PREFIX_BYTE_SIZE;
+
+/// Offset of the tag field in a serialized datagram.
+pub const TAG_OFFSET: usize = VERSION_OFFSET + VERSION_BYTE_SIZE;
+
+/// Size of the fixed datagram header, including the size-prefix, which is also the offset of the
+/// message body of a datagram without a checksum or extension fields.
+pub const DATAGRAM_HEADER_BYTE_SIZE: usize = TAG_OFFSET + TAG_BYTE_SIZE;
+
+/// Number of leading bytes of a serialized datagram inspected by [`is_plausible_header`].
+pub(crate) const HEADER_PROBE_BYTE_SIZE: usize = TAG_OFFSET;
 
 /// Encountered when there is an issue constructing, serializing, or deserializing a [`ConnectDatagram`].
 ///
@@ -21,12 +121,43 @@ pub enum DatagramError {
     /// Tried to construct a [`ConnectDatagram`] with a message body larger than 100MB.
     TooLargeMessage,
 
+    /// Tried to add extension fields larger than 64KB in total to a [`ConnectDatagram`].
+    TooLargeExtensions,
+
+    /// Tried to construct a [`ConnectDatagram`] with a version number that uses the bits of the
+    /// version field reserved for header flags.
+    InvalidVersion,
+
     /// Did not provide the complete byte-string necessary to deserialize the [`ConnectDatagram`].
     InsufficientBytes,
 
+    /// The size-prefix of the serialized [`ConnectDatagram`] does not match the number of bytes
+    /// provided to deserialize it.
+    SizeMismatch,
+
+    /// The serialized size of the [`ConnectDatagram`] would not fit in its 4-byte size-prefix,
+    /// independently of the limit on the size of the message body.
+    SizePrefixOverflow,
+
     /// Wraps a [`TryFromSliceError`] encountered when the version or tag fields cannot be
     /// parsed from the provided bytes.
     BytesParseFail(TryFromSliceError),
+
+    /// Could not compress the message body of the [`ConnectDatagram`].
+    #[cfg(feature = "compression")]
+    CompressionFail,
+
+    /// Could not decompress the message body of the [`ConnectDatagram`].
+    #[cfg(feature = "compression")]
+    DecompressionFail,
+
+    /// The CRC32C checksum in the header does not match the received message body.
+    #[cfg(feature = "checksum")]
+    ChecksumMismatch,
+
+    /// The header of the received [`ConnectDatagram`] was framed in the other [`ByteOrder`] than
+    /// the one the reader was configured with.
+    ByteOrderMismatch,
 }
 
 impl Error for DatagramError {}
@@ -36,20 +167,166 @@ impl std::fmt::Display for DatagramError {
         match self {
             DatagramError::EmptyMessage => formatter.write_str("tried to construct a `ConnectDatagram` with an empty message body"),
             DatagramError::TooLargeMessage => formatter.write_str("tried to construct a `ConnectDatagram` with a message body larger than 100MB"),
+            DatagramError::TooLargeExtensions => formatter.write_str("tried to add extension fields larger than 64KB to a `ConnectDatagram`"),
+            DatagramError::InvalidVersion => formatter.write_str("tried to construct a `ConnectDatagram` with a version number that overlaps the header flags"),
             DatagramError::InsufficientBytes => formatter.write_str("did not provide the complete byte-string necessary to deserialize the `ConnectDatagram`"),
+            DatagramError::SizeMismatch => formatter.write_str("the size-prefix of the `ConnectDatagram` does not match the number of bytes provided"),
+            DatagramError::SizePrefixOverflow => formatter.write_str("the serialized size of the `ConnectDatagram` does not fit in its size-prefix"),
             DatagramError::BytesParseFail(err) => std::fmt::Display::fmt(err, formatter),
+            #[cfg(feature = "compression")]
+            DatagramError::CompressionFail => formatter.write_str("could not compress the message body of the `ConnectDatagram`"),
+            #[cfg(feature = "compression")]
+            DatagramError::DecompressionFail => formatter.write_str("could not decompress the message body of the `ConnectDatagram`"),
+            #[cfg(feature = "checksum")]
+            DatagramError::ChecksumMismatch => formatter.write_str("the checksum of the `ConnectDatagram` does not match its message body"),
+            DatagramError::ByteOrderMismatch => formatter.write_str("the header of the `ConnectDatagram` was framed in the other byte order"),
+        }
+    }
+}
+
+/// Compression algorithms that can be applied to the message body of a [`ConnectDatagram`].
+///
+#[cfg(feature = "compression")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Compresses the message body with [zstd](https://facebook.github.io/zstd/).
+    Zstd,
+}
+
+#[cfg(feature = "compression")]
+fn compress(data: &[u8]) -> Result<Vec<u8>, DatagramError> {
+    zstd::bulk::compress(data, 0).map_err(|_| DatagramError::CompressionFail)
+}
+
+#[cfg(feature = "compression")]
+fn decompress(data: &[u8]) -> Result<Vec<u8>, DatagramError> {
+    use std::io::Read;
+
+    let decoder =
+        zstd::stream::Decoder::with_buffer(data).map_err(|_| DatagramError::DecompressionFail)?;
+
+    // bound the output so that a malicious payload cannot expand past the message size limit
+    let mut decompressed = Vec::new();
+    decoder
+        .take(MAX_DATA_BYTE_SIZE as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|_| DatagramError::DecompressionFail)?;
+
+    if decompressed.len() > MAX_DATA_BYTE_SIZE {
+        Err(DatagramError::TooLargeMessage)
+    } else {
+        Ok(decompressed)
+    }
+}
+
+/// Byte order of the size-prefix, version and tag fields that start every serialized datagram.
+///
+/// The protocol is big-endian, which is the default. Little-endian framing only exists to
+/// interoperate with peers that cannot be changed, and must be set on both the
+/// [`ConnectionReader`](`crate::ConnectionReader`) and the
+/// [`ConnectionWriter`](`crate::ConnectionWriter`) of the connection to such a peer. Checksum and
+/// extension fields are big-endian regardless.
+///
+/// Transports that frame datagrams themselves, such as UDP and WebSocket, read the size-prefix in
+/// the byte order of the reader or writer of their connection.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    /// Most significant byte first, the byte order of the protocol.
+    #[default]
+    BigEndian,
+
+    /// Least significant byte first.
+    LittleEndian,
+}
+
+impl ByteOrder {
+    /// Converts the header fields at the start of a serialized datagram between big-endian and
+    /// this byte order in place, skipping any field that `buffer` is too short to hold.
+    ///
+    /// Since this only reverses the bytes of each field, the same call converts in either
+    /// direction.
+    pub(crate) fn convert_header(self, buffer: &mut [u8]) {
+        if self == ByteOrder::BigEndian {
+            return;
+        }
+
+        let fields = [
+            0..SIZE_PREFIX_BYTE_SIZE,
+            VERSION_OFFSET..TAG_OFFSET,
+            TAG_OFFSET..DATAGRAM_HEADER_BYTE_SIZE,
+        ];
+
+        for field in fields {
+            if let Some(bytes) = buffer.get_mut(field) {
+                bytes.reverse();
+            }
+        }
+    }
+
+    /// Converts a header field parsed as big-endian from bytes in this byte order.
+    pub(crate) fn convert_field(self, field: u16) -> u16 {
+        match self {
+            ByteOrder::BigEndian => field,
+            ByteOrder::LittleEndian => field.swap_bytes(),
+        }
+    }
+
+    /// Reads the size-prefix at the start of a serialized datagram framed in this byte order, or
+    /// `None` if `buffer` is too short to hold it.
+    pub(crate) fn read_size_prefix(self, buffer: &[u8]) -> Option<u32> {
+        let bytes = buffer.get(..SIZE_PREFIX_BYTE_SIZE)?.try_into().ok()?;
+
+        Some(match self {
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+        })
+    }
+
+    /// Frames a size-prefix in this byte order.
+    #[cfg(feature = "websocket")]
+    pub(crate) fn size_prefix(self, size: u32) -> [u8; SIZE_PREFIX_BYTE_SIZE] {
+        match self {
+            ByteOrder::BigEndian => size.to_be_bytes(),
+            ByteOrder::LittleEndian => size.to_le_bytes(),
         }
     }
 }
 
+/// A [`ByteOrder`] shared between the reader or writer of a connection and a transport that frames
+/// datagrams itself, so that the transport follows the byte order set on the connection.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SharedByteOrder(Arc<AtomicBool>);
+
+impl SharedByteOrder {
+    pub(crate) fn get(&self) -> ByteOrder {
+        if self.0.load(Ordering::Relaxed) {
+            ByteOrder::LittleEndian
+        } else {
+            ByteOrder::BigEndian
+        }
+    }
+
+    pub(crate) fn set(&self, byte_order: ByteOrder) {
+        self.0
+            .store(byte_order == ByteOrder::LittleEndian, Ordering::Relaxed);
+    }
+}
+
 /// A simple size-prefixed packet format containing a version id, optional tag, and message payload.
 ///
 /// The version tag is decided by the library version and used to maintain backwards
-/// compatibility with previous datagram formats.
+/// compatibility with previous datagram formats. The upper bits of the version field are reserved
+/// for header flags, such as whether the message body is compressed or followed by a checksum.
+///
+/// Datagrams may also carry key-value extension fields, such as trace IDs or content types, in a
+/// block between the header and the message body. See
+/// [`set_extension`](ConnectDatagram::set_extension).
 ///
 #[derive(Clone)]
 pub struct ConnectDatagram {
     buffer: Vec<u8>,
+    payload: Option<Vec<u8>>,
 }
 
 #[allow(dead_code)]
@@ -74,218 +351,1378 @@ impl ConnectDatagram {
     /// This will return a [TooLargeMessage](`DatagramError::TooLargeMessage`) error if the `data`
     /// parameter contains a buffer size greater than 100,000,000 (bytes), or 100MB.
     ///
+    /// Use [`control`](ConnectDatagram::control) for a datagram that only carries a tag.
+    ///
     pub fn with_tag(tag: u16, data: Vec<u8>) -> Result<Self, DatagramError> {
-        if data.len() > 100_000_000 {
-            Err(DatagramError::TooLargeMessage)
-        } else if data.len() > 0 {
-            let mut buffer: Vec<u8> = Vec::with_capacity(DATAGRAM_HEADER_BYTE_SIZE + data.len());
+        Self::check_data_size(data.len())?;
 
-            buffer.extend(
-                ((DATAGRAM_HEADER_BYTE_SIZE - SIZE_PREFIX_BYTE_SIZE + data.len()) as u32)
-                    .to_be_bytes(),
-            );
-            buffer.extend(VERSION.to_be_bytes());
-            buffer.extend(tag.to_be_bytes());
-            buffer.extend(data);
+        Ok(Self {
+            buffer: Self::encode(VERSION, tag, &data),
+            payload: None,
+        })
+    }
 
-            Ok(Self { buffer })
-        } else {
-            Err(DatagramError::EmptyMessage)
+    /// Creates a new [`ConnectDatagram`] with an explicit version number instead of the current
+    /// protocol [`VERSION`], such as to prototype an extended datagram format.
+    ///
+    /// The datagram is otherwise framed like one created with [`with_tag`], so peers can read it
+    /// and dispatch on its [`version`] with
+    /// [`ConnectionReader::set_version_handler`](`crate::ConnectionReader::set_version_handler`).
+    ///
+    /// This will return an [InvalidVersion](`DatagramError::InvalidVersion`) error if `version`
+    /// uses the bits in [`FLAGS_MASK`], and otherwise the same errors as [`with_tag`].
+    ///
+    /// [`with_tag`]: ConnectDatagram::with_tag
+    /// [`version`]: ConnectDatagram::version
+    pub fn with_version(version: u16, tag: u16, data: Vec<u8>) -> Result<Self, DatagramError> {
+        if version & FLAGS_MASK != 0 {
+            return Err(DatagramError::InvalidVersion);
         }
+
+        Self::check_data_size(data.len())?;
+
+        Ok(Self {
+            buffer: Self::encode(version, tag, &data),
+            payload: None,
+        })
     }
 
-    /// Updates the size prefix value in the internal buffer to the current size of the buffer.
+    /// Creates a new [`ConnectDatagram`] with a tag field and no message body, for control or
+    /// signaling messages where the tag alone carries the meaning.
     ///
-    #[inline]
-    fn update_size_prefix(&mut self) {
-        self.buffer.splice(
-            ..VERSION_BYTE_SIZE,
-            ((DATAGRAM_HEADER_BYTE_SIZE - SIZE_PREFIX_BYTE_SIZE + self.data_size()) as u32)
-                .to_be_bytes(),
-        );
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// writer.send(ConnectDatagram::control(ACK_TAG)).await?;
+    /// ```
+    pub fn control(tag: u16) -> Self {
+        Self {
+            buffer: Self::encode(VERSION, tag, &[]),
+            payload: None,
+        }
     }
 
-    /// Gets the version number field of the datagram protocol.
+    /// Creates an empty buffer with room reserved for the datagram header, to which a message body
+    /// of up to `capacity` bytes can be appended without reallocating.
     ///
-    pub fn version(&self) -> u16 {
-        let start = SIZE_PREFIX_BYTE_SIZE;
-        let end = start + VERSION_BYTE_SIZE;
-
-        let buf = self.buffer[start..end]
-            .as_ref()
-            .try_into()
-            .expect("could not parse big-endian bytes into version variable");
+    /// Pass the filled buffer to [`from_reserved`](ConnectDatagram::from_reserved) to construct the
+    /// datagram without copying the message body.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut buffer = ConnectDatagram::reserved_buffer(data.len());
+    /// buffer.extend_from_slice(&data);
+    ///
+    /// let msg = ConnectDatagram::from_reserved(tag, buffer)?;
+    /// ```
+    pub fn reserved_buffer(capacity: usize) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(DATAGRAM_HEADER_BYTE_SIZE + capacity);
+        buffer.resize(DATAGRAM_HEADER_BYTE_SIZE, 0);
 
-        u16::from_be_bytes(buf)
+        buffer
     }
 
-    /// Gets the tag field of the datagram.
+    /// Creates a new [`ConnectDatagram`] from a tag field and a buffer whose first
+    /// [`DATAGRAM_HEADER_BYTE_SIZE`] bytes are reserved for the header and are followed by the
+    /// message body.
     ///
-    pub fn tag(&self) -> u16 {
-        let start = SIZE_PREFIX_BYTE_SIZE + VERSION_BYTE_SIZE;
-        let end = start + TAG_BYTE_SIZE;
+    /// The header is written in place over the reserved bytes, so unlike [`with_tag`] the message
+    /// body is not copied. See [`reserved_buffer`] for creating such a buffer.
+    ///
+    /// This will return an [InsufficientBytes](`DatagramError::InsufficientBytes`) error if the
+    /// `buffer` is shorter than the reserved header, and otherwise the same errors as
+    /// [`with_tag`].
+    ///
+    /// [`with_tag`]: ConnectDatagram::with_tag
+    /// [`reserved_buffer`]: ConnectDatagram::reserved_buffer
+    pub fn from_reserved(tag: u16, buffer: Vec<u8>) -> Result<Self, DatagramError> {
+        if buffer.len() < DATAGRAM_HEADER_BYTE_SIZE {
+            return Err(DatagramError::InsufficientBytes);
+        }
 
-        let buf = self.buffer[start..end]
-            .as_ref()
-            .try_into()
-            .expect("could not parse big-endian bytes into tag variable");
+        Self::check_data_size(buffer.len() - DATAGRAM_HEADER_BYTE_SIZE)?;
 
-        u16::from_be_bytes(buf)
+        Ok(Self::encode_reserved(VERSION, tag, buffer))
     }
 
-    /// Sets the message body of the datagram.
+    /// Creates a chunk of a stream from a tag field and a buffer with a reserved header, where an
+    /// empty chunk marks the end of the stream.
     ///
-    pub fn set_tag(&mut self, tag: u16) {
-        let start = SIZE_PREFIX_BYTE_SIZE + VERSION_BYTE_SIZE;
-        let end = start + TAG_BYTE_SIZE;
+    pub(crate) fn stream_chunk(tag: u16, buffer: Vec<u8>) -> Result<Self, DatagramError> {
+        if buffer.len() < DATAGRAM_HEADER_BYTE_SIZE {
+            return Err(DatagramError::InsufficientBytes);
+        }
 
-        self.buffer.splice(start..end, tag.to_be_bytes());
+        Self::check_max_data_size(buffer.len() - DATAGRAM_HEADER_BYTE_SIZE)?;
+
+        Ok(Self::encode_reserved(
+            VERSION | STREAM_CHUNK_FLAG,
+            tag,
+            buffer,
+        ))
     }
 
-    /// Gets the message body of the datagram.
+    /// Writes the header fields over the reserved bytes at the start of a buffer that already
+    /// holds the message body.
     ///
-    pub fn data(&self) -> &[u8] {
-        &self.buffer[DATAGRAM_HEADER_BYTE_SIZE..]
+    fn encode_reserved(version: u16, tag: u16, mut buffer: Vec<u8>) -> Self {
+        let size = Self::size_prefix_bytes(buffer.len() - SIZE_PREFIX_BYTE_SIZE)
+            .expect("message body size is checked to fit in the size-prefix");
+        buffer[..SIZE_PREFIX_BYTE_SIZE].copy_from_slice(&size);
+        buffer[VERSION_OFFSET..TAG_OFFSET].copy_from_slice(&version.to_be_bytes());
+        buffer[TAG_OFFSET..DATAGRAM_HEADER_BYTE_SIZE].copy_from_slice(&tag.to_be_bytes());
+
+        Self {
+            buffer,
+            payload: None,
+        }
     }
 
-    /// Sets the message body of the datagram and returns the previous contents.
+    /// Creates a new [`ConnectDatagram`] based on an intended tag field and message body, where the
+    /// message body is compressed before being sent over the network.
     ///
-    pub fn set_data(&mut self, data: Vec<u8>) -> Result<Vec<u8>, DatagramError> {
-        let data_size = data.len();
-
-        if data_size > 100_000_000 {
-            Err(DatagramError::TooLargeMessage)
-        } else if data_size > 0 {
-            if data_size < self.buffer.len() {
-                self.buffer.truncate(DATAGRAM_HEADER_BYTE_SIZE + data_size);
-            }
+    /// The size-prefix reflects the compressed length of the message body, while [`data`] returns
+    /// the original uncompressed bytes. Readers transparently decompress the message body when the
+    /// `compression` feature is enabled.
+    ///
+    /// This will return the same errors as [`with_tag`], as well as a
+    /// [CompressionFail](`DatagramError::CompressionFail`) error if the message body cannot be
+    /// compressed.
+    ///
+    /// [`data`]: ConnectDatagram::data
+    /// [`with_tag`]: ConnectDatagram::with_tag
+    #[cfg(feature = "compression")]
+    pub fn with_compression(
+        tag: u16,
+        data: Vec<u8>,
+        compression: Compression,
+    ) -> Result<Self, DatagramError> {
+        Self::check_data_size(data.len())?;
 
-            let old_data = self
-                .buffer
-                .splice(DATAGRAM_HEADER_BYTE_SIZE.., data)
-                .collect();
+        let compressed = match compression {
+            Compression::Zstd => compress(&data)?,
+        };
 
-            self.update_size_prefix();
+        Ok(Self {
+            buffer: Self::encode(VERSION | COMPRESSED_FLAG, tag, &compressed),
+            payload: Some(data),
+        })
+    }
 
-            Ok(old_data)
-        } else {
+    #[inline]
+    fn check_data_size(data_size: usize) -> Result<(), DatagramError> {
+        if data_size == 0 {
             Err(DatagramError::EmptyMessage)
+        } else {
+            Self::check_max_data_size(data_size)
         }
     }
 
-    /// Calculates the size-prefixed serialized byte-size of the datagram.
-    ///
-    /// This will include the byte-size of the size-prefix.
-    ///
-    pub fn serialized_size(&self) -> usize {
-        self.buffer.len()
+    #[inline]
+    fn check_max_data_size(data_size: usize) -> Result<(), DatagramError> {
+        if data_size > MAX_DATA_BYTE_SIZE {
+            Err(DatagramError::TooLargeMessage)
+        } else if data_size > MAX_PREFIXED_DATA_BYTE_SIZE {
+            Err(DatagramError::SizePrefixOverflow)
+        } else {
+            Ok(())
+        }
     }
 
-    /// Calculates the byte-size of the datagram message body.
-    ///
-    /// This will exclude all datagram header fields like the tag.
+    /// Encodes the size-prefix of a datagram that is `size` bytes long after the size-prefix,
+    /// failing instead of wrapping around if `size` does not fit in the size-prefix.
     ///
-    pub fn data_size(&self) -> usize {
-        self.buffer.len() - DATAGRAM_HEADER_BYTE_SIZE
+    #[inline]
+    fn size_prefix_bytes(size: usize) -> Result<[u8; SIZE_PREFIX_BYTE_SIZE], DatagramError> {
+        u32::try_from(size)
+            .map(u32::to_be_bytes)
+            .map_err(|_| DatagramError::SizePrefixOverflow)
     }
 
-    /// Constructs a serialized representation of the datagram contents.
+    /// Serializes the header fields and message body into a size-prefixed buffer.
     ///
-    pub(crate) fn as_bytes(&self) -> &[u8] {
-        self.buffer.as_slice()
+    fn encode(version: u16, tag: u16, body: &[u8]) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::with_capacity(DATAGRAM_HEADER_BYTE_SIZE + body.len());
+
+        buffer.extend(
+            Self::size_prefix_bytes(DATAGRAM_HEADER_BYTE_SIZE - SIZE_PREFIX_BYTE_SIZE + body.len())
+                .expect("message body size is checked to fit in the size-prefix"),
+        );
+        buffer.extend(version.to_be_bytes());
+        buffer.extend(tag.to_be_bytes());
+        buffer.extend_from_slice(body);
+
+        buffer
     }
 
-    /// Serializes the datagram.
+    /// Constructs the datagram from a complete size-prefixed buffer, validating the checksum and
+    /// decompressing the message body if necessary.
     ///
-    pub fn into_bytes(self) -> Vec<u8> {
-        self.buffer
+    pub(crate) fn from_buffer(buffer: Vec<u8>) -> Result<Self, DatagramError> {
+        #[allow(unused_mut)]
+        let mut datagram = Self {
+            buffer,
+            payload: None,
+        };
+
+        if datagram.buffer.len() < datagram.extensions_offset() {
+            return Err(DatagramError::InsufficientBytes);
+        }
+
+        if datagram.size_prefix() != datagram.buffer.len() - SIZE_PREFIX_BYTE_SIZE {
+            return Err(DatagramError::SizeMismatch);
+        }
+
+        if datagram.has_extensions() && !datagram.has_valid_extensions() {
+            return Err(DatagramError::InsufficientBytes);
+        }
+
+        if datagram.buffer.len() < datagram.data_offset() {
+            return Err(DatagramError::InsufficientBytes);
+        }
+
+        #[cfg(feature = "checksum")]
+        if datagram.has_checksum() && datagram.checksum() != datagram.compute_checksum() {
+            return Err(DatagramError::ChecksumMismatch);
+        }
+
+        #[cfg(feature = "compression")]
+        if datagram.is_compressed() {
+            datagram.payload = Some(decompress(&datagram.buffer[datagram.data_offset()..])?);
+        }
+
+        Ok(datagram)
     }
 
-    /// Deserializes the datagram from bytes.
+    /// Gets the offset in the internal buffer at which the extensions block, if any, starts.
     ///
-    pub fn from_bytes(buffer: &[u8]) -> Result<Self, DatagramError> {
-        if buffer.len() > DATAGRAM_HEADER_BYTE_SIZE {
-            Ok(Self {
-                buffer: buffer.to_vec(),
-            })
+    #[inline]
+    fn extensions_offset(&self) -> usize {
+        if self.has_checksum() {
+            DATAGRAM_HEADER_BYTE_SIZE + CHECKSUM_BYTE_SIZE
         } else {
-            Err(DatagramError::InsufficientBytes)
+            DATAGRAM_HEADER_BYTE_SIZE
         }
     }
 
-    /// Deserializes the datagram from bytes, and infers the size-prefix given the data.
+    /// Gets the offset in the internal buffer at which the message body starts.
     ///
-    pub fn from_bytes_without_prefix(buffer: &[u8]) -> Result<Self, DatagramError> {
-        if buffer.len() > DATAGRAM_HEADER_BYTE_SIZE - SIZE_PREFIX_BYTE_SIZE {
-            let mut new_buffer = Vec::with_capacity(SIZE_PREFIX_BYTE_SIZE + buffer.len());
-            new_buffer.extend((buffer.len() as u32).to_be_bytes());
-            new_buffer.extend_from_slice(buffer);
+    #[inline]
+    fn data_offset(&self) -> usize {
+        if self.has_extensions() {
+            let start = self.extensions_offset();
+            let end = start + EXTENSIONS_LENGTH_BYTE_SIZE;
+
+            let buf = self.buffer[start..end]
+                .as_ref()
+                .try_into()
+                .expect("could not parse big-endian bytes into extensions length variable");
 
-            Ok(Self { buffer: new_buffer })
+            end + u16::from_be_bytes(buf) as usize
         } else {
-            Err(DatagramError::InsufficientBytes)
+            self.extensions_offset()
         }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::{protocol::ConnectDatagram, DATAGRAM_HEADER_BYTE_SIZE};
-
-    #[test]
-    fn serialized_size() -> anyhow::Result<()> {
-        let data: Vec<u8> = vec![0, 1, 2, 3, 4];
-        assert_eq!(5, data.len());
 
-        let sample = ConnectDatagram::with_tag(1, data)?;
-        assert_eq!(DATAGRAM_HEADER_BYTE_SIZE + 5, sample.serialized_size());
-        assert_eq!(DATAGRAM_HEADER_BYTE_SIZE + 5, sample.into_bytes().len());
+    /// Gets the size prefix value in the internal buffer.
+    ///
+    #[inline]
+    fn size_prefix(&self) -> usize {
+        let buf = self.buffer[..SIZE_PREFIX_BYTE_SIZE]
+            .as_ref()
+            .try_into()
+            .expect("could not parse big-endian bytes into size prefix variable");
 
-        Ok(())
+        u32::from_be_bytes(buf) as usize
     }
 
-    #[test]
-    fn get_data() -> anyhow::Result<()> {
-        let data: Vec<u8> = vec![0, 1, 2, 3, 4];
-        assert_eq!(5, data.len());
+    /// Updates the size prefix value in the internal buffer to the current size of the buffer.
+    ///
+    #[inline]
+    fn update_size_prefix(&mut self) {
+        let size = Self::size_prefix_bytes(self.buffer.len() - SIZE_PREFIX_BYTE_SIZE)
+            .expect("message body and extensions sizes are checked to fit in the size-prefix");
+        self.buffer[..SIZE_PREFIX_BYTE_SIZE].copy_from_slice(&size);
+    }
 
-        let sample = ConnectDatagram::with_tag(1, data)?;
+    /// Gets the raw version field, including any header flags.
+    ///
+    fn version_field(&self) -> u16 {
+        let start = VERSION_OFFSET;
+        let end = start + VERSION_BYTE_SIZE;
 
-        let taken_data = sample.data();
-        assert_eq!(5, taken_data.len());
+        let buf = self.buffer[start..end]
+            .as_ref()
+            .try_into()
+            .expect("could not parse big-endian bytes into version variable");
 
-        Ok(())
+        u16::from_be_bytes(buf)
     }
 
-    #[test]
-    fn encode_and_decode() -> anyhow::Result<()> {
-        let data: Vec<u8> = vec![0, 1, 2, 3, 4];
-        assert_eq!(5, data.len());
-
-        let sample = ConnectDatagram::with_tag(1, data)?;
-        let serialized_size = sample.serialized_size();
-        assert_eq!(DATAGRAM_HEADER_BYTE_SIZE + 5, serialized_size);
+    fn set_version_field(&mut self, version: u16) {
+        let start = VERSION_OFFSET;
+        let end = start + VERSION_BYTE_SIZE;
 
-        let payload = sample.into_bytes();
-        assert_eq!(serialized_size, payload.len());
+        self.buffer[start..end].copy_from_slice(&version.to_be_bytes());
+    }
 
-        let sample_back_res = ConnectDatagram::from_bytes(payload.as_slice());
-        assert!(sample_back_res.is_ok());
+    /// Gets the version number field of the datagram protocol.
+    ///
+    pub fn version(&self) -> u16 {
+        self.version_field() & !FLAGS_MASK
+    }
 
-        let sample_back = sample_back_res.unwrap();
-        assert_eq!(sample_back.version(), 1);
-        assert_eq!(sample_back.tag(), 1);
-        assert_eq!(sample_back.data().len(), 5);
+    /// Checks whether the message body is compressed on the network.
+    ///
+    /// Without the `compression` feature, compressed message bodies are not decompressed and
+    /// [`data`](ConnectDatagram::data) returns the compressed bytes.
+    ///
+    pub fn is_compressed(&self) -> bool {
+        self.version_field() & COMPRESSED_FLAG != 0
+    }
 
-        Ok(())
+    /// Checks whether the datagram carries a CRC32C checksum of its extension fields and message
+    /// body.
+    ///
+    /// Without the `checksum` feature, checksums are carried but not validated.
+    ///
+    pub fn has_checksum(&self) -> bool {
+        self.version_field() & CHECKSUM_FLAG != 0
     }
 
-    #[test]
-    fn encode_and_decode_without_prefix() -> anyhow::Result<()> {
-        let data: Vec<u8> = vec![0, 1, 2, 3, 4];
-        assert_eq!(5, data.len());
+    /// Adds a CRC32C checksum of the extension fields and message body to the datagram header,
+    /// which is validated when the datagram is deserialized.
+    ///
+    /// This grows the serialized datagram by 4 bytes. A datagram received with a mismatching
+    /// checksum is rejected with a [ChecksumMismatch](`DatagramError::ChecksumMismatch`) error.
+    ///
+    #[cfg(feature = "checksum")]
+    pub fn enable_checksum(&mut self) {
+        if !self.has_checksum() {
+            self.set_version_field(self.version_field() | CHECKSUM_FLAG);
+            self.buffer.splice(
+                DATAGRAM_HEADER_BYTE_SIZE..DATAGRAM_HEADER_BYTE_SIZE,
+                [0; CHECKSUM_BYTE_SIZE],
+            );
 
-        let sample = ConnectDatagram::with_tag(1, data)?;
+            self.update_checksum();
+            self.update_size_prefix();
+        }
+    }
+
+    #[cfg(feature = "checksum")]
+    fn checksum(&self) -> u32 {
+        let buf = self.buffer
+            [DATAGRAM_HEADER_BYTE_SIZE..DATAGRAM_HEADER_BYTE_SIZE + CHECKSUM_BYTE_SIZE]
+            .as_ref()
+            .try_into()
+            .expect("could not parse big-endian bytes into checksum variable");
+
+        u32::from_be_bytes(buf)
+    }
+
+    #[cfg(feature = "checksum")]
+    fn compute_checksum(&self) -> u32 {
+        crc32c::crc32c(&self.buffer[self.extensions_offset()..])
+    }
+
+    /// Recomputes the checksum in the header after the message body has changed.
+    ///
+    #[inline]
+    fn update_checksum(&mut self) {
+        #[cfg(feature = "checksum")]
+        if self.has_checksum() {
+            let checksum = self.compute_checksum().to_be_bytes();
+            self.buffer[DATAGRAM_HEADER_BYTE_SIZE..DATAGRAM_HEADER_BYTE_SIZE + CHECKSUM_BYTE_SIZE]
+                .copy_from_slice(&checksum);
+        }
+    }
+
+    /// Checks whether the datagram is a chunk of a stream sent with
+    /// [`ConnectionWriter::send_stream`](`crate::ConnectionWriter::send_stream`), which can be
+    /// read back with [`ConnectionReader::recv_stream`](`crate::ConnectionReader::recv_stream`).
+    ///
+    /// A chunk without a message body marks the end of its stream.
+    ///
+    pub fn is_stream_chunk(&self) -> bool {
+        self.version_field() & STREAM_CHUNK_FLAG != 0
+    }
+
+    /// Checks whether the datagram carries any extension fields.
+    ///
+    pub fn has_extensions(&self) -> bool {
+        self.version_field() & EXTENSIONS_FLAG != 0
+    }
+
+    /// Gets the extension fields of the datagram, without the extensions block length.
+    ///
+    fn extensions_block(&self) -> &[u8] {
+        if self.has_extensions() {
+            &self.buffer[self.extensions_offset() + EXTENSIONS_LENGTH_BYTE_SIZE..self.data_offset()]
+        } else {
+            &[]
+        }
+    }
+
+    /// Splits the first extension field off of an extensions block into its key, its value, and
+    /// the rest of the block, returning `None` if the block is empty or the field is incomplete.
+    ///
+    fn split_extension(block: &[u8]) -> Option<(u16, &[u8], &[u8])> {
+        if block.len() < EXTENSION_HEADER_BYTE_SIZE {
+            return None;
+        }
+
+        let key = u16::from_be_bytes([block[0], block[1]]);
+        let value_size = u16::from_be_bytes([block[2], block[3]]) as usize;
+        let end = EXTENSION_HEADER_BYTE_SIZE + value_size;
+
+        if block.len() < end {
+            return None;
+        }
+
+        Some((key, &block[EXTENSION_HEADER_BYTE_SIZE..end], &block[end..]))
+    }
+
+    /// Checks that the extensions block fits in the buffer and consists of complete fields.
+    ///
+    fn has_valid_extensions(&self) -> bool {
+        if self.buffer.len() < self.extensions_offset() + EXTENSIONS_LENGTH_BYTE_SIZE
+            || self.buffer.len() < self.data_offset()
+        {
+            return false;
+        }
+
+        let mut block = self.extensions_block();
+        while !block.is_empty() {
+            match Self::split_extension(block) {
+                Some((_, _, rest)) => block = rest,
+                None => return false,
+            }
+        }
+
+        true
+    }
+
+    /// Gets the key-value extension fields of the datagram, in the order they were added.
+    ///
+    pub fn extensions(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        let mut block = self.extensions_block();
+
+        std::iter::from_fn(move || {
+            let (key, value, rest) = Self::split_extension(block)?;
+            block = rest;
+            Some((key, value))
+        })
+    }
+
+    /// Gets the value of the extension field with the provided key.
+    ///
+    pub fn get_extension(&self, key: u16) -> Option<&[u8]> {
+        self.extensions()
+            .find(|(extension_key, _)| *extension_key == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Gets the sequence number that the datagram was stamped with by a writer with sequencing
+    /// enabled, see [`Connection::enable_sequencing`](`crate::Connection::enable_sequencing`).
+    ///
+    pub fn sequence(&self) -> Option<u64> {
+        let value = self.get_extension(SEQUENCE_EXTENSION_KEY)?;
+        Some(u64::from_be_bytes(value.try_into().ok()?))
+    }
+
+    /// Sets the value of the extension field with the provided key and returns its previous value.
+    ///
+    /// Extension fields carry small metadata, such as trace IDs or content types, alongside the
+    /// message body without being part of it. Each field grows the serialized datagram by 4 bytes
+    /// plus the size of its value, and the first field by another 2 bytes. Extension fields are
+    /// neither compressed nor included in the serde representation of the datagram.
+    ///
+    /// Keys from `0xff00` up are reserved for this crate, see the
+    /// [reserved extension keys](`crate::protocol#reserved-extension-keys`).
+    ///
+    /// This will return a [TooLargeExtensions](`DatagramError::TooLargeExtensions`) error if the
+    /// extension fields would be larger than 65,535 bytes in total.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// msg.set_extension(TRACE_ID_KEY, trace_id.to_be_bytes().to_vec())?;
+    /// ```
+    pub fn set_extension(
+        &mut self,
+        key: u16,
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, DatagramError> {
+        let mut extensions: Vec<(u16, Vec<u8>)> = self
+            .extensions()
+            .map(|(key, value)| (key, value.to_vec()))
+            .collect();
+
+        let old_value = match extensions.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, old_value)) => Some(std::mem::replace(old_value, value)),
+            None => {
+                extensions.push((key, value));
+                None
+            }
+        };
+
+        let extensions_size: usize = extensions
+            .iter()
+            .map(|(_, value)| EXTENSION_HEADER_BYTE_SIZE + value.len())
+            .sum();
+
+        if extensions_size > MAX_EXTENSIONS_BYTE_SIZE {
+            return Err(DatagramError::TooLargeExtensions);
+        }
+
+        self.replace_extensions(&extensions);
+        Ok(old_value)
+    }
+
+    /// Removes the extension field with the provided key and returns its value.
+    ///
+    pub fn remove_extension(&mut self, key: u16) -> Option<Vec<u8>> {
+        let old_value = self.get_extension(key)?.to_vec();
+
+        let extensions: Vec<(u16, Vec<u8>)> = self
+            .extensions()
+            .filter(|(extension_key, _)| *extension_key != key)
+            .map(|(key, value)| (key, value.to_vec()))
+            .collect();
+
+        self.replace_extensions(&extensions);
+        Some(old_value)
+    }
+
+    /// Rewrites the extensions block, removing it entirely when there are no extension fields.
+    ///
+    fn replace_extensions(&mut self, extensions: &[(u16, Vec<u8>)]) {
+        let mut block = Vec::new();
+
+        if !extensions.is_empty() {
+            let extensions_size: usize = extensions
+                .iter()
+                .map(|(_, value)| EXTENSION_HEADER_BYTE_SIZE + value.len())
+                .sum();
+
+            block.extend((extensions_size as u16).to_be_bytes());
+            for (key, value) in extensions {
+                block.extend(key.to_be_bytes());
+                block.extend((value.len() as u16).to_be_bytes());
+                block.extend_from_slice(value);
+            }
+        }
+
+        let range = self.extensions_offset()..self.data_offset();
+        self.buffer.splice(range, block);
+
+        if extensions.is_empty() {
+            self.set_version_field(self.version_field() & !EXTENSIONS_FLAG);
+        } else {
+            self.set_version_field(self.version_field() | EXTENSIONS_FLAG);
+        }
+
+        self.update_checksum();
+        self.update_size_prefix();
+    }
+
+    /// Gets the tag field of the datagram.
+    ///
+    pub fn tag(&self) -> u16 {
+        serialized_tag(&self.buffer)
+    }
+
+    /// Sets the message body of the datagram.
+    ///
+    pub fn set_tag(&mut self, tag: u16) {
+        let start = TAG_OFFSET;
+        let end = start + TAG_BYTE_SIZE;
+
+        self.buffer.splice(start..end, tag.to_be_bytes());
+    }
+
+    /// Gets the message body of the datagram.
+    ///
+    pub fn data(&self) -> &[u8] {
+        match &self.payload {
+            Some(payload) => payload.as_slice(),
+            None => &self.buffer[self.data_offset()..],
+        }
+    }
+
+    /// Moves the message body out of the datagram, leaving the datagram without a message body.
+    ///
+    /// Returns `None` if the datagram has no message body, such as when it has already been taken,
+    /// so a second call returns `None`.
+    ///
+    pub fn take_data(&mut self) -> Option<Vec<u8>> {
+        let data_offset = self.data_offset();
+
+        let data = match self.payload.take() {
+            Some(payload) => {
+                self.buffer.truncate(data_offset);
+                payload
+            }
+
+            None => self.buffer.split_off(data_offset),
+        };
+
+        self.update_checksum();
+        self.update_size_prefix();
+
+        if data.is_empty() {
+            None
+        } else {
+            Some(data)
+        }
+    }
+
+    /// Consumes the datagram to get its message body as [`Bytes`], without copying it.
+    ///
+    /// The returned [`Bytes`] shares the allocation of the datagram, so it can be cheaply cloned and
+    /// handed to other sinks, such as when relaying received messages.
+    ///
+    pub fn into_data_bytes(self) -> Bytes {
+        let data_offset = self.data_offset();
+
+        match self.payload {
+            Some(payload) => Bytes::from(payload),
+            None => Bytes::from(self.buffer).slice(data_offset..),
+        }
+    }
+
+    /// Sets the message body of the datagram and returns the previous contents.
+    ///
+    /// If the datagram is compressed, the new message body is compressed as well.
+    ///
+    pub fn set_data(&mut self, data: Vec<u8>) -> Result<Vec<u8>, DatagramError> {
+        Self::check_data_size(data.len())?;
+
+        #[cfg(feature = "compression")]
+        if self.payload.is_some() {
+            let compressed = compress(&data)?;
+
+            self.buffer.truncate(self.data_offset());
+            self.buffer.extend(compressed);
+            self.update_checksum();
+            self.update_size_prefix();
+
+            return Ok(self.payload.replace(data).unwrap_or_default());
+        }
+
+        let data_offset = self.data_offset();
+        let old_data = self.buffer.splice(data_offset.., data).collect();
+
+        self.update_checksum();
+        self.update_size_prefix();
+
+        Ok(old_data)
+    }
+
+    /// Sets the message body of the datagram by copying `data` into the existing buffer, discarding
+    /// the previous contents.
+    ///
+    /// Unlike [`set_data`](ConnectDatagram::set_data), the previous message body is not returned,
+    /// so no allocation is needed when the new message body is the same size or smaller than the
+    /// previous one.
+    ///
+    /// If the datagram is compressed, the new message body is compressed as well.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// while let Some(mut msg) = reader.next().await {
+    ///     let reply = transform(msg.data());
+    ///     msg.set_data_in_place(&reply)?;
+    ///     writer.send(msg).await?;
+    /// }
+    /// ```
+    pub fn set_data_in_place(&mut self, data: &[u8]) -> Result<(), DatagramError> {
+        Self::check_data_size(data.len())?;
+
+        #[cfg(feature = "compression")]
+        if let Some(payload) = self.payload.as_mut() {
+            payload.clear();
+            payload.extend_from_slice(data);
+
+            let compressed = compress(data)?;
+            self.buffer.truncate(self.data_offset());
+            self.buffer.extend_from_slice(&compressed);
+            self.update_checksum();
+            self.update_size_prefix();
+
+            return Ok(());
+        }
+
+        self.buffer.truncate(self.data_offset());
+        self.buffer.extend_from_slice(data);
+
+        self.update_checksum();
+        self.update_size_prefix();
+
+        Ok(())
+    }
+
+    /// Calculates the size-prefixed serialized byte-size of the datagram.
+    ///
+    /// This will include the byte-size of the size-prefix.
+    ///
+    pub fn serialized_size(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Calculates the size-prefixed serialized byte-size that a datagram created with
+    /// [`with_tag`](ConnectDatagram::with_tag) would have for a message body of `data_len` bytes,
+    /// without constructing it.
+    ///
+    /// This does not account for checksums, extension fields, or compression, which change the
+    /// serialized size of a datagram once they are applied.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// if batch_size + ConnectDatagram::wire_size(payload.len()) > MTU {
+    ///     flush_batch().await?;
+    /// }
+    /// ```
+    pub const fn wire_size(data_len: usize) -> usize {
+        DATAGRAM_HEADER_BYTE_SIZE + data_len
+    }
+
+    /// Calculates the largest message body that fits in a datagram of at most `budget` serialized
+    /// bytes, the inverse of [`wire_size`](ConnectDatagram::wire_size).
+    ///
+    /// Returns `0` when the budget cannot even fit the datagram header, and never exceeds the
+    /// maximum message body size of 100MB.
+    ///
+    pub const fn max_data_for_wire_size(budget: usize) -> usize {
+        let data_len = budget.saturating_sub(DATAGRAM_HEADER_BYTE_SIZE);
+
+        if data_len > MAX_DATA_BYTE_SIZE {
+            MAX_DATA_BYTE_SIZE
+        } else {
+            data_len
+        }
+    }
+
+    /// Calculates the byte-size of the datagram message body.
+    ///
+    /// This will exclude all datagram header fields like the tag. For compressed datagrams, this is
+    /// the size of the uncompressed message body.
+    ///
+    pub fn data_size(&self) -> usize {
+        self.data().len()
+    }
+
+    /// Constructs a serialized representation of the datagram contents.
+    ///
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        self.buffer.as_slice()
+    }
+
+    /// Serializes the datagram.
+    ///
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    /// Deserializes the datagram from bytes.
+    ///
+    /// This will return a [SizeMismatch](`DatagramError::SizeMismatch`) error if the size-prefix
+    /// does not match the length of `buffer`, such as when `buffer` holds a truncated datagram or
+    /// more than one datagram.
+    ///
+    pub fn from_bytes(buffer: &[u8]) -> Result<Self, DatagramError> {
+        if buffer.len() >= DATAGRAM_HEADER_BYTE_SIZE {
+            Self::from_buffer(buffer.to_vec())
+        } else {
+            Err(DatagramError::InsufficientBytes)
+        }
+    }
+
+    /// Deserializes the datagram from bytes, and infers the size-prefix given the data.
+    ///
+    pub fn from_bytes_without_prefix(buffer: &[u8]) -> Result<Self, DatagramError> {
+        if buffer.len() >= DATAGRAM_HEADER_BYTE_SIZE - SIZE_PREFIX_BYTE_SIZE {
+            let mut new_buffer = Vec::with_capacity(SIZE_PREFIX_BYTE_SIZE + buffer.len());
+            new_buffer.extend(Self::size_prefix_bytes(buffer.len())?);
+            new_buffer.extend_from_slice(buffer);
+
+            Self::from_buffer(new_buffer)
+        } else {
+            Err(DatagramError::InsufficientBytes)
+        }
+    }
+
+    /// Iterates over the datagrams serialized back to back in `buffer`, such as a capture of the
+    /// bytes received on a connection, using each size-prefix to find where the next datagram
+    /// starts.
+    ///
+    /// A datagram that fails to deserialize yields its error, and iteration continues with the
+    /// next datagram since the size-prefix still delimits it. Iteration ends after yielding an
+    /// [InsufficientBytes](`DatagramError::InsufficientBytes`) error for a truncated datagram at
+    /// the end of `buffer`, or a [TooLargeMessage](`DatagramError::TooLargeMessage`) error for a
+    /// size-prefix exceeding [`MAX_SERIALIZED_BYTE_SIZE`], as the rest of `buffer` cannot be
+    /// delimited.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let capture = std::fs::read("traffic.bin")?;
+    ///
+    /// for datagram in ConnectDatagram::iter_from_bytes(&capture) {
+    ///     replay(datagram?);
+    /// }
+    /// ```
+    pub fn iter_from_bytes(
+        buffer: &[u8],
+    ) -> impl Iterator<Item = Result<ConnectDatagram, DatagramError>> + '_ {
+        let mut remaining = buffer;
+
+        std::iter::from_fn(move || {
+            if remaining.is_empty() {
+                return None;
+            }
+
+            let size = match remaining.get(..SIZE_PREFIX_BYTE_SIZE) {
+                Some(prefix) => {
+                    let prefix = prefix
+                        .try_into()
+                        .expect("could not parse big-endian bytes into size prefix variable");
+
+                    SIZE_PREFIX_BYTE_SIZE + u32::from_be_bytes(prefix) as usize
+                }
+
+                None => {
+                    remaining = &[];
+                    return Some(Err(DatagramError::InsufficientBytes));
+                }
+            };
+
+            if size > MAX_SERIALIZED_BYTE_SIZE {
+                remaining = &[];
+                return Some(Err(DatagramError::TooLargeMessage));
+            }
+
+            if remaining.len() < size {
+                remaining = &[];
+                return Some(Err(DatagramError::InsufficientBytes));
+            }
+
+            let (datagram, rest) = remaining.split_at(size);
+            remaining = rest;
+
+            Some(Self::from_bytes(datagram))
+        })
+    }
+}
+
+impl std::fmt::Debug for ConnectDatagram {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let data = self.data();
+        let mut debug = formatter.debug_struct("ConnectDatagram");
+
+        debug
+            .field("version", &self.version())
+            .field("tag", &self.tag())
+            .field("data_size", &data.len());
+
+        if self.has_extensions() {
+            debug.field("extensions", &self.extensions().collect::<Vec<_>>());
+        }
+
+        if data.len() > DEBUG_DATA_BYTE_SIZE {
+            debug.field(
+                "data",
+                &format_args!("{:?}..", &data[..DEBUG_DATA_BYTE_SIZE]),
+            );
+        } else {
+            debug.field("data", &data);
+        }
+
+        debug.finish()
+    }
+}
+
+impl PartialEq for ConnectDatagram {
+    /// Compares the version, tag, extension fields, and message body of two datagrams.
+    fn eq(&self, other: &Self) -> bool {
+        self.version() == other.version()
+            && self.tag() == other.tag()
+            && self.extensions().eq(other.extensions())
+            && self.data() == other.data()
+    }
+}
+
+impl Eq for ConnectDatagram {}
+
+/// A datagram whose message body is made up of separate segments, such as a header, a body and a
+/// trailer produced by a serializer, which are written to the network stream without being
+/// concatenated first.
+///
+/// The serialized datagram is identical to a [`ConnectDatagram`] whose message body is the
+/// concatenation of the segments, so the peer receives it as a regular [`ConnectDatagram`]. Send it
+/// with [`ConnectionWriter::send_segmented`](`crate::ConnectionWriter::send_segmented`).
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let msg = SegmentedDatagram::with_tag(tag, vec![header, body, trailer])?;
+/// writer.send_segmented(msg).await?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct SegmentedDatagram {
+    header: Bytes,
+    segments: Vec<Bytes>,
+}
+
+impl SegmentedDatagram {
+    /// Creates a new [`SegmentedDatagram`] based on an intended tag field and the segments of its
+    /// message body, in order.
+    ///
+    /// This will return a [EmptyMessage](`DatagramError::EmptyMessage`) error if the segments
+    /// contain no bytes, and a [TooLargeMessage](`DatagramError::TooLargeMessage`) error if they
+    /// contain more than 100,000,000 bytes, or 100MB, in total.
+    ///
+    pub fn with_tag(tag: u16, segments: Vec<Bytes>) -> Result<Self, DatagramError> {
+        let data_size = segments.iter().map(Bytes::len).sum();
+        ConnectDatagram::check_data_size(data_size)?;
+
+        let mut header = ConnectDatagram::encode(VERSION, tag, &[]);
+        let size = ConnectDatagram::size_prefix_bytes(
+            DATAGRAM_HEADER_BYTE_SIZE - SIZE_PREFIX_BYTE_SIZE + data_size,
+        )?;
+        header[..SIZE_PREFIX_BYTE_SIZE].copy_from_slice(&size);
+
+        Ok(Self {
+            header: Bytes::from(header),
+            segments,
+        })
+    }
+
+    /// Get the tag of the datagram.
+    pub fn tag(&self) -> u16 {
+        serialized_tag(&self.header)
+    }
+
+    /// Calculates the byte-size of the datagram message body across all of its segments.
+    pub fn data_size(&self) -> usize {
+        self.segments.iter().map(Bytes::len).sum()
+    }
+
+    /// Calculates the size-prefixed serialized byte-size of the datagram.
+    pub fn serialized_size(&self) -> usize {
+        self.header.len() + self.data_size()
+    }
+
+    /// Concatenates the segments into a [`ConnectDatagram`] with the same serialized bytes.
+    pub fn into_datagram(self) -> ConnectDatagram {
+        let mut buffer = Vec::with_capacity(self.serialized_size());
+        buffer.extend_from_slice(&self.header);
+
+        for segment in self.segments {
+            buffer.extend_from_slice(&segment);
+        }
+
+        ConnectDatagram {
+            buffer,
+            payload: None,
+        }
+    }
+
+    /// Splits the datagram into its serialized header and the segments of its message body.
+    pub(crate) fn into_parts(self) -> (Bytes, Vec<Bytes>) {
+        (self.header, self.segments)
+    }
+}
+
+/// Serializes the logical fields of the datagram (version, tag, and message body) rather than its
+/// wire format, so it round-trips across serde formats.
+///
+#[cfg(feature = "serde")]
+impl serde::Serialize for ConnectDatagram {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("ConnectDatagram", 3)?;
+        state.serialize_field("version", &self.version())?;
+        state.serialize_field("tag", &self.tag())?;
+        state.serialize_field("data", self.data())?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ConnectDatagram {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        #[derive(serde::Deserialize)]
+        #[serde(rename = "ConnectDatagram")]
+        struct Fields {
+            version: u16,
+            tag: u16,
+            data: Vec<u8>,
+        }
+
+        let fields = Fields::deserialize(deserializer)?;
+
+        if fields.version & FLAGS_MASK != 0 {
+            return Err(D::Error::custom(format!(
+                "invalid `ConnectDatagram` version {}",
+                fields.version
+            )));
+        }
+
+        Self::check_max_data_size(fields.data.len()).map_err(D::Error::custom)?;
+
+        Ok(Self {
+            buffer: Self::encode(fields.version, fields.tag, &fields.data),
+            payload: None,
+        })
+    }
+}
+
+/// Checks whether the first [`HEADER_PROBE_BYTE_SIZE`] bytes of `buffer` could start a serialized
+/// datagram, i.e. whether the size-prefix is within bounds and the version is supported.
+pub(crate) fn is_plausible_header(buffer: &[u8]) -> bool {
+    let size = u32::from_be_bytes(
+        buffer[..SIZE_PREFIX_BYTE_SIZE]
+            .try_into()
+            .expect("could not parse big-endian bytes into size prefix variable"),
+    ) as usize;
+    let version = u16::from_be_bytes(
+        buffer[SIZE_PREFIX_BYTE_SIZE..HEADER_PROBE_BYTE_SIZE]
+            .try_into()
+            .expect("could not parse big-endian bytes into version variable"),
+    );
+
+    (VERSION_BYTE_SIZE + TAG_BYTE_SIZE..=MAX_SERIALIZED_BYTE_SIZE - SIZE_PREFIX_BYTE_SIZE)
+        .contains(&size)
+        && version & !FLAGS_MASK == VERSION
+}
+
+/// Gets the version number of a serialized datagram, without its header flags, or `None` if the
+/// buffer is too short to hold the version field.
+pub(crate) fn serialized_version(buffer: &[u8]) -> Option<u16> {
+    let buf = buffer.get(VERSION_OFFSET..TAG_OFFSET)?.try_into().ok()?;
+
+    Some(u16::from_be_bytes(buf) & !FLAGS_MASK)
+}
+
+/// Checks whether the version field of a serialized datagram only holds the current protocol
+/// [`VERSION`] once its bytes are swapped, meaning that the datagram was framed in the other
+/// [`ByteOrder`].
+pub(crate) fn has_swapped_version(buffer: &[u8]) -> bool {
+    let version = match buffer.get(VERSION_OFFSET..TAG_OFFSET) {
+        Some(buf) => u16::from_be_bytes(buf.try_into().expect("slice has the size of a u16")),
+        None => return false,
+    };
+
+    version & !FLAGS_MASK != VERSION && version.swap_bytes() & !FLAGS_MASK == VERSION
+}
+
+/// Gets the offset of the message body of a serialized datagram from its fixed header, or `None`
+/// if the message body cannot be read before the rest of the datagram is received, because it is
+/// compressed, follows extension fields, is a stream chunk or belongs to another version.
+pub(crate) fn streamable_data_offset(header: &[u8]) -> Option<usize> {
+    let buf = header.get(VERSION_OFFSET..TAG_OFFSET)?.try_into().ok()?;
+    let version = u16::from_be_bytes(buf);
+
+    if version & !FLAGS_MASK != VERSION
+        || version & (COMPRESSED_FLAG | EXTENSIONS_FLAG | STREAM_CHUNK_FLAG) != 0
+    {
+        return None;
+    }
+
+    if version & CHECKSUM_FLAG != 0 {
+        Some(DATAGRAM_HEADER_BYTE_SIZE + CHECKSUM_BYTE_SIZE)
+    } else {
+        Some(DATAGRAM_HEADER_BYTE_SIZE)
+    }
+}
+
+/// Gets the checksum field of a serialized datagram, or `None` if it does not carry one.
+#[cfg(feature = "checksum")]
+pub(crate) fn serialized_checksum(buffer: &[u8]) -> Option<u32> {
+    let version = buffer.get(VERSION_OFFSET..TAG_OFFSET)?.try_into().ok()?;
+    if u16::from_be_bytes(version) & CHECKSUM_FLAG == 0 {
+        return None;
+    }
+
+    let start = DATAGRAM_HEADER_BYTE_SIZE;
+    let checksum = buffer
+        .get(start..start + CHECKSUM_BYTE_SIZE)?
+        .try_into()
+        .ok()?;
+
+    Some(u32::from_be_bytes(checksum))
+}
+
+/// Gets the tag field of a serialized datagram that has already been validated.
+pub(crate) fn serialized_tag(buffer: &[u8]) -> u16 {
+    let start = TAG_OFFSET;
+    let end = start + TAG_BYTE_SIZE;
+
+    let buf = buffer[start..end]
+        .try_into()
+        .expect("could not parse big-endian bytes into tag variable");
+
+    u16::from_be_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{protocol::ConnectDatagram, DatagramError, DATAGRAM_HEADER_BYTE_SIZE};
+
+    #[test]
+    fn serialized_size() -> anyhow::Result<()> {
+        let data: Vec<u8> = vec![0, 1, 2, 3, 4];
+        assert_eq!(5, data.len());
+
+        let sample = ConnectDatagram::with_tag(1, data)?;
+        assert_eq!(DATAGRAM_HEADER_BYTE_SIZE + 5, sample.serialized_size());
+        assert_eq!(DATAGRAM_HEADER_BYTE_SIZE + 5, sample.into_bytes().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn iter_from_bytes() -> anyhow::Result<()> {
+        let datagrams = vec![
+            ConnectDatagram::with_tag(1, vec![1])?,
+            ConnectDatagram::with_tag(2, vec![2; 100])?,
+            ConnectDatagram::control(3),
+        ];
+
+        let mut buffer: Vec<u8> = datagrams
+            .iter()
+            .flat_map(|d| d.clone().into_bytes())
+            .collect();
+        let complete = buffer.len();
+
+        let parsed: Vec<ConnectDatagram> =
+            ConnectDatagram::iter_from_bytes(&buffer).collect::<Result<_, _>>()?;
+        assert_eq!(datagrams, parsed);
+
+        // a trailing datagram that is cut short ends the iteration with an error
+        buffer.extend_from_slice(&ConnectDatagram::with_tag(4, vec![4; 10])?.into_bytes()[..12]);
+        let mut iter = ConnectDatagram::iter_from_bytes(&buffer);
+        for datagram in datagrams.iter() {
+            assert_eq!(datagram, &iter.next().expect("datagram missing")?);
+        }
+        assert!(matches!(
+            iter.next(),
+            Some(Err(DatagramError::InsufficientBytes))
+        ));
+        assert!(iter.next().is_none());
+        drop(iter);
+
+        // as does a trailing partial size-prefix
+        buffer.truncate(complete + 2);
+        assert!(matches!(
+            ConnectDatagram::iter_from_bytes(&buffer).nth(3),
+            Some(Err(DatagramError::InsufficientBytes))
+        ));
+
+        assert!(ConnectDatagram::iter_from_bytes(&[]).next().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn size_prefix_overflow() {
+        assert_eq!(
+            [0xff; 4],
+            ConnectDatagram::size_prefix_bytes(u32::MAX as usize).unwrap()
+        );
+        assert!(matches!(
+            ConnectDatagram::size_prefix_bytes(u32::MAX as usize + 1),
+            Err(DatagramError::SizePrefixOverflow)
+        ));
+    }
+
+    #[test]
+    fn wire_size() -> anyhow::Result<()> {
+        let sample = ConnectDatagram::with_tag(1, vec![0; 100])?;
+        assert_eq!(sample.serialized_size(), ConnectDatagram::wire_size(100));
+
+        assert_eq!(
+            100,
+            ConnectDatagram::max_data_for_wire_size(sample.serialized_size())
+        );
+        assert_eq!(
+            0,
+            ConnectDatagram::max_data_for_wire_size(DATAGRAM_HEADER_BYTE_SIZE - 1)
+        );
+        assert_eq!(
+            100_000_000,
+            ConnectDatagram::max_data_for_wire_size(usize::MAX)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_data() -> anyhow::Result<()> {
+        let data: Vec<u8> = vec![0, 1, 2, 3, 4];
+        assert_eq!(5, data.len());
+
+        let sample = ConnectDatagram::with_tag(1, data)?;
+
+        let taken_data = sample.data();
+        assert_eq!(5, taken_data.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn into_data_bytes() -> anyhow::Result<()> {
+        let sample = ConnectDatagram::with_tag(1, vec![0, 1, 2, 3, 4])?;
+        let data_ptr = sample.data().as_ptr();
+
+        let data = sample.into_data_bytes();
+        assert_eq!(&[0, 1, 2, 3, 4][..], data.as_ref());
+        assert_eq!(data_ptr, data.as_ptr());
+
+        Ok(())
+    }
+
+    #[test]
+    fn control() -> anyhow::Result<()> {
+        let sample = ConnectDatagram::control(7);
+        assert_eq!(7, sample.tag());
+        assert_eq!(0, sample.data_size());
+        assert_eq!(DATAGRAM_HEADER_BYTE_SIZE, sample.serialized_size());
+        assert!(ConnectDatagram::with_tag(7, Vec::new()).is_err());
+
+        let sample_back = ConnectDatagram::from_bytes(sample.clone().into_bytes().as_slice())?;
+        assert_eq!(sample, sample_back);
+
+        Ok(())
+    }
+
+    #[test]
+    fn with_version() -> anyhow::Result<()> {
+        let sample = ConnectDatagram::with_version(9, 7, vec![1, 2])?;
+        assert_eq!(9, sample.version());
+        assert_eq!(7, sample.tag());
+        assert_eq!(&[1, 2], sample.data());
+
+        let sample_back = ConnectDatagram::from_bytes(sample.clone().into_bytes().as_slice())?;
+        assert_eq!(sample, sample_back);
+
+        assert!(ConnectDatagram::with_version(0x8001, 7, vec![1]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn from_reserved() -> anyhow::Result<()> {
+        let mut buffer = ConnectDatagram::reserved_buffer(5);
+        buffer.extend_from_slice(&[0, 1, 2, 3, 4]);
+        let buffer_ptr = buffer.as_ptr();
+
+        let sample = ConnectDatagram::from_reserved(1, buffer)?;
+        assert_eq!(1, sample.tag());
+        assert_eq!(&[0, 1, 2, 3, 4], sample.data());
+
+        let payload = sample.into_bytes();
+        assert_eq!(buffer_ptr, payload.as_ptr());
+        assert_eq!(
+            ConnectDatagram::with_tag(1, vec![0, 1, 2, 3, 4])?.into_bytes(),
+            payload
+        );
+
+        assert!(ConnectDatagram::from_reserved(1, vec![0; 3]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn take_data() -> anyhow::Result<()> {
+        let data: Vec<u8> = vec![0, 1, 2, 3, 4];
+        assert_eq!(5, data.len());
+
+        let mut sample = ConnectDatagram::with_tag(1, data)?;
+
+        let taken_data = sample.take_data();
+        assert_eq!(Some(vec![0, 1, 2, 3, 4]), taken_data);
+        assert_eq!(0, sample.data_size());
+        assert_eq!(DATAGRAM_HEADER_BYTE_SIZE, sample.serialized_size());
+        assert_eq!(1, sample.tag());
+
+        assert_eq!(None, sample.take_data());
+
+        Ok(())
+    }
+
+    #[test]
+    fn encode_and_decode() -> anyhow::Result<()> {
+        let data: Vec<u8> = vec![0, 1, 2, 3, 4];
+        assert_eq!(5, data.len());
+
+        let sample = ConnectDatagram::with_tag(1, data)?;
+        let serialized_size = sample.serialized_size();
+        assert_eq!(DATAGRAM_HEADER_BYTE_SIZE + 5, serialized_size);
+
+        let payload = sample.into_bytes();
+        assert_eq!(serialized_size, payload.len());
+
+        let sample_back_res = ConnectDatagram::from_bytes(payload.as_slice());
+        assert!(sample_back_res.is_ok());
+
+        let sample_back = sample_back_res.unwrap();
+        assert_eq!(sample_back.version(), 1);
+        assert_eq!(sample_back.tag(), 1);
+        assert_eq!(sample_back.data().len(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn decode_size_mismatch() -> anyhow::Result<()> {
+        use crate::DatagramError;
+
+        let payload = ConnectDatagram::with_tag(1, vec![0, 1, 2, 3, 4])?.into_bytes();
+
+        assert!(matches!(
+            ConnectDatagram::from_bytes(&payload[..payload.len() - 1]),
+            Err(DatagramError::SizeMismatch)
+        ));
+
+        let mut extended = payload.clone();
+        extended.push(5);
+        assert!(matches!(
+            ConnectDatagram::from_bytes(extended.as_slice()),
+            Err(DatagramError::SizeMismatch)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn encode_and_decode_without_prefix() -> anyhow::Result<()> {
+        let data: Vec<u8> = vec![0, 1, 2, 3, 4];
+        assert_eq!(5, data.len());
+
+        let sample = ConnectDatagram::with_tag(1, data)?;
         let serialized_size = sample.serialized_size();
         assert_eq!(DATAGRAM_HEADER_BYTE_SIZE + 5, serialized_size);
 
@@ -303,4 +1740,188 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn set_data_updates_size_prefix() -> anyhow::Result<()> {
+        let mut sample = ConnectDatagram::with_tag(1, vec![0, 1, 2, 3, 4])?;
+
+        let old_data = sample.set_data(vec![5, 6])?;
+        assert_eq!(vec![0, 1, 2, 3, 4], old_data);
+        assert_eq!(&[5, 6], sample.data());
+        assert_eq!(DATAGRAM_HEADER_BYTE_SIZE + 2, sample.serialized_size());
+
+        let sample_back = ConnectDatagram::from_bytes(sample.into_bytes().as_slice())?;
+        assert_eq!(sample_back.tag(), 1);
+        assert_eq!(&[5, 6], sample_back.data());
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_data_in_place() -> anyhow::Result<()> {
+        let mut sample = ConnectDatagram::with_tag(1, vec![0, 1, 2, 3, 4])?;
+        let buffer = sample.as_bytes().as_ptr();
+
+        sample.set_data_in_place(&[5, 6])?;
+        assert_eq!(&[5, 6], sample.data());
+        assert_eq!(DATAGRAM_HEADER_BYTE_SIZE + 2, sample.serialized_size());
+        assert_eq!(buffer, sample.as_bytes().as_ptr());
+
+        sample.set_data_in_place(&[7, 8, 9, 10, 11])?;
+        assert_eq!(buffer, sample.as_bytes().as_ptr());
+
+        let sample_back = ConnectDatagram::from_bytes(sample.into_bytes().as_slice())?;
+        assert_eq!(&[7, 8, 9, 10, 11], sample_back.data());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn encode_and_decode_compressed() -> anyhow::Result<()> {
+        use crate::protocol::Compression;
+
+        let data: Vec<u8> = b"hello world ".repeat(100);
+
+        let sample = ConnectDatagram::with_compression(1, data.clone(), Compression::Zstd)?;
+        assert!(sample.is_compressed());
+        assert_eq!(sample.version(), 1);
+        assert_eq!(sample.data(), data.as_slice());
+        assert!(sample.serialized_size() < DATAGRAM_HEADER_BYTE_SIZE + data.len());
+
+        let payload = sample.into_bytes();
+        let size_prefix = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+        assert_eq!(
+            payload.len() - crate::SIZE_PREFIX_BYTE_SIZE,
+            size_prefix as usize
+        );
+
+        let sample_back = ConnectDatagram::from_bytes(payload.as_slice())?;
+        assert!(sample_back.is_compressed());
+        assert_eq!(sample_back.tag(), 1);
+        assert_eq!(sample_back.data(), data.as_slice());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn encode_and_decode_checksum() -> anyhow::Result<()> {
+        use crate::DatagramError;
+
+        let mut sample = ConnectDatagram::with_tag(1, vec![0, 1, 2, 3, 4])?;
+        sample.enable_checksum();
+        assert!(sample.has_checksum());
+        assert_eq!(sample.version(), 1);
+        assert_eq!(sample.data(), &[0, 1, 2, 3, 4]);
+        assert_eq!(DATAGRAM_HEADER_BYTE_SIZE + 4 + 5, sample.serialized_size());
+
+        sample.set_data(vec![5, 6, 7])?;
+        sample.set_extension(1, vec![8])?;
+        let mut payload = sample.into_bytes();
+
+        let sample_back = ConnectDatagram::from_bytes(payload.as_slice())?;
+        assert!(sample_back.has_checksum());
+        assert_eq!(sample_back.tag(), 1);
+        assert_eq!(sample_back.data(), &[5, 6, 7]);
+        assert_eq!(sample_back.get_extension(1), Some(&[8][..]));
+
+        let last = payload.len() - 1;
+        payload[last] ^= 0xff;
+        assert!(matches!(
+            ConnectDatagram::from_bytes(payload.as_slice()),
+            Err(DatagramError::ChecksumMismatch)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn encode_and_decode_extensions() -> anyhow::Result<()> {
+        use crate::DatagramError;
+
+        let mut sample = ConnectDatagram::with_tag(1, vec![0, 1, 2, 3, 4])?;
+        assert!(!sample.has_extensions());
+
+        assert_eq!(None, sample.set_extension(7, b"trace".to_vec())?);
+        assert_eq!(None, sample.set_extension(8, vec![1])?);
+        assert_eq!(Some(vec![1]), sample.set_extension(8, vec![2, 3])?);
+        assert!(sample.has_extensions());
+        assert_eq!(1, sample.version());
+        assert_eq!(Some(&b"trace"[..]), sample.get_extension(7));
+        assert_eq!(None, sample.get_extension(9));
+        assert_eq!(&[0, 1, 2, 3, 4], sample.data());
+        assert_eq!(
+            DATAGRAM_HEADER_BYTE_SIZE + 2 + (4 + 5) + (4 + 2) + 5,
+            sample.serialized_size()
+        );
+
+        let payload = sample.clone().into_bytes();
+        let size_prefix = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+        assert_eq!(payload.len() - 4, size_prefix as usize);
+
+        let sample_back = ConnectDatagram::from_bytes(payload.as_slice())?;
+        assert_eq!(sample, sample_back);
+        assert_eq!(
+            vec![(7, &b"trace"[..]), (8, &[2, 3][..])],
+            sample_back.extensions().collect::<Vec<_>>()
+        );
+
+        // an extension field that claims more bytes than the block holds is rejected
+        let mut truncated = payload.clone();
+        truncated[DATAGRAM_HEADER_BYTE_SIZE + 2 + 3] = 0xff;
+        assert!(matches!(
+            ConnectDatagram::from_bytes(truncated.as_slice()),
+            Err(DatagramError::InsufficientBytes)
+        ));
+
+        assert_eq!(Some(b"trace".to_vec()), sample.remove_extension(7));
+        assert_eq!(Some(vec![2, 3]), sample.remove_extension(8));
+        assert!(!sample.has_extensions());
+        assert_eq!(
+            ConnectDatagram::with_tag(1, vec![0, 1, 2, 3, 4])?.into_bytes(),
+            sample.into_bytes()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn debug_and_eq() -> anyhow::Result<()> {
+        let sample = ConnectDatagram::with_tag(1, vec![0, 1, 2])?;
+        assert_eq!(
+            "ConnectDatagram { version: 1, tag: 1, data_size: 3, data: [0, 1, 2] }",
+            format!("{:?}", sample)
+        );
+
+        let sample_back = ConnectDatagram::from_bytes(sample.clone().into_bytes().as_slice())?;
+        assert_eq!(sample, sample_back);
+        assert_ne!(sample, ConnectDatagram::with_tag(2, vec![0, 1, 2])?);
+        assert_ne!(sample, ConnectDatagram::with_tag(1, vec![0, 1])?);
+
+        let large = ConnectDatagram::with_tag(1, vec![7; 1000])?;
+        assert_eq!(
+            "ConnectDatagram { version: 1, tag: 1, data_size: 1000, data: [7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7].. }",
+            format!("{:?}", large)
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() -> anyhow::Result<()> {
+        let sample = ConnectDatagram::with_tag(1, vec![0, 1, 2, 3, 4])?;
+
+        let json = serde_json::to_string(&sample)?;
+        assert_eq!(r#"{"version":1,"tag":1,"data":[0,1,2,3,4]}"#, json);
+        assert_eq!(sample, serde_json::from_str::<ConnectDatagram>(&json)?);
+
+        let encoded = bincode::serialize(&sample)?;
+        let sample_back: ConnectDatagram = bincode::deserialize(&encoded)?;
+        assert_eq!(sample, sample_back);
+        assert_eq!(sample.into_bytes(), sample_back.into_bytes());
+
+        Ok(())
+    }
 }
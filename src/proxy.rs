@@ -0,0 +1,188 @@
+use crate::logging::*;
+use crate::{Connection, ConnectionReader, ConnectionWriteError, ConnectionWriter};
+use futures::{AsyncRead, AsyncWrite, SinkExt, StreamExt};
+
+/// Relays datagrams in both directions between two connections until both directions have ended,
+/// such as to proxy a downstream client to an upstream server.
+///
+/// Each datagram read from one connection is written to the other as it was serialized, without
+/// being re-encoded. Reading from a connection pauses while the writer of the other connection is
+/// at its buffer limit (see [`ConnectionWriter::set_buffer_limit`]), so a slow peer applies
+/// backpressure to the fast one instead of datagrams piling up in memory.
+///
+/// When one connection's reader ends, the other connection's writer is flushed and shut down (see
+/// [`ConnectionWriter::shutdown`]), while datagrams keep flowing in the opposite direction until
+/// it ends as well. If writing to either connection fails, both connections are dropped straight
+/// away and the error is returned.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// while let Some(downstream) = listener.next().await {
+///     async_std::task::spawn(async move {
+///         let upstream = Connection::tcp_client(upstream_addr).await?;
+///         connect::proxy(downstream, upstream).await
+///     });
+/// }
+/// ```
+pub async fn proxy(a: Connection, b: Connection) -> Result<(), ConnectionWriteError> {
+    let (a_reader, a_writer) = a.split();
+    let (b_reader, b_writer) = b.split();
+
+    futures::try_join!(forward(a_reader, b_writer), forward(b_reader, a_writer))?;
+    Ok(())
+}
+
+/// Forwards every datagram read by `reader` to `writer`, then shuts down `writer` once `reader`
+/// has ended.
+async fn forward<R, W>(
+    reader: ConnectionReader<R>,
+    mut writer: ConnectionWriter<W>,
+) -> Result<(), ConnectionWriteError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let from = reader.peer_addr();
+    let to = writer.peer_addr();
+    let mut datagrams = reader.map(Ok);
+
+    if let Err(err) = writer.send_all(&mut datagrams).await {
+        debug!("Stopped proxying from {} to {}: {}", from, to, err);
+        return Err(err);
+    }
+
+    debug!(
+        "Connection with {} stopped sending, shutting down proxied writes to {}",
+        from, to
+    );
+    writer.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proxy;
+    use crate::{ConnectDatagram, Connection, ConnectionWriteError};
+    use async_std::net::{Shutdown, TcpListener, TcpStream};
+    use futures::{SinkExt, StreamExt};
+
+    /// Connects a client to a server through a pair of connections to be proxied, returning the
+    /// client's stream so that it can be half-closed.
+    async fn proxied() -> anyhow::Result<(TcpStream, Connection, Connection, Connection)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let client = TcpStream::connect(addr).await?;
+        let downstream = Connection::from(listener.accept().await?.0);
+        let upstream = Connection::from(TcpStream::connect(addr).await?);
+        let server = Connection::from(listener.accept().await?.0);
+
+        Ok((client, downstream, upstream, server))
+    }
+
+    #[async_std::test]
+    async fn relays_both_directions() -> anyhow::Result<()> {
+        let (client_stream, downstream, upstream, mut server) = proxied().await?;
+        let mut client = Connection::from(client_stream);
+        let relay = async_std::task::spawn(proxy(downstream, upstream));
+
+        for tag in 1..=3 {
+            client
+                .writer()
+                .feed(ConnectDatagram::with_tag(tag, vec![tag as u8; 4])?)
+                .await?;
+        }
+        client.writer().flush().await?;
+
+        for tag in 1..=3 {
+            let datagram = server.reader().next().await.expect("connection closed");
+            assert_eq!(tag, datagram.tag());
+            assert_eq!(&[tag as u8; 4], datagram.data());
+
+            server.writer().send(datagram).await?;
+        }
+
+        let echoed: Vec<u16> = client
+            .reader()
+            .take(3)
+            .map(|datagram| datagram.tag())
+            .collect()
+            .await;
+        assert_eq!(vec![1, 2, 3], echoed);
+
+        // the client going away ends one direction, the server going away ends the other
+        drop(client);
+        drop(server);
+        relay.await?;
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn half_closed_direction_keeps_relaying() -> anyhow::Result<()> {
+        let (client_stream, downstream, upstream, mut server) = proxied().await?;
+        let mut client = Connection::from(client_stream.clone());
+        let relay = async_std::task::spawn(proxy(downstream, upstream));
+
+        // the client stops sending, while the server keeps replying
+        client
+            .writer()
+            .send(ConnectDatagram::with_tag(1, vec![1])?)
+            .await?;
+        client_stream.shutdown(Shutdown::Write)?;
+
+        assert_eq!(
+            1,
+            server
+                .reader()
+                .next()
+                .await
+                .expect("connection closed")
+                .tag()
+        );
+        for tag in 2..=3 {
+            server
+                .writer()
+                .send(ConnectDatagram::with_tag(tag, vec![tag as u8])?)
+                .await?;
+        }
+
+        let replies: Vec<u16> = client
+            .reader()
+            .take(2)
+            .map(|datagram| datagram.tag())
+            .collect()
+            .await;
+        assert_eq!(vec![2, 3], replies);
+
+        drop(server);
+        relay.await?;
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn closed_writer_ends_proxy() -> anyhow::Result<()> {
+        let (client_stream, downstream, mut upstream, mut server) = proxied().await?;
+        let mut client = Connection::from(client_stream);
+        upstream.writer().close().await?;
+        let relay = async_std::task::spawn(proxy(downstream, upstream));
+
+        client
+            .writer()
+            .send(ConnectDatagram::with_tag(1, vec![1])?)
+            .await?;
+        assert!(matches!(
+            relay.await,
+            Err(ConnectionWriteError::ConnectionClosed)
+        ));
+
+        // both proxied connections were dropped
+        assert!(client.reader().next().await.is_none());
+        assert!(server.reader().next().await.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use crate::logging::*;
+use crate::Connection;
+use async_std::net::ToSocketAddrs;
+use quinn::{ClientConfig, Endpoint};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+impl Connection {
+    /// Creates a [`Connection`] that uses a QUIC transport, by establishing a new QUIC connection
+    /// and opening a single bidirectional stream on it.
+    ///
+    /// The `server_name` is the name that the server's certificate is verified against. To open
+    /// more streams on the same QUIC connection, establish it with a [`quinn::Endpoint`] and use
+    /// [`Connection::quic_stream`] instead.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let config = ClientConfig::with_root_certificates(Arc::new(roots))?;
+    /// let mut conn = Connection::quic_client("127.0.0.1:3456", "localhost", config).await?;
+    /// ```
+    pub async fn quic_client<A: ToSocketAddrs + std::fmt::Display>(
+        ip_addrs: A,
+        server_name: &str,
+        config: ClientConfig,
+    ) -> anyhow::Result<Self> {
+        let peer_addr = ip_addrs
+            .to_socket_addrs()
+            .await?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("could not resolve address {}", ip_addrs))?;
+
+        let bind_addr: SocketAddr = if peer_addr.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+
+        let mut endpoint = Endpoint::client(bind_addr)?;
+        endpoint.set_default_client_config(config);
+
+        let connection = endpoint.connect(peer_addr, server_name)?.await?;
+        info!("Established client QUIC connection to {}", ip_addrs);
+
+        Self::quic_stream(&endpoint, &connection).await
+    }
+
+    /// Creates a [`Connection`] by opening a new bidirectional stream on an established QUIC
+    /// connection.
+    ///
+    /// Each call opens an independent stream, so several [`Connection`]s can share a single QUIC
+    /// connection without a slow stream holding up the others. The peer is only notified of the
+    /// stream once the first datagram is written to it.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let connection = endpoint.connect(server_addr, "localhost")?.await?;
+    ///
+    /// let mut control = Connection::quic_stream(&endpoint, &connection).await?;
+    /// let mut bulk = Connection::quic_stream(&endpoint, &connection).await?;
+    /// ```
+    pub async fn quic_stream(
+        endpoint: &Endpoint,
+        connection: &quinn::Connection,
+    ) -> anyhow::Result<Self> {
+        let (send, recv) = connection.open_bi().await?;
+        let peer_addr = connection.remote_address();
+        debug!("Opened QUIC stream {} to {}", send.id(), peer_addr);
+
+        Ok(Self::from_quic_stream(
+            endpoint.local_addr()?,
+            peer_addr,
+            send,
+            recv,
+        ))
+    }
+}
@@ -0,0 +1,269 @@
+use crate::logging::*;
+use crate::Connection;
+use async_std::net::{SocketAddr, ToSocketAddrs};
+use async_std::pin::Pin;
+use async_std::task::{Context, Poll};
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{Stream, StreamExt};
+use quinn::{ConnectionError, Endpoint, RecvStream, SendStream, ServerConfig};
+
+type HandshakeFuture = BoxFuture<'static, (SocketAddr, Result<quinn::Connection, ConnectionError>)>;
+type StreamFuture = BoxFuture<
+    'static,
+    (
+        quinn::Connection,
+        Result<(SendStream, RecvStream), ConnectionError>,
+    ),
+>;
+
+/// Listens on a bound socket for incoming QUIC streams to be handled as independent
+/// [`Connection`]s.
+///
+/// Implements the [`Stream`] trait to asynchronously accept incoming QUIC connections, yielding a
+/// [`Connection`] for every bidirectional stream that the peers open on them. Please see the
+/// [module documentation](`crate::quic`) for how QUIC streams map to [`Connection`]s.
+///
+/// QUIC handshakes and new streams only make progress while the listener is polled, so keep
+/// polling it (e.g. from a dedicated task) while the accepted connections are handled elsewhere.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let config = ServerConfig::with_single_cert(cert_chain, key)?;
+/// let mut server = QuicListener::bind("127.0.0.1:3456", config).await?;
+///
+/// // wait for a stream to be opened and accepted
+/// while let Some(mut conn) = server.next().await {
+///     // do something with connection
+/// }
+/// ```
+pub struct QuicListener {
+    local_addr: SocketAddr,
+    endpoint: Endpoint,
+    incoming: Option<BoxFuture<'static, Option<quinn::Incoming>>>,
+    handshakes: FuturesUnordered<HandshakeFuture>,
+    streams: FuturesUnordered<StreamFuture>,
+}
+
+impl QuicListener {
+    /// Creates a [`QuicListener`] by binding to an IP address and port and listens for incoming
+    /// QUIC connections.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = QuicListener::bind("127.0.0.1:3456", config).await?;
+    /// ```
+    pub async fn bind<A: ToSocketAddrs + std::fmt::Display>(
+        ip_addrs: A,
+        config: ServerConfig,
+    ) -> anyhow::Result<Self> {
+        let addr = ip_addrs
+            .to_socket_addrs()
+            .await?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("could not resolve address {}", ip_addrs))?;
+
+        let endpoint = Endpoint::server(config, addr)?;
+        info!("Started QUIC server at {}", &ip_addrs);
+
+        Ok(Self {
+            local_addr: endpoint.local_addr()?,
+            incoming: Some(accept_connection(endpoint.clone())),
+            endpoint,
+            handshakes: FuturesUnordered::new(),
+            streams: FuturesUnordered::new(),
+        })
+    }
+
+    /// Get the local IP address and port.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Get the underlying [`quinn::Endpoint`], such as to close it or to read its statistics.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+}
+
+fn accept_connection(endpoint: Endpoint) -> BoxFuture<'static, Option<quinn::Incoming>> {
+    Box::pin(async move { endpoint.accept().await })
+}
+
+fn accept_stream(connection: quinn::Connection) -> StreamFuture {
+    Box::pin(async move {
+        let res = connection.accept_bi().await;
+        (connection, res)
+    })
+}
+
+impl Stream for QuicListener {
+    type Item = Connection;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Poll::Ready(Some((connection, res))) = self.streams.poll_next_unpin(cx) {
+                let peer_addr = connection.remote_address();
+
+                match res {
+                    Ok((send, recv)) => {
+                        debug!("Accepted QUIC stream {} from {}", send.id(), peer_addr);
+                        self.streams.push(accept_stream(connection));
+
+                        let local_addr = self.local_addr;
+                        return Poll::Ready(Some(Connection::from_quic_stream(
+                            local_addr, peer_addr, send, recv,
+                        )));
+                    }
+
+                    Err(err) => {
+                        debug!("QUIC connection with {} closed: {}", peer_addr, err);
+                    }
+                }
+
+                continue;
+            }
+
+            if let Poll::Ready(Some((peer_addr, res))) = self.handshakes.poll_next_unpin(cx) {
+                match res {
+                    Ok(connection) => {
+                        debug!("Completed QUIC handshake with {}", peer_addr);
+                        self.streams.push(accept_stream(connection));
+                    }
+
+                    Err(err) => warn!(
+                        "Could not complete QUIC handshake with {}: {}",
+                        peer_addr, err
+                    ),
+                }
+
+                continue;
+            }
+
+            let incoming = match self.incoming.as_mut() {
+                Some(incoming) => incoming.as_mut().poll(cx),
+                None => Poll::Ready(None),
+            };
+
+            match incoming {
+                Poll::Ready(Some(incoming)) => {
+                    let peer_addr = incoming.remote_address();
+                    debug!("Received QUIC connection attempt from {}", peer_addr);
+
+                    match incoming.accept() {
+                        Ok(connecting) => self
+                            .handshakes
+                            .push(Box::pin(async move { (peer_addr, connecting.await) })),
+
+                        Err(err) => error!(
+                            "Encountered error when trying to accept new QUIC connection {}",
+                            err
+                        ),
+                    }
+
+                    self.incoming = Some(accept_connection(self.endpoint.clone()));
+                }
+
+                Poll::Ready(None) => {
+                    if self.incoming.take().is_some() {
+                        debug!("QUIC endpoint at {} was closed", self.local_addr);
+                    }
+
+                    if self.handshakes.is_empty() && self.streams.is_empty() {
+                        return Poll::Ready(None);
+                    }
+
+                    return Poll::Pending;
+                }
+
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QuicListener;
+    use crate::{ConnectDatagram, Connection};
+    use futures::{SinkExt, StreamExt};
+    use quinn::rustls::pki_types::pem::PemObject;
+    use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use quinn::rustls::RootCertStore;
+    use quinn::{ClientConfig, Endpoint, ServerConfig};
+    use std::sync::Arc;
+
+    fn server_config() -> anyhow::Result<ServerConfig> {
+        let certs = CertificateDer::pem_slice_iter(include_bytes!("../tls/testdata/server.cert"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_slice(include_bytes!("../tls/testdata/server.rsa"))?;
+
+        Ok(ServerConfig::with_single_cert(certs, key)?)
+    }
+
+    fn client_config() -> anyhow::Result<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(include_bytes!("../tls/testdata/ca.cert")) {
+            roots.add(cert?)?;
+        }
+
+        Ok(ClientConfig::with_root_certificates(Arc::new(roots))?)
+    }
+
+    #[async_std::test]
+    async fn streams_map_to_connections() -> anyhow::Result<()> {
+        let mut server = QuicListener::bind("127.0.0.1:0", server_config()?).await?;
+        let server_addr = server.local_addr();
+
+        // the listener must be polled for QUIC handshakes to complete, so echo from another task
+        async_std::task::spawn(async move {
+            while let Some(conn) = server.next().await {
+                async_std::task::spawn(async move {
+                    let (mut reader, mut writer) = conn.split();
+                    while let Some(msg) = reader.next().await {
+                        writer.send(msg).await.expect("could not echo datagram");
+                    }
+                });
+            }
+        });
+
+        let mut client =
+            Connection::quic_client(server_addr, "localhost", client_config()?).await?;
+        client
+            .writer()
+            .send(ConnectDatagram::with_tag(1, vec![1])?)
+            .await?;
+        let reply = client.reader().next().await.expect("stream closed");
+        assert_eq!(1, reply.tag());
+
+        // further streams on the same QUIC connection are accepted as separate connections
+        let mut endpoint = Endpoint::client("127.0.0.1:0".parse()?)?;
+        endpoint.set_default_client_config(client_config()?);
+        let connection = endpoint.connect(server_addr, "localhost")?.await?;
+
+        let mut first = Connection::quic_stream(&endpoint, &connection).await?;
+        let mut second = Connection::quic_stream(&endpoint, &connection).await?;
+        second
+            .writer()
+            .send(ConnectDatagram::with_tag(2, vec![2])?)
+            .await?;
+        first
+            .writer()
+            .send(ConnectDatagram::with_tag(3, vec![3])?)
+            .await?;
+
+        let reply = second.reader().next().await.expect("stream closed");
+        assert_eq!(2, reply.tag());
+        let reply = first.reader().next().await.expect("stream closed");
+        assert_eq!(3, reply.tag());
+        assert_eq!(first.peer_addr(), second.peer_addr());
+
+        Ok(())
+    }
+}
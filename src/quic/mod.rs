@@ -0,0 +1,50 @@
+//! QUIC transport client and listener implementations.
+//!
+//! <br/>
+//!
+//! This module primarily exposes the QUIC client implementation over a [`Connection`] type and the
+//! QUIC listener implementation as [`QuicListener`].
+//!
+//! QUIC encrypts every connection itself, so this transport is configured with a
+//! [`quinn::ClientConfig`] or [`quinn::ServerConfig`] rather than going through the `tls` feature.
+//!
+//! # Streams and connections
+//!
+//! A single QUIC connection multiplexes any number of bidirectional streams, which are delivered
+//! independently of each other so that a lost packet only delays the stream it belongs to. Each
+//! bidirectional stream is mapped to its own [`Connection`], whose [`ConnectionReader`] and
+//! [`ConnectionWriter`] read and write datagrams on that stream only:
+//!
+//! - [`Connection::quic_client`] establishes a new QUIC connection and opens a single stream on it.
+//! - [`Connection::quic_stream`] opens another stream on an established [`quinn::Connection`], so
+//!   that several [`Connection`]s share one QUIC connection.
+//! - [`QuicListener`] yields a [`Connection`] for every stream opened by a peer, across all of the
+//!   QUIC connections it has accepted.
+//!
+//! The peer only learns of a new stream once something is written to it, so the side that opens a
+//! stream should send the first datagram.
+//!
+//! [`ConnectionReader`]: `crate::ConnectionReader`
+//! [`ConnectionWriter`]: `crate::ConnectionWriter`
+
+pub(crate) mod client;
+pub(crate) mod listener;
+
+pub use listener::*;
+
+pub use quinn;
+
+use crate::Connection;
+use std::net::SocketAddr;
+
+impl Connection {
+    /// Creates a [`Connection`] over a bidirectional QUIC stream.
+    pub(crate) fn from_quic_stream(
+        local_addr: SocketAddr,
+        peer_addr: SocketAddr,
+        send: quinn::SendStream,
+        recv: quinn::RecvStream,
+    ) -> Self {
+        Self::new(local_addr, peer_addr, Box::pin(recv), Box::pin(send))
+    }
+}
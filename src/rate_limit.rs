@@ -0,0 +1,83 @@
+use crate::rt::{Instant, Timer};
+use futures::task::{Context, Poll};
+use futures::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// A token bucket used to pace an operation to a fixed rate.
+///
+/// The bucket holds up to one second's worth of tokens, so short bursts up to the configured rate
+/// are allowed before pacing kicks in. Taking more tokens than are available puts the bucket into
+/// debt, which is paid back before any further tokens are handed out.
+///
+pub(crate) struct TokenBucket {
+    rate: u64,
+    tokens: f64,
+    last_refill: Instant,
+    delay: Option<Timer>,
+}
+
+impl TokenBucket {
+    /// Creates a full [`TokenBucket`] that refills at `rate` tokens per second.
+    ///
+    pub(crate) fn new(rate: u64) -> Self {
+        let rate = rate.max(1);
+
+        Self {
+            rate,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+            delay: None,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.last_refill = now;
+    }
+
+    /// Waits until `amount` tokens are available without taking them.
+    ///
+    /// Requests for more tokens than the bucket can hold only wait for the bucket to fill up.
+    ///
+    pub(crate) fn poll_ready(&mut self, cx: &mut Context<'_>, amount: u64) -> Poll<()> {
+        let required = amount.min(self.rate) as f64;
+
+        loop {
+            self.refill();
+
+            if self.tokens >= required {
+                self.delay.take();
+                return Poll::Ready(());
+            }
+
+            let wait = Duration::from_secs_f64((required - self.tokens) / self.rate as f64);
+            let delay = self.delay.get_or_insert_with(|| Timer::after(wait));
+
+            match Pin::new(delay).poll(cx) {
+                Poll::Ready(_) => {
+                    self.delay.take();
+                }
+
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    /// Gets the number of whole tokens that are available to be taken.
+    ///
+    pub(crate) fn available(&mut self) -> u64 {
+        self.refill();
+        self.tokens.max(0.0) as u64
+    }
+
+    /// Takes `amount` tokens from the bucket.
+    ///
+    pub(crate) fn take(&mut self, amount: u64) {
+        self.refill();
+        self.tokens -= amount as f64;
+    }
+}
@@ -1,22 +1,117 @@
-use crate::SIZE_PREFIX_BYTE_SIZE;
-use crate::{protocol::ConnectDatagram, DATAGRAM_HEADER_BYTE_SIZE};
-use async_std::net::SocketAddr;
-use async_std::pin::Pin;
-use bytes::BytesMut;
-use futures::task::{Context, Poll};
-use futures::{AsyncRead, Stream};
-use log::*;
-use std::convert::TryInto;
+use crate::conn_limit::ConnectionSlot;
+use crate::heartbeat::ReaderHeartbeat;
+use crate::idle::ReaderIdle;
+use crate::logging::*;
+use crate::observer::ConnectionObserver;
+use crate::protocol::{
+    has_swapped_version, is_plausible_header, serialized_tag, serialized_version,
+    streamable_data_offset, ByteOrder, ConnectDatagram, DatagramError, SharedByteOrder,
+    HEADER_PROBE_BYTE_SIZE, MAX_SERIALIZED_BYTE_SIZE,
+};
+use crate::rt;
+use crate::sequence::SequenceGap;
+use crate::stats::ConnectionStats;
+use crate::{DATAGRAM_HEADER_BYTE_SIZE, SIZE_PREFIX_BYTE_SIZE};
+use bytes::{Buf, Bytes};
+use futures::channel::mpsc;
+use futures::future::poll_fn;
+use futures::task::{noop_waker, Context, Poll, Waker};
+use futures::{AsyncRead, Sink, Stream};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub use futures::{SinkExt, StreamExt};
 
 /// A default buffer size to read in bytes and then deserialize as messages.
 pub(crate) const BUFFER_SIZE: usize = 8192;
 
+/// The default number of datagrams a reader processes before yielding to the executor, see
+/// [`ConnectionReader::set_poll_budget`].
+pub const DEFAULT_POLL_BUDGET_DATAGRAMS: usize = 128;
+
+/// The default number of bytes a reader reads before yielding to the executor, see
+/// [`ConnectionReader::set_poll_budget`].
+pub const DEFAULT_POLL_BUDGET_BYTES: usize = 64 * BUFFER_SIZE;
+
+/// The boxed network stream that a [`ConnectionReader`] reads from by default, which erases the
+/// type of the transport so that readers of different transports share a single type.
+pub type BoxedReadStream = Pin<Box<dyn AsyncRead + Send + Sync>>;
+
+/// A custom deserializer for the serialized datagrams of a version number, see
+/// [`ConnectionReader::set_version_handler`].
+pub(crate) type VersionHandler =
+    Box<dyn Fn(Vec<u8>) -> Result<ConnectDatagram, DatagramError> + Send + Sync>;
+
+/// The outcome of waiting for the next datagram with [`ConnectionReader::next_or_progress`].
+///
+#[derive(Debug)]
+pub enum NextResult {
+    /// A complete datagram was received.
+    Datagram(ConnectDatagram),
+
+    /// The timeout elapsed while a datagram was partially received.
+    Progress {
+        /// Bytes of the datagram received so far, excluding the size-prefix.
+        received: usize,
+
+        /// Total bytes of the datagram, excluding the size-prefix.
+        total: usize,
+    },
+
+    /// The timeout elapsed before any bytes of the next datagram were received.
+    Timeout,
+
+    /// The stream of messages from the network is closed.
+    Closed,
+}
+
+/// The next message received with [`ConnectionReader::next_or_payload`].
+///
+pub enum NextPayload<'a, R: AsyncRead + Unpin = BoxedReadStream> {
+    /// A complete datagram, whose message body is smaller than the threshold or cannot be streamed.
+    Datagram(ConnectDatagram),
+
+    /// The message body of a large datagram, read as it arrives from the network stream.
+    Payload(PayloadReader<'a, R>),
+}
+
+/// The reason a [`ConnectionReader`] stream ended, as reported by
+/// [`ConnectionReader::close_reason`].
+///
+#[derive(Debug)]
+pub enum CloseReason {
+    /// The peer closed the connection, ending the network stream.
+    PeerClosed,
+
+    /// Encountered an IO-level error when reading from the network stream.
+    IoError(std::io::Error),
+
+    /// No datagram was received before the heartbeat timeout or the idle timeout elapsed.
+    Timeout,
+
+    /// The reader was closed locally with [`ConnectionReader::close`].
+    LocalShutdown,
+
+    /// The peer sent bytes that violate the protocol, such as a datagram exceeding the maximum
+    /// size.
+    ProtocolError(DatagramError),
+}
+
 /// An interface to read messages from the network connection.
 ///
 /// Implements the `Stream` trait to asynchronously read messages from the network connection.
 ///
+/// Datagrams that cannot be deserialized, such as those failing a checksum or decompression, are
+/// logged and skipped rather than ending the stream.
+///
+/// When the peer closes the connection, every datagram it completely sent beforehand is still
+/// yielded before the stream ends. Only the bytes of a datagram that was cut off by the close are
+/// discarded, with a warning.
+///
 /// # Example
 ///
 /// Basic usage:
@@ -27,18 +122,89 @@ pub(crate) const BUFFER_SIZE: usize = 8192;
 /// }
 /// ```
 ///
+/// # Cancellation safety
+///
+/// Every byte read from the network stream is kept in the reader until the datagram it belongs
+/// to is complete, so dropping a `next()` future, such as in a losing branch of `select!`, never
+/// loses a datagram or yields one twice, and the next call continues reading where the dropped
+/// one left off. The same holds for [`peek`](ConnectionReader::peek),
+/// [`next_or_progress`](ConnectionReader::next_or_progress) and
+/// [`read_into`](ConnectionReader::read_into).
+///
+/// # Stream type
+///
+/// By default the reader reads from a [`BoxedReadStream`], so that every [`ConnectionReader`] has
+/// the same type regardless of the transport. A reader constructed with
+/// [`ConnectionReader::from_stream`] reads from a concrete stream type `R` instead, which avoids
+/// the allocation and the dynamic dispatch of each read.
+///
 /// Please see the [tcp-client](https://github.com/sachanganesh/connect-rs/blob/main/examples/tcp-client/)
 /// example program or other client example programs for a more thorough showcase.
 ///
-
-pub struct ConnectionReader {
+pub struct ConnectionReader<R: AsyncRead + Unpin = BoxedReadStream> {
     local_addr: SocketAddr,
     peer_addr: SocketAddr,
-    read_stream: Pin<Box<dyn AsyncRead + Send + Sync>>,
-    buffer: Option<BytesMut>,
-    pending_read: Option<BytesMut>,
-    pending_datagram: Option<usize>,
+    read_stream: R,
+    buffer: Vec<u8>,
+    buffer_pos: usize,
+    buffer_len: usize,
+    size_prefix: [u8; SIZE_PREFIX_BYTE_SIZE],
+    size_prefix_len: usize,
+    pending_datagram: Option<PendingDatagram>,
+    deferred: VecDeque<ConnectDatagram>,
+    deferred_bytes: usize,
+    max_buffered_bytes: Option<usize>,
+
+    /// Woken once the deferred datagrams drop below `max_buffered_bytes` again.
+    buffer_waker: Option<Waker>,
+    stats: ConnectionStats,
     closed: bool,
+    close_reason: Option<CloseReason>,
+    slot: Option<Arc<ConnectionSlot>>,
+    heartbeat: Option<ReaderHeartbeat>,
+    idle: Option<ReaderIdle>,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+    resync_on_error: bool,
+    resync_skipped: usize,
+    version_handlers: HashMap<u16, VersionHandler>,
+    expected_sequence: Option<u64>,
+    spare_buffer: Option<Vec<u8>>,
+    byte_order: ByteOrder,
+    framing_byte_order: Option<SharedByteOrder>,
+    label: Option<String>,
+    poll_budget: Option<PollBudget>,
+    stream_threshold: Option<usize>,
+    payload: Option<PayloadState>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+/// Limits how much a [`ConnectionReader`] processes before it yields to the executor.
+struct PollBudget {
+    max_datagrams: usize,
+    max_bytes: usize,
+    datagrams: usize,
+    bytes: usize,
+}
+
+impl PollBudget {
+    fn new(max_datagrams: usize, max_bytes: usize) -> Self {
+        Self {
+            max_datagrams,
+            max_bytes,
+            datagrams: 0,
+            bytes: 0,
+        }
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.datagrams >= self.max_datagrams || self.bytes >= self.max_bytes
+    }
+
+    fn reset(&mut self) {
+        self.datagrams = 0;
+        self.bytes = 0;
+    }
 }
 
 impl ConnectionReader {
@@ -47,30 +213,108 @@ impl ConnectionReader {
     pub fn new(
         local_addr: SocketAddr,
         peer_addr: SocketAddr,
-        read_stream: Pin<Box<dyn AsyncRead + Send + Sync>>,
+        read_stream: BoxedReadStream,
     ) -> Self {
-        let mut buffer = BytesMut::with_capacity(BUFFER_SIZE);
-        buffer.resize(BUFFER_SIZE, 0);
+        Self::from_stream(local_addr, peer_addr, read_stream)
+    }
+}
 
+impl<R: AsyncRead + Unpin> ConnectionReader<R> {
+    /// Creates a new [`ConnectionReader`] that reads from a concrete stream type and the local and
+    /// peer socket metadata, without boxing the stream.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let reader = ConnectionReader::from_stream(local_addr, peer_addr, stream.clone());
+    /// ```
+    pub fn from_stream(local_addr: SocketAddr, peer_addr: SocketAddr, read_stream: R) -> Self {
         Self {
             local_addr,
             peer_addr,
             read_stream,
-            buffer: Some(buffer),
-            pending_read: None,
+            buffer: vec![0; BUFFER_SIZE],
+            buffer_pos: 0,
+            buffer_len: 0,
+            size_prefix: [0; SIZE_PREFIX_BYTE_SIZE],
+            size_prefix_len: 0,
             pending_datagram: None,
+            deferred: VecDeque::new(),
+            deferred_bytes: 0,
+            max_buffered_bytes: None,
+            buffer_waker: None,
+            stats: ConnectionStats::default(),
             closed: false,
+            close_reason: None,
+            slot: None,
+            heartbeat: None,
+            idle: None,
+            observer: None,
+            resync_on_error: false,
+            version_handlers: HashMap::new(),
+            expected_sequence: None,
+            spare_buffer: None,
+            byte_order: ByteOrder::default(),
+            framing_byte_order: None,
+            label: None,
+            poll_budget: Some(PollBudget::new(
+                DEFAULT_POLL_BUDGET_DATAGRAMS,
+                DEFAULT_POLL_BUDGET_BYTES,
+            )),
+            stream_threshold: None,
+            payload: None,
+            resync_skipped: 0,
+            #[cfg(feature = "tracing")]
+            span: crate::logging::connection_span(local_addr, peer_addr),
         }
     }
 
     /// Get the local IP address and port.
     pub fn local_addr(&self) -> SocketAddr {
-        self.local_addr.clone()
+        self.local_addr
     }
 
     /// Get the peer IP address and port.
     pub fn peer_addr(&self) -> SocketAddr {
-        self.peer_addr.clone()
+        self.peer_addr
+    }
+
+    /// Sets a label that identifies the connection in log lines alongside the peer address, such
+    /// as the authenticated user or a request ID, to tell apart connections that share a peer
+    /// address. See [`Connection::set_label`](`crate::Connection::set_label`).
+    pub fn set_label(&mut self, label: String) {
+        #[cfg(feature = "tracing")]
+        self.span.record("label", label.as_str());
+
+        self.label = Some(label);
+    }
+
+    /// Get the label that identifies the connection in log lines, if it has one.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Identifies the peer of the connection in log lines.
+    fn peer(&self) -> Peer<'_> {
+        Peer {
+            label: self.label.as_deref(),
+            addr: self.peer_addr,
+        }
+    }
+
+    /// Get the total number of bytes read from the network stream, including datagram headers.
+    pub fn bytes_read(&self) -> u64 {
+        self.stats.bytes_read
+    }
+
+    /// Get a snapshot of the messages and bytes read so far.
+    ///
+    /// The write counters are always zero; see [`Connection::stats`](`crate::Connection::stats`)
+    /// for the statistics of both halves.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats
     }
 
     /// Check if the `Stream` of messages from the network is closed.
@@ -78,155 +322,2654 @@ impl ConnectionReader {
         self.closed
     }
 
-    pub(crate) fn close_stream(&mut self) {
-        debug!("Closing the stream for connection with {}", self.peer_addr);
-        self.buffer.take();
+    /// Get the reason the `Stream` of messages from the network was closed, or `None` while it is
+    /// still open.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// while let Some(msg) = reader.next().await {
+    ///   // handle the received message
+    /// }
+    ///
+    /// if let Some(CloseReason::Timeout) = reader.close_reason() {
+    ///     reconnect().await?;
+    /// }
+    /// ```
+    pub fn close_reason(&self) -> Option<&CloseReason> {
+        self.close_reason.as_ref()
+    }
+
+    /// Closes the `Stream` of messages from the network locally, discarding any partially received
+    /// datagram. Datagrams that were already received out of turn, such as by
+    /// [`peek`](ConnectionReader::peek), are still yielded before the stream ends.
+    pub fn close(&mut self) {
+        self.close_stream(CloseReason::LocalShutdown);
+    }
+
+    /// Waits for the next datagram and returns a reference to it without removing it from the
+    /// stream, so that the following call to `next()` yields the same datagram.
+    ///
+    /// Returns `None` if the stream of messages from the network is closed.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// if let Some(msg) = reader.peek().await {
+    ///     if msg.tag() == CONTROL_TAG {
+    ///         handle_control(reader.next().await.unwrap());
+    ///     }
+    /// }
+    /// ```
+    pub async fn peek(&mut self) -> Option<&ConnectDatagram> {
+        if self.deferred.is_empty() {
+            let datagram = self.next().await?;
+            self.defer(datagram);
+        }
+
+        self.deferred.front()
+    }
+
+    /// Waits up to `timeout` for the next datagram, reporting how much of a partially received
+    /// datagram has arrived if the timeout elapses first.
+    ///
+    /// Buffered bytes are kept when the timeout elapses, so calling this again continues reading
+    /// the same datagram.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// loop {
+    ///     match reader.next_or_progress(Duration::from_millis(100)).await {
+    ///         NextResult::Datagram(msg) => break handle(msg),
+    ///         NextResult::Progress { received, total } => show_progress(received, total),
+    ///         NextResult::Timeout => continue,
+    ///         NextResult::Closed => break,
+    ///     }
+    /// }
+    /// ```
+    pub async fn next_or_progress(&mut self, timeout: Duration) -> NextResult {
+        match rt::timeout(timeout, self.next()).await {
+            Ok(Some(datagram)) => NextResult::Datagram(datagram),
+
+            Ok(None) => NextResult::Closed,
+
+            Err(_) => match self.pending_datagram.as_ref() {
+                Some(pending) => NextResult::Progress {
+                    received: pending.filled - SIZE_PREFIX_BYTE_SIZE,
+                    total: pending.total - SIZE_PREFIX_BYTE_SIZE,
+                },
+
+                None => NextResult::Timeout,
+            },
+        }
+    }
+
+    /// Waits for the next datagram and copies its message body into `buf`, returning the tag and the
+    /// length of the message body, or `None` if the stream of messages from the network is closed.
+    ///
+    /// `buf` is cleared first, and grows if its capacity is smaller than the message body, so a
+    /// receive loop that reuses the same `buf` only allocates until it has seen its largest
+    /// message. The reader in turn reuses the buffer of the datagram for receiving the next one, so
+    /// that the steady-state receive path does not allocate per message. Compressed datagrams are
+    /// still decompressed into a new allocation.
+    ///
+    /// Datagrams yielded through the `Stream` implementation are unaffected, so both can be used on
+    /// the same reader.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut buf = Vec::with_capacity(64 * 1024);
+    ///
+    /// while let Some((tag, len)) = reader.read_into(&mut buf).await {
+    ///     handle(tag, &buf[..len]);
+    /// }
+    /// ```
+    pub async fn read_into(&mut self, buf: &mut Vec<u8>) -> Option<(u16, usize)> {
+        let datagram = self.next().await?;
+        let tag = datagram.tag();
+
+        buf.clear();
+        buf.extend_from_slice(datagram.data());
+        self.spare_buffer = Some(datagram.into_bytes());
+
+        Some((tag, buf.len()))
+    }
+
+    /// Consumes the [`ConnectionReader`] to create a [`TeeReader`] that forwards a copy of every
+    /// received datagram to `sink` while still yielding it to the consumer.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut reader = reader.tee(audit_sink);
+    ///
+    /// while let Some(msg) = reader.next().await {
+    ///   // handle the received message, a copy has already been queued on `audit_sink`
+    /// }
+    /// ```
+    pub fn tee<S>(self, sink: S) -> TeeReader<S, R>
+    where
+        S: Sink<ConnectDatagram> + Unpin,
+        S::Error: Display,
+    {
+        TeeReader {
+            reader: self,
+            sink: Some(sink),
+            pending: None,
+        }
+    }
+
+    /// Consumes the [`ConnectionReader`] to create a [`TagRouter`] that demultiplexes received
+    /// datagrams into per-tag streams.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut router = reader.split_by_tag();
+    /// let mut chat = router.subscribe(CHAT_TAG);
+    /// let mut presence = router.subscribe(PRESENCE_TAG);
+    ///
+    /// // drive the router, handling datagrams with any other tag
+    /// task::spawn(router.for_each(|msg| async move {
+    ///     warn!("Received message with unknown tag {}", msg.tag());
+    /// }));
+    ///
+    /// while let Some(msg) = chat.next().await {
+    ///   // handle the received chat message
+    /// }
+    /// ```
+    pub fn split_by_tag(self) -> TagRouter<R> {
+        TagRouter {
+            budget: self.max_buffered_bytes.map(BufferBudget::new),
+            reader: self,
+            subscribers: HashMap::new(),
+            pending: None,
+        }
+    }
+
+    /// Consumes the [`ConnectionReader`] to spawn a task that forwards received datagrams into a
+    /// bounded channel of `capacity` datagrams, decoupling reading from processing.
+    ///
+    /// The channel is a runtime-agnostic [`futures::channel::mpsc`] channel, while the task is
+    /// spawned on the `async-std` runtime. While the channel is full, the task stops reading from
+    /// the network stream, so a slow consumer applies backpressure to the peer through the
+    /// transport's flow control. The task also stops reading while the datagrams in the channel
+    /// add up to the limit set with
+    /// [`set_max_buffered_bytes`](ConnectionReader::set_max_buffered_bytes), if any. The channel
+    /// ends when the connection closes, and the task stops once the [`DatagramReceiver`] is
+    /// dropped and the next datagram is received.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut messages = reader.into_channel(64);
+    ///
+    /// while let Some(msg) = messages.next().await {
+    ///   // slowly process the received message
+    /// }
+    /// ```
+    pub fn into_channel(mut self, capacity: usize) -> DatagramReceiver
+    where
+        R: Send + 'static,
+    {
+        let (mut sender, receiver) = mpsc::channel(capacity);
+        let budget = self.max_buffered_bytes.map(BufferBudget::new);
+        let task_budget = budget.clone();
+
+        rt::spawn(async move {
+            loop {
+                if let Some(budget) = task_budget.as_ref() {
+                    poll_fn(|cx| budget.poll_room(cx)).await;
+                }
+
+                let datagram = match self.next().await {
+                    Some(datagram) => datagram,
+                    None => break,
+                };
+
+                if let Some(budget) = task_budget.as_ref() {
+                    budget.reserve(datagram.serialized_size());
+                }
+
+                if sender.send(datagram).await.is_err() {
+                    debug!(
+                        "Stopped forwarding datagrams from {} since the channel was dropped",
+                        self.peer()
+                    );
+                    break;
+                }
+            }
+        });
+
+        DatagramReceiver { receiver, budget }
+    }
+
+    /// Waits for the next message, streaming the message body of a datagram through a
+    /// [`PayloadReader`] if it is at least `threshold` bytes, or returns `None` if the stream of
+    /// messages from the network is closed.
+    ///
+    /// A streamed message body is yielded as soon as the header of its datagram is received, and
+    /// its bytes are read from the network stream as the [`PayloadReader`] is read, so that a large
+    /// message can be processed incrementally without buffering all of it in memory. Since the
+    /// [`PayloadReader`] borrows the reader, no other message is received until it is dropped.
+    /// Dropping it before reading the whole message body discards the rest of it, and the reader
+    /// continues with the next datagram.
+    ///
+    /// A datagram is always returned whole as [`NextPayload::Datagram`] if its message body is
+    /// smaller than `threshold`, or if it cannot be read before the rest of the datagram is
+    /// received: because it is compressed, carries extensions such as a sequence number, is a
+    /// chunk of a stream sent with
+    /// [`ConnectionWriter::send_stream`](`crate::ConnectionWriter::send_stream`), or belongs to
+    /// another version of the protocol. Heartbeats are handled as usual and never streamed.
+    ///
+    /// The `Stream` implementation is unaffected and always yields whole datagrams, so both can be
+    /// used on the same reader. Datagrams kept by [`peek`](ConnectionReader::peek) or while reading
+    /// a stream are returned first.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// while let Some(next) = reader.next_or_payload(64 * 1024).await {
+    ///     match next {
+    ///         NextPayload::Datagram(msg) => handle(msg),
+    ///         NextPayload::Payload(payload) => {
+    ///             let mut file = async_std::fs::File::create(path_for(payload.tag())).await?;
+    ///             async_std::io::copy(payload, &mut file).await?;
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub async fn next_or_payload(&mut self, threshold: usize) -> Option<NextPayload<'_, R>> {
+        if let Some(datagram) = self.take_deferred(0) {
+            return Some(NextPayload::Datagram(datagram));
+        }
+
+        let received = poll_fn(|cx| {
+            self.stream_threshold = Some(threshold);
+            let received = self.poll_incoming(cx);
+            self.stream_threshold = None;
+
+            received
+        })
+        .await?;
+
+        let next = match received {
+            Received::Datagram(datagram) => NextPayload::Datagram(datagram),
+
+            Received::Payload => {
+                let payload = self.payload.as_ref()?;
+                let (tag, data_size) = (payload.tag, payload.remaining);
+
+                NextPayload::Payload(PayloadReader {
+                    reader: self,
+                    tag,
+                    data_size,
+                    finished: false,
+                })
+            }
+        };
+
+        Some(next)
+    }
+
+    /// Creates a [`ChunkReader`] that reads back a stream sent by the peer with
+    /// [`ConnectionWriter::send_stream`](`crate::ConnectionWriter::send_stream`) and the provided
+    /// tag.
+    ///
+    /// Other datagrams received before the stream ends are kept and yielded by the
+    /// [`ConnectionReader`] once the [`ChunkReader`] is dropped, so they are buffered in memory
+    /// while the stream is being read, up to the limit set with
+    /// [`set_max_buffered_bytes`](ConnectionReader::set_max_buffered_bytes).
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut file = async_std::fs::File::create(path).await?;
+    /// async_std::io::copy(reader.recv_stream(FILE_TAG), &mut file).await?;
+    /// ```
+    pub fn recv_stream(&mut self, tag: u16) -> ChunkReader<'_, R> {
+        ChunkReader {
+            reader: self,
+            tag,
+            chunk: Bytes::new(),
+            finished: false,
+        }
+    }
+
+    /// Get a [`Stream`] of the message bodies of the datagrams read from the network, discarding
+    /// their tags and other header fields.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// while let Some(payload) = reader.payloads().next().await {
+    ///     // do something with the payload bytes
+    /// }
+    /// ```
+    pub fn payloads(&mut self) -> impl Stream<Item = Vec<u8>> + Unpin + '_ {
+        self.map(|mut datagram| datagram.take_data().unwrap_or_default())
+    }
+
+    /// Get a [`Stream`] of the message bodies of the datagrams read from the network with the
+    /// provided tag. Datagrams with any other tag are discarded.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut updates = reader.payloads_for_tag(UPDATE_TAG);
+    /// ```
+    pub fn payloads_for_tag(&mut self, tag: u16) -> impl Stream<Item = Vec<u8>> + Unpin + '_ {
+        self.filter_map(move |mut datagram| {
+            let payload = (datagram.tag() == tag).then(|| datagram.take_data().unwrap_or_default());
+            futures::future::ready(payload)
+        })
+    }
+
+    /// Caps the total size of the datagrams that the reader has received but not yet delivered at
+    /// `max` bytes, bounding the memory held for the connection beyond a single datagram.
+    ///
+    /// Once the buffered datagrams reach `max` bytes, the reader stops reading from the network
+    /// stream until they are delivered, so the peer is held back by transport backpressure, such as
+    /// the TCP window. Since the last datagram read may cross the limit, up to `max` bytes plus one
+    /// datagram are buffered.
+    ///
+    /// The [`Stream`] implementation is pull-based: the reader only reads from the network stream
+    /// while it is polled, and yields each datagram as soon as it is complete, so nothing piles up
+    /// while the consumer is busy. Datagrams are only buffered when they are received out of turn,
+    /// or handed off to be consumed elsewhere:
+    ///
+    /// - [`peek`](ConnectionReader::peek) holds on to a single datagram.
+    /// - [`recv_stream`](ConnectionReader::recv_stream) keeps the other datagrams received while a
+    ///   stream is being read. Once they reach `max` bytes, the [`ChunkReader`] waits without
+    ///   reading any further, which stalls the stream until the [`ChunkReader`] is dropped and the
+    ///   buffered datagrams are yielded by the reader, so reading such a stream needs a timeout.
+    /// - [`into_channel`](ConnectionReader::into_channel) and [`TagRouter`] stop reading while the
+    ///   datagrams waiting in their channels reach `max` bytes, on top of their limits on the
+    ///   number of datagrams.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// reader.set_max_buffered_bytes(16 * 1024 * 1024);
+    /// ```
+    pub fn set_max_buffered_bytes(&mut self, max: usize) {
+        self.max_buffered_bytes = Some(max);
+    }
+
+    /// Get the total size of the datagrams that the reader has received but not yet yielded.
+    pub fn buffered_bytes(&self) -> usize {
+        self.deferred_bytes
+    }
+
+    /// Keeps a datagram received out of turn to be yielded later.
+    fn defer(&mut self, datagram: ConnectDatagram) {
+        self.deferred_bytes += datagram.serialized_size();
+        self.deferred.push_back(datagram);
+    }
+
+    /// Reads whatever the network stream has ready without waiting, keeping any datagrams received
+    /// to be yielded by the stream, and returns whether the stream is closed.
+    pub(crate) fn probe_closed(&mut self) -> bool {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        // yielding would be mistaken for the network stream having nothing ready
+        let poll_budget = self.poll_budget.take();
+        let mut closed = true;
+
+        while !self.closed {
+            // a full buffer only means that the peer is ahead of the consumer
+            if self.poll_buffer_room(&mut cx).is_pending() {
+                closed = false;
+                break;
+            }
+
+            match self.poll_received(&mut cx) {
+                Poll::Ready(Some(datagram)) => self.defer(datagram),
+                Poll::Ready(None) => break,
+                Poll::Pending => {
+                    closed = false;
+                    break;
+                }
+            }
+        }
+
+        self.poll_budget = poll_budget;
+        closed
+    }
+
+    /// Removes the datagram at `index` from the datagrams received out of turn.
+    fn take_deferred(&mut self, index: usize) -> Option<ConnectDatagram> {
+        let datagram = self.deferred.remove(index)?;
+        self.deferred_bytes -= datagram.serialized_size();
+
+        if !self.is_buffer_full() {
+            if let Some(waker) = self.buffer_waker.take() {
+                waker.wake();
+            }
+        }
+
+        Some(datagram)
+    }
+
+    /// Checks whether the datagrams received out of turn reached the limit set with
+    /// [`set_max_buffered_bytes`](ConnectionReader::set_max_buffered_bytes).
+    fn is_buffer_full(&self) -> bool {
+        self.max_buffered_bytes
+            .is_some_and(|max| !self.deferred.is_empty() && self.deferred_bytes >= max)
+    }
+
+    /// Waits until there is room to defer more datagrams, so that the reader stops reading from
+    /// the network stream while its buffer is full.
+    fn poll_buffer_room(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_buffer_full() {
+            trace!(
+                "buffered datagrams from {} reached {} bytes, waiting to read",
+                self.peer(),
+                self.deferred_bytes
+            );
+            self.buffer_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        Poll::Ready(())
+    }
+
+    pub(crate) fn set_slot(&mut self, slot: Arc<ConnectionSlot>) {
+        self.slot = Some(slot);
+    }
+
+    pub(crate) fn set_heartbeat(&mut self, heartbeat: ReaderHeartbeat) {
+        self.heartbeat = Some(heartbeat);
+    }
+
+    /// Sets whether the reader tries to find the next datagram after receiving bytes that cannot be
+    /// the start of a datagram, instead of closing the stream or misreading the bytes that follow.
+    ///
+    /// This keeps a best-effort feed alive if the byte stream ever desynchronizes, such as when a
+    /// buggy peer sends a wrong size-prefix. When the size-prefix and version of a datagram header
+    /// are implausible, the reader skips ahead one byte at a time until it finds a plausible header
+    /// and continues reading from there.
+    ///
+    /// Resynchronization is inherently heuristic:
+    ///
+    /// - garbage that happens to look like a datagram header is read as a datagram, which swallows
+    ///   the datagrams that follow it until its claimed size is reached, and is then usually
+    ///   skipped for failing to deserialize
+    /// - the skipped bytes are lost, so the application must tolerate missing datagrams
+    /// - a peer sending garbage can make the reader scan large amounts of data
+    ///
+    /// Disabled by default.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// reader.set_resync_on_error(true);
+    /// ```
+    pub fn set_resync_on_error(&mut self, enabled: bool) {
+        self.resync_on_error = enabled;
+    }
+
+    /// Deserializes the received datagrams with the provided version number using `handler`,
+    /// instead of as a datagram of the current protocol [`VERSION`](`crate::protocol::VERSION`).
+    ///
+    /// This allows experimenting with extended datagram formats that are framed by the usual
+    /// size-prefix and version fields. The handler receives the complete serialized datagram,
+    /// including its size-prefix, and returns the [`ConnectDatagram`] to yield, such as one created
+    /// with [`ConnectDatagram::with_version`]. Datagrams the handler fails to deserialize are logged
+    /// and skipped like any other malformed datagram. Setting a handler for a version replaces the
+    /// previous one, and datagrams of versions without a handler are deserialized as usual.
+    ///
+    /// Datagrams of other versions are never plausible headers when resynchronizing with
+    /// [`set_resync_on_error`](ConnectionReader::set_resync_on_error).
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// reader.set_version_handler(EXPERIMENTAL_VERSION, |buffer| {
+    ///     let (tag, data) = experimental::decode(&buffer)?;
+    ///     ConnectDatagram::with_version(EXPERIMENTAL_VERSION, tag, data)
+    /// });
+    /// ```
+    pub fn set_version_handler<F>(&mut self, version: u16, handler: F)
+    where
+        F: Fn(Vec<u8>) -> Result<ConnectDatagram, DatagramError> + Send + Sync + 'static,
+    {
+        self.version_handlers.insert(version, Box::new(handler));
+    }
+
+    /// Validates the sequence numbers of received datagrams, expecting the next one to be
+    /// `sequence`, to detect datagrams that were lost or repeated between the peer's writer and
+    /// this reader, such as across a reconnect.
+    ///
+    /// Each received datagram with a sequence number (see
+    /// [`ConnectionWriter::set_next_sequence`](`crate::ConnectionWriter::set_next_sequence`)) is
+    /// compared to the expected one. On a mismatch, a warning is logged and the connection's
+    /// [`ConnectionObserver::on_sequence_gap`] is called with a [`SequenceGap`]. Either way, the
+    /// datagram is still yielded and the reader expects the sequence number following it next.
+    /// Datagrams without a sequence number are not checked.
+    ///
+    /// Calling this again resets the expected sequence, such as to resume from a checkpoint after
+    /// reconnecting.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut conn = Connection::tcp_client(ip_address).await?.with_observer(gap_alerts);
+    /// conn.reader().set_expected_sequence(checkpoint.expected_sequence);
+    /// ```
+    pub fn set_expected_sequence(&mut self, sequence: u64) {
+        self.expected_sequence = Some(sequence);
+    }
+
+    /// Get the sequence number expected of the next received datagram, or `None` if sequence
+    /// numbers are not validated.
+    pub fn expected_sequence(&self) -> Option<u64> {
+        self.expected_sequence
+    }
+
+    /// Stops validating the sequence numbers enabled by
+    /// [`set_expected_sequence`](ConnectionReader::set_expected_sequence).
+    pub fn remove_sequencing(&mut self) {
+        self.expected_sequence = None;
+    }
+
+    /// Sets the byte order in which the header fields of received datagrams are expected to be
+    /// framed, which must match the [`ByteOrder`] the peer writes in.
+    ///
+    /// Datagrams are otherwise deserialized as usual, and the serialized datagram given to a
+    /// [version handler](ConnectionReader::set_version_handler) is already converted to
+    /// big-endian. A datagram whose version field only makes sense in the other byte order is
+    /// never misread: the reader logs an error, reports
+    /// [ByteOrderMismatch](`DatagramError::ByteOrderMismatch`) to the connection's
+    /// [`ConnectionObserver::on_read_error`], and closes the stream, since the size-prefix that
+    /// framed the datagram cannot be trusted either.
+    ///
+    /// Big-endian by default.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// reader.set_byte_order(ByteOrder::LittleEndian);
+    /// ```
+    pub fn set_byte_order(&mut self, byte_order: ByteOrder) {
+        self.byte_order = byte_order;
+
+        if let Some(framing) = self.framing_byte_order.as_ref() {
+            framing.set(byte_order);
+        }
+    }
+
+    /// Shares the byte order with the transport that frames the datagrams of the network stream.
+    pub(crate) fn share_byte_order(&mut self, framing: SharedByteOrder) {
+        framing.set(self.byte_order);
+        self.framing_byte_order = Some(framing);
+    }
+
+    /// Get the byte order in which the header fields of received datagrams are expected.
+    pub fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+
+    /// Sets how much the reader processes before it yields to the executor, so that a peer flooding
+    /// the connection cannot starve the other tasks of the executor.
+    ///
+    /// A reader whose network stream always has bytes ready never has to wait, so a loop awaiting
+    /// its datagrams would otherwise run without ever giving the executor back, which stalls every
+    /// other task on a single-threaded executor. Once the reader has processed `datagrams`
+    /// datagrams, or read `bytes` bytes, since it last had to wait, it wakes its task and returns
+    /// `Poll::Pending` once, like the cooperative budget of `tokio`. Datagrams the reader skips,
+    /// such as heartbeats and datagrams that cannot be deserialized, count towards the budget.
+    ///
+    /// Defaults to [`DEFAULT_POLL_BUDGET_DATAGRAMS`] datagrams and [`DEFAULT_POLL_BUDGET_BYTES`]
+    /// bytes.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// reader.set_poll_budget(32, 256 * 1024);
+    /// ```
+    pub fn set_poll_budget(&mut self, datagrams: usize, bytes: usize) {
+        self.poll_budget = Some(PollBudget::new(datagrams, bytes));
+    }
+
+    /// Stops yielding to the executor as set with
+    /// [`set_poll_budget`](ConnectionReader::set_poll_budget), such as for a reader that is the
+    /// only task of its executor.
+    pub fn remove_poll_budget(&mut self) {
+        self.poll_budget = None;
+    }
+
+    pub(crate) fn set_idle(&mut self, idle: ReaderIdle) {
+        self.idle = Some(idle);
+    }
+
+    pub(crate) fn set_observer(&mut self, observer: Arc<dyn ConnectionObserver>) {
+        self.observer = Some(observer);
+    }
+
+    pub(crate) fn close_stream(&mut self, reason: CloseReason) {
+        if self.closed {
+            return;
+        }
+
+        debug!(
+            "Closing the stream for connection with {}: {:?}",
+            self.peer(),
+            reason
+        );
+        self.buffer = Vec::new();
+        self.buffer_pos = 0;
+        self.buffer_len = 0;
+        self.size_prefix_len = 0;
         self.pending_datagram.take();
-        self.pending_read.take();
+        self.payload.take();
         self.closed = true;
+        self.close_reason = Some(reason);
     }
 }
 
-impl Stream for ConnectionReader {
-    type Item = ConnectDatagram;
+/// A datagram whose serialized bytes are still being read from the network stream.
+struct PendingDatagram {
+    /// Buffer of the full serialized size of the datagram, including the size-prefix.
+    buffer: Vec<u8>,
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        loop {
-            if let Some(size) = self.pending_datagram.take() {
-                if let Some(pending_buf) = self.pending_read.take() {
-                    if pending_buf.len() >= size {
-                        trace!("{} pending bytes is large enough to deserialize datagram of size {} bytes", pending_buf.len(), size);
-                        let mut data_buf = pending_buf;
-                        let pending_buf = data_buf.split_off(size);
-
-                        let datagram = ConnectDatagram::from_bytes_without_prefix(
-                            data_buf.as_ref(),
-                        )
-                        .expect(
-                            "could not construct ConnectDatagram from bytes despite explicit check",
-                        );
+    /// Number of bytes of `buffer` that have been read so far.
+    filled: usize,
 
-                        trace!(
-                            "deserialized message of size {} bytes",
-                            datagram.serialized_size()
-                        );
-                        return match datagram.version() {
-                            // do some special work based on version number if necessary
-                            _ => {
-                                if pending_buf.len() >= DATAGRAM_HEADER_BYTE_SIZE {
-                                    trace!("can deserialize size of next datagram from remaining {} pending bytes", pending_buf.len());
-
-                                    let mut size_buf = pending_buf;
-                                    let pending_buf = size_buf.split_off(SIZE_PREFIX_BYTE_SIZE);
-
-                                    let size = u32::from_be_bytes(
-                                        size_buf
-                                            .to_vec()
-                                            .as_slice()
-                                            .try_into()
-                                            .expect("could not parse bytes into u32"),
-                                    ) as usize;
-
-                                    trace!("removed size of next datagram from pending bytes ({}), leaving {} pending bytes remaining", size, pending_buf.len());
-                                    self.pending_datagram.replace(size);
-                                    self.pending_read.replace(pending_buf);
-                                } else {
-                                    trace!("cannot deserialize size of next datagram from remaining {} pending bytes", pending_buf.len());
-                                    self.pending_read.replace(pending_buf);
-                                }
-
-                                trace!("returning deserialized datagram to user");
-                                Poll::Ready(Some(datagram))
-                            }
-                        };
-                    } else {
-                        trace!("{} pending bytes is not large enough to deserialize datagram of size {} bytes", pending_buf.len(), size);
-                        self.pending_datagram.replace(size);
-                        self.pending_read.replace(pending_buf);
-                    }
-                } else {
-                    unreachable!()
-                }
-            }
+    /// Full serialized size of the datagram, including the size-prefix, which is larger than
+    /// `buffer` while only the header of a datagram that may be streamed is being read.
+    total: usize,
+}
 
-            let mut buffer = if let Some(buffer) = self.buffer.take() {
-                trace!("prepare buffer to read from the network stream");
-                buffer
-            } else {
-                trace!("construct new buffer to read from the network stream");
-                let mut buffer = BytesMut::with_capacity(BUFFER_SIZE);
-                buffer.resize(BUFFER_SIZE, 0);
-                buffer
-            };
+/// The message body of a datagram that is being streamed by a [`PayloadReader`].
+struct PayloadState {
+    tag: u16,
 
-            trace!("reading from the network stream");
-            let stream = self.read_stream.as_mut();
-            match stream.poll_read(cx, &mut buffer) {
-                Poll::Ready(Ok(bytes_read)) => {
-                    if bytes_read > 0 {
-                        trace!("read {} bytes from the network stream", bytes_read);
-                    } else {
-                        self.close_stream();
-                        return Poll::Ready(None);
-                    }
+    /// Full serialized size of the datagram, including the size-prefix.
+    serialized_size: usize,
 
-                    let mut pending_buf = if let Some(pending_buf) = self.pending_read.take() {
-                        trace!("preparing {} pending bytes", pending_buf.len());
-                        pending_buf
-                    } else {
-                        trace!("constructing new pending bytes");
-                        BytesMut::new()
-                    };
+    /// Number of bytes of the message body that have not been read yet.
+    remaining: usize,
 
-                    trace!(
-                        "prepending incomplete data ({} bytes) from earlier read of network stream",
-                        pending_buf.len()
-                    );
-                    pending_buf.extend_from_slice(&buffer[0..bytes_read]);
+    /// Whether a [`PayloadReader`] has been handed out for the message body, so that whatever it
+    /// leaves unread is discarded.
+    announced: bool,
 
-                    if self.pending_datagram.is_none() && pending_buf.len() >= SIZE_PREFIX_BYTE_SIZE
-                    {
-                        trace!(
-                            "can deserialize size of next datagram from remaining {} pending bytes",
-                            pending_buf.len()
-                        );
-                        let mut size_buf = pending_buf;
-                        let pending_buf = size_buf.split_off(SIZE_PREFIX_BYTE_SIZE);
-
-                        let size = u32::from_be_bytes(
-                            size_buf
-                                .to_vec()
-                                .as_slice()
-                                .try_into()
-                                .expect("could not parse bytes into u32"),
-                        ) as usize;
-
-                        trace!("removed size of next datagram from pending bytes ({}), leaving {} pending bytes remaining", size, pending_buf.len());
-                        self.pending_datagram.replace(size);
-                        self.pending_read.replace(pending_buf);
-                    } else {
-                        trace!("size of next datagram already deserialized");
-                        self.pending_read.replace(pending_buf);
-                    }
+    #[cfg(feature = "checksum")]
+    checksum: Option<u32>,
 
-                    trace!("finished reading from stream and storing buffer");
-                    self.buffer.replace(buffer);
+    #[cfg(feature = "checksum")]
+    crc: u32,
+}
+
+/// What the reader received from the network stream, see
+/// [`ConnectionReader::next_or_payload`].
+enum Received {
+    Datagram(ConnectDatagram),
+
+    /// The header of a datagram whose message body is streamed by a [`PayloadReader`].
+    Payload,
+}
+
+impl<R: AsyncRead + Unpin> ConnectionReader<R> {
+    /// Deserializes the pending datagram once all of its bytes have been read.
+    fn finish_datagram(&mut self) -> Option<ConnectDatagram> {
+        match self.pending_datagram.as_ref() {
+            Some(pending) if pending.filled == pending.buffer.len() => {}
+            _ => return None,
+        }
+
+        let mut pending = self.pending_datagram.take()?;
+        self.byte_order.convert_header(&mut pending.buffer);
+
+        if let Some(budget) = self.poll_budget.as_mut() {
+            budget.datagrams += 1;
+        }
+
+        let handler = serialized_version(&pending.buffer)
+            .and_then(|version| self.version_handlers.get(&version));
+
+        let res = match handler {
+            Some(handler) => handler(pending.buffer),
+            None => ConnectDatagram::from_buffer(pending.buffer),
+        };
+
+        match res {
+            Ok(datagram) => {
+                self.stats.messages_read += 1;
+                trace!(
+                    "deserialized message of size {} bytes",
+                    datagram.serialized_size()
+                );
+
+                if let Some(observer) = self.observer.as_ref() {
+                    observer.on_message_read(datagram.tag(), datagram.serialized_size());
                 }
 
-                Poll::Ready(Err(err)) => {
-                    error!(
-                        "Encountered error when trying to read from network stream {}",
-                        err
-                    );
-                    self.close_stream();
-                    return Poll::Ready(None);
+                self.check_sequence(&datagram);
+                Some(datagram)
+            }
+
+            Err(err) => {
+                warn!(
+                    "Could not deserialize datagram from {}: {}",
+                    self.peer(),
+                    err
+                );
+
+                if let Some(observer) = self.observer.as_ref() {
+                    observer.on_read_error(&err);
                 }
 
-                Poll::Pending => {
-                    self.buffer.replace(buffer);
-                    return Poll::Pending;
+                None
+            }
+        }
+    }
+
+    /// Compares the sequence number of a received datagram to the expected one, reporting any gap.
+    fn check_sequence(&mut self, datagram: &ConnectDatagram) {
+        let (expected, got) = match (self.expected_sequence, datagram.sequence()) {
+            (Some(expected), Some(got)) => (expected, got),
+            _ => return,
+        };
+
+        if got != expected {
+            warn!(
+                "Expected datagram with sequence number {} from {}, but received {}",
+                expected,
+                self.peer(),
+                got
+            );
+
+            if let Some(observer) = self.observer.as_ref() {
+                observer.on_sequence_gap(SequenceGap { expected, got });
+            }
+        }
+
+        self.expected_sequence = Some(got.wrapping_add(1));
+    }
+
+    /// Moves bytes from the read buffer into the size-prefix or the pending datagram, returning a
+    /// datagram once one is complete.
+    fn consume_buffer(&mut self) -> Option<ConnectDatagram> {
+        let available = &self.buffer[self.buffer_pos..self.buffer_len];
+
+        match self.pending_datagram.as_mut() {
+            Some(pending) => {
+                let len = available.len().min(pending.buffer.len() - pending.filled);
+
+                pending.buffer[pending.filled..pending.filled + len]
+                    .copy_from_slice(&available[..len]);
+                pending.filled += len;
+                self.buffer_pos += len;
+
+                self.verify_pending_header();
+                self.continue_streamed_header();
+                self.finish_datagram()
+            }
+
+            None => {
+                let len = available
+                    .len()
+                    .min(SIZE_PREFIX_BYTE_SIZE - self.size_prefix_len);
+
+                self.size_prefix[self.size_prefix_len..self.size_prefix_len + len]
+                    .copy_from_slice(&available[..len]);
+                self.size_prefix_len += len;
+                self.buffer_pos += len;
+
+                if self.size_prefix_len == SIZE_PREFIX_BYTE_SIZE {
+                    self.size_prefix_len = 0;
+                    self.start_datagram();
                 }
+
+                None
+            }
+        }
+    }
+
+    /// Allocates the pending datagram for the size-prefix that has just been read.
+    fn start_datagram(&mut self) {
+        let mut size_prefix = self.size_prefix;
+        self.byte_order.convert_header(&mut size_prefix);
+        let size = SIZE_PREFIX_BYTE_SIZE + u32::from_be_bytes(size_prefix) as usize;
+
+        if self.resync_on_error
+            && !(DATAGRAM_HEADER_BYTE_SIZE..=MAX_SERIALIZED_BYTE_SIZE).contains(&size)
+        {
+            let size_prefix = self.size_prefix;
+            self.resync(&size_prefix);
+            return;
+        }
+
+        if size > MAX_SERIALIZED_BYTE_SIZE {
+            error!(
+                "Received size-prefix of {} bytes from {}, which exceeds the maximum datagram size",
+                size,
+                self.peer()
+            );
+
+            if let Some(observer) = self.observer.as_ref() {
+                observer.on_read_error(&DatagramError::TooLargeMessage);
             }
+
+            self.close_stream(CloseReason::ProtocolError(DatagramError::TooLargeMessage));
+            return;
         }
+
+        // only the header is read until it tells whether the message body can be streamed
+        let len = match self.stream_threshold {
+            Some(threshold) if size >= DATAGRAM_HEADER_BYTE_SIZE.saturating_add(threshold) => {
+                DATAGRAM_HEADER_BYTE_SIZE
+            }
+            _ => size,
+        };
+
+        trace!("reading datagram of size {} bytes", size);
+        let mut buffer = match self.spare_buffer.take() {
+            Some(mut buffer) => {
+                buffer.clear();
+                buffer.resize(len, 0);
+                buffer
+            }
+            None => vec![0; len],
+        };
+        buffer[..SIZE_PREFIX_BYTE_SIZE].copy_from_slice(&self.size_prefix);
+
+        self.pending_datagram.replace(PendingDatagram {
+            buffer,
+            filled: SIZE_PREFIX_BYTE_SIZE,
+            total: size,
+        });
+    }
+
+    /// Once the header of a datagram that may be streamed has been read, either starts streaming
+    /// its message body or continues reading the rest of the datagram.
+    fn continue_streamed_header(&mut self) {
+        let mut header = match self.pending_datagram.as_ref() {
+            Some(pending)
+                if pending.filled == pending.buffer.len() && pending.filled < pending.total =>
+            {
+                pending.buffer.clone()
+            }
+
+            _ => return,
+        };
+        self.byte_order.convert_header(&mut header);
+
+        let has_handler = serialized_version(&header)
+            .is_some_and(|version| self.version_handlers.contains_key(&version));
+        let tag = serialized_tag(&header);
+        let is_heartbeat = self
+            .heartbeat
+            .as_ref()
+            .is_some_and(|heartbeat| heartbeat.tag() == tag);
+
+        let threshold = self.stream_threshold;
+        let pending = match self.pending_datagram.as_mut() {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        let data_offset = match (streamable_data_offset(&header), threshold) {
+            (Some(offset), Some(threshold))
+                if !has_handler
+                    && !is_heartbeat
+                    && pending.total >= offset.saturating_add(threshold) =>
+            {
+                offset
+            }
+
+            _ => {
+                pending.buffer.resize(pending.total, 0);
+                return;
+            }
+        };
+
+        if pending.buffer.len() < data_offset {
+            pending.buffer.resize(data_offset, 0);
+            return;
+        }
+
+        trace!(
+            "streaming message body of datagram of size {} bytes",
+            pending.total
+        );
+        self.payload = Some(PayloadState {
+            tag,
+            serialized_size: pending.total,
+            remaining: pending.total - data_offset,
+            announced: false,
+            #[cfg(feature = "checksum")]
+            checksum: crate::protocol::serialized_checksum(&header),
+            #[cfg(feature = "checksum")]
+            crc: 0,
+        });
+        self.pending_datagram = None;
+    }
+
+    /// Discards the unread message body of a streamed datagram whose [`PayloadReader`] was dropped,
+    /// returning whether all of it has been discarded.
+    fn discard_payload(&mut self) -> bool {
+        let payload = match self.payload.as_mut() {
+            Some(payload) => payload,
+            None => return true,
+        };
+
+        let len = payload.remaining.min(self.buffer_len - self.buffer_pos);
+        payload.remaining -= len;
+        self.buffer_pos += len;
+
+        if payload.remaining > 0 {
+            return false;
+        }
+
+        let tag = payload.tag;
+        debug!(
+            "Discarded the unread message body of datagram with tag {} from {}",
+            tag,
+            self.peer()
+        );
+        self.payload = None;
+        true
+    }
+
+    /// Finishes streaming a message body once all of it has been read, validating its checksum.
+    fn finish_payload(&mut self) -> std::io::Result<()> {
+        let payload = match self.payload.take() {
+            Some(payload) => payload,
+            None => return Ok(()),
+        };
+
+        #[cfg(feature = "checksum")]
+        if payload
+            .checksum
+            .is_some_and(|checksum| checksum != payload.crc)
+        {
+            let err = DatagramError::ChecksumMismatch;
+            warn!(
+                "Could not deserialize datagram from {}: {}",
+                self.peer(),
+                err
+            );
+
+            if let Some(observer) = self.observer.as_ref() {
+                observer.on_read_error(&err);
+            }
+
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err));
+        }
+
+        self.stats.messages_read += 1;
+        trace!("streamed message of size {} bytes", payload.serialized_size);
+
+        if let Some(observer) = self.observer.as_ref() {
+            observer.on_message_read(payload.tag, payload.serialized_size);
+        }
+
+        Ok(())
+    }
+
+    /// Records bytes read from the network stream.
+    fn received_bytes(&mut self, bytes_read: usize) {
+        trace!("read {} bytes from the network stream", bytes_read);
+        self.stats.bytes_read += bytes_read as u64;
+
+        if let Some(budget) = self.poll_budget.as_mut() {
+            budget.bytes += bytes_read;
+        }
+
+        if let Some(heartbeat) = self.heartbeat.as_mut() {
+            heartbeat.received();
+        }
+    }
+
+    /// Closes the stream after failing to read from the network stream.
+    fn read_failed(&mut self, err: std::io::Error) {
+        error!(
+            "Encountered error when trying to read from network stream {}",
+            err
+        );
+
+        if let Some(observer) = self.observer.as_ref() {
+            observer.on_read_error(&err);
+        }
+
+        self.close_stream(CloseReason::IoError(err));
+    }
+
+    /// Closes the stream if enough of the pending datagram has been read to tell that it was framed
+    /// in the other byte order, or discards it if resynchronizing on errors and it does not start
+    /// with a plausible datagram header.
+    fn verify_pending_header(&mut self) {
+        let mut header = [0; HEADER_PROBE_BYTE_SIZE];
+
+        match self.pending_datagram.as_ref() {
+            Some(pending) if pending.filled >= HEADER_PROBE_BYTE_SIZE => {
+                header.copy_from_slice(&pending.buffer[..HEADER_PROBE_BYTE_SIZE]);
+                self.byte_order.convert_header(&mut header);
+            }
+
+            _ => return,
+        }
+
+        let has_handler = serialized_version(&header)
+            .is_some_and(|version| self.version_handlers.contains_key(&version));
+
+        if has_swapped_version(&header) && !has_handler {
+            error!(
+                "Received datagram header from {} in the other byte order than {:?}",
+                self.peer(),
+                self.byte_order
+            );
+
+            if let Some(observer) = self.observer.as_ref() {
+                observer.on_read_error(&DatagramError::ByteOrderMismatch);
+            }
+
+            self.close_stream(CloseReason::ProtocolError(DatagramError::ByteOrderMismatch));
+            return;
+        }
+
+        if !self.resync_on_error {
+            return;
+        }
+
+        if is_plausible_header(&header) {
+            if self.resync_skipped > 0 {
+                info!(
+                    "Resynchronized with {} after skipping {} bytes",
+                    self.peer(),
+                    self.resync_skipped
+                );
+                self.resync_skipped = 0;
+            }
+
+            return;
+        }
+
+        if let Some(pending) = self.pending_datagram.take() {
+            self.resync(&pending.buffer[..pending.filled]);
+        }
+    }
+
+    /// Skips the first byte of a frame that does not start with a plausible datagram header, and
+    /// puts the rest of its bytes back to be searched for the next datagram header.
+    fn resync(&mut self, frame: &[u8]) {
+        if self.resync_skipped == 0 {
+            warn!(
+                "Received bytes from {} that do not start a datagram, searching for the next datagram",
+                self.peer()
+            );
+        }
+        self.resync_skipped += 1;
+
+        self.unread(&frame[1..]);
+    }
+
+    /// Puts bytes back in front of the buffered bytes, so that they are read before anything else
+    /// received from the network stream.
+    pub(crate) fn unread(&mut self, unread: &[u8]) {
+        if self.buffer_pos >= unread.len() {
+            self.buffer_pos -= unread.len();
+            self.buffer[self.buffer_pos..self.buffer_pos + unread.len()].copy_from_slice(unread);
+        } else {
+            let mut buffer = Vec::with_capacity(BUFFER_SIZE.max(unread.len() + self.buffer_len));
+            buffer.extend_from_slice(unread);
+            buffer.extend_from_slice(&self.buffer[self.buffer_pos..self.buffer_len]);
+
+            self.buffer_pos = 0;
+            self.buffer_len = buffer.len();
+            buffer.resize(buffer.capacity(), 0);
+            self.buffer = buffer;
+        }
+    }
+
+    fn poll_next_datagram(&mut self, cx: &mut Context<'_>) -> Poll<Option<Received>> {
+        loop {
+            if let Some(payload) = self.payload.as_mut() {
+                if !payload.announced {
+                    payload.announced = true;
+
+                    if self.stream_threshold.is_some() {
+                        return Poll::Ready(Some(Received::Payload));
+                    }
+                }
+            }
+
+            if let Some(budget) = self.poll_budget.as_mut() {
+                if budget.is_exhausted() {
+                    trace!("yielding to the executor after exhausting the poll budget");
+                    budget.reset();
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+            }
+
+            if self.discard_payload() {
+                while self.buffer_pos < self.buffer_len {
+                    if let Some(datagram) = self.consume_buffer() {
+                        return Poll::Ready(Some(Received::Datagram(datagram)));
+                    }
+
+                    if self.payload.is_some() {
+                        break;
+                    }
+                }
+
+                // a message body that has just started streaming is announced before reading on
+                if self.payload.is_some() {
+                    continue;
+                }
+            }
+
+            if self.closed {
+                return Poll::Ready(None);
+            }
+
+            // large datagrams are read directly into their own buffer to avoid copying them
+            let res = match self.pending_datagram.as_mut() {
+                Some(pending) if pending.buffer.len() - pending.filled >= BUFFER_SIZE => {
+                    trace!("reading from the network stream into pending datagram");
+                    Pin::new(&mut self.read_stream)
+                        .poll_read(cx, &mut pending.buffer[pending.filled..])
+                        .map_ok(|bytes_read| {
+                            pending.filled += bytes_read;
+                            (bytes_read, true)
+                        })
+                }
+
+                _ => {
+                    trace!("reading from the network stream");
+                    Pin::new(&mut self.read_stream)
+                        .poll_read(cx, &mut self.buffer)
+                        .map_ok(|bytes_read| (bytes_read, false))
+                }
+            };
+
+            match res {
+                // the buffered bytes were consumed above, so only an incomplete datagram is lost
+                Poll::Ready(Ok((0, _))) => {
+                    let partial = self.size_prefix_len
+                        + self
+                            .pending_datagram
+                            .as_ref()
+                            .map_or(0, |pending| pending.filled);
+
+                    if partial > 0 {
+                        warn!(
+                            "Connection with {} was closed while receiving a datagram, discarding {} bytes",
+                            self.peer(),
+                            partial
+                        );
+                    }
+
+                    self.close_stream(CloseReason::PeerClosed);
+                    return Poll::Ready(None);
+                }
+
+                Poll::Ready(Ok((bytes_read, direct))) => {
+                    self.received_bytes(bytes_read);
+
+                    if direct {
+                        self.verify_pending_header();
+
+                        if let Some(datagram) = self.finish_datagram() {
+                            return Poll::Ready(Some(Received::Datagram(datagram)));
+                        }
+                    } else {
+                        self.buffer_pos = 0;
+                        self.buffer_len = bytes_read;
+                    }
+                }
+
+                Poll::Ready(Err(err)) => {
+                    self.read_failed(err);
+                    return Poll::Ready(None);
+                }
+
+                Poll::Pending => {
+                    if let Some(budget) = self.poll_budget.as_mut() {
+                        budget.reset();
+                    }
+
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> ConnectionReader<R> {
+    /// Receives the next datagram from the network stream, handling and skipping heartbeats.
+    fn poll_received(&mut self, cx: &mut Context<'_>) -> Poll<Option<ConnectDatagram>> {
+        self.poll_incoming(cx).map(|received| match received? {
+            Received::Datagram(datagram) => Some(datagram),
+            Received::Payload => {
+                unreachable!("message bodies are only streamed by next_or_payload")
+            }
+        })
+    }
+
+    /// Receives the next datagram or streamed message body from the network stream, handling and
+    /// skipping heartbeats.
+    fn poll_incoming(&mut self, cx: &mut Context<'_>) -> Poll<Option<Received>> {
+        #[cfg(feature = "tracing")]
+        let _span = self.span.clone().entered();
+
+        if self
+            .slot
+            .as_ref()
+            .is_some_and(|slot| slot.poll_reader_closed(cx))
+        {
+            if !self.closed {
+                debug!("Connection with {} was closed by its listener", self.peer());
+            }
+
+            self.close_stream(CloseReason::LocalShutdown);
+            return Poll::Ready(None);
+        }
+
+        loop {
+            match self.poll_next_datagram(cx) {
+                Poll::Ready(Some(received)) => {
+                    if let Some(idle) = self.idle.as_ref() {
+                        idle.touch();
+                    }
+
+                    if let (Some(heartbeat), Received::Datagram(datagram)) =
+                        (self.heartbeat.as_mut(), &received)
+                    {
+                        if heartbeat.handle(datagram) {
+                            trace!("received heartbeat from {}", self.peer());
+                            continue;
+                        }
+                    }
+
+                    return Poll::Ready(Some(received));
+                }
+
+                Poll::Ready(None) => return Poll::Ready(None),
+
+                Poll::Pending => {
+                    if let Some(heartbeat) = self.heartbeat.as_mut() {
+                        if heartbeat.poll_expired(cx) {
+                            warn!(
+                                "No heartbeat received from {} before the timeout, closing the connection",
+                                self.peer()
+                            );
+                            self.close_stream(CloseReason::Timeout);
+                            return Poll::Ready(None);
+                        }
+                    }
+
+                    if let Some(idle) = self.idle.as_mut() {
+                        if idle.poll_expired(cx) {
+                            warn!(
+                                "Connection with {} has been idle for too long, closing the connection",
+                                self.peer()
+                            );
+                            self.close_stream(CloseReason::Timeout);
+                            return Poll::Ready(None);
+                        }
+                    }
+
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+
+    /// Receives the next chunk of the stream with the provided tag, deferring any other datagrams
+    /// received in the meantime.
+    ///
+    /// Stops reading while the deferred datagrams are at the limit set with
+    /// [`set_max_buffered_bytes`](ConnectionReader::set_max_buffered_bytes).
+    fn poll_stream_chunk(
+        &mut self,
+        tag: u16,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<Option<ConnectDatagram>>> {
+        let is_chunk =
+            |datagram: &ConnectDatagram| datagram.is_stream_chunk() && datagram.tag() == tag;
+
+        if let Some(index) = self.deferred.iter().position(is_chunk) {
+            return Poll::Ready(Ok(self.take_deferred(index)));
+        }
+
+        loop {
+            if self.poll_buffer_room(cx).is_pending() {
+                return Poll::Pending;
+            }
+
+            match self.poll_received(cx) {
+                Poll::Ready(Some(datagram)) if is_chunk(&datagram) => {
+                    return Poll::Ready(Ok(Some(datagram)))
+                }
+
+                Poll::Ready(Some(datagram)) => {
+                    trace!(
+                        "deferring datagram with tag {} received while reading stream",
+                        datagram.tag()
+                    );
+                    self.defer(datagram);
+                }
+
+                Poll::Ready(None) => return Poll::Ready(Ok(None)),
+
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for ConnectionReader<R> {
+    type Item = ConnectDatagram;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(datagram) = self.take_deferred(0) {
+            return Poll::Ready(Some(datagram));
+        }
+
+        self.poll_received(cx)
+    }
+}
+
+/// Reads back a stream sent by the peer with
+/// [`ConnectionWriter::send_stream`](`crate::ConnectionWriter::send_stream`), reassembling its
+/// chunks in order.
+///
+/// Constructed with [`ConnectionReader::recv_stream`]. Reading ends once the empty chunk that
+/// marks the end of the stream is received, and fails with
+/// [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof) if the connection closes before then.
+///
+/// Dropping a [`ChunkReader`] before the stream ends discards the rest of the chunk it was
+/// reading, and the remaining chunks are yielded by the [`ConnectionReader`] as datagrams.
+///
+pub struct ChunkReader<'a, R: AsyncRead + Unpin = BoxedReadStream> {
+    reader: &'a mut ConnectionReader<R>,
+    tag: u16,
+    chunk: Bytes,
+    finished: bool,
+}
+
+impl<R: AsyncRead + Unpin> ChunkReader<'_, R> {
+    /// Get the tag of the stream being read.
+    pub fn tag(&self) -> u16 {
+        self.tag
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ChunkReader<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        loop {
+            if !self.chunk.is_empty() {
+                let len = buf.len().min(self.chunk.len());
+                buf[..len].copy_from_slice(&self.chunk[..len]);
+                self.chunk.advance(len);
+
+                return Poll::Ready(Ok(len));
+            }
+
+            if self.finished {
+                return Poll::Ready(Ok(0));
+            }
+
+            let tag = self.tag;
+            match self.reader.poll_stream_chunk(tag, cx)? {
+                Poll::Ready(Some(datagram)) => {
+                    if datagram.data_size() == 0 {
+                        trace!("received end of stream with tag {}", tag);
+                        self.finished = true;
+                    } else {
+                        self.chunk = datagram.into_data_bytes();
+                    }
+                }
+
+                Poll::Ready(None) => {
+                    return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()));
+                }
+
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Reads the message body of a large datagram as it arrives from the network stream.
+///
+/// Constructed with [`ConnectionReader::next_or_payload`]. Reading ends once the whole message body
+/// has been read, and fails with [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof) if the
+/// connection closes before then. With the `checksum` feature, the checksum of the datagram is
+/// validated once the whole message body has been read, failing with
+/// [`InvalidData`](std::io::ErrorKind::InvalidData) if it does not match, so the bytes already
+/// read should not be trusted until reading ends.
+///
+/// Dropping a [`PayloadReader`] before reading the whole message body discards the rest of it.
+///
+pub struct PayloadReader<'a, R: AsyncRead + Unpin = BoxedReadStream> {
+    reader: &'a mut ConnectionReader<R>,
+    tag: u16,
+    data_size: usize,
+    finished: bool,
+}
+
+impl<R: AsyncRead + Unpin> PayloadReader<'_, R> {
+    /// Get the tag of the datagram.
+    pub fn tag(&self) -> u16 {
+        self.tag
+    }
+
+    /// Get the size of the message body in bytes.
+    pub fn data_size(&self) -> usize {
+        self.data_size
+    }
+
+    /// Get the number of bytes of the message body that have not been read yet.
+    pub fn remaining(&self) -> usize {
+        match self.reader.payload.as_ref() {
+            Some(payload) if !self.finished => payload.remaining,
+            _ => 0,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for PayloadReader<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if self.finished {
+            return Poll::Ready(Ok(0));
+        }
+
+        let reader = &mut *self.reader;
+        let remaining = match reader.payload.as_ref() {
+            Some(payload) => payload.remaining,
+            None => return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into())),
+        };
+
+        if remaining == 0 {
+            let res = reader.finish_payload();
+            self.finished = true;
+
+            return Poll::Ready(res.map(|_| 0));
+        }
+
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let len = buf.len().min(remaining);
+        let bytes_read = if reader.buffer_pos < reader.buffer_len {
+            let len = len.min(reader.buffer_len - reader.buffer_pos);
+            buf[..len].copy_from_slice(&reader.buffer[reader.buffer_pos..reader.buffer_pos + len]);
+            reader.buffer_pos += len;
+
+            len
+        } else {
+            trace!("reading from the network stream into message body");
+            match futures::ready!(Pin::new(&mut reader.read_stream).poll_read(cx, &mut buf[..len]))
+            {
+                Ok(0) => {
+                    warn!(
+                        "Connection with {} was closed while receiving a message body, {} bytes short",
+                        reader.peer(),
+                        remaining
+                    );
+
+                    reader.close_stream(CloseReason::PeerClosed);
+                    return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()));
+                }
+
+                Ok(bytes_read) => {
+                    reader.received_bytes(bytes_read);
+                    bytes_read
+                }
+
+                Err(err) => {
+                    let kind = err.kind();
+                    reader.read_failed(err);
+
+                    return Poll::Ready(Err(kind.into()));
+                }
+            }
+        };
+
+        if let Some(payload) = reader.payload.as_mut() {
+            payload.remaining -= bytes_read;
+
+            #[cfg(feature = "checksum")]
+            if payload.checksum.is_some() {
+                payload.crc = crc32c::crc32c_append(payload.crc, &buf[..bytes_read]);
+            }
+        }
+
+        Poll::Ready(Ok(bytes_read))
+    }
+}
+
+/// A [`ConnectionReader`] that duplicates every received datagram to a secondary [`Sink`].
+///
+/// Implements the `Stream` trait to yield the same datagrams as the wrapped [`ConnectionReader`].
+///
+/// A datagram is only yielded to the consumer after the previous copy has been accepted by the
+/// secondary sink, so a slow secondary sink slows down the consumer as well. If the secondary sink
+/// returns an error, it is logged and dropped, and the reader continues without it. The secondary
+/// sink is closed once the [`ConnectionReader`] stream ends.
+///
+/// Constructed with [`ConnectionReader::tee`].
+///
+pub struct TeeReader<S, R: AsyncRead + Unpin = BoxedReadStream> {
+    reader: ConnectionReader<R>,
+    sink: Option<S>,
+    pending: Option<ConnectDatagram>,
+}
+
+impl<S, R: AsyncRead + Unpin> TeeReader<S, R>
+where
+    S: Sink<ConnectDatagram> + Unpin,
+    S::Error: Display,
+{
+    /// Get a reference to the underlying [`ConnectionReader`].
+    pub fn get_ref(&self) -> &ConnectionReader<R> {
+        &self.reader
+    }
+
+    fn drop_sink(&mut self, err: S::Error) -> Poll<()> {
+        warn!(
+            "Encountered error when forwarding datagram from {} to tee sink, no longer forwarding: {}",
+            self.reader.peer(), err
+        );
+
+        self.sink.take();
+        self.pending.take();
+        Poll::Ready(())
+    }
+
+    /// Forwards the pending datagram copy to the secondary sink and flushes it.
+    fn poll_tee(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let sink = match self.sink.as_mut() {
+            Some(sink) => sink,
+            None => return Poll::Ready(()),
+        };
+
+        if let Some(datagram) = self.pending.take() {
+            match Pin::new(&mut *sink).poll_ready(cx) {
+                Poll::Pending => {
+                    self.pending.replace(datagram);
+                    return Poll::Pending;
+                }
+
+                Poll::Ready(Ok(())) => {
+                    if let Err(err) = Pin::new(&mut *sink).start_send(datagram) {
+                        return self.drop_sink(err);
+                    }
+                }
+
+                Poll::Ready(Err(err)) => return self.drop_sink(err),
+            }
+        }
+
+        match Pin::new(sink).poll_flush(cx) {
+            Poll::Ready(Err(err)) => self.drop_sink(err),
+            poll => poll.map(|_| ()),
+        }
+    }
+}
+
+impl<S, R: AsyncRead + Unpin> Stream for TeeReader<S, R>
+where
+    S: Sink<ConnectDatagram> + Unpin,
+    S::Error: Display,
+{
+    type Item = ConnectDatagram;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.poll_tee(cx).is_pending() && self.pending.is_some() {
+            trace!("waiting for tee sink to accept previous datagram");
+            return Poll::Pending;
+        }
+
+        match Pin::new(&mut self.reader).poll_next(cx) {
+            Poll::Ready(Some(datagram)) => {
+                if self.sink.is_some() {
+                    self.pending.replace(datagram.clone());
+                    let _ = self.poll_tee(cx);
+                }
+
+                Poll::Ready(Some(datagram))
+            }
+
+            Poll::Ready(None) => {
+                if self.poll_tee(cx).is_pending() {
+                    return Poll::Pending;
+                }
+
+                if let Some(sink) = self.sink.as_mut() {
+                    match Pin::new(sink).poll_close(cx) {
+                        Poll::Pending => return Poll::Pending,
+
+                        Poll::Ready(Err(err)) => {
+                            let _ = self.drop_sink(err);
+                        }
+
+                        Poll::Ready(Ok(())) => {
+                            self.sink.take();
+                        }
+                    }
+                }
+
+                Poll::Ready(None)
+            }
+
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// The number of datagrams buffered for a [`TagSubscriber`] before the [`TagRouter`] stops reading
+/// from the network stream.
+const SUBSCRIBER_BUFFER_SIZE: usize = 32;
+
+/// A [`ConnectionReader`] that fans out received datagrams to per-tag [`TagSubscriber`] streams.
+///
+/// Implements the `Stream` trait to yield the datagrams whose tag has no subscriber, which makes
+/// the router itself the default destination for unmatched tags. The underlying
+/// [`ConnectionReader`] is only read while the router is polled, so the router must be driven
+/// (for example by spawning a task that consumes it) for subscribers to receive anything.
+///
+/// Each subscriber buffers up to 32 datagrams. Once the buffer of a slow subscriber is full, or
+/// the datagrams buffered for all subscribers reach the limit set with
+/// [`ConnectionReader::set_max_buffered_bytes`], the router stops reading from the network stream
+/// until the subscribers catch up, which delays the datagrams of every other tag as well. Datagrams for a subscriber that has been dropped are
+/// yielded by the router as unmatched. All subscriber streams end once the [`ConnectionReader`]
+/// stream ends.
+///
+/// Constructed with [`ConnectionReader::split_by_tag`].
+///
+pub struct TagRouter<R: AsyncRead + Unpin = BoxedReadStream> {
+    reader: ConnectionReader<R>,
+    subscribers: HashMap<u16, mpsc::Sender<ConnectDatagram>>,
+    pending: Option<ConnectDatagram>,
+    budget: Option<Arc<BufferBudget>>,
+}
+
+impl<R: AsyncRead + Unpin> TagRouter<R> {
+    /// Get a reference to the underlying [`ConnectionReader`].
+    pub fn get_ref(&self) -> &ConnectionReader<R> {
+        &self.reader
+    }
+
+    /// Creates a stream of the received datagrams with the provided tag.
+    ///
+    /// Subscribing to a tag that already has a subscriber replaces it, ending the stream of the
+    /// previous subscriber.
+    pub fn subscribe(&mut self, tag: u16) -> TagSubscriber {
+        let (sender, receiver) = mpsc::channel(SUBSCRIBER_BUFFER_SIZE);
+        self.subscribers.insert(tag, sender);
+
+        TagSubscriber {
+            tag,
+            receiver: DatagramReceiver {
+                receiver,
+                budget: self.budget.clone(),
+            },
+        }
+    }
+
+    /// Forwards the pending datagram to the subscriber of its tag, returning it instead if there
+    /// is no subscriber.
+    fn poll_route(&mut self, cx: &mut Context<'_>) -> Poll<Option<ConnectDatagram>> {
+        let datagram = match self.pending.take() {
+            Some(datagram) => datagram,
+            None => return Poll::Ready(None),
+        };
+
+        let tag = datagram.tag();
+        let subscriber = match self.subscribers.get_mut(&tag) {
+            Some(subscriber) => subscriber,
+            None => return Poll::Ready(Some(datagram)),
+        };
+
+        match subscriber.poll_ready(cx) {
+            Poll::Pending => {
+                trace!("waiting for subscriber of tag {} to accept datagram", tag);
+                self.pending.replace(datagram);
+                Poll::Pending
+            }
+
+            Poll::Ready(Ok(())) => {
+                let size = datagram.serialized_size();
+
+                match subscriber.try_send(datagram) {
+                    Ok(()) => {
+                        if let Some(budget) = self.budget.as_ref() {
+                            budget.reserve(size);
+                        }
+
+                        Poll::Ready(None)
+                    }
+
+                    Err(err) => {
+                        debug!("Subscriber of tag {} was dropped", tag);
+                        self.subscribers.remove(&tag);
+                        Poll::Ready(Some(err.into_inner()))
+                    }
+                }
+            }
+
+            Poll::Ready(Err(_)) => {
+                debug!("Subscriber of tag {} was dropped", tag);
+                self.subscribers.remove(&tag);
+                Poll::Ready(Some(datagram))
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for TagRouter<R> {
+    type Item = ConnectDatagram;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match self.poll_route(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(datagram)) => return Poll::Ready(Some(datagram)),
+                Poll::Ready(None) => {}
+            }
+
+            if let Some(budget) = self.budget.as_ref() {
+                if budget.poll_room(cx).is_pending() {
+                    trace!("subscribers are at the buffered bytes limit, waiting to read");
+                    return Poll::Pending;
+                }
+            }
+
+            match Pin::new(&mut self.reader).poll_next(cx) {
+                Poll::Ready(Some(datagram)) => {
+                    self.pending.replace(datagram);
+                }
+
+                Poll::Ready(None) => {
+                    self.subscribers.clear();
+                    return Poll::Ready(None);
+                }
+
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// A stream of the datagrams with a single tag received by a [`TagRouter`].
+///
+/// Constructed with [`TagRouter::subscribe`].
+///
+pub struct TagSubscriber {
+    tag: u16,
+    receiver: DatagramReceiver,
+}
+
+impl TagSubscriber {
+    /// Get the tag of the datagrams yielded by this stream.
+    pub fn tag(&self) -> u16 {
+        self.tag
+    }
+}
+
+impl Stream for TagSubscriber {
+    type Item = ConnectDatagram;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+/// The total size of the datagrams that a reader handed off to channels but that have not been
+/// received from them yet, capped at the limit set with
+/// [`ConnectionReader::set_max_buffered_bytes`].
+struct BufferBudget {
+    max: usize,
+    state: Mutex<BufferBudgetState>,
+}
+
+#[derive(Default)]
+struct BufferBudgetState {
+    used: usize,
+    waker: Option<Waker>,
+}
+
+impl BufferBudget {
+    fn new(max: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max,
+            state: Mutex::new(BufferBudgetState::default()),
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BufferBudgetState> {
+        self.state.lock().expect("buffer budget lock is poisoned")
+    }
+
+    /// Waits until the handed off datagrams are below the limit, or there are none at all.
+    fn poll_room(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.lock();
+
+        if state.used == 0 || state.used < self.max {
+            Poll::Ready(())
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    fn reserve(&self, bytes: usize) {
+        self.lock().used += bytes;
+    }
+
+    fn release(&self, bytes: usize) {
+        let waker = {
+            let mut state = self.lock();
+            state.used -= bytes;
+
+            if state.used < self.max {
+                state.waker.take()
+            } else {
+                None
+            }
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// A stream of the datagrams forwarded into a channel by a [`ConnectionReader`].
+///
+/// Constructed with [`ConnectionReader::into_channel`].
+///
+pub struct DatagramReceiver {
+    receiver: mpsc::Receiver<ConnectDatagram>,
+    budget: Option<Arc<BufferBudget>>,
+}
+
+impl DatagramReceiver {
+    /// Closes the channel, so that no more datagrams are forwarded into it, while the datagrams
+    /// already in the channel can still be received.
+    pub fn close(&mut self) {
+        self.receiver.close();
+    }
+}
+
+impl Stream for DatagramReceiver {
+    type Item = ConnectDatagram;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let datagram = futures::ready!(Pin::new(&mut self.receiver).poll_next(cx));
+
+        if let (Some(datagram), Some(budget)) = (datagram.as_ref(), self.budget.as_ref()) {
+            budget.release(datagram.serialized_size());
+        }
+
+        Poll::Ready(datagram)
+    }
+}
+
+impl Drop for DatagramReceiver {
+    fn drop(&mut self) {
+        // the datagrams left in the channel no longer count against the limit
+        if let Some(budget) = self.budget.as_ref() {
+            self.receiver.close();
+
+            while let Ok(datagram) = self.receiver.try_recv() {
+                budget.release(datagram.serialized_size());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CloseReason, NextPayload, NextResult};
+    use crate::{
+        ByteOrder, ConnectDatagram, ConnectionObserver, ConnectionReader, ConnectionWriter,
+        DatagramError, DATAGRAM_HEADER_BYTE_SIZE,
+    };
+    use async_std::net::{SocketAddr, TcpListener, TcpStream};
+    use futures::channel::mpsc;
+    use futures::executor::LocalPool;
+    use futures::io::Cursor;
+    use futures::task::{Context, LocalSpawnExt, Poll};
+    use futures::{
+        AsyncRead, AsyncReadExt, AsyncWriteExt, FutureExt, SinkExt, StreamExt, TryStreamExt,
+    };
+    use std::cell::Cell;
+    use std::pin::Pin;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn test_addr() -> SocketAddr {
+        "127.0.0.1:0".parse().unwrap()
+    }
+
+    fn reader_over(datagrams: &[ConnectDatagram]) -> ConnectionReader {
+        let bytes: Vec<u8> = datagrams
+            .iter()
+            .flat_map(|d| d.clone().into_bytes())
+            .collect();
+
+        ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)))
+    }
+
+    #[async_std::test]
+    async fn tee_to_secondary_sink() -> anyhow::Result<()> {
+        let datagrams = vec![
+            ConnectDatagram::with_tag(1, vec![1])?,
+            ConnectDatagram::with_tag(2, vec![2, 2])?,
+            ConnectDatagram::with_tag(3, vec![3, 3, 3])?,
+        ];
+
+        let (tx, rx) = mpsc::unbounded();
+        let received: Vec<ConnectDatagram> = reader_over(&datagrams).tee(tx).collect().await;
+        let teed: Vec<ConnectDatagram> = rx.collect().await;
+
+        assert_eq!(datagrams.len(), received.len());
+        assert_eq!(datagrams.len(), teed.len());
+        for ((original, received), teed) in datagrams.iter().zip(&received).zip(&teed) {
+            assert_eq!(original.tag(), received.tag());
+            assert_eq!(original.data(), received.data());
+            assert_eq!(original.tag(), teed.tag());
+            assert_eq!(original.data(), teed.data());
+        }
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn oversized_size_prefix_closes_stream() -> anyhow::Result<()> {
+        let bytes = u32::MAX.to_be_bytes().to_vec();
+        let mut reader =
+            ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)));
+
+        assert!(reader.next().await.is_none());
+        assert!(reader.is_closed());
+        assert!(matches!(
+            reader.close_reason(),
+            Some(CloseReason::ProtocolError(DatagramError::TooLargeMessage))
+        ));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn byte_order_mismatch_closes_stream() -> anyhow::Result<()> {
+        // a size-prefix that is plausible in either byte order
+        let datagrams = vec![ConnectDatagram::with_tag(1, vec![0; 252])?];
+
+        let mut reader = reader_over(&datagrams);
+        reader.set_byte_order(ByteOrder::LittleEndian);
+
+        assert!(reader.next().await.is_none());
+        assert!(matches!(
+            reader.close_reason(),
+            Some(CloseReason::ProtocolError(DatagramError::ByteOrderMismatch))
+        ));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn cancelled_next_keeps_partial_datagram() -> anyhow::Result<()> {
+        let datagrams = vec![
+            ConnectDatagram::with_tag(1, vec![1; 10])?,
+            ConnectDatagram::with_tag(2, vec![2; 10])?,
+        ];
+        let bytes: Vec<u8> = datagrams
+            .iter()
+            .flat_map(|d| d.clone().into_bytes())
+            .collect();
+
+        let (tx, rx) = mpsc::unbounded();
+        let mut reader =
+            ConnectionReader::from_stream(test_addr(), test_addr(), rx.into_async_read());
+
+        // poll once per chunk of bytes and drop the future, as a losing select! branch would
+        let mut received = Vec::new();
+        for chunk in bytes.chunks(3) {
+            tx.unbounded_send(Ok(chunk.to_vec()))?;
+
+            if let Some(datagram) = reader.next().now_or_never() {
+                received.push(datagram.expect("connection closed"));
+            }
+        }
+
+        drop(tx);
+        assert!(reader.next().await.is_none());
+        assert_eq!(datagrams, received);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn version_handler() -> anyhow::Result<()> {
+        let datagrams = vec![
+            ConnectDatagram::with_tag(1, vec![1])?,
+            ConnectDatagram::with_version(2, 2, vec![1, 2])?,
+        ];
+
+        let mut reader = reader_over(&datagrams);
+        reader.set_version_handler(2, |buffer| {
+            // an experimental format that reverses the message body
+            let datagram = ConnectDatagram::from_bytes(&buffer)?;
+            let data = datagram.data().iter().rev().copied().collect();
+            ConnectDatagram::with_version(2, datagram.tag() + 1, data)
+        });
+
+        let received: Vec<ConnectDatagram> = reader.collect().await;
+        assert_eq!(datagrams[0], received[0]);
+        assert_eq!(2, received[1].version());
+        assert_eq!(3, received[1].tag());
+        assert_eq!(&[2, 1], received[1].data());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn close_reasons() -> anyhow::Result<()> {
+        let datagrams = vec![ConnectDatagram::with_tag(1, vec![1])?];
+
+        let mut reader = reader_over(&datagrams);
+        assert!(reader.close_reason().is_none());
+        assert_eq!(1, reader.by_ref().count().await);
+        assert!(matches!(
+            reader.close_reason(),
+            Some(CloseReason::PeerClosed)
+        ));
+
+        let mut reader = reader_over(&datagrams);
+        reader.close();
+        assert!(reader.next().await.is_none());
+        assert!(matches!(
+            reader.close_reason(),
+            Some(CloseReason::LocalShutdown)
+        ));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn streams_large_payloads() -> anyhow::Result<()> {
+        let (tx, rx) = mpsc::unbounded::<std::io::Result<Vec<u8>>>();
+        let mut reader =
+            ConnectionReader::from_stream(test_addr(), test_addr(), rx.into_async_read());
+
+        let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let bytes = ConnectDatagram::with_tag(1, data.clone())?.into_bytes();
+        tx.unbounded_send(Ok(bytes[..1000].to_vec()))?;
+
+        // the message body is yielded before the rest of the datagram has arrived
+        let mut payload = match reader.next_or_payload(1024).await {
+            Some(NextPayload::Payload(payload)) => payload,
+            _ => panic!("expected a streamed message body"),
+        };
+        assert_eq!(1, payload.tag());
+        assert_eq!(data.len(), payload.data_size());
+
+        let mut buf = vec![0; 1000 - DATAGRAM_HEADER_BYTE_SIZE];
+        payload.read_exact(&mut buf).await?;
+        assert_eq!(&data[..buf.len()], buf.as_slice());
+
+        tx.unbounded_send(Ok(bytes[1000..].to_vec()))?;
+        tx.unbounded_send(Ok(ConnectDatagram::with_tag(2, vec![2; 16])?.into_bytes()))?;
+        drop(tx);
+
+        let mut rest = Vec::new();
+        payload.read_to_end(&mut rest).await?;
+        assert_eq!(&data[buf.len()..], rest.as_slice());
+        assert_eq!(0, payload.remaining());
+
+        // smaller message bodies are received whole
+        match reader.next_or_payload(1024).await {
+            Some(NextPayload::Datagram(datagram)) => assert_eq!(2, datagram.tag()),
+            _ => panic!("expected a datagram"),
+        }
+        assert!(reader.next_or_payload(1024).await.is_none());
+        assert_eq!(2, reader.stats().messages_read);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn dropped_payload_is_discarded() -> anyhow::Result<()> {
+        let mut reader = reader_over(&[
+            ConnectDatagram::with_tag(1, vec![1; 50_000])?,
+            ConnectDatagram::with_tag(2, vec![2; 50_000])?,
+            ConnectDatagram::with_tag(3, vec![3; 50_000])?,
+        ]);
+
+        match reader.next_or_payload(1024).await {
+            Some(NextPayload::Payload(mut payload)) => {
+                let mut buf = [0; 10];
+                payload.read_exact(&mut buf).await?;
+                assert_eq!([1; 10], buf);
+            }
+            _ => panic!("expected a streamed message body"),
+        }
+
+        // the rest of the message body is skipped, and the stream yields whole datagrams
+        let datagram = reader.next().await.expect("connection closed");
+        assert_eq!(2, datagram.tag());
+        assert_eq!(50_000, datagram.data_size());
+
+        match reader.next_or_payload(1024).await {
+            Some(NextPayload::Payload(payload)) => assert_eq!(3, payload.tag()),
+            _ => panic!("expected a streamed message body"),
+        }
+        assert!(reader.next_or_payload(1024).await.is_none());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "checksum")]
+    #[async_std::test]
+    async fn streamed_payload_checksum() -> anyhow::Result<()> {
+        let mut datagram = ConnectDatagram::with_tag(1, vec![1; 10_000])?;
+        datagram.enable_checksum();
+
+        let mut corrupted = datagram.clone().into_bytes();
+        *corrupted.last_mut().unwrap() = 2;
+        let mut bytes = datagram.into_bytes();
+        bytes.extend_from_slice(&corrupted);
+
+        let mut reader =
+            ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)));
+
+        for valid in [true, false] {
+            let mut payload = match reader.next_or_payload(1024).await {
+                Some(NextPayload::Payload(payload)) => payload,
+                _ => panic!("expected a streamed message body"),
+            };
+            assert_eq!(10_000, payload.data_size());
+
+            let res = payload.read_to_end(&mut Vec::new()).await;
+            assert_eq!(valid, res.is_ok());
+        }
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn drains_datagrams_before_peer_close() -> anyhow::Result<()> {
+        let listener = TcpListener::bind(test_addr()).await?;
+        let client = TcpStream::connect(listener.local_addr()?).await?;
+        let (server, _) = listener.accept().await?;
+
+        let mut writer = ConnectionWriter::new(test_addr(), test_addr(), Box::pin(client));
+        for tag in 0..100 {
+            writer
+                .feed(ConnectDatagram::with_tag(tag, vec![tag as u8; 64])?)
+                .await?;
+        }
+        writer.close().await?;
+        drop(writer);
+
+        // wait until the close has arrived, so that it is read along with the last datagrams
+        async_std::task::sleep(Duration::from_millis(50)).await;
+
+        let mut reader = ConnectionReader::new(test_addr(), test_addr(), Box::pin(server));
+        for tag in 0..100 {
+            let datagram = reader.next().await.expect("datagram was discarded");
+            assert_eq!(tag, datagram.tag());
+        }
+
+        assert!(reader.next().await.is_none());
+        assert!(matches!(
+            reader.close_reason(),
+            Some(CloseReason::PeerClosed)
+        ));
+
+        // only the bytes of a datagram cut off by the close are discarded
+        let mut bytes = ConnectDatagram::with_tag(1, vec![1; 8])?.into_bytes();
+        let truncated = ConnectDatagram::with_tag(2, vec![2; 8])?.into_bytes();
+        bytes.extend_from_slice(&truncated[..truncated.len() - 1]);
+
+        let mut reader =
+            ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)));
+        assert_eq!(Some(1), reader.next().await.map(|datagram| datagram.tag()));
+        assert!(reader.next().await.is_none());
+        assert!(matches!(
+            reader.close_reason(),
+            Some(CloseReason::PeerClosed)
+        ));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn resync_after_garbage() -> anyhow::Result<()> {
+        let mut bytes = ConnectDatagram::with_tag(1, vec![1])?.into_bytes();
+        // an oversized size-prefix
+        bytes.extend_from_slice(b"hello garbage");
+        bytes.extend(ConnectDatagram::with_tag(2, vec![2, 2])?.into_bytes());
+        // a plausible size-prefix followed by an unsupported version
+        bytes.extend_from_slice(&[0, 0, 0, 6, 0, 9, 0, 0]);
+        bytes.extend(ConnectDatagram::with_tag(3, vec![3, 3, 3])?.into_bytes());
+
+        let mut reader =
+            ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)));
+        reader.set_resync_on_error(true);
+
+        let received: Vec<ConnectDatagram> = reader.collect().await;
+        let tags: Vec<u16> = received.iter().map(|d| d.tag()).collect();
+        assert_eq!(vec![1, 2, 3], tags);
+        assert_eq!(&[3, 3, 3], received[2].data());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn channel_bridge() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let client = TcpStream::connect(listener.local_addr()?).await?;
+        let server = listener.accept().await?.0;
+
+        let writer = ConnectionWriter::new(test_addr(), test_addr(), Box::pin(client));
+        let reader = ConnectionReader::new(test_addr(), test_addr(), Box::pin(server));
+
+        let mut outbox = writer.into_channel(2);
+        let inbox = reader.into_channel(2);
+
+        for tag in 0..10 {
+            outbox
+                .send(ConnectDatagram::with_tag(tag, vec![1])?)
+                .await?;
+        }
+        drop(outbox);
+
+        let tags: Vec<u16> = inbox.map(|d| d.tag()).collect().await;
+        assert_eq!((0..10).collect::<Vec<u16>>(), tags);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn max_buffered_bytes_bounds_channel() -> anyhow::Result<()> {
+        struct CountingObserver(AtomicUsize);
+
+        impl ConnectionObserver for CountingObserver {
+            fn on_message_read(&self, _tag: u16, _bytes: usize) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let datagrams = (0..10)
+            .map(|tag| ConnectDatagram::with_tag(tag, vec![0; 100]))
+            .collect::<Result<Vec<_>, _>>()?;
+        let observer = Arc::new(CountingObserver(AtomicUsize::new(0)));
+
+        let mut reader = reader_over(&datagrams);
+        reader.set_observer(observer.clone());
+        reader.set_max_buffered_bytes(250);
+        let mut inbox = reader.into_channel(64);
+
+        // the task stops reading once the channel holds three datagrams
+        async_std::task::sleep(Duration::from_millis(50)).await;
+        assert_eq!(3, observer.0.load(Ordering::SeqCst));
+
+        assert_eq!(0, inbox.next().await.unwrap().tag());
+        async_std::task::sleep(Duration::from_millis(50)).await;
+        assert_eq!(4, observer.0.load(Ordering::SeqCst));
+
+        let tags: Vec<u16> = inbox.map(|d| d.tag()).collect().await;
+        assert_eq!((1..10).collect::<Vec<u16>>(), tags);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn unboxed_streams() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let client = TcpStream::connect(listener.local_addr()?).await?;
+        let server = listener.accept().await?.0;
+
+        let mut writer: ConnectionWriter<TcpStream> =
+            ConnectionWriter::from_stream(test_addr(), test_addr(), client);
+        let reader: ConnectionReader<TcpStream> =
+            ConnectionReader::from_stream(test_addr(), test_addr(), server);
+
+        writer.send(ConnectDatagram::with_tag(1, vec![1])?).await?;
+        drop(writer);
+
+        let received: Vec<ConnectDatagram> = reader.collect().await;
+        assert_eq!(vec![ConnectDatagram::with_tag(1, vec![1])?], received);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn read_into_reuses_buffer() -> anyhow::Result<()> {
+        let mut bytes = ConnectDatagram::with_tag(1, vec![1; 16])?.into_bytes();
+        bytes.extend(ConnectDatagram::with_tag(2, vec![2; 4])?.into_bytes());
+        bytes.extend(ConnectDatagram::with_tag(3, vec![3; 64])?.into_bytes());
+
+        let mut reader =
+            ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)));
+        let mut buf = Vec::with_capacity(16);
+
+        assert_eq!(Some((1, 16)), reader.read_into(&mut buf).await);
+        assert_eq!(&[1; 16], &buf[..]);
+
+        // a smaller message body replaces the previous one
+        assert_eq!(Some((2, 4)), reader.read_into(&mut buf).await);
+        assert_eq!(&[2; 4], &buf[..]);
+
+        // a larger message body grows the buffer
+        assert_eq!(Some((3, 64)), reader.read_into(&mut buf).await);
+        assert_eq!(&[3; 64], &buf[..]);
+
+        assert_eq!(None, reader.read_into(&mut buf).await);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn control_datagrams() -> anyhow::Result<()> {
+        let mut bytes = ConnectDatagram::control(1).into_bytes();
+        bytes.extend(ConnectDatagram::with_tag(2, vec![2])?.into_bytes());
+        bytes.extend(ConnectDatagram::control(3).into_bytes());
+
+        let reader = ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)));
+        let received: Vec<ConnectDatagram> = reader.collect().await;
+
+        assert_eq!(3, received.len());
+        assert_eq!(ConnectDatagram::control(1), received[0]);
+        assert_eq!(&[2], received[1].data());
+        assert_eq!(ConnectDatagram::control(3), received[2]);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn peek_then_next() -> anyhow::Result<()> {
+        let datagrams = vec![
+            ConnectDatagram::with_tag(1, vec![1])?,
+            ConnectDatagram::with_tag(2, vec![2, 2])?,
+        ];
+
+        let mut reader = reader_over(&datagrams);
+
+        assert_eq!(Some(&datagrams[0]), reader.peek().await);
+        assert_eq!(Some(&datagrams[0]), reader.peek().await);
+        assert_eq!(Some(datagrams[0].clone()), reader.next().await);
+        assert_eq!(Some(&datagrams[1]), reader.peek().await);
+        assert_eq!(Some(datagrams[1].clone()), reader.next().await);
+        assert_eq!(None, reader.peek().await);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn split_by_tag() -> anyhow::Result<()> {
+        let datagrams = vec![
+            ConnectDatagram::with_tag(1, vec![1])?,
+            ConnectDatagram::with_tag(2, vec![2])?,
+            ConnectDatagram::with_tag(3, vec![3])?,
+            ConnectDatagram::with_tag(1, vec![1, 1])?,
+            ConnectDatagram::with_tag(2, vec![2, 2])?,
+        ];
+
+        let mut router = reader_over(&datagrams).split_by_tag();
+        let ones = router.subscribe(1);
+        let twos = router.subscribe(2);
+
+        let unmatched: Vec<ConnectDatagram> = router.collect().await;
+        let ones: Vec<ConnectDatagram> = ones.collect().await;
+        let twos: Vec<ConnectDatagram> = twos.collect().await;
+
+        assert_eq!(vec![datagrams[2].clone()], unmatched);
+        assert_eq!(vec![datagrams[0].clone(), datagrams[3].clone()], ones);
+        assert_eq!(vec![datagrams[1].clone(), datagrams[4].clone()], twos);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn send_and_recv_stream() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let client = TcpStream::connect(listener.local_addr()?).await?;
+        let (server, _) = listener.accept().await?;
+
+        let data: Vec<u8> = (0..300_000u32).map(|i| i as u8).collect();
+        let sent = data.clone();
+        let sender = async_std::task::spawn(async move {
+            let mut writer = ConnectionWriter::new(test_addr(), test_addr(), Box::pin(client));
+            writer
+                .send(ConnectDatagram::with_tag(1, b"before".to_vec())?)
+                .await?;
+            let bytes_sent = writer.send_stream(2, Cursor::new(sent), 64 * 1024).await?;
+            writer
+                .send(ConnectDatagram::with_tag(3, b"after".to_vec())?)
+                .await?;
+
+            anyhow::Ok(bytes_sent)
+        });
+
+        let mut reader = ConnectionReader::new(test_addr(), test_addr(), Box::pin(server));
+        assert_eq!(1, reader.peek().await.expect("connection closed").tag());
+
+        let mut received = Vec::new();
+        reader.recv_stream(2).read_to_end(&mut received).await?;
+        assert_eq!(data, received);
+        assert_eq!(data.len() as u64, sender.await?);
+
+        // datagrams received around the stream are still yielded in order
+        assert_eq!(b"before", reader.next().await.unwrap().data());
+        assert_eq!(b"after", reader.next().await.unwrap().data());
+
+        // the stream fails if the connection closes before it ends
+        assert!(reader
+            .recv_stream(2)
+            .read_to_end(&mut received)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn max_buffered_bytes_stalls_stream() -> anyhow::Result<()> {
+        let mut datagrams = (1..=3)
+            .map(|tag| ConnectDatagram::with_tag(tag, vec![0; 100]))
+            .collect::<Result<Vec<_>, _>>()?;
+        datagrams.push(ConnectDatagram::stream_chunk(
+            4,
+            vec![0; DATAGRAM_HEADER_BYTE_SIZE + 1],
+        )?);
+
+        let mut reader = reader_over(&datagrams);
+        reader.set_max_buffered_bytes(150);
+
+        // the reader stops reading once two datagrams are buffered
+        let mut received = Vec::new();
+        assert!(reader
+            .recv_stream(4)
+            .read_to_end(&mut received)
+            .now_or_never()
+            .is_none());
+        assert_eq!(2 * datagrams[0].serialized_size(), reader.buffered_bytes());
+
+        // the deferred datagrams are still yielded in order
+        assert_eq!(1, reader.next().await.unwrap().tag());
+        assert_eq!(2, reader.next().await.unwrap().tag());
+        assert_eq!(0, reader.buffered_bytes());
+        assert_eq!(3, reader.next().await.unwrap().tag());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn next_or_progress() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = TcpStream::connect(listener.local_addr()?).await?;
+        let (server, _) = listener.accept().await?;
+
+        let mut reader = ConnectionReader::new(test_addr(), test_addr(), Box::pin(server));
+        let timeout = Duration::from_millis(50);
+
+        assert!(matches!(
+            reader.next_or_progress(timeout).await,
+            NextResult::Timeout
+        ));
+
+        let datagram = ConnectDatagram::with_tag(1, vec![7; 1000])?;
+        let bytes = datagram.clone().into_bytes();
+        client.write_all(&bytes[..504]).await?;
+
+        match reader.next_or_progress(timeout).await {
+            NextResult::Progress { received, total } => {
+                assert_eq!(500, received);
+                assert_eq!(1004, total);
+            }
+
+            other => panic!("expected progress report, received {:?}", other),
+        }
+
+        client.write_all(&bytes[504..]).await?;
+        match reader.next_or_progress(timeout).await {
+            NextResult::Datagram(received) => assert_eq!(datagram, received),
+            other => panic!("expected datagram, received {:?}", other),
+        }
+
+        drop(client);
+        assert!(matches!(
+            reader.next_or_progress(timeout).await,
+            NextResult::Closed
+        ));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn payload_streams() -> anyhow::Result<()> {
+        let datagrams = vec![
+            ConnectDatagram::with_tag(1, vec![1])?,
+            ConnectDatagram::with_tag(2, vec![2, 2])?,
+            ConnectDatagram::with_tag(1, vec![3])?,
+        ];
+
+        let mut reader = reader_over(&datagrams);
+        let payloads: Vec<Vec<u8>> = reader.payloads().collect().await;
+        assert_eq!(vec![vec![1], vec![2, 2], vec![3]], payloads);
+
+        let mut reader = reader_over(&datagrams);
+        let payloads: Vec<Vec<u8>> = reader.payloads_for_tag(1).collect().await;
+        assert_eq!(vec![vec![1], vec![3]], payloads);
+
+        Ok(())
+    }
+
+    /// A network stream that always has another copy of the same datagram ready.
+    struct Flood {
+        bytes: Vec<u8>,
+        pos: usize,
+    }
+
+    impl AsyncRead for Flood {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            for byte in buf.iter_mut() {
+                *byte = self.bytes[self.pos];
+                self.pos = (self.pos + 1) % self.bytes.len();
+            }
+
+            Poll::Ready(Ok(buf.len()))
+        }
+    }
+
+    #[test]
+    fn flooded_reader_yields_to_executor() -> anyhow::Result<()> {
+        let flood = Flood {
+            bytes: ConnectDatagram::with_tag(1, vec![1; 16])?.into_bytes(),
+            pos: 0,
+        };
+        let mut reader = ConnectionReader::from_stream(test_addr(), test_addr(), flood);
+        reader.set_poll_budget(32, usize::MAX);
+
+        let mut pool = LocalPool::new();
+        let other_ran = Rc::new(Cell::new(false));
+        let received_before_other = Rc::new(Cell::new(None));
+
+        let ran = other_ran.clone();
+        let received = received_before_other.clone();
+        pool.spawner().spawn_local(async move {
+            for count in 0..10_000 {
+                reader.next().await.expect("connection closed");
+
+                if ran.get() {
+                    received.set(Some(count));
+                    break;
+                }
+            }
+        })?;
+
+        let ran = other_ran.clone();
+        pool.spawner().spawn_local(async move { ran.set(true) })?;
+
+        pool.run();
+
+        // the flooded task gives the executor back once its budget is exhausted
+        assert!(other_ran.get());
+        assert_eq!(Some(32), received_before_other.get());
+
+        Ok(())
     }
 }
@@ -0,0 +1,381 @@
+//! Request/response layer that correlates replies with the requests awaiting them.
+//!
+//! <br/>
+//!
+//! This module exposes the [`RpcConnection`] wrapper, which attaches a correlation id to every
+//! request as an extension field of the datagram, so that the tag field stays free for the
+//! application to use.
+
+use crate::logging::*;
+use crate::rt;
+use crate::{ConnectDatagram, Connection, ConnectionWriteError, ConnectionWriter, DatagramError};
+use futures::channel::{mpsc, oneshot};
+use futures::lock::Mutex as AsyncMutex;
+use futures::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Extension key carrying the correlation id of a request sent with [`RpcConnection::call`].
+///
+/// See the [`protocol`](`crate::protocol`) module for the other reserved extension keys.
+pub const REQUEST_ID_EXTENSION: u16 = 0xfffe;
+
+/// Extension key carrying the correlation id of the request that a reply sent with
+/// [`RpcConnection::reply`] answers.
+pub const REPLY_ID_EXTENSION: u16 = 0xffff;
+
+/// Encountered when a call or reply made with an [`RpcConnection`] could not be completed.
+///
+#[derive(Debug)]
+pub enum RpcError {
+    /// The connection closed before a reply was received.
+    ConnectionClosed,
+
+    /// No reply was received before the timeout of the call elapsed.
+    TimedOut,
+
+    /// The request is not an RPC request, so it cannot be replied to.
+    NotARequest,
+
+    /// Wraps a [`DatagramError`] encountered when attaching the correlation id to the datagram.
+    Datagram(DatagramError),
+
+    /// Wraps a [`ConnectionWriteError`] encountered when sending the datagram.
+    Write(ConnectionWriteError),
+}
+
+impl Error for RpcError {}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RpcError::ConnectionClosed => {
+                formatter.write_str("cannot complete call because the connection is closed")
+            }
+            RpcError::TimedOut => formatter.write_str("no reply was received before the timeout"),
+            RpcError::NotARequest => formatter.write_str("datagram is not an RPC request"),
+            RpcError::Datagram(err) => std::fmt::Display::fmt(err, formatter),
+            RpcError::Write(err) => std::fmt::Display::fmt(err, formatter),
+        }
+    }
+}
+
+/// The calls awaiting a reply, which are resolved with an error once the connection closes.
+struct PendingCalls {
+    senders: Mutex<Option<HashMap<u64, oneshot::Sender<ConnectDatagram>>>>,
+}
+
+impl PendingCalls {
+    fn register(&self, id: u64, sender: oneshot::Sender<ConnectDatagram>) -> Result<(), RpcError> {
+        match self
+            .senders
+            .lock()
+            .expect("pending calls lock poisoned")
+            .as_mut()
+        {
+            Some(senders) => {
+                senders.insert(id, sender);
+                Ok(())
+            }
+
+            None => Err(RpcError::ConnectionClosed),
+        }
+    }
+
+    fn take(&self, id: u64) -> Option<oneshot::Sender<ConnectDatagram>> {
+        self.senders
+            .lock()
+            .expect("pending calls lock poisoned")
+            .as_mut()
+            .and_then(|senders| senders.remove(&id))
+    }
+
+    /// Drops the senders of all outstanding calls, so that they resolve with an error.
+    fn close(&self) {
+        self.senders
+            .lock()
+            .expect("pending calls lock poisoned")
+            .take();
+    }
+}
+
+/// Removes a call from the pending calls if it is dropped before its reply is received, such as
+/// when it times out.
+struct CallGuard<'a> {
+    calls: &'a PendingCalls,
+    id: u64,
+}
+
+impl Drop for CallGuard<'_> {
+    fn drop(&mut self) {
+        self.calls.take(self.id);
+    }
+}
+
+/// Wraps a [`Connection`] to send requests and await their matching replies.
+///
+/// Every request sent with [`call`](RpcConnection::call) is assigned a correlation id, and a
+/// background task reads the connection to resolve each call with the reply carrying its id. Any
+/// other datagrams received, including requests sent by the peer, are returned by
+/// [`next_request`](RpcConnection::next_request) and answered with
+/// [`reply`](RpcConnection::reply).
+///
+/// Calls take `&self`, so an [`RpcConnection`] can be shared in an [`Arc`] to make calls
+/// concurrently. When the connection closes, all outstanding calls fail with
+/// [`ConnectionClosed`](RpcError::ConnectionClosed). Dropping the [`RpcConnection`] stops the
+/// background task and closes the reading half of the connection.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let rpc = RpcConnection::new(Connection::tcp_client(ip_address).await?);
+/// let reply = rpc.call(ConnectDatagram::with_tag(GET_USER, user_id)?).await?;
+///
+/// // on the peer
+/// while let Some(request) = rpc.next_request().await {
+///     let response = handle(&request)?;
+///     rpc.reply(&request, response).await?;
+/// }
+/// ```
+pub struct RpcConnection {
+    writer: AsyncMutex<ConnectionWriter>,
+    calls: Arc<PendingCalls>,
+    requests: AsyncMutex<mpsc::UnboundedReceiver<ConnectDatagram>>,
+    next_id: AtomicU64,
+    _stop: oneshot::Sender<()>,
+}
+
+impl RpcConnection {
+    /// Creates an [`RpcConnection`] from a [`Connection`], spawning the task that reads from it.
+    pub fn new(conn: Connection) -> Self {
+        let (reader, writer) = conn.split();
+        let calls = Arc::new(PendingCalls {
+            senders: Mutex::new(Some(HashMap::new())),
+        });
+        let (requests_tx, requests_rx) = mpsc::unbounded();
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+
+        let pump_calls = calls.clone();
+        rt::spawn(async move {
+            let mut reader = reader.take_until(stop_rx);
+
+            while let Some(mut datagram) = reader.next().await {
+                match correlation_id(&datagram, REPLY_ID_EXTENSION) {
+                    Some(id) => match pump_calls.take(id) {
+                        Some(sender) => {
+                            datagram.remove_extension(REPLY_ID_EXTENSION);
+                            let _ = sender.send(datagram);
+                        }
+
+                        None => debug!("Discarding reply to call {} that is no longer awaited", id),
+                    },
+
+                    None => {
+                        let _ = requests_tx.unbounded_send(datagram);
+                    }
+                }
+            }
+
+            pump_calls.close();
+        });
+
+        Self {
+            writer: AsyncMutex::new(writer),
+            calls,
+            requests: AsyncMutex::new(requests_rx),
+            next_id: AtomicU64::new(0),
+            _stop: stop_tx,
+        }
+    }
+
+    /// Sends `request` and waits for the peer to reply to it.
+    ///
+    /// The correlation id is attached to the request with the [`REQUEST_ID_EXTENSION`] key. Calls
+    /// wait for a reply indefinitely unless the connection closes; use
+    /// [`call_timeout`](RpcConnection::call_timeout) to give up on a reply.
+    pub async fn call(&self, mut request: ConnectDatagram) -> Result<ConnectDatagram, RpcError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        request
+            .set_extension(REQUEST_ID_EXTENSION, id.to_be_bytes().to_vec())
+            .map_err(RpcError::Datagram)?;
+
+        let (sender, receiver) = oneshot::channel();
+        self.calls.register(id, sender)?;
+        let _guard = CallGuard {
+            calls: &self.calls,
+            id,
+        };
+
+        self.writer
+            .lock()
+            .await
+            .send(request)
+            .await
+            .map_err(RpcError::Write)?;
+
+        receiver.await.map_err(|_| RpcError::ConnectionClosed)
+    }
+
+    /// Sends `request` and waits for the peer to reply to it, failing with
+    /// [`TimedOut`](RpcError::TimedOut) if no reply is received within `timeout`.
+    ///
+    /// A reply that arrives after the timeout is discarded.
+    pub async fn call_timeout(
+        &self,
+        request: ConnectDatagram,
+        timeout: Duration,
+    ) -> Result<ConnectDatagram, RpcError> {
+        match rt::timeout(timeout, self.call(request)).await {
+            Ok(res) => res,
+            Err(_) => Err(RpcError::TimedOut),
+        }
+    }
+
+    /// Waits for the next datagram from the peer that is not a reply to a call, such as a request
+    /// to be answered with [`reply`](RpcConnection::reply).
+    ///
+    /// Returns `None` once the connection is closed.
+    pub async fn next_request(&self) -> Option<ConnectDatagram> {
+        self.requests.lock().await.next().await
+    }
+
+    /// Sends `response` as the reply to a `request` received with
+    /// [`next_request`](RpcConnection::next_request).
+    ///
+    /// Fails with [`NotARequest`](RpcError::NotARequest) if the request was not sent with
+    /// [`call`](RpcConnection::call).
+    pub async fn reply(
+        &self,
+        request: &ConnectDatagram,
+        mut response: ConnectDatagram,
+    ) -> Result<(), RpcError> {
+        let id = correlation_id(request, REQUEST_ID_EXTENSION).ok_or(RpcError::NotARequest)?;
+        response
+            .set_extension(REPLY_ID_EXTENSION, id.to_be_bytes().to_vec())
+            .map_err(RpcError::Datagram)?;
+
+        self.writer
+            .lock()
+            .await
+            .send(response)
+            .await
+            .map_err(RpcError::Write)
+    }
+}
+
+/// Gets the correlation id stored in the extension field `key` of the datagram, if any.
+fn correlation_id(datagram: &ConnectDatagram, key: u16) -> Option<u64> {
+    datagram
+        .get_extension(key)
+        .and_then(|value| value.try_into().ok())
+        .map(u64::from_be_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RpcConnection, RpcError};
+    use crate::{ConnectDatagram, Connection};
+    use async_std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    async fn rpc_pair() -> anyhow::Result<(RpcConnection, RpcConnection)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let client = Connection::from(TcpStream::connect(listener.local_addr()?).await?);
+        let server = Connection::from(listener.accept().await?.0);
+
+        Ok((RpcConnection::new(client), RpcConnection::new(server)))
+    }
+
+    #[async_std::test]
+    async fn replies_resolve_matching_calls() -> anyhow::Result<()> {
+        let (client, server) = rpc_pair().await?;
+
+        async_std::task::spawn(async move {
+            while let Some(request) = server.next_request().await {
+                let response = ConnectDatagram::with_tag(request.tag(), request.data().to_vec())
+                    .expect("could not create response");
+                server
+                    .reply(&request, response)
+                    .await
+                    .expect("could not reply");
+            }
+        });
+
+        let requests = (1..=10)
+            .map(|i| ConnectDatagram::with_tag(i, vec![i as u8]))
+            .collect::<Result<Vec<_>, _>>()?;
+        let calls = requests.into_iter().map(|request| client.call(request));
+
+        for (i, reply) in (1..=10).zip(futures::future::join_all(calls).await) {
+            let reply = reply?;
+            assert_eq!(i, reply.tag());
+            assert_eq!(&[i as u8], reply.data());
+        }
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn calls_over_sequenced_connections() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = Connection::from(TcpStream::connect(listener.local_addr()?).await?);
+        let mut server = Connection::from(listener.accept().await?.0);
+        client.enable_sequencing();
+        server.enable_sequencing();
+        let (client, server) = (RpcConnection::new(client), RpcConnection::new(server));
+
+        async_std::task::spawn(async move {
+            while let Some(request) = server.next_request().await {
+                let response = ConnectDatagram::with_tag(request.tag(), request.data().to_vec())
+                    .expect("could not create response");
+                server
+                    .reply(&request, response)
+                    .await
+                    .expect("could not reply");
+            }
+        });
+
+        for i in 1..=5 {
+            let reply = client
+                .call_timeout(
+                    ConnectDatagram::with_tag(i, vec![i as u8])?,
+                    Duration::from_secs(5),
+                )
+                .await?;
+            assert_eq!(i, reply.tag());
+            assert_eq!(&[i as u8], reply.data());
+            assert_eq!(Some(i as u64 - 1), reply.sequence());
+        }
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn outstanding_calls_fail() -> anyhow::Result<()> {
+        let (client, server) = rpc_pair().await?;
+
+        let res = client
+            .call_timeout(
+                ConnectDatagram::with_tag(1, vec![1])?,
+                Duration::from_millis(50),
+            )
+            .await;
+        assert!(matches!(res, Err(RpcError::TimedOut)));
+
+        let call = client.call(ConnectDatagram::with_tag(2, vec![2])?);
+        let (res, _) = futures::join!(call, async {
+            server.next_request().await.expect("connection closed");
+            server.next_request().await.expect("connection closed");
+            drop(server);
+        });
+        assert!(matches!(res, Err(RpcError::ConnectionClosed)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+//! Selects the runtime primitives used by the core of the crate: those of `async-std` and
+//! `async-io` by default, or ones backed by the browser on `wasm32`, where neither is available.
+
+use futures::Future;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use async_io::Timer;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use async_std::future::timeout;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use std::time::Instant;
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) use wasm::{timeout, Timer};
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web_time::Instant;
+
+/// Spawns a background task, on the `async-std` runtime or on the browser's event loop.
+pub(crate) fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    async_std::task::spawn(future);
+
+    #[cfg(target_arch = "wasm32")]
+    wasm_bindgen_futures::spawn_local(future);
+}
+
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use super::Instant;
+    use futures::task::{Context, Poll};
+    use futures::{Future, Stream};
+    use futures_timer::Delay;
+    use std::pin::Pin;
+    use std::time::Duration;
+
+    /// A timer with the subset of the API of `async_io::Timer` used by the crate, backed by the
+    /// browser's timers.
+    pub(crate) struct Timer {
+        deadline: Option<Instant>,
+        period: Option<Duration>,
+        delay: Delay,
+    }
+
+    impl Timer {
+        /// Creates a timer that fires once after `duration`.
+        pub(crate) fn after(duration: Duration) -> Self {
+            Self::at(Instant::now() + duration)
+        }
+
+        /// Creates a timer that fires once at `deadline`.
+        pub(crate) fn at(deadline: Instant) -> Self {
+            Self {
+                deadline: Some(deadline),
+                period: None,
+                delay: Delay::new(deadline.saturating_duration_since(Instant::now())),
+            }
+        }
+
+        /// Creates a timer that fires every `period`, starting after `period`.
+        pub(crate) fn interval(period: Duration) -> Self {
+            Self {
+                period: Some(period),
+                ..Self::after(period)
+            }
+        }
+
+        /// Reschedules the timer to fire once after `duration`.
+        pub(crate) fn set_after(&mut self, duration: Duration) {
+            self.set_at(Instant::now() + duration);
+        }
+
+        /// Reschedules the timer to fire once at `deadline`.
+        pub(crate) fn set_at(&mut self, deadline: Instant) {
+            self.deadline = Some(deadline);
+            self.period = None;
+            self.delay
+                .reset(deadline.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    impl Future for Timer {
+        type Output = Instant;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            // like `async_io::Timer`, a timer that has fired and has no period never fires again
+            let deadline = match self.deadline {
+                Some(deadline) => deadline,
+                None => return Poll::Pending,
+            };
+
+            futures::ready!(Pin::new(&mut self.delay).poll(cx));
+
+            match self.period {
+                Some(period) => {
+                    let next = deadline + period;
+                    self.deadline = Some(next);
+                    self.delay
+                        .reset(next.saturating_duration_since(Instant::now()));
+                }
+                None => self.deadline = None,
+            }
+
+            Poll::Ready(deadline)
+        }
+    }
+
+    impl Stream for Timer {
+        type Item = Instant;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            self.poll(cx).map(Some)
+        }
+    }
+
+    /// Returned by [`timeout`] when the future did not complete in time.
+    #[derive(Debug)]
+    pub(crate) struct TimeoutError;
+
+    /// Awaits `future` for up to `duration`.
+    pub(crate) async fn timeout<F: Future>(
+        duration: Duration,
+        future: F,
+    ) -> Result<F::Output, TimeoutError> {
+        futures::pin_mut!(future);
+
+        match futures::future::select(future, Timer::after(duration)).await {
+            futures::future::Either::Left((output, _)) => Ok(output),
+            futures::future::Either::Right(_) => Err(TimeoutError),
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use crate::protocol::{ConnectDatagram, DatagramError};
+
+/// Extension key reserved for the sequence numbers stamped on datagrams when sequencing is
+/// enabled, see [`Connection::enable_sequencing`](`crate::Connection::enable_sequencing`).
+///
+/// See the [`protocol`](`crate::protocol`) module for the other reserved extension keys.
+pub const SEQUENCE_EXTENSION_KEY: u16 = 0xfffd;
+
+/// Reported to the [`ConnectionObserver`](`crate::ConnectionObserver`) of a connection when the
+/// sequence number of a received datagram is not the one that was expected, meaning that
+/// datagrams were lost or repeated between the peer's writer and this reader, such as across a
+/// reconnect.
+///
+/// A `got` greater than `expected` means that `got - expected` datagrams are missing, while a
+/// `got` less than `expected` means that datagrams were sent again.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceGap {
+    /// The sequence number that the reader expected next.
+    pub expected: u64,
+
+    /// The sequence number of the datagram that was received instead.
+    pub got: u64,
+}
+
+/// Stamps a datagram with a sequence number, replacing any it already had.
+pub(crate) fn stamp(datagram: &mut ConnectDatagram, sequence: u64) -> Result<(), DatagramError> {
+    datagram
+        .set_extension(SEQUENCE_EXTENSION_KEY, sequence.to_be_bytes().to_vec())
+        .map(|_| ())
+}
@@ -0,0 +1,187 @@
+use crate::{ConnectDatagram, Connection, ConnectionReader, ConnectionWriteError};
+use futures::channel::mpsc;
+use futures::task::{Context, Poll};
+use futures::Sink;
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+/// The number of datagrams queued for the writer task of a [`SharedWriter`] before producers wait
+/// for it to catch up.
+pub const SHARED_WRITER_CAPACITY: usize = 64;
+
+/// A handle to write messages on a connection that is shared by several producer tasks.
+///
+/// Constructed with [`Connection::split_shared`]. Each clone of a [`SharedWriter`] is a separate
+/// handle that can be moved into its own task, and the datagrams sent through every handle are
+/// queued for a single task that owns the [`ConnectionWriter`](`crate::ConnectionWriter`), see
+/// [`ConnectionWriter::into_channel`](`crate::ConnectionWriter::into_channel`).
+///
+/// # Ordering
+///
+/// The datagrams sent through one handle are written in the order they were sent. The datagrams
+/// of different handles are interleaved in an unspecified order, so a peer must not rely on the
+/// order of datagrams sent from different tasks. Cloning a handle does not carry over its order:
+/// the clone is a separate producer.
+///
+/// # Flushing
+///
+/// Sending through a handle completes once the datagram is queued for the writer task, not once it
+/// is written to the network stream, and flushing a handle does not wait for the network stream
+/// either. The writer task flushes the network stream itself whenever it has written every queued
+/// datagram, so datagrams are never left sitting in its buffers. Once every handle is dropped or
+/// closed, the writer task writes the remaining datagrams and closes the writer. If writing fails,
+/// every handle fails with [`ConnectionWriteError::ConnectionClosed`].
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let (mut reader, writer) = conn.split_shared();
+///
+/// for producer in producers {
+///     let mut writer = writer.clone();
+///
+///     task::spawn(async move {
+///         while let Some(msg) = producer.next().await {
+///             writer.send(msg).await?;
+///         }
+///
+///         Ok::<_, ConnectionWriteError>(())
+///     });
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SharedWriter {
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
+    sender: mpsc::Sender<ConnectDatagram>,
+}
+
+impl SharedWriter {
+    /// Get the local IP address and port.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Get the peer IP address and port.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Check if the writer task has stopped, after which nothing can be sent through any handle.
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+}
+
+impl Sink<ConnectDatagram> for SharedWriter {
+    type Error = ConnectionWriteError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sender
+            .poll_ready(cx)
+            .map_err(|_| ConnectionWriteError::ConnectionClosed)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: ConnectDatagram) -> Result<(), Self::Error> {
+        self.sender
+            .start_send(item)
+            .map_err(|_| ConnectionWriteError::ConnectionClosed)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sender)
+            .poll_flush(cx)
+            .map_err(|_| ConnectionWriteError::ConnectionClosed)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sender)
+            .poll_close(cx)
+            .map_err(|_| ConnectionWriteError::ConnectionClosed)
+    }
+}
+
+impl Connection {
+    /// Consume the [`Connection`] to split into a [`ConnectionReader`] and a [`SharedWriter`]
+    /// whose clones let several tasks write on the connection without a `Mutex`.
+    ///
+    /// The [`ConnectionWriter`](`crate::ConnectionWriter`) is moved into a spawned task that
+    /// writes the datagrams of every handle, queueing up to [`SHARED_WRITER_CAPACITY`] of them.
+    /// Please see the [`SharedWriter`] for the ordering guarantees.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let (mut reader, writer) = conn.split_shared();
+    /// let mut alerts = writer.clone();
+    /// ```
+    pub fn split_shared(self) -> (ConnectionReader, SharedWriter) {
+        let local_addr = self.local_addr();
+        let peer_addr = self.peer_addr();
+        let (reader, writer) = self.split();
+
+        let writer = SharedWriter {
+            local_addr,
+            peer_addr,
+            sender: writer.into_channel(SHARED_WRITER_CAPACITY),
+        };
+
+        (reader, writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ConnectDatagram, Connection};
+    use async_std::net::{TcpListener, TcpStream};
+    use futures::{SinkExt, StreamExt};
+
+    #[async_std::test]
+    async fn producers_keep_their_order() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let client = TcpStream::connect(listener.local_addr()?).await?;
+        let (server, _) = listener.accept().await?;
+
+        let (_, writer) = Connection::from(client).split_shared();
+        let mut conn = Connection::from(server);
+
+        let producers: Vec<_> = (1..=4u16)
+            .map(|tag| {
+                let mut writer = writer.clone();
+
+                async_std::task::spawn(async move {
+                    for count in 0..100u8 {
+                        writer
+                            .send(ConnectDatagram::with_tag(tag, vec![count])?)
+                            .await?;
+                    }
+
+                    Ok::<_, anyhow::Error>(())
+                })
+            })
+            .collect();
+        drop(writer);
+
+        for producer in producers {
+            producer.await?;
+        }
+
+        let mut next_count = [0u8; 4];
+        for _ in 0..400 {
+            let datagram = conn.reader().next().await.expect("connection closed");
+            let producer = datagram.tag() as usize - 1;
+
+            assert_eq!(&[next_count[producer]], datagram.data());
+            next_count[producer] += 1;
+        }
+
+        // the writer is closed once every handle is dropped
+        assert!(conn.reader().next().await.is_none());
+
+        Ok(())
+    }
+}
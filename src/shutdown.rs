@@ -0,0 +1,172 @@
+use crate::conn_limit::ConnectionLimiter;
+use crate::logging::*;
+use crate::rt;
+use futures::future::poll_fn;
+use futures::task::{AtomicWaker, Context};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A handle used to gracefully shut down a listener from outside of its accept loop.
+///
+/// Once [`shutdown`](`ShutdownHandle::shutdown`) is called, the listener stops accepting new
+/// connections and its stream finishes with `None` after any accept already in progress resolves.
+/// Connections that were previously accepted are unaffected and can be left to drain.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let mut server = TcpListener::bind("127.0.0.1:3456").await?;
+/// let handle = server.shutdown_handle();
+///
+/// async_std::task::spawn(async move {
+///     wait_for_sigterm().await;
+///     handle.shutdown();
+/// });
+///
+/// while let Some(conn) = server.next().await {
+///     // handle the connection
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    inner: Arc<ShutdownState>,
+}
+
+#[derive(Default)]
+struct ShutdownState {
+    shutdown: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signals the listener to stop accepting new connections.
+    pub fn shutdown(&self) {
+        self.inner.shutdown.store(true, Ordering::SeqCst);
+        self.inner.waker.wake();
+    }
+
+    /// Returns whether shutdown has been signaled.
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Registers the current task to be woken on shutdown and returns whether shutdown has been
+    /// signaled.
+    pub(crate) fn poll_shutdown(&self, cx: &mut Context<'_>) -> bool {
+        self.inner.waker.register(cx.waker());
+        self.is_shutdown()
+    }
+}
+
+impl std::fmt::Debug for ShutdownHandle {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter
+            .debug_struct("ShutdownHandle")
+            .field("shutdown", &self.is_shutdown())
+            .finish()
+    }
+}
+
+/// A handle used to gracefully drain a listener and the connections it has accepted, such as for a
+/// zero-downtime rolling restart.
+///
+/// The listener tracks every [`Connection`](`crate::Connection`) it accepts until it is dropped,
+/// and a [`split`](`crate::Connection::split`) connection until both of its halves are dropped.
+/// [`drain`](`ListenerHandle::drain`) stops accepting new connections and waits for the tracked
+/// connections to finish, closing any that are still alive once the timeout elapses.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let mut server = TcpListener::bind("127.0.0.1:3456").await?;
+/// let handle = server.handle();
+///
+/// async_std::task::spawn(async move {
+///     while let Some(conn) = server.next().await {
+///         async_std::task::spawn(handle_connection(conn));
+///     }
+/// });
+///
+/// wait_for_sigterm().await;
+/// handle.drain(Duration::from_secs(30)).await;
+/// ```
+#[derive(Clone)]
+pub struct ListenerHandle {
+    shutdown: ShutdownHandle,
+    connections: ConnectionLimiter,
+}
+
+impl ListenerHandle {
+    pub(crate) fn new(shutdown: ShutdownHandle, connections: ConnectionLimiter) -> Self {
+        Self {
+            shutdown,
+            connections,
+        }
+    }
+
+    /// Signals the listener to stop accepting new connections, like
+    /// [`ShutdownHandle::shutdown`].
+    pub fn shutdown(&self) {
+        self.shutdown.shutdown();
+    }
+
+    /// Get the number of live connections accepted by the listener.
+    pub fn live_connections(&self) -> usize {
+        self.connections.live()
+    }
+
+    /// Closes every live connection accepted by the listener.
+    ///
+    /// The reader stream of each connection ends with
+    /// [`CloseReason::LocalShutdown`](`crate::CloseReason::LocalShutdown`), and its writer fails
+    /// with [`ConnectionClosed`](`crate::ConnectionWriteError::ConnectionClosed`), so that the
+    /// tasks handling the connections finish and drop them. Messages that have not been flushed
+    /// are lost.
+    pub fn close_all(&self) {
+        self.connections.close_all();
+    }
+
+    /// Stops the listener from accepting new connections and waits up to `timeout` for every
+    /// live connection to be dropped, then closes the remaining connections with
+    /// [`close_all`](`ListenerHandle::close_all`).
+    ///
+    /// Returns the number of connections that had to be closed because they were still alive when
+    /// the timeout elapsed. Only one task should drain a listener at a time.
+    pub async fn drain(&self, timeout: Duration) -> usize {
+        self.shutdown();
+
+        let drained = rt::timeout(timeout, poll_fn(|cx| self.connections.poll_drained(cx))).await;
+        if drained.is_ok() {
+            debug!("All connections of the listener were drained");
+            return 0;
+        }
+
+        let remaining = self.connections.live();
+        warn!(
+            "Closing {} connections that were not drained before the timeout",
+            remaining
+        );
+        self.close_all();
+
+        remaining
+    }
+}
+
+impl std::fmt::Debug for ListenerHandle {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter
+            .debug_struct("ListenerHandle")
+            .field("shutdown", &self.shutdown.is_shutdown())
+            .field("live_connections", &self.live_connections())
+            .finish()
+    }
+}
@@ -0,0 +1,29 @@
+/// A snapshot of the activity on a connection, or on one of its halves.
+///
+/// The counters are accumulated by the [`ConnectionReader`](`crate::ConnectionReader`) and
+/// [`ConnectionWriter`](`crate::ConnectionWriter`) as they read and write on the network stream,
+/// and include heartbeat datagrams. They are never reset, so they only start again from zero when
+/// a new connection is created.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let stats = conn.stats();
+/// info!("received {} messages ({} bytes)", stats.messages_read, stats.bytes_read);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// Number of datagrams read from the network stream.
+    pub messages_read: u64,
+
+    /// Number of datagrams completely written to the network stream.
+    pub messages_written: u64,
+
+    /// Number of bytes read from the network stream, including datagram headers.
+    pub bytes_read: u64,
+
+    /// Number of bytes written to the network stream, including datagram headers.
+    pub bytes_written: u64,
+}
@@ -0,0 +1,177 @@
+use crate::logging::*;
+use crate::Connection;
+use async_std::process::{Child, ChildStdin};
+use futures::task::{Context, Poll};
+use futures::AsyncWrite;
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+/// Address reported as both the local and peer address of a connection over standard I/O, which
+/// has no socket addresses.
+const UNSPECIFIED_ADDR: ([u8; 4], u16) = ([0, 0, 0, 0], 0);
+
+/// The stdin of a child process, which is dropped when closed so that the child reads the end of
+/// its input, since closing a [`ChildStdin`] only flushes it.
+struct ChildInput(Option<ChildStdin>);
+
+impl AsyncWrite for ChildInput {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.0.as_mut() {
+            Some(stdin) => Pin::new(stdin).poll_write(cx, buf),
+            None => Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.0.as_mut() {
+            Some(stdin) => Pin::new(stdin).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if let Some(stdin) = self.0.as_mut() {
+            futures::ready!(Pin::new(stdin).poll_close(cx))?;
+        }
+
+        self.0 = None;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Connection {
+    /// Creates a [`Connection`] to a spawned child process that reads datagrams from the child's
+    /// stdout and writes datagrams to its stdin, such as to exchange messages with a plugin.
+    ///
+    /// Both handles must have been set up with [`Stdio::piped`](`std::process::Stdio::piped`) and
+    /// are taken out of `child`, which remains responsible for waiting on the process. The child's
+    /// stderr is not touched, so it can still be inherited or read separately for diagnostics.
+    ///
+    /// The child exiting, or otherwise closing its stdout, ends the [`ConnectionReader`]'s stream
+    /// like a peer closing a network connection. Closing the [`ConnectionWriter`] closes the child's
+    /// stdin, which most programs take as the signal to exit.
+    ///
+    /// Pipes have no socket addresses, so the [`local_addr`](Connection::local_addr) and
+    /// [`peer_addr`](Connection::peer_addr) of the connection are both the unspecified address
+    /// `0.0.0.0:0`.
+    ///
+    /// [`ConnectionReader`]: `crate::ConnectionReader`
+    /// [`ConnectionWriter`]: `crate::ConnectionWriter`
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut child = Command::new("./plugin")
+    ///     .stdin(Stdio::piped())
+    ///     .stdout(Stdio::piped())
+    ///     .spawn()?;
+    ///
+    /// let mut conn = Connection::from_child(&mut child)?;
+    /// ```
+    pub fn from_child(child: &mut Child) -> anyhow::Result<Self> {
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("the stdin of the child process is not piped"))?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("the stdout of the child process is not piped"))?;
+
+        info!("Established connection to child process {}", child.id());
+
+        let addr = SocketAddr::from(UNSPECIFIED_ADDR);
+        Ok(Self::new(
+            addr,
+            addr,
+            Box::pin(stdout),
+            Box::pin(ChildInput(Some(stdin))),
+        ))
+    }
+
+    /// Creates a [`Connection`] to the parent process over the standard input and output of the
+    /// current process, the counterpart of [`Connection::from_child`] for use in the child.
+    ///
+    /// Nothing else may read from stdin or write to stdout while the connection is in use, since
+    /// that would corrupt the datagram framing, so log to stderr instead.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut conn = Connection::from_stdio();
+    ///
+    /// while let Some(request) = conn.reader().next().await {
+    ///     conn.writer().send(handle(request)).await?;
+    /// }
+    /// ```
+    pub fn from_stdio() -> Self {
+        let addr = SocketAddr::from(UNSPECIFIED_ADDR);
+
+        Self::new(
+            addr,
+            addr,
+            Box::pin(async_std::io::stdin()),
+            Box::pin(async_std::io::stdout()),
+        )
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::{CloseReason, ConnectDatagram, Connection};
+    use async_std::process::{Command, Stdio};
+    use futures::{SinkExt, StreamExt};
+
+    #[async_std::test]
+    async fn child_echoes_datagrams() -> anyhow::Result<()> {
+        let mut child = Command::new("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let (mut reader, mut writer) = Connection::from_child(&mut child)?.split();
+        assert!(child.stdin.is_none() && child.stdout.is_none());
+
+        for tag in 1..=3 {
+            writer
+                .send(ConnectDatagram::with_tag(tag, vec![tag as u8; 8])?)
+                .await?;
+        }
+
+        for tag in 1..=3 {
+            let datagram = reader.next().await.expect("connection closed");
+            assert_eq!(tag, datagram.tag());
+            assert_eq!(&[tag as u8; 8], datagram.data());
+        }
+
+        // closing stdin makes the child exit, which closes its stdout
+        writer.close().await?;
+        assert!(reader.next().await.is_none());
+        assert!(matches!(
+            reader.close_reason(),
+            Some(CloseReason::PeerClosed)
+        ));
+        assert!(child.status().await?.success());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn child_without_pipes() -> anyhow::Result<()> {
+        let mut child = Command::new("true").stdin(Stdio::null()).spawn()?;
+
+        assert!(Connection::from_child(&mut child).is_err());
+        child.status().await?;
+
+        Ok(())
+    }
+}
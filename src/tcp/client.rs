@@ -1,7 +1,138 @@
-use log::*;
+use crate::logging::*;
 
 use crate::Connection;
-use async_std::net::{TcpStream, ToSocketAddrs};
+use async_std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use socket2::{SockRef, TcpKeepalive};
+use std::error::Error;
+use std::time::Duration;
+
+/// Socket options applied to TCP connections, either when connecting with
+/// [`Connection::tcp_client_with_options`] or when accepting with
+/// [`TcpListener::with_socket_options`](`crate::tcp::TcpListener::with_socket_options`).
+///
+/// Options left as `None` keep the operating system defaults.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let options = TcpConnectOptions {
+///     nodelay: false,
+///     keepalive: Some(Duration::from_secs(60)),
+///     connect_timeout: Some(Duration::from_secs(5)),
+///     ..TcpConnectOptions::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpConnectOptions {
+    /// Whether to disable Nagle's algorithm with `TCP_NODELAY`. Defaults to `true`.
+    pub nodelay: bool,
+
+    /// Enables `SO_KEEPALIVE`, using this duration as both the idle time before the first probe
+    /// and the interval between probes.
+    pub keepalive: Option<Duration>,
+
+    /// Maximum time to wait for the connection to be established. Ignored for accepted
+    /// connections.
+    pub connect_timeout: Option<Duration>,
+
+    /// Size of the socket send buffer with `SO_SNDBUF`.
+    pub send_buffer_size: Option<usize>,
+
+    /// Size of the socket receive buffer with `SO_RCVBUF`.
+    pub recv_buffer_size: Option<usize>,
+}
+
+impl Default for TcpConnectOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive: None,
+            connect_timeout: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
+}
+
+impl TcpConnectOptions {
+    /// Establishes a TCP connection within the connect timeout and applies the socket options.
+    pub(crate) async fn connect<A: ToSocketAddrs + std::fmt::Display>(
+        &self,
+        ip_addrs: A,
+    ) -> anyhow::Result<TcpStream> {
+        let stream = match self.connect_timeout {
+            Some(timeout) => async_std::io::timeout(timeout, TcpStream::connect(&ip_addrs)).await?,
+            None => TcpStream::connect(&ip_addrs).await?,
+        };
+        info!("Established client TCP connection to {}", ip_addrs);
+
+        self.apply(&stream)?;
+        Ok(stream)
+    }
+
+    pub(crate) fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        let socket = SockRef::from(stream);
+
+        socket.set_nodelay(self.nodelay)?;
+
+        if let Some(keepalive) = self.keepalive {
+            socket.set_tcp_keepalive(
+                &TcpKeepalive::new()
+                    .with_time(keepalive)
+                    .with_interval(keepalive),
+            )?;
+        }
+
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Encountered when none of the addresses passed to [`Connection::tcp_client_any`] could be
+/// connected to.
+///
+#[derive(Debug)]
+pub struct TcpConnectAnyError {
+    attempts: Vec<(SocketAddr, std::io::Error)>,
+}
+
+impl TcpConnectAnyError {
+    /// Get the error of every connection attempt, in the order that the attempts failed.
+    ///
+    /// This is empty when no addresses were provided.
+    pub fn attempts(&self) -> &[(SocketAddr, std::io::Error)] {
+        &self.attempts
+    }
+}
+
+impl Error for TcpConnectAnyError {}
+
+impl std::fmt::Display for TcpConnectAnyError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.attempts.is_empty() {
+            return write!(formatter, "no addresses to connect to");
+        }
+
+        write!(formatter, "could not connect to any address")?;
+        for (i, (addr, err)) in self.attempts.iter().enumerate() {
+            let separator = if i == 0 { ": " } else { ", " };
+            write!(formatter, "{}{} ({})", separator, addr, err)?;
+        }
+
+        Ok(())
+    }
+}
 
 impl Connection {
     /// Creates a [`Connection`] that uses a TCP transport.
@@ -19,12 +150,162 @@ impl Connection {
     pub async fn tcp_client<A: ToSocketAddrs + std::fmt::Display>(
         ip_addrs: A,
     ) -> anyhow::Result<Self> {
+        Self::tcp_client_with_options(ip_addrs, TcpConnectOptions::default()).await
+    }
+
+    /// Creates a [`Connection`] that uses a TCP transport, failing with a
+    /// [`TimedOut`](`std::io::ErrorKind::TimedOut`) error if the connection is not established
+    /// within `timeout`.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut conn = Connection::tcp_client_timeout("127.0.0.1:3456", Duration::from_secs(2)).await?;
+    /// ```
+    pub async fn tcp_client_timeout<A: ToSocketAddrs + std::fmt::Display>(
+        ip_addrs: A,
+        timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let options = TcpConnectOptions {
+            connect_timeout: Some(timeout),
+            ..TcpConnectOptions::default()
+        };
+
+        Self::tcp_client_with_options(ip_addrs, options).await
+    }
+
+    /// Creates a [`Connection`] that uses a TCP transport, configuring the socket with `options`.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let options = TcpConnectOptions {
+    ///     keepalive: Some(Duration::from_secs(60)),
+    ///     ..TcpConnectOptions::default()
+    /// };
+    /// let mut conn = Connection::tcp_client_with_options("127.0.0.1:3456", options).await?;
+    /// ```
+    pub async fn tcp_client_with_options<A: ToSocketAddrs + std::fmt::Display>(
+        ip_addrs: A,
+        options: TcpConnectOptions,
+    ) -> anyhow::Result<Self> {
+        let stream = options.connect(ip_addrs).await?;
+        Ok(Self::from(stream))
+    }
+
+    /// Creates a [`Connection`] that uses a TCP transport, calling `configure` on the established
+    /// [`TcpStream`] before it is wrapped, to set socket options not covered by
+    /// [`TcpConnectOptions`] such as the TTL, DSCP marking or `SO_LINGER`.
+    ///
+    /// The default options are applied first, so `configure` can also override them. Any error
+    /// returned by `configure` fails the connection attempt, and the socket is closed.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut conn = Connection::tcp_client_with("127.0.0.1:3456", |stream| {
+    ///     stream.set_ttl(32)?;
+    ///     SockRef::from(stream).set_tos(0xb8) // DSCP EF
+    /// })
+    /// .await?;
+    /// ```
+    pub async fn tcp_client_with<A, F>(ip_addrs: A, configure: F) -> anyhow::Result<Self>
+    where
+        A: ToSocketAddrs + std::fmt::Display,
+        F: FnOnce(&TcpStream) -> std::io::Result<()>,
+    {
         let stream = TcpStream::connect(&ip_addrs).await?;
         info!("Established client TCP connection to {}", ip_addrs);
 
-        stream.set_nodelay(true)?;
+        TcpConnectOptions::default().apply(&stream)?;
+        configure(&stream)?;
         Ok(Self::from(stream))
     }
+
+    /// Creates a [`Connection`] that uses a TCP transport to whichever of `addrs` accepts the
+    /// connection first, such as the IPv4 and IPv6 addresses of a dual-stack host or the replicas
+    /// of a service.
+    ///
+    /// All addresses are attempted concurrently, and each attempt fails with a
+    /// [`TimedOut`](`std::io::ErrorKind::TimedOut`) error if it is not established within
+    /// `timeout`. As soon as one attempt succeeds the others are cancelled, which closes their
+    /// sockets.
+    ///
+    /// If every attempt fails, the returned error is a [`TcpConnectAnyError`] listing the error of
+    /// each address, which can be inspected with `downcast_ref`.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let replicas: Vec<SocketAddr> = discover("my-service").await?;
+    /// let mut conn = Connection::tcp_client_any(replicas, Duration::from_secs(2)).await?;
+    /// ```
+    pub async fn tcp_client_any<I: IntoIterator<Item = SocketAddr>>(
+        addrs: I,
+        timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let mut attempts: FuturesUnordered<_> = addrs
+            .into_iter()
+            .map(|addr| async move {
+                let res = async_std::io::timeout(timeout, TcpStream::connect(addr)).await;
+                (addr, res)
+            })
+            .collect();
+
+        let mut errors = Vec::new();
+        while let Some((addr, res)) = attempts.next().await {
+            match res {
+                Ok(stream) => {
+                    info!("Established client TCP connection to {}", addr);
+                    drop(attempts);
+
+                    TcpConnectOptions::default().apply(&stream)?;
+                    return Ok(Self::from(stream));
+                }
+
+                Err(err) => {
+                    debug!("Could not connect to {}: {}", addr, err);
+                    errors.push((addr, err));
+                }
+            }
+        }
+
+        Err(TcpConnectAnyError { attempts: errors }.into())
+    }
+
+    /// Creates a [`Connection`] using a TCP transport from an async [`TcpStream`] whose first
+    /// bytes have already been read, such as by a front-end that peeks at them to route the stream
+    /// by protocol.
+    ///
+    /// The `initial` bytes are processed by the [`ConnectionReader`](`crate::ConnectionReader`)
+    /// before anything else is read from the stream, so they must be the bytes that were consumed
+    /// from the start of the stream, in order.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut sniffed = vec![0; 4];
+    /// stream.read_exact(&mut sniffed).await?;
+    ///
+    /// if is_connect_protocol(&sniffed) {
+    ///     let conn = Connection::from_prebuffered(stream, sniffed);
+    /// }
+    /// ```
+    pub fn from_prebuffered(stream: TcpStream, initial: impl AsRef<[u8]>) -> Self {
+        let mut conn = Self::from(stream);
+        conn.reader().unread(initial.as_ref());
+        conn
+    }
 }
 
 impl From<TcpStream> for Connection {
@@ -48,3 +329,106 @@ impl From<TcpStream> for Connection {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{TcpConnectAnyError, TcpConnectOptions};
+    use crate::{ConnectDatagram, Connection, StreamExt};
+    use async_std::net::{TcpListener, TcpStream};
+    use futures::AsyncWriteExt;
+    use socket2::SockRef;
+    use std::time::Duration;
+
+    #[async_std::test]
+    async fn apply_options() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let stream = TcpStream::connect(listener.local_addr()?).await?;
+
+        let options = TcpConnectOptions {
+            nodelay: false,
+            keepalive: Some(Duration::from_secs(30)),
+            ..TcpConnectOptions::default()
+        };
+        options.apply(&stream)?;
+
+        let socket = SockRef::from(&stream);
+        assert!(!socket.nodelay()?);
+        assert!(socket.keepalive()?);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn configure_stream() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+
+        let mut ttl = None;
+        Connection::tcp_client_with(listener.local_addr()?, |stream| {
+            stream.set_ttl(42)?;
+            ttl = Some(stream.ttl()?);
+            Ok(())
+        })
+        .await?;
+        assert_eq!(Some(42), ttl);
+
+        let res = Connection::tcp_client_with(listener.local_addr()?, |_| {
+            Err(std::io::Error::other("rejected by configure"))
+        })
+        .await;
+        assert!(res.is_err());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn prebuffered_bytes_read_first() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = TcpStream::connect(listener.local_addr()?).await?;
+        let server = listener.accept().await?.0;
+
+        let first = ConnectDatagram::with_tag(1, vec![1])?;
+        let second = ConnectDatagram::with_tag(2, vec![2; 16])?;
+
+        // the front-end consumed the first datagram and part of the second
+        let mut initial = first.clone().into_bytes();
+        let second_bytes = second.clone().into_bytes();
+        initial.extend_from_slice(&second_bytes[..8]);
+
+        client.write_all(&second_bytes[8..]).await?;
+        drop(client);
+
+        let mut conn = Connection::from_prebuffered(server, initial);
+        assert_eq!(Some(first), conn.reader().next().await);
+        assert_eq!(Some(second), conn.reader().next().await);
+        assert_eq!(None, conn.reader().next().await);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn connect_to_any_address() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+
+        // reserve an address that refuses connections
+        let closed_addr = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+
+        let conn = Connection::tcp_client_any(
+            vec![closed_addr, listener.local_addr()?],
+            Duration::from_secs(5),
+        )
+        .await?;
+        assert_eq!(listener.local_addr()?, conn.peer_addr());
+
+        let err = Connection::tcp_client_any(vec![closed_addr], Duration::from_secs(5))
+            .await
+            .err()
+            .expect("connected to a closed address");
+        let err = err
+            .downcast_ref::<TcpConnectAnyError>()
+            .expect("unexpected error type");
+        assert_eq!(1, err.attempts().len());
+        assert_eq!(closed_addr, err.attempts()[0].0);
+
+        Ok(())
+    }
+}
@@ -1,11 +1,26 @@
-use crate::Connection;
+use crate::affinity::AffinityAssigner;
+use crate::conn_limit::ConnectionLimiter;
+use crate::logging::*;
+use crate::rate_limit::TokenBucket;
+use crate::tcp::TcpConnectOptions;
+use crate::{AffinityStrategy, Connection, ListenerHandle, ShutdownHandle};
 use async_std::net::{SocketAddr, TcpListener as AsyncListener, TcpStream, ToSocketAddrs};
 use async_std::pin::Pin;
 use async_std::task::{Context, Poll};
 use async_stream::stream;
+use futures::future::poll_fn;
 use futures::Stream;
 use futures_lite::StreamExt;
-use log::*;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::Ipv6Addr;
+use std::time::Duration;
+
+type AcceptStream =
+    Pin<Box<dyn Stream<Item = Option<Result<TcpStream, std::io::Error>>> + Send + Sync>>;
+
+/// A predicate on the peer address of each incoming connection, which drops the connection when
+/// it returns `false`.
+pub(crate) type AcceptFilter = Box<dyn Fn(&SocketAddr) -> bool + Send + Sync>;
 
 /// Listens on a bound socket for incoming TCP connections to be handled as independent
 /// [`Connection`]s.
@@ -31,8 +46,14 @@ use log::*;
 pub struct TcpListener {
     local_addrs: SocketAddr,
     // listener: AsyncListener,
-    conn_stream:
-        Pin<Box<dyn Stream<Item = Option<Result<TcpStream, std::io::Error>>> + Send + Sync>>,
+    conn_stream: AcceptStream,
+    accept_limiter: Option<TokenBucket>,
+    affinity: Option<AffinityAssigner>,
+    conn_limiter: ConnectionLimiter,
+    socket_options: Option<TcpConnectOptions>,
+    idle_timeout: Option<Duration>,
+    accept_filter: Option<AcceptFilter>,
+    shutdown: ShutdownHandle,
 }
 
 impl TcpListener {
@@ -50,6 +71,49 @@ impl TcpListener {
         let listener = AsyncListener::bind(&ip_addrs).await?;
         info!("Started TCP server at {}", &ip_addrs);
 
+        Self::from_listener(listener)
+    }
+
+    /// Creates a [`TcpListener`] by binding a single dual-stack socket to the unspecified IPv6
+    /// address `[::]` and the provided port, to accept both IPv4 and IPv6 connections.
+    ///
+    /// Whether a socket bound with [`bind`](TcpListener::bind) to `[::]` also accepts IPv4
+    /// connections depends on the platform's default for `IPV6_V6ONLY`, which this clears
+    /// explicitly. Passing port `0` binds to a port chosen by the operating system.
+    ///
+    /// Connections over IPv4 are reported with an IPv4-mapped IPv6 address such as
+    /// `::ffff:127.0.0.1` as their [`peer_addr`](Connection::peer_addr), which can be converted back
+    /// to the IPv4 address with [`IpAddr::to_canonical`](std::net::IpAddr::to_canonical).
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TcpListener::bind_dual_stack(3456).await?;
+    ///
+    /// while let Some(conn) = server.next().await {
+    ///     let peer_ip = conn.peer_addr().ip().to_canonical();
+    /// }
+    /// ```
+    pub async fn bind_dual_stack(port: u16) -> anyhow::Result<Self> {
+        let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+
+        let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_only_v6(false)?;
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        socket.set_nonblocking(true)?;
+
+        let listener = AsyncListener::from(std::net::TcpListener::from(socket));
+        info!("Started dual-stack TCP server at {}", addr);
+
+        Self::from_listener(listener)
+    }
+
+    fn from_listener(listener: AsyncListener) -> anyhow::Result<Self> {
         let local_addrs = listener.local_addr()?;
 
         let stream = Box::pin(stream! {
@@ -62,56 +126,543 @@ impl TcpListener {
             local_addrs,
             // listener,
             conn_stream: stream,
+            accept_limiter: None,
+            affinity: None,
+            conn_limiter: ConnectionLimiter::unlimited(),
+            socket_options: None,
+            idle_timeout: None,
+            accept_filter: None,
+            shutdown: ShutdownHandle::new(),
         })
     }
 
-    // /// Creates a [`Connection`] for the next `accept`ed TCP connection at the bound socket.
-    // ///
-    // /// # Example
-    // ///
-    // /// Basic usage:
-    // ///
-    // /// ```ignore
-    // /// let mut server = TcpListener::bind("127.0.0.1:3456").await?;
-    // /// while let Ok(mut conn) = server.accept().await? {
-    // ///     // handle the connection
-    // /// }
-    // /// ```
-    // pub async fn accept(&self) -> anyhow::Result<Connection> {
-    //     let (stream, ip_addr) = self.listener.accept().await?;
-    //     debug!("Received connection attempt from {}", ip_addr);
-    //
-    //     Ok(Connection::from(stream))
-    // }
+    /// Get the local IP address and port that the listener is bound to.
+    ///
+    /// When binding to port `0`, this is the port that was assigned by the operating system, such
+    /// as to tell clients where to connect to.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let server = TcpListener::bind("127.0.0.1:0").await?;
+    /// let port = server.local_addr().port();
+    /// ```
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs
+    }
+
+    /// Assigns an affinity hint to each accepted [`Connection`] according to `strategy`, which can
+    /// be read with [`Connection::affinity_hint`] to dispatch the connection to a worker.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TcpListener::bind("127.0.0.1:3456")
+    ///     .await?
+    ///     .with_affinity(AffinityStrategy::RoundRobin { workers: 4 });
+    ///
+    /// while let Some(conn) = server.next().await {
+    ///     workers[conn.affinity_hint()].send(conn).await?;
+    /// }
+    /// ```
+    pub fn with_affinity(mut self, strategy: AffinityStrategy) -> Self {
+        self.affinity = Some(AffinityAssigner::new(strategy));
+        self
+    }
+
+    /// Applies `options` to the socket of each accepted TCP connection.
+    ///
+    /// The `connect_timeout` option does not apply to accepted connections and is ignored.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TcpListener::bind("127.0.0.1:3456")
+    ///     .await?
+    ///     .with_socket_options(TcpConnectOptions {
+    ///         keepalive: Some(Duration::from_secs(60)),
+    ///         ..TcpConnectOptions::default()
+    ///     });
+    /// ```
+    pub fn with_socket_options(mut self, options: TcpConnectOptions) -> Self {
+        self.socket_options = Some(options);
+        self
+    }
+
+    /// Closes each accepted [`Connection`] once no datagram has been read or written on it for
+    /// longer than `timeout`. See [`Connection::set_idle_timeout`].
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TcpListener::bind("127.0.0.1:3456")
+    ///     .await?
+    ///     .with_idle_timeout(Duration::from_secs(300));
+    /// ```
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Drops incoming TCP connections for which `filter` returns `false` on the peer address,
+    /// before any resources are spent on them.
+    ///
+    /// Rejected connections are logged at the debug level and closed right away, and they do not
+    /// count towards the accept rate limit or the maximum number of connections.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TcpListener::bind("0.0.0.0:3456")
+    ///     .await?
+    ///     .with_accept_filter(move |peer_addr| !denylist.contains(&peer_addr.ip()));
+    /// ```
+    pub fn with_accept_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        self.accept_filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Caps the number of live [`Connection`]s accepted by the listener at `max`.
+    ///
+    /// Once `max` accepted connections are alive, the listener stops accepting and its stream stays
+    /// pending, leaving new connection attempts in the OS backlog. Accepting resumes as soon as a
+    /// connection is dropped. A [`split`](`Connection::split`) connection stays alive until both of
+    /// its halves are dropped.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TcpListener::bind("127.0.0.1:3456")
+    ///     .await?
+    ///     .with_max_connections(10_000);
+    /// ```
+    pub fn with_max_connections(self, max: usize) -> Self {
+        self.conn_limiter.set_max(max);
+        self
+    }
+
+    /// Get a [`ShutdownHandle`] that stops the listener from accepting new connections, causing
+    /// its stream to finish.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TcpListener::bind("127.0.0.1:3456").await?;
+    /// let handle = server.shutdown_handle();
+    ///
+    /// // later, from another task
+    /// handle.shutdown();
+    /// ```
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Get a [`ListenerHandle`] that stops the listener from accepting new connections and drains
+    /// or closes the connections it has accepted.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TcpListener::bind("127.0.0.1:3456").await?;
+    /// let handle = server.handle();
+    ///
+    /// // later, from another task
+    /// handle.drain(Duration::from_secs(30)).await;
+    /// ```
+    pub fn handle(&self) -> ListenerHandle {
+        ListenerHandle::new(self.shutdown.clone(), self.conn_limiter.clone())
+    }
+
+    /// Caps the rate at which new TCP connections are accepted to `per_sec` connections per
+    /// second.
+    ///
+    /// Connection attempts beyond the rate are left in the OS backlog until the listener is ready to
+    /// accept them again. The limit is enforced with a token bucket, so a burst of up to `per_sec`
+    /// connections can still be accepted at once after a quiet period.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TcpListener::bind("127.0.0.1:3456").await?;
+    /// server.set_accept_rate_limit(100);
+    /// ```
+    pub fn set_accept_rate_limit(&mut self, per_sec: u32) {
+        self.accept_limiter = Some(TokenBucket::new(per_sec as u64));
+    }
+
+    /// Creates a [`Connection`] for the next `accept`ed TCP connection at the bound socket.
+    ///
+    /// Unlike the [`Stream`] implementation, which logs and skips accept errors, this returns them
+    /// to the caller so that conditions such as running out of file descriptors can be handled
+    /// (e.g. by backing off before accepting again).
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TcpListener::bind("127.0.0.1:3456").await?;
+    /// loop {
+    ///     match server.accept().await {
+    ///         Ok(conn) => {
+    ///             // handle the connection
+    ///         }
+    ///         Err(err) => {
+    ///             error!("Could not accept connection: {}", err);
+    ///             async_std::task::sleep(Duration::from_millis(100)).await;
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub async fn accept(&mut self) -> std::io::Result<Connection> {
+        match poll_fn(|cx| self.poll_accept(cx)).await {
+            Some(res) => res,
+            None => Err(std::io::Error::other("TCP listener is closed")),
+        }
+    }
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<Option<std::io::Result<Connection>>> {
+        loop {
+            if self.shutdown.poll_shutdown(cx) {
+                debug!("TCP listener at {} was shut down", self.local_addrs);
+                return Poll::Ready(None);
+            }
+
+            if self.conn_limiter.poll_ready(cx).is_pending() {
+                trace!("maximum number of live connections reached, waiting for one to drop");
+                return Poll::Pending;
+            }
+
+            if let Some(limiter) = self.accept_limiter.as_mut() {
+                if limiter.poll_ready(cx, 1).is_pending() {
+                    trace!("accept rate limit reached, waiting to accept the next connection");
+                    return Poll::Pending;
+                }
+            }
+
+            return match self.conn_stream.poll_next(cx) {
+                Poll::Ready(Some(Some(Ok(tcp_stream)))) => {
+                    let peer_addr = match tcp_stream.peer_addr() {
+                        Ok(peer_addr) => peer_addr,
+                        Err(err) => return Poll::Ready(Some(Err(err))),
+                    };
+                    debug!("Received connection attempt from {}", peer_addr);
+
+                    if let Some(filter) = self.accept_filter.as_ref() {
+                        if !filter(&peer_addr) {
+                            debug!(
+                                "Rejected connection from {} by the accept filter",
+                                peer_addr
+                            );
+                            continue;
+                        }
+                    }
+
+                    if let Some(options) = self.socket_options.as_ref() {
+                        if let Err(err) = options.apply(&tcp_stream) {
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                    }
+
+                    if let Some(limiter) = self.accept_limiter.as_mut() {
+                        limiter.take(1);
+                    }
+
+                    let mut conn = Connection::from(tcp_stream);
+                    if let Some(affinity) = self.affinity.as_mut() {
+                        conn.set_affinity_hint(affinity.assign(&peer_addr));
+                    }
+                    conn.set_slot(self.conn_limiter.acquire());
+                    if let Some(timeout) = self.idle_timeout {
+                        conn.set_idle_timeout(timeout);
+                    }
+
+                    Poll::Ready(Some(Ok(conn)))
+                }
+
+                Poll::Ready(Some(Some(Err(err)))) => Poll::Ready(Some(Err(err))),
+
+                Poll::Ready(Some(None)) => Poll::Ready(None),
+
+                Poll::Ready(None) => Poll::Ready(None),
+
+                Poll::Pending => Poll::Pending,
+            };
+        }
+    }
 }
 
 impl Stream for TcpListener {
     type Item = Connection;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match self.conn_stream.poll_next(cx) {
-            Poll::Ready(Some(Some(Ok(tcp_stream)))) => {
-                let peer_addr = tcp_stream
-                    .peer_addr()
-                    .expect("Could not retrieve peer IP address");
-                debug!("Received connection attempt from {}", peer_addr);
-
-                Poll::Ready(Some(Connection::from(tcp_stream)))
-            }
+        match self.poll_accept(cx) {
+            Poll::Ready(Some(Ok(conn))) => Poll::Ready(Some(conn)),
 
-            Poll::Ready(Some(Some(Err(err)))) => {
+            Poll::Ready(Some(Err(err))) => {
                 error!(
                     "Encountered error when trying to accept new connection {}",
                     err
                 );
+
+                // the error was not caused by a pending operation, so make sure to be polled again
+                cx.waker().wake_by_ref();
                 Poll::Pending
             }
 
-            Poll::Ready(Some(None)) => Poll::Ready(None),
-
             Poll::Ready(None) => Poll::Ready(None),
 
             Poll::Pending => Poll::Pending,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::TcpListener;
+    use crate::{AffinityStrategy, CloseReason, ConnectDatagram, Connection};
+    use async_std::net::TcpStream;
+    use futures::{AsyncReadExt, SinkExt, StreamExt};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::time::{Duration, Instant};
+
+    #[async_std::test]
+    async fn accept_rate_limit() -> anyhow::Result<()> {
+        let mut server = TcpListener::bind("127.0.0.1:0").await?;
+        server.set_accept_rate_limit(10);
+
+        let mut clients = Vec::new();
+        for _ in 0..15 {
+            clients.push(TcpStream::connect(server.local_addrs).await?);
+        }
+
+        let start = Instant::now();
+        for _ in 0..15 {
+            assert!(server.next().await.is_some());
+        }
+
+        // the first 10 connections are accepted as a burst, the remaining 5 are paced at 10/s
+        assert!(start.elapsed() >= Duration::from_millis(450));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn dual_stack_accepts_both_families() -> anyhow::Result<()> {
+        let mut server = TcpListener::bind_dual_stack(0).await?;
+        let port = server.local_addrs.port();
+
+        let _v4 = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).await?;
+        let conn = server.next().await.expect("listener closed unexpectedly");
+        assert!(conn.peer_addr().is_ipv6());
+        assert_eq!(
+            IpAddr::from(Ipv4Addr::LOCALHOST),
+            conn.peer_addr().ip().to_canonical()
+        );
+
+        let _v6 = TcpStream::connect((Ipv6Addr::LOCALHOST, port)).await?;
+        let conn = server.next().await.expect("listener closed unexpectedly");
+        assert_eq!(IpAddr::from(Ipv6Addr::LOCALHOST), conn.peer_addr().ip());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn accept_filter_drops_connections() -> anyhow::Result<()> {
+        let mut server = TcpListener::bind("127.0.0.1:0")
+            .await?
+            .with_accept_filter(|peer_addr| peer_addr.port() % 2 == 0);
+
+        let mut clients = Vec::new();
+        for _ in 0..4 {
+            clients.push(TcpStream::connect(server.local_addrs).await?);
+        }
+        let (allowed, rejected): (Vec<_>, Vec<_>) = clients
+            .into_iter()
+            .partition(|client| client.local_addr().unwrap().port() % 2 == 0);
+
+        for _ in 0..allowed.len() {
+            let conn = server.next().await.expect("listener closed unexpectedly");
+            assert_eq!(0, conn.peer_addr().port() % 2);
+        }
+
+        let next = async_std::future::timeout(Duration::from_millis(100), server.next()).await;
+        assert!(next.is_err());
+
+        // rejected connections are closed right away
+        for mut client in rejected {
+            assert_eq!(0, client.read(&mut [0; 1]).await?);
+        }
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn round_robin_affinity() -> anyhow::Result<()> {
+        let mut server = TcpListener::bind("127.0.0.1:0")
+            .await?
+            .with_affinity(AffinityStrategy::RoundRobin { workers: 4 });
+
+        let mut clients = Vec::new();
+        for _ in 0..12 {
+            clients.push(TcpStream::connect(server.local_addrs).await?);
+        }
+
+        let mut counts = [0; 4];
+        for _ in 0..12 {
+            let conn = server.next().await.expect("listener closed unexpectedly");
+            counts[conn.affinity_hint()] += 1;
+        }
+
+        assert_eq!([3, 3, 3, 3], counts);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn idle_connection_closes() -> anyhow::Result<()> {
+        let mut server = TcpListener::bind("127.0.0.1:0")
+            .await?
+            .with_idle_timeout(Duration::from_millis(100));
+        let mut client = Connection::from(TcpStream::connect(server.local_addrs).await?);
+
+        let conn = server.next().await.expect("listener closed unexpectedly");
+        let (mut reader, writer) = conn.split();
+
+        client
+            .writer()
+            .send(ConnectDatagram::with_tag(1, vec![1])?)
+            .await?;
+        assert!(reader.next().await.is_some());
+
+        let start = Instant::now();
+        let next = async_std::future::timeout(Duration::from_secs(5), reader.next()).await?;
+        assert!(next.is_none());
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        assert!(writer.is_closed());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn accept_returns_connection() -> anyhow::Result<()> {
+        let mut server = TcpListener::bind("127.0.0.1:0").await?;
+        let client = TcpStream::connect(server.local_addrs).await?;
+
+        let conn = server.accept().await?;
+        assert_eq!(client.local_addr()?, conn.peer_addr());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn drain_closes_lingering_connections() -> anyhow::Result<()> {
+        let mut server = TcpListener::bind("127.0.0.1:0").await?;
+        let handle = server.handle();
+
+        let finished_client = Connection::tcp_client(server.local_addr()).await?;
+        let _lingering_client = Connection::tcp_client(server.local_addr()).await?;
+
+        let mut tasks = Vec::new();
+        for _ in 0..2 {
+            let mut conn = server.next().await.expect("listener closed unexpectedly");
+
+            tasks.push(async_std::task::spawn(async move {
+                while conn.reader().next().await.is_some() {}
+                matches!(
+                    conn.reader().close_reason(),
+                    Some(CloseReason::LocalShutdown)
+                )
+            }));
+        }
+        assert_eq!(2, handle.live_connections());
+
+        drop(finished_client);
+        assert_eq!(1, handle.drain(Duration::from_millis(500)).await);
+        assert!(server.next().await.is_none());
+
+        let closed_by_listener: Vec<bool> = futures::future::join_all(tasks).await;
+        assert_eq!(
+            1,
+            closed_by_listener.iter().filter(|closed| **closed).count()
+        );
+        assert_eq!(0, handle.live_connections());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn shutdown_finishes_stream() -> anyhow::Result<()> {
+        let mut server = TcpListener::bind("127.0.0.1:0").await?;
+        let handle = server.shutdown_handle();
+
+        async_std::task::spawn(async move {
+            async_std::task::sleep(Duration::from_millis(50)).await;
+            handle.shutdown();
+        });
+
+        let next = async_std::future::timeout(Duration::from_secs(5), server.next()).await?;
+        assert!(next.is_none());
+        assert!(server.next().await.is_none());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn max_connections() -> anyhow::Result<()> {
+        let mut server = TcpListener::bind("127.0.0.1:0")
+            .await?
+            .with_max_connections(2);
+
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            clients.push(TcpStream::connect(server.local_addrs).await?);
+        }
+
+        let first = server.next().await.expect("listener closed unexpectedly");
+        let (reader, writer) = server
+            .next()
+            .await
+            .expect("listener closed unexpectedly")
+            .split();
+
+        let wait = Duration::from_millis(100);
+        assert!(async_std::future::timeout(wait, server.next())
+            .await
+            .is_err());
+
+        // a split connection is still alive while one of its halves is
+        drop(reader);
+        assert!(async_std::future::timeout(wait, server.next())
+            .await
+            .is_err());
+
+        drop(writer);
+        let third = async_std::future::timeout(Duration::from_secs(5), server.next()).await?;
+        assert!(third.is_some());
+        drop(first);
+
+        Ok(())
+    }
+}
@@ -3,13 +3,18 @@
 //! <br/>
 //!
 //! This module primarily exposes the TCP client implementation over a [`Connection`] type and the
-//! TCP listener implementation as [`TcpListener`].
+//! TCP listener implementation as [`TcpListener`], along with the [`ReconnectingConnection`]
+//! client and the [`ConnectionPool`] of client connections.
 
 #[allow(unused_imports)]
 pub(crate) use crate::Connection;
 
 pub(crate) mod client;
 pub(crate) mod listener;
+pub(crate) mod pool;
+pub(crate) mod reconnect;
 
 pub use client::*;
 pub use listener::*;
+pub use pool::*;
+pub use reconnect::*;
@@ -0,0 +1,557 @@
+use crate::logging::*;
+use crate::tcp::TcpConnectOptions;
+use crate::Connection;
+use futures::channel::oneshot;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The default number of idle connections kept per peer address, see
+/// [`ConnectionPool::with_max_idle`].
+pub const DEFAULT_POOL_MAX_IDLE: usize = 8;
+
+/// The default time after which an idle connection is closed, see
+/// [`ConnectionPool::with_idle_timeout`].
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+struct IdleConnection {
+    conn: Connection,
+    since: Instant,
+}
+
+/// How [`ConnectionPool::get`] obtains a connection.
+#[allow(clippy::large_enum_variant)]
+enum Checkout<'a> {
+    Idle(Connection),
+    Connect(Reservation<'a>),
+    Wait(oneshot::Receiver<()>),
+}
+
+/// Room for a new connection reserved in a [`ConnectionPool`], which is given back to the pool
+/// when dropped unless it is filled, such as when connecting fails or `get` is cancelled while
+/// connecting.
+struct Reservation<'a> {
+    pool: &'a ConnectionPool,
+    filled: bool,
+}
+
+impl Reservation<'_> {
+    fn fill(mut self, addr: SocketAddr, conn: Connection) -> PooledConnection {
+        self.filled = true;
+        self.pool.checked_out(addr, conn)
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.filled {
+            self.pool.detach();
+        }
+    }
+}
+
+struct PoolInner {
+    idle: HashMap<SocketAddr, Vec<IdleConnection>>,
+    open: usize,
+    waiters: Vec<oneshot::Sender<()>>,
+}
+
+impl PoolInner {
+    /// Removes the connections that have been idle for longer than `timeout`.
+    fn evict_expired(&mut self, timeout: Duration, evicted: &mut Vec<Connection>) {
+        let before = evicted.len();
+
+        for (addr, idle) in self.idle.iter_mut() {
+            while idle
+                .first()
+                .is_some_and(|idle| idle.since.elapsed() >= timeout)
+            {
+                debug!("Closing pooled connection to {} after idle timeout", addr);
+                evicted.push(idle.remove(0).conn);
+            }
+        }
+
+        self.idle.retain(|_, idle| !idle.is_empty());
+        self.open -= evicted.len() - before;
+    }
+
+    /// Removes the connection that has been idle for the longest time, across every address.
+    fn evict_oldest(&mut self) -> Option<Connection> {
+        let addr = *self
+            .idle
+            .iter()
+            .filter_map(|(addr, idle)| idle.first().map(|idle| (addr, idle.since)))
+            .min_by_key(|(_, since)| *since)?
+            .0;
+
+        let idle = self.idle.get_mut(&addr)?;
+        let conn = idle.remove(0).conn;
+        if idle.is_empty() {
+            self.idle.remove(&addr);
+        }
+
+        debug!("Closing idle pooled connection to {} to make room", addr);
+        self.open -= 1;
+        Some(conn)
+    }
+
+    /// Wakes the tasks waiting for a connection to be returned to the pool or closed.
+    fn notify(&mut self) {
+        for waiter in self.waiters.drain(..) {
+            let _ = waiter.send(());
+        }
+    }
+}
+
+/// A pool of TCP client [`Connection`]s that keeps idle connections to each peer address, so that
+/// they can be reused instead of connecting again for every interaction.
+///
+/// [`get`](ConnectionPool::get) hands out a [`PooledConnection`], which returns the connection to
+/// the pool when it is dropped.
+///
+/// # Eviction policy
+///
+/// - An idle connection is only reused after checking that it is still alive with
+///   [`Connection::is_alive`], and the most recently returned connection to an address is reused
+///   first, so that the others can time out when the load drops.
+/// - A connection is closed instead of returned to the pool if it is no longer alive, if datagrams
+///   received on it were left unread, or if its address already has the maximum number of idle
+///   connections.
+/// - A connection that has been idle for longer than the idle timeout is closed. There is no
+///   background task, so expired connections are closed whenever the pool is next used.
+/// - When the maximum number of open connections is reached, the connection that has been idle
+///   the longest, to any address, is closed to make room. If none are idle, `get` waits until a
+///   connection is returned or closed.
+///
+/// # Thread-safety
+///
+/// A [`ConnectionPool`] is cheap to clone, and every clone shares the same connections, so it can
+/// be handed to any number of tasks on any threads. Each [`PooledConnection`] is used by a single
+/// task at a time, but can be moved to another task or thread and returns its connection from
+/// wherever it is dropped. The pool's lock is never held while connecting or waiting.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let pool = ConnectionPool::new()
+///     .with_max_idle(4)
+///     .with_max_open(64)
+///     .with_idle_timeout(Duration::from_secs(30));
+///
+/// let mut conn = pool.get(server_addr).await?;
+/// conn.writer().send(request).await?;
+/// let response = conn.reader().next().await;
+///
+/// // the connection is returned to the pool here
+/// drop(conn);
+/// ```
+#[derive(Clone)]
+pub struct ConnectionPool {
+    inner: Arc<Mutex<PoolInner>>,
+    max_idle: usize,
+    max_open: usize,
+    idle_timeout: Duration,
+    options: TcpConnectOptions,
+}
+
+impl Default for ConnectionPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConnectionPool {
+    /// Creates a [`ConnectionPool`] that keeps up to [`DEFAULT_POOL_MAX_IDLE`] idle connections per
+    /// address for up to [`DEFAULT_POOL_IDLE_TIMEOUT`], without limiting the open connections.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(PoolInner {
+                idle: HashMap::new(),
+                open: 0,
+                waiters: Vec::new(),
+            })),
+            max_idle: DEFAULT_POOL_MAX_IDLE,
+            max_open: usize::MAX,
+            idle_timeout: DEFAULT_POOL_IDLE_TIMEOUT,
+            options: TcpConnectOptions::default(),
+        }
+    }
+
+    /// Sets the maximum number of idle connections kept per peer address.
+    pub fn with_max_idle(mut self, max: usize) -> Self {
+        self.max_idle = max;
+        self
+    }
+
+    /// Sets the maximum number of connections open at once across every address, counting both
+    /// idle connections and the ones that are checked out.
+    pub fn with_max_open(mut self, max: usize) -> Self {
+        self.max_open = max.max(1);
+        self
+    }
+
+    /// Sets the time after which an idle connection is closed.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the socket options of new connections.
+    pub fn with_connect_options(mut self, options: TcpConnectOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Get the number of open connections, counting both idle connections and the ones that are
+    /// checked out.
+    pub fn open_connections(&self) -> usize {
+        self.lock().open
+    }
+
+    /// Get the number of idle connections across every address.
+    pub fn idle_connections(&self) -> usize {
+        self.lock().idle.values().map(Vec::len).sum()
+    }
+
+    /// Closes every idle connection. The connections that are checked out are unaffected.
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        let evicted: Vec<IdleConnection> = inner.idle.drain().flat_map(|(_, idle)| idle).collect();
+        inner.open -= evicted.len();
+        inner.notify();
+
+        // the connections are closed without holding the lock
+        drop(inner);
+        drop(evicted);
+    }
+
+    /// Checks out a connection to `addr`, reusing an idle connection that is still alive or
+    /// connecting anew if there is none.
+    ///
+    /// Waits for a connection to be returned or closed while the maximum number of open
+    /// connections are checked out.
+    pub async fn get(&self, addr: SocketAddr) -> anyhow::Result<PooledConnection> {
+        loop {
+            match self.checkout(addr) {
+                Checkout::Idle(mut conn) => {
+                    if is_reusable(&mut conn) {
+                        trace!("reusing pooled connection to {}", addr);
+                        return Ok(self.checked_out(addr, conn));
+                    }
+
+                    debug!(
+                        "Closing pooled connection to {} that is no longer alive or has unread datagrams",
+                        addr
+                    );
+                    self.detach();
+                }
+
+                Checkout::Connect(reservation) => {
+                    let conn = Connection::tcp_client_with_options(addr, self.options).await?;
+                    return Ok(reservation.fill(addr, conn));
+                }
+
+                Checkout::Wait(receiver) => {
+                    trace!("waiting for a pooled connection to be returned");
+                    let _ = receiver.await;
+                }
+            }
+        }
+    }
+
+    /// Takes an idle connection to `addr` out of the pool, or reserves room to open a new one.
+    fn checkout(&self, addr: SocketAddr) -> Checkout<'_> {
+        let mut evicted = Vec::new();
+        let mut inner = self.lock();
+        inner.evict_expired(self.idle_timeout, &mut evicted);
+
+        if let Some(idle) = inner.idle.get_mut(&addr).and_then(Vec::pop) {
+            return Checkout::Idle(idle.conn);
+        }
+
+        if inner.open >= self.max_open {
+            if let Some(conn) = inner.evict_oldest() {
+                evicted.push(conn);
+            }
+        }
+
+        if inner.open < self.max_open {
+            inner.open += 1;
+            return Checkout::Connect(Reservation {
+                pool: self,
+                filled: false,
+            });
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        inner.waiters.push(sender);
+        Checkout::Wait(receiver)
+    }
+
+    fn checked_out(&self, addr: SocketAddr, conn: Connection) -> PooledConnection {
+        PooledConnection {
+            pool: self.clone(),
+            addr,
+            conn: Some(conn),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolInner> {
+        self.inner.lock().expect("connection pool lock is poisoned")
+    }
+
+    /// Takes a checked out connection back, keeping it idle for reuse if it is healthy.
+    fn release(&self, addr: SocketAddr, mut conn: Connection) {
+        let reusable = is_reusable(&mut conn);
+
+        let mut evicted = Vec::new();
+        let mut inner = self.lock();
+        inner.evict_expired(self.idle_timeout, &mut evicted);
+
+        let idle = inner.idle.entry(addr).or_default();
+        if reusable && idle.len() < self.max_idle {
+            trace!("returning connection to {} to the pool", addr);
+            idle.push(IdleConnection {
+                conn,
+                since: Instant::now(),
+            });
+        } else {
+            if idle.is_empty() {
+                inner.idle.remove(&addr);
+            }
+
+            debug!("Closing pooled connection to {}", addr);
+            inner.open -= 1;
+            evicted.push(conn);
+        }
+
+        inner.notify();
+    }
+
+    /// Forgets a checked out connection that is detached from the pool.
+    fn detach(&self) {
+        let mut inner = self.lock();
+        inner.open -= 1;
+        inner.notify();
+    }
+}
+
+/// Checks whether a connection is still alive and has no datagrams left unread, since a response
+/// left unread would be mistaken for the reply to the next request.
+fn is_reusable(conn: &mut Connection) -> bool {
+    // checking that the connection is alive moves any datagrams waiting on the socket into the
+    // reader's buffer, so it must come first
+    conn.is_alive() && conn.reader().buffered_bytes() == 0
+}
+
+/// A [`Connection`] checked out of a [`ConnectionPool`], which is returned to the pool when
+/// dropped.
+///
+/// Dereferences to the [`Connection`], so it can be used like one.
+///
+pub struct PooledConnection {
+    pool: ConnectionPool,
+    addr: SocketAddr,
+    conn: Option<Connection>,
+}
+
+impl PooledConnection {
+    /// Closes the connection instead of returning it to the pool, such as after a protocol error
+    /// that leaves it in an unknown state.
+    pub fn discard(mut self) {
+        if self.conn.take().is_some() {
+            self.pool.detach();
+        }
+    }
+
+    /// Detaches the connection from the pool, which no longer counts it as open.
+    pub fn into_inner(mut self) -> Connection {
+        let conn = self
+            .conn
+            .take()
+            .expect("pooled connection was already taken");
+        self.pool.detach();
+
+        conn
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn
+            .as_ref()
+            .expect("pooled connection was already taken")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conn
+            .as_mut()
+            .expect("pooled connection was already taken")
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.release(self.addr, conn);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConnectionPool;
+    use crate::tcp::TcpListener;
+    use crate::{ConnectDatagram, SinkExt, StreamExt};
+    use futures::FutureExt;
+    use std::time::Duration;
+
+    #[async_std::test]
+    async fn reuses_returned_connections() -> anyhow::Result<()> {
+        let mut server = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = server.local_addr();
+        let pool = ConnectionPool::new();
+
+        let conn = pool.get(addr).await?;
+        let local_addr = conn.local_addr();
+        let mut accepted = server.next().await.expect("listener closed");
+        drop(conn);
+        assert_eq!(1, pool.idle_connections());
+
+        let mut conn = pool.get(addr).await?;
+        assert_eq!(local_addr, conn.local_addr());
+        assert_eq!(1, pool.open_connections());
+
+        conn.writer()
+            .send(ConnectDatagram::with_tag(1, vec![1])?)
+            .await?;
+        assert!(accepted.reader().next().await.is_some());
+
+        // a connection closed by the peer is not reused
+        drop(conn);
+        drop(accepted);
+        async_std::task::sleep(Duration::from_millis(50)).await;
+
+        let conn = pool.get(addr).await?;
+        assert_ne!(local_addr, conn.local_addr());
+        assert_eq!(1, pool.open_connections());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn unread_datagrams_are_not_reused() -> anyhow::Result<()> {
+        let mut server = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = server.local_addr();
+        let pool = ConnectionPool::new();
+
+        let conn = pool.get(addr).await?;
+        let mut accepted = server.next().await.expect("listener closed");
+
+        // the client never reads the reply before returning the connection
+        accepted
+            .writer()
+            .send(ConnectDatagram::with_tag(1, vec![1])?)
+            .await?;
+        async_std::task::sleep(Duration::from_millis(50)).await;
+        drop(conn);
+        assert_eq!(0, pool.idle_connections());
+        assert_eq!(0, pool.open_connections());
+
+        let conn = pool.get(addr).await?;
+        let local_addr = conn.local_addr();
+        let mut accepted = server.next().await.expect("listener closed");
+        drop(conn);
+        assert_eq!(1, pool.idle_connections());
+
+        // nor is an idle connection that received a datagram while in the pool
+        accepted
+            .writer()
+            .send(ConnectDatagram::with_tag(2, vec![2])?)
+            .await?;
+        async_std::task::sleep(Duration::from_millis(50)).await;
+
+        let conn = pool.get(addr).await?;
+        assert_ne!(local_addr, conn.local_addr());
+        assert_eq!(1, pool.open_connections());
+
+        drop(accepted);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn waits_for_max_open() -> anyhow::Result<()> {
+        let server = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = server.local_addr();
+        let pool = ConnectionPool::new().with_max_open(1);
+
+        let conn = pool.get(addr).await?;
+        let local_addr = conn.local_addr();
+
+        let mut waiting = Box::pin(pool.get(addr));
+        assert!((&mut waiting).now_or_never().is_none());
+
+        drop(conn);
+        let conn = waiting.await?;
+        assert_eq!(local_addr, conn.local_addr());
+
+        drop(server);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn cancelled_connect_releases_slot() -> anyhow::Result<()> {
+        let server = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = server.local_addr();
+        let pool = ConnectionPool::new().with_max_open(1);
+
+        // cancel the checkout while it is still connecting
+        assert!(pool.get(addr).now_or_never().is_none());
+        assert_eq!(0, pool.open_connections());
+
+        let conn = async_std::future::timeout(Duration::from_secs(5), pool.get(addr)).await??;
+        assert_eq!(1, pool.open_connections());
+
+        drop(conn);
+        drop(server);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn evicts_idle_connections() -> anyhow::Result<()> {
+        let server = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = server.local_addr();
+        let pool = ConnectionPool::new()
+            .with_max_idle(1)
+            .with_idle_timeout(Duration::from_millis(50));
+
+        let first = pool.get(addr).await?;
+        let second = pool.get(addr).await?;
+        drop(first);
+        drop(second);
+
+        // only one idle connection is kept per address
+        assert_eq!(1, pool.idle_connections());
+        assert_eq!(1, pool.open_connections());
+
+        async_std::task::sleep(Duration::from_millis(100)).await;
+        let conn = pool.get(addr).await?;
+        assert_eq!(0, pool.idle_connections());
+        assert_eq!(1, pool.open_connections());
+
+        drop(conn);
+        pool.clear();
+        assert_eq!(0, pool.open_connections());
+
+        drop(server);
+        Ok(())
+    }
+}
@@ -0,0 +1,399 @@
+use crate::logging::*;
+use crate::{
+    ConnectDatagram, Connection, ConnectionReader, ConnectionWriteError, ConnectionWriter,
+};
+use async_io::Timer;
+use async_std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use async_std::pin::Pin;
+use async_std::task::{Context, Poll};
+use bytes::Bytes;
+use futures::{Future, Sink, SinkExt, Stream, StreamExt};
+use std::time::Duration;
+
+type ConnectFuture = Pin<Box<dyn Future<Output = std::io::Result<TcpStream>> + Send + Sync>>;
+
+/// Controls how a [`ReconnectingConnection`] re-dials its peer after the connection is lost.
+///
+/// The delay before each reconnection attempt starts at `base_delay` and doubles with every failed
+/// attempt, up to `max_delay`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Delay before the first reconnection attempt.
+    pub base_delay: Duration,
+
+    /// Upper bound on the delay between reconnection attempts.
+    pub max_delay: Duration,
+
+    /// Number of consecutive failed attempts after which the connection is given up on, or `None`
+    /// to retry forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+enum ReconnectState {
+    Connected {
+        reader: ConnectionReader,
+        writer: ConnectionWriter,
+    },
+    Reconnecting {
+        attempt: u32,
+        connect: ConnectFuture,
+    },
+    Closed,
+}
+
+/// A TCP client connection that transparently re-dials its peer with exponential backoff when the
+/// underlying network stream closes or fails.
+///
+/// Implements the [`Stream`] and [`Sink`] traits with the same semantics as a
+/// [`ConnectionReader`] and [`ConnectionWriter`], except that a lost connection is reconnected
+/// instead of ending the stream. The stream only ends once the [`RetryPolicy`] gives up or the
+/// sink is closed.
+///
+/// Datagrams that were queued on the writer but not completely written to the network stream when
+/// the connection was lost are replayed after reconnecting. Datagrams that were already written to
+/// the network stream but not received by the peer are lost, and a partially received datagram is
+/// discarded by the peer, so applications that need delivery guarantees must acknowledge messages
+/// themselves.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let mut conn = Connection::tcp_client_reconnecting("127.0.0.1:3456", RetryPolicy::default()).await?;
+///
+/// conn.send(ConnectDatagram::with_tag(1, b"hello".to_vec())?).await?;
+///
+/// while let Some(msg) = conn.next().await {
+///     // handle the received message, reconnecting as needed
+/// }
+/// ```
+pub struct ReconnectingConnection {
+    ip_addrs: String,
+    policy: RetryPolicy,
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
+    state: ReconnectState,
+    replay: Vec<Bytes>,
+}
+
+impl Connection {
+    /// Creates a [`ReconnectingConnection`] that uses a TCP transport and re-dials the peer
+    /// according to `policy` whenever the connection is lost.
+    ///
+    /// The initial connection attempt is not retried, and its error is returned directly.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut conn = Connection::tcp_client_reconnecting("127.0.0.1:3456", RetryPolicy::default()).await?;
+    /// ```
+    pub async fn tcp_client_reconnecting<A: ToSocketAddrs + std::fmt::Display>(
+        ip_addrs: A,
+        policy: RetryPolicy,
+    ) -> anyhow::Result<ReconnectingConnection> {
+        let stream = TcpStream::connect(&ip_addrs).await?;
+        info!("Established client TCP connection to {}", ip_addrs);
+
+        let mut conn = ReconnectingConnection {
+            ip_addrs: ip_addrs.to_string(),
+            policy,
+            local_addr: stream.local_addr()?,
+            peer_addr: stream.peer_addr()?,
+            state: ReconnectState::Closed,
+            replay: Vec::new(),
+        };
+        conn.connected(stream)?;
+
+        Ok(conn)
+    }
+}
+
+impl ReconnectingConnection {
+    /// Get the local IP address and port of the current network stream.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Get the peer IP address and port of the current network stream.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Check if the connection currently has an established network stream.
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, ReconnectState::Connected { .. })
+    }
+
+    fn connected(&mut self, stream: TcpStream) -> std::io::Result<()> {
+        stream.set_nodelay(true)?;
+        self.local_addr = stream.local_addr()?;
+        self.peer_addr = stream.peer_addr()?;
+
+        let reader =
+            ConnectionReader::new(self.local_addr, self.peer_addr, Box::pin(stream.clone()));
+        let mut writer = ConnectionWriter::new(self.local_addr, self.peer_addr, Box::pin(stream));
+        writer.restore_pending_writes(std::mem::take(&mut self.replay));
+
+        self.state = ReconnectState::Connected { reader, writer };
+        Ok(())
+    }
+
+    fn dial(&self, attempt: u32) -> ConnectFuture {
+        let ip_addrs = self.ip_addrs.clone();
+        let delay = self.policy.delay(attempt);
+
+        Box::pin(async move {
+            Timer::after(delay).await;
+            TcpStream::connect(ip_addrs.as_str()).await
+        })
+    }
+
+    fn reconnect(&mut self) {
+        if let ReconnectState::Connected { mut writer, .. } =
+            std::mem::replace(&mut self.state, ReconnectState::Closed)
+        {
+            warn!(
+                "Lost connection with {}, reconnecting to {}",
+                self.peer_addr, self.ip_addrs
+            );
+            self.replay = writer.take_pending_writes();
+            self.state = ReconnectState::Reconnecting {
+                attempt: 0,
+                connect: self.dial(0),
+            };
+        }
+    }
+
+    fn poll_connected(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), ConnectionWriteError>> {
+        loop {
+            let (attempt, connect) = match &mut self.state {
+                ReconnectState::Connected { .. } => return Poll::Ready(Ok(())),
+                ReconnectState::Closed => {
+                    return Poll::Ready(Err(ConnectionWriteError::ConnectionClosed))
+                }
+                ReconnectState::Reconnecting { attempt, connect } => (*attempt, connect),
+            };
+
+            let res = match connect.as_mut().poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(res) => res.and_then(|stream| self.connected(stream)),
+            };
+
+            match res {
+                Ok(()) => {
+                    info!("Reconnected to {}", self.ip_addrs);
+                    return Poll::Ready(Ok(()));
+                }
+
+                Err(err) => {
+                    let attempt = attempt + 1;
+                    warn!(
+                        "Reconnection attempt {} to {} failed: {}",
+                        attempt, self.ip_addrs, err
+                    );
+
+                    if self.policy.max_attempts.is_some_and(|max| attempt >= max) {
+                        error!(
+                            "Giving up on reconnecting to {} after {} attempts",
+                            self.ip_addrs, attempt
+                        );
+                        self.replay.clear();
+                        self.state = ReconnectState::Closed;
+                        return Poll::Ready(Err(ConnectionWriteError::IoError(err)));
+                    }
+
+                    self.state = ReconnectState::Reconnecting {
+                        attempt,
+                        connect: self.dial(attempt),
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl Stream for ReconnectingConnection {
+    type Item = ConnectDatagram;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match self.poll_connected(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(_)) => return Poll::Ready(None),
+                Poll::Ready(Ok(())) => {}
+            }
+
+            if let ReconnectState::Connected { reader, .. } = &mut self.state {
+                match reader.poll_next_unpin(cx) {
+                    Poll::Ready(None) => self.reconnect(),
+                    res => return res,
+                }
+            }
+        }
+    }
+}
+
+impl Sink<ConnectDatagram> for ReconnectingConnection {
+    type Error = ConnectionWriteError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        loop {
+            match self.poll_connected(cx) {
+                Poll::Ready(Ok(())) => {}
+                res => return res,
+            }
+
+            if let ReconnectState::Connected { writer, .. } = &mut self.state {
+                match writer.poll_ready_unpin(cx) {
+                    Poll::Ready(Err(_)) => self.reconnect(),
+                    res => return res,
+                }
+            }
+        }
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: ConnectDatagram) -> Result<(), Self::Error> {
+        match &mut self.state {
+            ReconnectState::Connected { writer, .. } => writer.start_send_unpin(item),
+
+            ReconnectState::Reconnecting { .. } => {
+                self.replay.push(Bytes::from(item.into_bytes()));
+                Ok(())
+            }
+
+            ReconnectState::Closed => Err(ConnectionWriteError::ConnectionClosed),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        loop {
+            match self.poll_connected(cx) {
+                Poll::Ready(Ok(())) => {}
+                res => return res,
+            }
+
+            if let ReconnectState::Connected { writer, .. } = &mut self.state {
+                match writer.poll_flush_unpin(cx) {
+                    Poll::Ready(Err(_)) => self.reconnect(),
+                    res => return res,
+                }
+            }
+        }
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let res = match &mut self.state {
+            ReconnectState::Connected { writer, .. } => match writer.poll_close_unpin(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(res) => res,
+            },
+
+            _ => Ok(()),
+        };
+
+        debug!("Closing the reconnecting connection to {}", self.ip_addrs);
+        self.replay.clear();
+        self.state = ReconnectState::Closed;
+        Poll::Ready(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use crate::{ConnectDatagram, Connection};
+    use async_std::net::TcpListener;
+    use futures::{SinkExt, StreamExt};
+    use std::time::Duration;
+
+    fn test_policy(max_attempts: Option<u32>) -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+            max_attempts,
+        }
+    }
+
+    #[test]
+    fn retry_policy_backoff() {
+        let policy = test_policy(None);
+
+        assert_eq!(Duration::from_millis(10), policy.delay(0));
+        assert_eq!(Duration::from_millis(40), policy.delay(2));
+        assert_eq!(Duration::from_millis(50), policy.delay(3));
+        assert_eq!(Duration::from_millis(50), policy.delay(u32::MAX));
+    }
+
+    #[async_std::test]
+    async fn reconnects_after_peer_closes() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client =
+            Connection::tcp_client_reconnecting(listener.local_addr()?, test_policy(None)).await?;
+
+        let (first, _) = listener.accept().await?;
+        drop(first);
+
+        let server = async_std::task::spawn(async move {
+            let (second, _) = listener.accept().await?;
+            let mut conn = Connection::from(second);
+            conn.writer()
+                .send(ConnectDatagram::with_tag(1, b"hello".to_vec())?)
+                .await?;
+
+            anyhow::Ok(conn.reader().next().await)
+        });
+
+        let received = client.next().await.expect("connection was given up on");
+        assert_eq!(b"hello", received.data());
+
+        client
+            .send(ConnectDatagram::with_tag(2, b"world".to_vec())?)
+            .await?;
+        let echoed = server.await?.expect("reconnected client did not send");
+        assert_eq!(b"world", echoed.data());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn gives_up_after_max_attempts() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client =
+            Connection::tcp_client_reconnecting(listener.local_addr()?, test_policy(Some(2)))
+                .await?;
+
+        let (first, _) = listener.accept().await?;
+        drop(listener);
+        drop(first);
+
+        let next = async_std::future::timeout(Duration::from_secs(5), client.next()).await?;
+        assert!(next.is_none());
+        assert!(!client.is_connected());
+
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
+use crate::logging::*;
 use async_std::net::{TcpStream, ToSocketAddrs};
-use async_tls::client;
-use async_tls::TlsConnector;
-use futures::AsyncReadExt;
-use log::*;
+use futures::{AsyncRead, AsyncWrite};
+use futures_rustls::webpki::DNSNameRef;
+use futures_rustls::{client, server, TlsConnector};
+use rustls::Session;
+use std::net::SocketAddr;
+use std::time::Duration;
 
 use crate::tls::TlsConnectionMetadata;
 use crate::Connection;
@@ -10,6 +13,10 @@ use crate::Connection;
 impl Connection {
     /// Creates a [`Connection`] that uses a TLS transport.
     ///
+    /// To authenticate with a client certificate for mutual TLS, configure the certificate chain
+    /// on the [`rustls::ClientConfig`] with `set_single_client_cert` before creating the
+    /// [`TlsConnector`].
+    ///
     /// # Example
     ///
     /// Please see the [tls-client](https://github.com/sachanganesh/connect-rs/blob/main/examples/tls-client/src/main.rs)
@@ -18,20 +25,76 @@ impl Connection {
     /// Basic usage:
     ///
     /// ```ignore
-    /// let mut conn = Connection::tls_client("127.0.0.1:3456", "localhost", client_config.into()).await?;
+    /// let mut conn = Connection::tls_client("127.0.0.1:3456", "localhost", Arc::new(client_config).into()).await?;
     /// ```
     pub async fn tls_client<A: ToSocketAddrs + std::fmt::Display>(
         ip_addrs: A,
         domain: &str,
         connector: TlsConnector,
+    ) -> anyhow::Result<Self> {
+        Self::connect_tls(ip_addrs, domain, connector).await
+    }
+
+    /// Creates a [`Connection`] that uses a TLS transport, failing with a
+    /// [`TimedOut`](`std::io::ErrorKind::TimedOut`) error if the TCP connection and TLS handshake
+    /// are not completed within `timeout`.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut conn = Connection::tls_client_timeout(
+    ///     "127.0.0.1:3456",
+    ///     "localhost",
+    ///     Arc::new(client_config).into(),
+    ///     Duration::from_secs(2),
+    /// )
+    /// .await?;
+    /// ```
+    pub async fn tls_client_timeout<A: ToSocketAddrs + std::fmt::Display>(
+        ip_addrs: A,
+        domain: &str,
+        connector: TlsConnector,
+        timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let target = ip_addrs.to_string();
+
+        match async_std::future::timeout(timeout, Self::connect_tls(ip_addrs, domain, connector))
+            .await
+        {
+            Ok(res) => res,
+            Err(_) => {
+                warn!("Timed out connecting to {} with TLS", target);
+                Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into())
+            }
+        }
+    }
+
+    async fn connect_tls<A: ToSocketAddrs + std::fmt::Display>(
+        ip_addrs: A,
+        domain: &str,
+        connector: TlsConnector,
     ) -> anyhow::Result<Self> {
         let stream = TcpStream::connect(&ip_addrs).await?;
         info!("Established client TCP connection to {}", ip_addrs);
         stream.set_nodelay(true)?;
 
-        let local_addr = stream.peer_addr()?;
+        Self::tls_handshake(stream, domain, connector).await
+    }
+
+    /// Creates a [`Connection`] by performing the TLS handshake as the client over an established
+    /// TCP stream.
+    pub(crate) async fn tls_handshake(
+        stream: TcpStream,
+        domain: &str,
+        connector: TlsConnector,
+    ) -> anyhow::Result<Self> {
+        let local_addr = stream.local_addr()?;
         let peer_addr = stream.peer_addr()?;
 
+        let domain = DNSNameRef::try_from_ascii_str(domain)
+            .map_err(|_| anyhow::anyhow!("invalid DNS name: {}", domain))?;
         let encrypted_stream: client::TlsStream<TcpStream> =
             connector.connect(domain, stream).await?;
         info!("Completed TLS handshake with {}", peer_addr);
@@ -42,6 +105,66 @@ impl Connection {
             stream: encrypted_stream,
         }))
     }
+
+    /// Creates a [`Connection`] over the client side of a TLS session whose handshake was already
+    /// completed, such as by a separate TLS management layer, with the provided addresses reported
+    /// as its local and peer socket addresses.
+    ///
+    /// The session can run over any byte stream, not only a TCP stream. Its peer certificates and
+    /// negotiated ALPN protocol are exposed by the connection as with
+    /// [`Connection::tls_client`]. Use [`Connection::from_io`] instead for the streams of other TLS
+    /// libraries.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let session = connector.connect(domain, tunnel).await?;
+    /// let mut conn = Connection::from_tls_client_stream(local_addr, peer_addr, session);
+    /// ```
+    pub fn from_tls_client_stream<S>(
+        local_addr: SocketAddr,
+        peer_addr: SocketAddr,
+        stream: client::TlsStream<S>,
+    ) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    {
+        let peer_certificates = stream.get_ref().1.get_peer_certificates();
+        let alpn_protocol = stream.get_ref().1.get_alpn_protocol().map(Vec::from);
+
+        let mut conn = Self::from_io(local_addr, peer_addr, stream);
+        conn.peer_certificates = peer_certificates;
+        conn.alpn_protocol = alpn_protocol;
+
+        conn
+    }
+
+    /// Creates a [`Connection`] over the server side of a TLS session whose handshake was already
+    /// completed, see [`Connection::from_tls_client_stream`].
+    ///
+    /// The connection also exposes the SNI hostname requested by the client, as with the
+    /// connections accepted by a [`TlsListener`](`crate::tls::TlsListener`).
+    pub fn from_tls_server_stream<S>(
+        local_addr: SocketAddr,
+        peer_addr: SocketAddr,
+        stream: server::TlsStream<S>,
+    ) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    {
+        let peer_certificates = stream.get_ref().1.get_peer_certificates();
+        let alpn_protocol = stream.get_ref().1.get_alpn_protocol().map(Vec::from);
+        let sni_hostname = stream.get_ref().1.get_sni_hostname().map(String::from);
+
+        let mut conn = Self::from_io(local_addr, peer_addr, stream);
+        conn.peer_certificates = peer_certificates;
+        conn.alpn_protocol = alpn_protocol;
+        conn.sni_hostname = sni_hostname;
+
+        conn
+    }
 }
 
 impl From<TlsConnectionMetadata> for Connection {
@@ -52,31 +175,13 @@ impl From<TlsConnectionMetadata> for Connection {
                 local_addr,
                 peer_addr,
                 stream,
-            } => {
-                let (read_stream, write_stream) = stream.split();
-
-                Self::new(
-                    local_addr,
-                    peer_addr,
-                    Box::pin(read_stream),
-                    Box::pin(write_stream),
-                )
-            }
+            } => Self::from_tls_client_stream(local_addr, peer_addr, stream),
 
             TlsConnectionMetadata::Listener {
                 local_addr,
                 peer_addr,
                 stream,
-            } => {
-                let (read_stream, write_stream) = stream.split();
-
-                Self::new(
-                    local_addr,
-                    peer_addr,
-                    Box::pin(read_stream),
-                    Box::pin(write_stream),
-                )
-            }
+            } => Self::from_tls_server_stream(local_addr, peer_addr, stream),
         }
     }
 }
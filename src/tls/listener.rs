@@ -1,19 +1,92 @@
+use crate::conn_limit::ConnectionLimiter;
+use crate::logging::*;
+use crate::tcp::listener::AcceptFilter;
 use crate::tls::TlsConnectionMetadata;
-use crate::Connection;
+use crate::{Connection, ListenerHandle, ShutdownHandle};
 use async_std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
 use async_std::pin::Pin;
 use async_std::task::{Context, Poll};
 use async_stream::stream;
-use async_tls::{server::TlsStream, TlsAcceptor};
-use futures::Stream;
+use futures::future::poll_fn;
+use futures::{Future, Stream};
 use futures_lite::StreamExt;
-use log::*;
+use futures_rustls::{Accept, TlsAcceptor};
+use std::error::Error;
+
+type AcceptStream =
+    Pin<Box<dyn Stream<Item = Option<Result<TcpStream, std::io::Error>>> + Send + Sync>>;
+
+/// Encountered when an incoming TLS connection could not be accepted.
+///
+#[derive(Debug)]
+pub enum TlsAcceptError {
+    /// Encountered when the underlying TCP connection could not be accepted.
+    Accept(std::io::Error),
+
+    /// Encountered when the TLS handshake with the peer failed, such as on a certificate or
+    /// protocol mismatch.
+    Handshake {
+        peer_addr: SocketAddr,
+        error: std::io::Error,
+    },
+}
+
+impl TlsAcceptError {
+    /// Get the peer IP address and port, if the failure happened after the TCP connection was
+    /// accepted.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            TlsAcceptError::Accept(_) => None,
+            TlsAcceptError::Handshake { peer_addr, .. } => Some(*peer_addr),
+        }
+    }
+}
+
+impl Error for TlsAcceptError {}
+
+impl std::fmt::Display for TlsAcceptError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TlsAcceptError::Accept(err) => std::fmt::Display::fmt(err, formatter),
+            TlsAcceptError::Handshake { peer_addr, error } => write!(
+                formatter,
+                "TLS handshake with {} failed: {}",
+                peer_addr, error
+            ),
+        }
+    }
+}
 
 /// Listens on a bound socket for incoming TLS connections to be handled as independent
 /// [`Connection`]s.
 ///
 /// Implements the [`Stream`] trait to asynchronously accept incoming TLS connections.
 ///
+/// # Virtual hosts
+///
+/// To serve several virtual hosts on one socket, configure the [`rustls::ServerConfig`] with a
+/// certificate resolver such as [`rustls::ResolvesServerCertUsingSNI`], which picks the
+/// certificate matching the server name requested by the client. The requested name is then
+/// available on each accepted connection through
+/// [`Connection::sni_hostname`](`crate::Connection::sni_hostname`) to route it accordingly:
+///
+/// ```ignore
+/// let mut resolver = ResolvesServerCertUsingSNI::new();
+/// resolver.add("api.example.com", api_key)?;
+/// resolver.add("chat.example.com", chat_key)?;
+///
+/// let mut config = ServerConfig::new(NoClientAuth::new());
+/// config.cert_resolver = Arc::new(resolver);
+///
+/// let mut server = TlsListener::bind("0.0.0.0:443", Arc::new(config).into()).await?;
+/// while let Some(conn) = server.next().await {
+///     match conn.sni_hostname() {
+///         Some("api.example.com") => task::spawn(handle_api(conn)),
+///         _ => task::spawn(handle_chat(conn)),
+///     };
+/// }
+/// ```
+///
 /// # Example
 ///
 /// Please see the [tls-echo-server](https://github.com/sachanganesh/connect-rs/blob/main/examples/tls-echo-server/src/main.rs)
@@ -22,7 +95,7 @@ use log::*;
 /// Basic usage:
 ///
 /// ```ignore
-/// let mut server = TlsListener::bind("127.0.0.1:3456", config.into()).await?;
+/// let mut server = TlsListener::bind("127.0.0.1:3456", Arc::new(config).into()).await?;
 ///
 /// // wait for a connection to come in and be accepted
 /// while let Some(mut conn) = server.next().await {
@@ -32,18 +105,12 @@ use log::*;
 #[allow(dead_code)]
 pub struct TlsListener {
     local_addrs: SocketAddr,
-    conn_stream: Pin<
-        Box<
-            dyn Stream<
-                    Item = Option<
-                        Option<(SocketAddr, Result<TlsStream<TcpStream>, std::io::Error>)>,
-                    >,
-                > + Send
-                + Sync,
-        >,
-    >,
-    // listener: TcpListener,
-    // acceptor: TlsAcceptor,
+    conn_stream: AcceptStream,
+    acceptor: TlsAcceptor,
+    handshake: Option<(SocketAddr, Accept<TcpStream>)>,
+    conn_limiter: ConnectionLimiter,
+    accept_filter: Option<AcceptFilter>,
+    shutdown: ShutdownHandle,
 }
 
 impl TlsListener {
@@ -55,7 +122,7 @@ impl TlsListener {
     /// Basic usage:
     ///
     /// ```ignore
-    /// let mut server = TlsListener::bind("127.0.0.1:3456", config.into()).await?;
+    /// let mut server = TlsListener::bind("127.0.0.1:3456", Arc::new(config).into()).await?;
     /// ```
     pub async fn bind<A: ToSocketAddrs + std::fmt::Display>(
         ip_addrs: A,
@@ -68,96 +135,511 @@ impl TlsListener {
 
         let stream = Box::pin(stream! {
             loop {
-                yield match listener.incoming().next().await {
-                    Some(Ok(tcp_stream)) => {
-                        let peer_addr = tcp_stream
-                            .peer_addr()
-                            .expect("Could not retrieve peer IP address");
-                        debug!("Received connection attempt from {}", peer_addr);
-
-                        Some(Some((peer_addr, acceptor.accept(tcp_stream).await)))
-                    }
-
-                    Some(Err(err)) => {
-                        error!(
-                            "Encountered error when trying to accept new connection {}",
-                            err
-                        );
-                        Some(None)
-                    }
-
-                    None => None,
-                }
+                yield listener.incoming().next().await;
             }
         });
 
         Ok(Self {
             local_addrs,
             conn_stream: stream,
-            // listener,
-            // acceptor,
+            acceptor,
+            handshake: None,
+            conn_limiter: ConnectionLimiter::unlimited(),
+            accept_filter: None,
+            shutdown: ShutdownHandle::new(),
         })
     }
 
-    // /// Creates a [`Connection`] for the next `accept`ed TCP connection at the bound socket.
-    // ///
-    // /// # Example
-    // ///
-    // /// Basic usage:
-    // ///
-    // /// ```ignore
-    // /// let mut server = TlsListener::bind("127.0.0.1:3456", config.into()).await?;
-    // /// while let Some(mut conn) = server.next().await {
-    // ///     // do something with connection
-    // /// }
-    // /// ```
-    // pub async fn accept(&self) -> anyhow::Result<Connection> {
-    //     let (tcp_stream, peer_addr) = self.listener.accept().await?;
-    //     debug!("Received connection attempt from {}", peer_addr);
-    //
-    //     match self.acceptor.accept(tcp_stream).await {
-    //         Ok(tls_stream) => {
-    //             debug!("Completed TLS handshake with {}", peer_addr);
-    //             Ok(Connection::from(TlsConnectionMetadata::Listener {
-    //                 local_addr: self.local_addrs.clone(),
-    //                 peer_addr,
-    //                 stream: tls_stream,
-    //             }))
-    //         }
-    //
-    //         Err(e) => {
-    //             warn!("Could not encrypt connection with TLS from {}", peer_addr);
-    //             Err(anyhow::Error::new(e))
-    //         }
-    //     }
-    // }
+    /// Get the local IP address and port that the listener is bound to.
+    ///
+    /// When binding to port `0`, this is the port that was assigned by the operating system, such
+    /// as to tell clients where to connect to.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let server = TlsListener::bind("127.0.0.1:0", Arc::new(config).into()).await?;
+    /// let port = server.local_addr().port();
+    /// ```
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs
+    }
+
+    /// Drops incoming TCP connections for which `filter` returns `false` on the peer address,
+    /// before the TLS handshake is started.
+    ///
+    /// Rejected connections are logged at the debug level and closed right away, and they do not
+    /// count towards the maximum number of connections.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TlsListener::bind("0.0.0.0:3456", Arc::new(config).into())
+    ///     .await?
+    ///     .with_accept_filter(move |peer_addr| !denylist.contains(&peer_addr.ip()));
+    /// ```
+    pub fn with_accept_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        self.accept_filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Caps the number of live [`Connection`]s accepted by the listener at `max`.
+    ///
+    /// Once `max` accepted connections are alive, the listener stops accepting and its stream stays
+    /// pending, leaving new connection attempts in the OS backlog. Accepting resumes as soon as a
+    /// connection is dropped. A [`split`](`Connection::split`) connection stays alive until both of
+    /// its halves are dropped.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TlsListener::bind("127.0.0.1:3456", Arc::new(config).into())
+    ///     .await?
+    ///     .with_max_connections(10_000);
+    /// ```
+    pub fn with_max_connections(self, max: usize) -> Self {
+        self.conn_limiter.set_max(max);
+        self
+    }
+
+    /// Get a [`ShutdownHandle`] that stops the listener from accepting new connections, causing
+    /// its stream to finish once any TLS handshake in progress has completed.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TlsListener::bind("127.0.0.1:3456", Arc::new(config).into()).await?;
+    /// let handle = server.shutdown_handle();
+    ///
+    /// // later, from another task
+    /// handle.shutdown();
+    /// ```
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Get a [`ListenerHandle`] that stops the listener from accepting new connections and drains
+    /// or closes the connections it has accepted.
+    pub fn handle(&self) -> ListenerHandle {
+        ListenerHandle::new(self.shutdown.clone(), self.conn_limiter.clone())
+    }
+
+    /// Creates a [`Connection`] for the next `accept`ed TLS connection at the bound socket.
+    ///
+    /// Unlike the [`Stream`] implementation, which logs and skips failed connection attempts, this
+    /// returns them to the caller so that failed TLS handshakes can be reported along with the
+    /// address of the peer.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = TlsListener::bind("127.0.0.1:3456", Arc::new(config).into()).await?;
+    /// loop {
+    ///     match server.accept().await {
+    ///         Ok(conn) => {
+    ///             // handle the connection
+    ///         }
+    ///         Err(TlsAcceptError::Handshake { peer_addr, error }) => {
+    ///             warn!("Failed TLS handshake from {}: {}", peer_addr, error);
+    ///         }
+    ///         Err(err) => error!("Could not accept connection: {}", err),
+    ///     }
+    /// }
+    /// ```
+    pub async fn accept(&mut self) -> Result<Connection, TlsAcceptError> {
+        match poll_fn(|cx| self.poll_accept(cx)).await {
+            Some(res) => res,
+            None => Err(TlsAcceptError::Accept(std::io::Error::other(
+                "TLS listener is closed",
+            ))),
+        }
+    }
+
+    fn poll_accept(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Connection, TlsAcceptError>>> {
+        loop {
+            if let Some((peer_addr, handshake)) = self.handshake.as_mut() {
+                let peer_addr = *peer_addr;
+                let res = match Pin::new(handshake).poll(cx) {
+                    Poll::Ready(res) => res,
+                    Poll::Pending => return Poll::Pending,
+                };
+                self.handshake = None;
+
+                return match res {
+                    Ok(tls_stream) => {
+                        debug!("Completed TLS handshake with {}", peer_addr);
+                        let mut conn = Connection::from(TlsConnectionMetadata::Listener {
+                            local_addr: self.local_addrs,
+                            peer_addr,
+                            stream: tls_stream,
+                        });
+                        conn.set_slot(self.conn_limiter.acquire());
+
+                        Poll::Ready(Some(Ok(conn)))
+                    }
+
+                    Err(error) => {
+                        Poll::Ready(Some(Err(TlsAcceptError::Handshake { peer_addr, error })))
+                    }
+                };
+            }
+
+            if self.shutdown.poll_shutdown(cx) {
+                debug!("TLS listener at {} was shut down", self.local_addrs);
+                return Poll::Ready(None);
+            }
+
+            if self.conn_limiter.poll_ready(cx).is_pending() {
+                trace!("maximum number of live connections reached, waiting for one to drop");
+                return Poll::Pending;
+            }
+
+            match self.conn_stream.poll_next(cx) {
+                Poll::Ready(Some(Some(Ok(tcp_stream)))) => {
+                    let peer_addr = match tcp_stream.peer_addr() {
+                        Ok(peer_addr) => peer_addr,
+                        Err(err) => return Poll::Ready(Some(Err(TlsAcceptError::Accept(err)))),
+                    };
+                    debug!("Received connection attempt from {}", peer_addr);
+
+                    if let Some(filter) = self.accept_filter.as_ref() {
+                        if !filter(&peer_addr) {
+                            debug!(
+                                "Rejected connection from {} by the accept filter",
+                                peer_addr
+                            );
+                            continue;
+                        }
+                    }
+
+                    self.handshake = Some((peer_addr, self.acceptor.accept(tcp_stream)));
+                }
+
+                Poll::Ready(Some(Some(Err(err)))) => {
+                    return Poll::Ready(Some(Err(TlsAcceptError::Accept(err))))
+                }
+
+                Poll::Ready(Some(None)) => return Poll::Ready(None),
+
+                Poll::Ready(None) => return Poll::Ready(None),
+
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
 }
 
 impl Stream for TlsListener {
     type Item = Connection;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match self.conn_stream.poll_next(cx) {
-            Poll::Ready(Some(Some(Some((peer_addr, Ok(tls_stream)))))) => {
-                debug!("Completed TLS handshake with {}", peer_addr);
-                Poll::Ready(Some(Connection::from(TlsConnectionMetadata::Listener {
-                    local_addr: self.local_addrs.clone(),
-                    peer_addr,
-                    stream: tls_stream,
-                })))
+        match self.poll_accept(cx) {
+            Poll::Ready(Some(Ok(conn))) => Poll::Ready(Some(conn)),
+
+            Poll::Ready(Some(Err(err @ TlsAcceptError::Handshake { .. }))) => {
+                warn!("Could not encrypt connection with TLS: {}", err);
+
+                // the error was not caused by a pending operation, so make sure to be polled again
+                cx.waker().wake_by_ref();
+                Poll::Pending
             }
 
-            Poll::Ready(Some(Some(Some((peer_addr, Err(err)))))) => {
-                warn!(
-                    "Could not encrypt connection with TLS from {}: {}",
-                    peer_addr, err
+            Poll::Ready(Some(Err(err))) => {
+                error!(
+                    "Encountered error when trying to accept new connection {}",
+                    err
                 );
+
+                // the error was not caused by a pending operation, so make sure to be polled again
+                cx.waker().wake_by_ref();
                 Poll::Pending
             }
 
+            Poll::Ready(None) => Poll::Ready(None),
+
             Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TlsAcceptError, TlsListener};
+    use crate::tls::{TlsAcceptor, TlsConnector};
+    use crate::{ConnectDatagram, Connection};
+    use async_std::net::TcpStream;
+    use futures::{AsyncReadExt, AsyncWriteExt, SinkExt, StreamExt};
+    use futures_rustls::webpki::DNSNameRef;
+    use rustls::{
+        AllowAnyAuthenticatedClient, Certificate, ClientConfig, NoClientAuth, PrivateKey,
+        RootCertStore, ServerConfig,
+    };
+    use rustls_pemfile::{certs, rsa_private_keys};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const CA_CERT: &[u8] = include_bytes!("testdata/ca.cert");
+
+    fn load_certs(mut pem: &[u8]) -> anyhow::Result<Vec<Certificate>> {
+        Ok(certs(&mut pem)?.into_iter().map(Certificate).collect())
+    }
+
+    fn load_key(mut pem: &[u8]) -> anyhow::Result<PrivateKey> {
+        Ok(PrivateKey(rsa_private_keys(&mut pem)?.remove(0)))
+    }
+
+    fn ca_roots() -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add_pem_file(&mut &CA_CERT[..])
+            .expect("could not load test CA certificate");
 
-            _ => Poll::Ready(None),
+        roots
+    }
+
+    fn server_config() -> anyhow::Result<ServerConfig> {
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.set_single_cert(
+            load_certs(include_bytes!("testdata/server.cert"))?,
+            load_key(include_bytes!("testdata/server.rsa"))?,
+        )?;
+
+        Ok(config)
+    }
+
+    #[async_std::test]
+    async fn accept_filter_skips_handshake() -> anyhow::Result<()> {
+        let mut server = TlsListener::bind("127.0.0.1:0", Arc::new(server_config()?).into())
+            .await?
+            .with_accept_filter(|_| false);
+
+        let mut client = TcpStream::connect(server.local_addrs).await?;
+
+        let res = async_std::future::timeout(Duration::from_millis(100), server.accept()).await;
+        assert!(res.is_err());
+
+        // the connection is closed without starting the handshake
+        assert_eq!(0, client.read(&mut [0; 1]).await?);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn accept_reports_handshake_failure() -> anyhow::Result<()> {
+        let mut server =
+            TlsListener::bind("127.0.0.1:0", Arc::new(server_config()?).into()).await?;
+
+        let mut client = TcpStream::connect(server.local_addrs).await?;
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await?;
+
+        match server.accept().await {
+            Err(err @ TlsAcceptError::Handshake { .. }) => {
+                assert_eq!(Some(client.local_addr()?), err.peer_addr());
+            }
+            Err(err) => panic!("unexpected accept error: {}", err),
+            Ok(_) => panic!("accepted a connection that did not complete a TLS handshake"),
         }
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn shutdown_finishes_stream() -> anyhow::Result<()> {
+        let mut server =
+            TlsListener::bind("127.0.0.1:0", Arc::new(server_config()?).into()).await?;
+        let handle = server.shutdown_handle();
+
+        async_std::task::spawn(async move {
+            async_std::task::sleep(Duration::from_millis(50)).await;
+            handle.shutdown();
+        });
+
+        let next = async_std::future::timeout(Duration::from_secs(5), server.next()).await?;
+        assert!(next.is_none());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn mutual_tls_exposes_peer_certificates() -> anyhow::Result<()> {
+        let mut server_config = server_config()?;
+        server_config.set_client_certificate_verifier(AllowAnyAuthenticatedClient::new(ca_roots()));
+        let mut server = TlsListener::bind("127.0.0.1:0", Arc::new(server_config).into()).await?;
+
+        let client_cert = load_certs(include_bytes!("testdata/client.cert"))?;
+        let mut client_config = ClientConfig::new();
+        client_config.root_store = ca_roots();
+        client_config.set_single_client_cert(
+            client_cert.clone(),
+            load_key(include_bytes!("testdata/client.rsa"))?,
+        )?;
+
+        let addr = server.local_addrs;
+        let client = async_std::task::spawn(async move {
+            Connection::tls_client(addr, "localhost", Arc::new(client_config).into()).await
+        });
+
+        let conn = server.accept().await?;
+        let client = client.await?;
+
+        assert_eq!(Some(&client_cert[..]), conn.peer_certificates());
+        assert_eq!(
+            Some(&load_certs(include_bytes!("testdata/server.cert"))?[..]),
+            client.peer_certificates()
+        );
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn tls_client_local_addr() -> anyhow::Result<()> {
+        let mut server =
+            TlsListener::bind("127.0.0.1:0", Arc::new(server_config()?).into()).await?;
+
+        let mut client_config = ClientConfig::new();
+        client_config.root_store = ca_roots();
+
+        let addr = server.local_addr();
+        assert_ne!(0, addr.port());
+
+        let client = async_std::task::spawn(async move {
+            Connection::tls_client(addr, "localhost", Arc::new(client_config).into()).await
+        });
+
+        let conn = server.accept().await?;
+        let client = client.await?;
+
+        assert_ne!(client.local_addr(), client.peer_addr());
+        assert_eq!(addr, client.peer_addr());
+        assert_eq!(conn.peer_addr(), client.local_addr());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn exposes_sni_hostname() -> anyhow::Result<()> {
+        let mut server =
+            TlsListener::bind("127.0.0.1:0", Arc::new(server_config()?).into()).await?;
+
+        let mut client_config = ClientConfig::new();
+        client_config.root_store = ca_roots();
+
+        let addr = server.local_addrs;
+        let client = async_std::task::spawn(async move {
+            Connection::tls_client(addr, "localhost", Arc::new(client_config).into()).await
+        });
+
+        let conn = server.accept().await?;
+        let client = client.await?;
+
+        assert_eq!(Some("localhost"), conn.sni_hostname());
+        assert_eq!(None, client.sni_hostname());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn exposes_negotiated_alpn() -> anyhow::Result<()> {
+        let mut server_config = server_config()?;
+        server_config.set_protocols(&[b"connect/2".to_vec(), b"connect/1".to_vec()]);
+        let mut server = TlsListener::bind("127.0.0.1:0", Arc::new(server_config).into()).await?;
+
+        let mut client_config = ClientConfig::new();
+        client_config.root_store = ca_roots();
+        client_config.set_protocols(&[b"connect/1".to_vec()]);
+
+        let addr = server.local_addrs;
+        let client = async_std::task::spawn(async move {
+            Connection::tls_client(addr, "localhost", Arc::new(client_config).into()).await
+        });
+
+        let conn = server.accept().await?;
+        let client = client.await?;
+
+        assert_eq!(Some(&b"connect/1"[..]), conn.negotiated_alpn());
+        assert_eq!(Some(&b"connect/1"[..]), client.negotiated_alpn());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn tls_client_timeout() -> anyhow::Result<()> {
+        // a plain TCP listener accepts the connection but never completes the handshake
+        let listener = async_std::net::TcpListener::bind("127.0.0.1:0").await?;
+
+        let mut client_config = ClientConfig::new();
+        client_config.root_store = ca_roots();
+
+        let res = Connection::tls_client_timeout(
+            listener.local_addr()?,
+            "localhost",
+            Arc::new(client_config).into(),
+            Duration::from_millis(50),
+        )
+        .await;
+
+        let err = res.err().expect("handshake should have timed out");
+        let io_err = err
+            .downcast_ref::<std::io::Error>()
+            .expect("not an IO error");
+        assert_eq!(std::io::ErrorKind::TimedOut, io_err.kind());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn frames_established_sessions() -> anyhow::Result<()> {
+        let listener = async_std::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let mut client_config = ClientConfig::new();
+        client_config.root_store = ca_roots();
+        let connector = TlsConnector::from(Arc::new(client_config));
+        let acceptor = TlsAcceptor::from(Arc::new(server_config()?));
+
+        // the handshakes are completed outside of the crate
+        let client = async_std::task::spawn(async move {
+            let stream = TcpStream::connect(addr).await?;
+            let local_addr = stream.local_addr()?;
+            let domain = DNSNameRef::try_from_ascii_str("localhost")?;
+            let session = connector.connect(domain, stream).await?;
+
+            Ok::<_, anyhow::Error>(Connection::from_tls_client_stream(
+                local_addr, addr, session,
+            ))
+        });
+
+        let (stream, peer_addr) = listener.accept().await?;
+        let session = acceptor.accept(stream).await?;
+        let mut conn = Connection::from_tls_server_stream(addr, peer_addr, session);
+        let mut client = client.await?;
+
+        assert_eq!(Some("localhost"), conn.sni_hostname());
+        assert!(client.peer_certificates().is_some());
+
+        client
+            .writer()
+            .send(ConnectDatagram::with_tag(1, vec![1, 2, 3])?)
+            .await?;
+        let datagram = conn.reader().next().await.expect("connection closed");
+        assert_eq!(&[1, 2, 3], datagram.data());
+
+        Ok(())
     }
 }
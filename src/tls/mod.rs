@@ -13,12 +13,14 @@ pub(crate) mod client;
 pub(crate) mod listener;
 
 use async_std::net::TcpStream;
-use async_tls::server;
+use futures_rustls::server;
 use std::net::SocketAddr;
 
-pub use client::*;
 pub use listener::*;
 
+pub use futures_rustls::{TlsAcceptor, TlsConnector};
+pub use rustls::Certificate;
+
 /// Used to differentiate between an outgoing connection ([Client](`TlsConnectionMetadata::Client`))
 /// or incoming connection listener ([Listener](`TlsConnectionMetadata::Listener`)).
 ///
@@ -29,7 +31,7 @@ pub enum TlsConnectionMetadata {
     Client {
         local_addr: SocketAddr,
         peer_addr: SocketAddr,
-        stream: async_tls::client::TlsStream<TcpStream>,
+        stream: futures_rustls::client::TlsStream<TcpStream>,
     },
     Listener {
         local_addr: SocketAddr,
@@ -0,0 +1,289 @@
+//! Abstractions over the transports, so that code can be written once for any of them.
+//!
+//! <br/>
+//!
+//! This module exposes the [`Transport`] trait to connect to a peer and the [`Listener`] trait to
+//! accept connections from peers, along with their implementations for the [`Tcp`], [`Udp`] and,
+//! with the `tls` feature, [`Tls`] transports. Both traits are object safe, so an application can
+//! choose its transport at runtime, such as from its configuration:
+//!
+//! ```ignore
+//! let transport: Box<dyn Transport> = match config.transport.as_str() {
+//!     "udp" => Box::new(Udp::new()),
+//!     _ => Box::new(Tcp::new()),
+//! };
+//!
+//! let mut conn = transport.connect(config.server_addr).await?;
+//! ```
+//!
+//! The concrete constructors, such as [`Connection::tcp_client`] and
+//! [`TcpListener::bind`](`crate::tcp::TcpListener::bind`), remain available and expose the options
+//! specific to each transport.
+
+use crate::tcp::{TcpConnectOptions, TcpListener};
+use crate::udp::{UdpListener, UdpOptions};
+use crate::Connection;
+use async_std::net::UdpSocket;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::task::{Context, Poll};
+use futures::Stream;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+
+#[cfg(feature = "tls")]
+use crate::tls::{TlsAcceptor, TlsConnector, TlsListener};
+
+/// A transport that can establish a [`Connection`] to a peer.
+pub trait Transport: Send + Sync {
+    /// Connects to the peer listening at `addr`.
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, anyhow::Result<Connection>>;
+}
+
+/// A transport that can accept a [`Connection`] from each peer that connects to an address.
+pub trait Listener: Send + Sync {
+    /// Binds to `addr` and listens for incoming connections.
+    fn bind(&self, addr: SocketAddr) -> BoxFuture<'_, anyhow::Result<Incoming>>;
+}
+
+/// The incoming connections of a bound [`Listener`], which implements the `Stream` trait to yield
+/// each accepted [`Connection`].
+pub struct Incoming {
+    local_addr: SocketAddr,
+    connections: BoxStream<'static, Connection>,
+}
+
+impl Incoming {
+    /// Wraps a stream of accepted connections of a listener bound to `local_addr`.
+    pub fn new<S>(local_addr: SocketAddr, connections: S) -> Self
+    where
+        S: Stream<Item = Connection> + Send + 'static,
+    {
+        Self {
+            local_addr,
+            connections: Box::pin(connections),
+        }
+    }
+
+    /// Get the local IP address and port that the listener is bound to, such as to find the port
+    /// assigned by the operating system when binding to port `0`.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Stream for Incoming {
+    type Item = Connection;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.connections.as_mut().poll_next(cx)
+    }
+}
+
+/// The TCP transport, see [`Connection::tcp_client_with_options`] and
+/// [`TcpListener`](`crate::tcp::TcpListener`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tcp {
+    options: TcpConnectOptions,
+}
+
+impl Tcp {
+    /// Creates a [`Tcp`] transport with the default socket options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the socket options of both outgoing and accepted connections.
+    pub fn with_options(mut self, options: TcpConnectOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl Transport for Tcp {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, anyhow::Result<Connection>> {
+        Box::pin(Connection::tcp_client_with_options(addr, self.options))
+    }
+}
+
+impl Listener for Tcp {
+    fn bind(&self, addr: SocketAddr) -> BoxFuture<'_, anyhow::Result<Incoming>> {
+        Box::pin(async move {
+            let listener = TcpListener::bind(addr)
+                .await?
+                .with_socket_options(self.options);
+
+            Ok(Incoming::new(listener.local_addr(), listener))
+        })
+    }
+}
+
+/// The UDP transport, see [`Connection::udp_with_options`] and
+/// [`UdpListener`](`crate::udp::UdpListener`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Udp {
+    options: UdpOptions,
+}
+
+impl Udp {
+    /// Creates a [`Udp`] transport with the default [`UdpOptions`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how datagrams split across multiple UDP packets are reassembled, for both outgoing and
+    /// accepted connections.
+    pub fn with_options(mut self, options: UdpOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl Transport for Udp {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, anyhow::Result<Connection>> {
+        Box::pin(async move {
+            let local_addr = match addr {
+                SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+            };
+
+            let socket = UdpSocket::bind(local_addr).await?;
+            socket.connect(addr).await?;
+
+            Connection::udp_with_options(socket, self.options)
+        })
+    }
+}
+
+impl Listener for Udp {
+    fn bind(&self, addr: SocketAddr) -> BoxFuture<'_, anyhow::Result<Incoming>> {
+        Box::pin(async move {
+            let listener = UdpListener::bind(addr).await?.with_options(self.options);
+
+            Ok(Incoming::new(listener.local_addr(), listener))
+        })
+    }
+}
+
+/// The TLS transport, see [`Connection::tls_client`] and [`TlsListener`].
+///
+/// Connecting requires a connector set with [`with_connector`](Tls::with_connector), and binding
+/// requires an acceptor set with [`with_acceptor`](Tls::with_acceptor), so that the same value can
+/// be used for either or both roles.
+#[cfg(feature = "tls")]
+#[derive(Clone, Default)]
+pub struct Tls {
+    connector: Option<(String, TlsConnector)>,
+    acceptor: Option<TlsAcceptor>,
+}
+
+#[cfg(feature = "tls")]
+impl Tls {
+    /// Creates a [`Tls`] transport that can neither connect nor bind until it is configured.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the connector of outgoing connections, which verify that the peer is `domain`.
+    pub fn with_connector(mut self, domain: &str, connector: TlsConnector) -> Self {
+        self.connector = Some((domain.to_string(), connector));
+        self
+    }
+
+    /// Sets the acceptor of incoming connections.
+    pub fn with_acceptor(mut self, acceptor: TlsAcceptor) -> Self {
+        self.acceptor = Some(acceptor);
+        self
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Transport for Tls {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, anyhow::Result<Connection>> {
+        Box::pin(async move {
+            let (domain, connector) = self
+                .connector
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("no TLS connector is configured"))?;
+
+            Connection::tls_client(addr, domain, connector.clone()).await
+        })
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Listener for Tls {
+    fn bind(&self, addr: SocketAddr) -> BoxFuture<'_, anyhow::Result<Incoming>> {
+        Box::pin(async move {
+            let acceptor = self
+                .acceptor
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("no TLS acceptor is configured"))?;
+
+            let listener = TlsListener::bind(addr, acceptor).await?;
+            Ok(Incoming::new(listener.local_addr(), listener))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Listener, Tcp, Transport, Udp};
+    use crate::{ConnectDatagram, SinkExt, StreamExt};
+
+    /// Echoes one datagram over any transport.
+    async fn echo<T: Transport + Listener>(transport: &T) -> anyhow::Result<()> {
+        let mut incoming = transport.bind("127.0.0.1:0".parse()?).await?;
+        let mut client = transport.connect(incoming.local_addr()).await?;
+
+        client
+            .writer()
+            .send(ConnectDatagram::with_tag(1, vec![1, 2, 3])?)
+            .await?;
+
+        let mut server = incoming.next().await.expect("listener closed");
+        let datagram = server.reader().next().await.expect("connection closed");
+        server.writer().send(datagram).await?;
+
+        let echoed = client.reader().next().await.expect("connection closed");
+        assert_eq!(1, echoed.tag());
+        assert_eq!(&[1, 2, 3], echoed.data());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn tcp_transport() -> anyhow::Result<()> {
+        echo(&Tcp::new()).await
+    }
+
+    #[async_std::test]
+    async fn udp_transport() -> anyhow::Result<()> {
+        echo(&Udp::new()).await
+    }
+
+    #[async_std::test]
+    async fn boxed_transport() -> anyhow::Result<()> {
+        let transport: Box<dyn Transport> = Box::new(Tcp::new());
+        let listener: Box<dyn Listener> = Box::new(Tcp::new());
+
+        let mut incoming = listener.bind("127.0.0.1:0".parse()?).await?;
+        let client = transport.connect(incoming.local_addr()).await?;
+        let server = incoming.next().await.expect("listener closed");
+
+        assert_eq!(client.local_addr(), server.peer_addr());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "tls")]
+    #[async_std::test]
+    async fn unconfigured_tls_transport() -> anyhow::Result<()> {
+        let tls = super::Tls::new();
+
+        assert!(tls.bind("127.0.0.1:0".parse()?).await.is_err());
+        assert!(tls.connect("127.0.0.1:1".parse()?).await.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+//! Typed message layer that serializes values into [`ConnectDatagram`]s with a pluggable [`Codec`].
+//!
+//! <br/>
+//!
+//! This module exposes the [`Codec`] trait, the built-in [`JsonCodec`] (`json` feature) and
+//! [`BincodeCodec`] (`bincode` feature), and the [`TypedConnection`] wrapper that sends and receives
+//! serializable values over a [`Connection`].
+//!
+//! Peers that mix serialization formats on one connection can stamp the format of each message
+//! in its tag, and decode received messages with the codec registered for their tag in a
+//! [`TagRegistry`].
+
+use crate::{ConnectDatagram, Connection, ConnectionReader, ConnectionWriteError, DatagramError};
+use futures::{AsyncRead, SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// A serialization format used to encode values into datagram message bodies and decode them
+/// back.
+///
+pub trait Codec {
+    /// Error encountered when encoding or decoding a value.
+    type Error: Error + Send + Sync + 'static;
+
+    /// Serializes `value` into bytes.
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Self::Error>;
+
+    /// Deserializes a value from `bytes`.
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::Error>;
+
+    /// The name of the serialization format, such as to report the format registered for a tag in
+    /// a [`TagRegistry`].
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// A [`Codec`] that serializes values as JSON.
+///
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl Codec for JsonCodec {
+    type Error = serde_json::Error;
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Self::Error> {
+        serde_json::to_vec(value)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::Error> {
+        serde_json::from_slice(bytes)
+    }
+
+    fn name(&self) -> &'static str {
+        "json"
+    }
+}
+
+/// A [`Codec`] that serializes values with bincode.
+///
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl Codec for BincodeCodec {
+    type Error = bincode::Error;
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Self::Error> {
+        bincode::serialize(value)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Self::Error> {
+        bincode::deserialize(bytes)
+    }
+
+    fn name(&self) -> &'static str {
+        "bincode"
+    }
+}
+
+/// Encountered when there is an issue sending or receiving a typed message.
+///
+#[derive(Debug)]
+pub enum TypedError {
+    /// Wraps an error encountered by the [`Codec`] when encoding or decoding a value.
+    Codec(Box<dyn Error + Send + Sync>),
+
+    /// Wraps a [`DatagramError`] encountered when constructing the datagram for a value.
+    Datagram(DatagramError),
+
+    /// Wraps a [`ConnectionWriteError`] encountered when sending the datagram for a value.
+    Write(ConnectionWriteError),
+
+    /// No codec is registered in the [`TagRegistry`] for the tag of a received datagram.
+    UnknownTag(u16),
+}
+
+impl Error for TypedError {}
+
+impl std::fmt::Display for TypedError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TypedError::Codec(err) => std::fmt::Display::fmt(err, formatter),
+            TypedError::Datagram(err) => std::fmt::Display::fmt(err, formatter),
+            TypedError::Write(err) => std::fmt::Display::fmt(err, formatter),
+            TypedError::UnknownTag(tag) => formatter.write_fmt(format_args!(
+                "no codec is registered for the datagram tag {}",
+                tag
+            )),
+        }
+    }
+}
+
+/// Wrapper around a [`Connection`] that sends and receives serializable values, encoded into
+/// datagram message bodies with a [`Codec`].
+///
+/// The wire format is unchanged, so a [`TypedConnection`] can talk to a peer using a plain
+/// [`Connection`] as long as both agree on the serialization format.
+///
+/// # Example
+///
+/// Please see the [typed-messages](https://github.com/sachanganesh/connect-rs/blob/main/examples/typed-messages/src/main.rs)
+/// example program for a more thorough showcase.
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let mut conn = TypedConnection::new(Connection::tcp_client(ip_address).await?, JsonCodec);
+///
+/// conn.send(&Greeting { text: String::from("Hello world!") }).await?;
+///
+/// if let Some(reply) = conn.recv::<Greeting>().await {
+///     let reply = reply?;
+/// }
+/// ```
+pub struct TypedConnection<C: Codec> {
+    conn: Connection,
+    codec: C,
+}
+
+impl<C: Codec> TypedConnection<C> {
+    /// Creates a [`TypedConnection`] that encodes values sent over `conn` with `codec`.
+    pub fn new(conn: Connection, codec: C) -> Self {
+        Self { conn, codec }
+    }
+
+    /// Get a reference to the underlying [`Connection`].
+    pub fn get_ref(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Get mutable access to the underlying [`Connection`].
+    pub fn get_mut(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+
+    /// Consume the [`TypedConnection`] to get the underlying [`Connection`].
+    pub fn into_inner(self) -> Connection {
+        self.conn
+    }
+
+    /// Encodes `value` and sends it with a tag of `0`.
+    pub async fn send<T: Serialize>(&mut self, value: &T) -> Result<(), TypedError> {
+        self.send_with_tag(0, value).await
+    }
+
+    /// Encodes `value` and sends it with the provided tag, which can be used to identify the type
+    /// of the value to the peer.
+    pub async fn send_with_tag<T: Serialize>(
+        &mut self,
+        tag: u16,
+        value: &T,
+    ) -> Result<(), TypedError> {
+        let data = self
+            .codec
+            .encode(value)
+            .map_err(|err| TypedError::Codec(Box::new(err)))?;
+        let datagram = ConnectDatagram::with_tag(tag, data).map_err(TypedError::Datagram)?;
+
+        self.conn
+            .writer()
+            .send(datagram)
+            .await
+            .map_err(TypedError::Write)
+    }
+
+    /// Receives the next datagram and decodes its message body, returning `None` when the
+    /// connection is closed.
+    pub async fn recv<T: DeserializeOwned>(&mut self) -> Option<Result<T, TypedError>> {
+        self.recv_with_tag()
+            .await
+            .map(|res| res.map(|(_, value)| value))
+    }
+
+    /// Receives the next datagram and decodes its message body along with its tag, returning
+    /// `None` when the connection is closed.
+    pub async fn recv_with_tag<T: DeserializeOwned>(
+        &mut self,
+    ) -> Option<Result<(u16, T), TypedError>> {
+        let datagram = self.conn.reader().next().await?;
+
+        Some(
+            self.codec
+                .decode(datagram.data())
+                .map(|value| (datagram.tag(), value))
+                .map_err(|err| TypedError::Codec(Box::new(err))),
+        )
+    }
+}
+
+/// Decodes the message body of a datagram with one of the [`TagRegistry`]'s codecs.
+type Decoder<T> = Box<dyn Fn(&[u8]) -> Result<T, TypedError> + Send + Sync>;
+
+/// A registry of the [`Codec`] to decode values of type `T` with, by the tag of the datagram that
+/// carries them, so that peers can use the tag as a content-type.
+///
+/// Senders stamp the serialization format of each value in the tag, such as with
+/// [`TypedConnection::send_with_tag`], and receivers decode values with
+/// [`ConnectionReader::recv_decoded`], which picks the codec by the tag of each datagram.
+///
+/// # Example
+///
+/// Basic usage, mixing JSON and bincode on one connection:
+///
+/// ```ignore
+/// const JSON: u16 = 1;
+/// const BINCODE: u16 = 2;
+///
+/// let mut registry = TagRegistry::new();
+/// registry.register(JSON, JsonCodec).register(BINCODE, BincodeCodec);
+///
+/// // the sender picks a format per message
+/// let data = serde_json::to_vec(&order)?;
+/// client.writer().send(ConnectDatagram::with_tag(JSON, data)?).await?;
+///
+/// let data = bincode::serialize(&order)?;
+/// client.writer().send(ConnectDatagram::with_tag(BINCODE, data)?).await?;
+///
+/// // the receiver decodes both with the codec registered for the tag
+/// while let Some(order) = conn.reader().recv_decoded::<Order>(&registry).await {
+///     let order = order?;
+/// }
+/// ```
+pub struct TagRegistry<T> {
+    codecs: HashMap<u16, (&'static str, Decoder<T>)>,
+}
+
+impl<T: DeserializeOwned> TagRegistry<T> {
+    /// Creates an empty [`TagRegistry`].
+    pub fn new() -> Self {
+        Self {
+            codecs: HashMap::new(),
+        }
+    }
+
+    /// Registers `codec` to decode the message bodies of datagrams with the provided tag,
+    /// replacing any codec previously registered for it.
+    pub fn register<C>(&mut self, tag: u16, codec: C) -> &mut Self
+    where
+        C: Codec + Send + Sync + 'static,
+    {
+        let name = codec.name();
+        let decoder = move |bytes: &[u8]| {
+            codec
+                .decode(bytes)
+                .map_err(|err| TypedError::Codec(Box::new(err)))
+        };
+
+        self.codecs.insert(tag, (name, Box::new(decoder)));
+        self
+    }
+
+    /// Removes the codec registered for the provided tag.
+    pub fn unregister(&mut self, tag: u16) {
+        self.codecs.remove(&tag);
+    }
+
+    /// Get the [name](Codec::name) of the codec registered for the provided tag.
+    pub fn codec_name(&self, tag: u16) -> Option<&'static str> {
+        self.codecs.get(&tag).map(|(name, _)| *name)
+    }
+
+    /// Decodes the message body of `datagram` with the codec registered for its tag.
+    pub fn decode(&self, datagram: &ConnectDatagram) -> Result<T, TypedError> {
+        match self.codecs.get(&datagram.tag()) {
+            Some((_, decoder)) => decoder(datagram.data()),
+            None => Err(TypedError::UnknownTag(datagram.tag())),
+        }
+    }
+}
+
+impl<T: DeserializeOwned> Default for TagRegistry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: AsyncRead + Unpin> ConnectionReader<R> {
+    /// Receives the next datagram and decodes its message body with the codec registered for its
+    /// tag in `registry`, returning `None` when the connection is closed.
+    ///
+    /// A datagram whose tag has no registered codec is consumed and reported as
+    /// [`TypedError::UnknownTag`], so that receiving can continue with the next datagram.
+    pub async fn recv_decoded<T: DeserializeOwned>(
+        &mut self,
+        registry: &TagRegistry<T>,
+    ) -> Option<Result<T, TypedError>> {
+        let datagram = self.next().await?;
+        Some(registry.decode(&datagram))
+    }
+}
+
+#[cfg(all(test, feature = "json", feature = "bincode"))]
+mod tests {
+    use super::{BincodeCodec, Codec, JsonCodec, TagRegistry, TypedConnection, TypedError};
+    use crate::{ConnectDatagram, Connection, SinkExt};
+    use async_std::net::{TcpListener, TcpStream};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Greeting {
+        id: u32,
+        text: String,
+    }
+
+    async fn round_trip<C: Codec + Copy>(codec: C) -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let client = TcpStream::connect(listener.local_addr()?).await?;
+        let (server, _) = listener.accept().await?;
+
+        let mut client = TypedConnection::new(Connection::from(client), codec);
+        let mut conn = TypedConnection::new(Connection::from(server), codec);
+
+        let greeting = Greeting {
+            id: 7,
+            text: String::from("Hello world!"),
+        };
+        client.send_with_tag(3, &greeting).await?;
+
+        let (tag, received) = conn.recv_with_tag::<Greeting>().await.unwrap()?;
+        assert_eq!(3, tag);
+        assert_eq!(greeting, received);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn json_round_trip() -> anyhow::Result<()> {
+        round_trip(JsonCodec).await
+    }
+
+    #[async_std::test]
+    async fn bincode_round_trip() -> anyhow::Result<()> {
+        round_trip(BincodeCodec).await
+    }
+
+    #[async_std::test]
+    async fn tag_registry_mixes_codecs() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let client = TcpStream::connect(listener.local_addr()?).await?;
+        let (server, _) = listener.accept().await?;
+
+        let mut client = Connection::from(client);
+        let mut conn = Connection::from(server);
+
+        let mut registry = TagRegistry::new();
+        registry.register(1, JsonCodec).register(2, BincodeCodec);
+        assert_eq!(Some("json"), registry.codec_name(1));
+        assert_eq!(Some("bincode"), registry.codec_name(2));
+
+        let greeting = Greeting {
+            id: 7,
+            text: String::from("Hello world!"),
+        };
+
+        for (tag, data) in [
+            (1, JsonCodec.encode(&greeting)?),
+            (2, BincodeCodec.encode(&greeting)?),
+            (3, Vec::from("unregistered")),
+        ] {
+            client
+                .writer()
+                .send(ConnectDatagram::with_tag(tag, data)?)
+                .await?;
+        }
+
+        let reader = conn.reader();
+        assert_eq!(greeting, reader.recv_decoded(&registry).await.unwrap()?);
+        assert_eq!(greeting, reader.recv_decoded(&registry).await.unwrap()?);
+        assert!(matches!(
+            reader.recv_decoded(&registry).await,
+            Some(Err(TypedError::UnknownTag(3)))
+        ));
+
+        Ok(())
+    }
+}
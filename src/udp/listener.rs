@@ -0,0 +1,352 @@
+use crate::logging::*;
+use crate::protocol::SharedByteOrder;
+use crate::udp::{
+    starts_datagram, PacketSource, UdpOptions, UdpReadStream, UdpWriteStream, MAX_PACKET_SIZE,
+};
+use crate::{ByteOrder, Connection};
+use async_io::Timer;
+use async_std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use async_std::pin::Pin;
+use async_std::task::{Context, Poll};
+use futures::channel::mpsc;
+use futures::{Future, Stream, StreamExt};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+type RecvFromFuture =
+    Pin<Box<dyn Future<Output = std::io::Result<(Vec<u8>, SocketAddr)>> + Send + Sync>>;
+
+/// The default time after which a peer that has not sent anything is forgotten by a
+/// [`UdpListener`].
+pub(crate) const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The number of UDP packets buffered for a peer before further packets from it are dropped.
+const PEER_BUFFER_SIZE: usize = 64;
+
+/// A peer known to a [`UdpListener`], whose packets are forwarded to its [`Connection`].
+struct Peer {
+    packets: mpsc::Sender<Vec<u8>>,
+    last_seen: Instant,
+}
+
+/// Listens on a bound UDP socket for packets from many peers, to be handled as independent
+/// [`Connection`]s.
+///
+/// Implements the [`Stream`] trait to yield a [`Connection`] for each distinct peer address the
+/// first time a valid datagram, or the first fragment of one, is received from it. Later packets from that peer are read by its
+/// [`Connection`], and messages written to the [`Connection`] are sent to the peer from the bound
+/// socket.
+///
+/// Packets are only received while the listener is polled, so the listener must be driven for as
+/// long as its connections are in use. Up to 64 packets are buffered for each peer whose
+/// [`Connection`] is not being read, after which its packets are dropped.
+///
+/// A peer that has not sent anything for the idle timeout is forgotten: the reader of its
+/// [`Connection`] ends, and the next packet from that peer yields a new [`Connection`]. Dropping a
+/// [`Connection`] forgets its peer in the same way. See [`UdpListener::with_idle_timeout`].
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let mut server = UdpListener::bind(ip_address).await?;
+///
+/// // wait for a packet from a new peer to come in
+/// while let Some(mut conn) = server.next().await {
+///     // do something with connection
+/// }
+/// ```
+pub struct UdpListener {
+    local_addr: SocketAddr,
+    socket: Arc<UdpSocket>,
+    recv: Option<RecvFromFuture>,
+    peers: HashMap<SocketAddr, Peer>,
+    idle_timeout: Duration,
+    expiry: Timer,
+    options: UdpOptions,
+}
+
+impl UdpListener {
+    /// Creates a [`UdpListener`] by binding to an IP address and port and listens for incoming UDP
+    /// packets.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = UdpListener::bind("127.0.0.1:3456").await?;
+    /// ```
+    pub async fn bind<A: ToSocketAddrs + std::fmt::Display>(ip_addrs: A) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(&ip_addrs).await?;
+        info!("Started UDP server at {}", &ip_addrs);
+
+        Ok(Self {
+            local_addr: socket.local_addr()?,
+            socket: Arc::new(socket),
+            recv: None,
+            peers: HashMap::new(),
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            expiry: Timer::interval(DEFAULT_IDLE_TIMEOUT),
+            options: UdpOptions::default(),
+        })
+    }
+
+    /// Get the local IP address and port.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Sets the time after which a peer that has not sent anything is forgotten, ending the reader
+    /// of its [`Connection`].
+    ///
+    /// Idle peers are checked for once every `timeout`, so a peer may be forgotten up to twice the
+    /// timeout after its last packet. Defaults to 60 seconds.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = UdpListener::bind("127.0.0.1:3456")
+    ///     .await?
+    ///     .with_idle_timeout(Duration::from_secs(10));
+    /// ```
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self.expiry = Timer::interval(timeout);
+        self
+    }
+
+    /// Reassembles datagrams split across multiple UDP packets according to `options` on each
+    /// [`Connection`] yielded by the listener.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = UdpListener::bind("127.0.0.1:3456")
+    ///     .await?
+    ///     .with_options(UdpOptions {
+    ///         reassembly_timeout: Duration::from_secs(1),
+    ///         ..UdpOptions::default()
+    ///     });
+    /// ```
+    pub fn with_options(mut self, options: UdpOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Forgets the peers that have been idle for longer than the idle timeout, or whose
+    /// [`Connection`] has been dropped.
+    fn expire_peers(&mut self) {
+        let idle_timeout = self.idle_timeout;
+
+        self.peers.retain(|peer_addr, peer| {
+            if peer.packets.is_closed() {
+                debug!("Connection with UDP peer {} was dropped", peer_addr);
+                false
+            } else if peer.last_seen.elapsed() >= idle_timeout {
+                debug!("UDP peer {} has been idle for too long", peer_addr);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Forwards a packet to the [`Connection`] of a known peer, returning the packet instead if the
+    /// peer is not known.
+    fn forward_packet(&mut self, packet: Vec<u8>, peer_addr: SocketAddr) -> Option<Vec<u8>> {
+        let peer = match self.peers.get_mut(&peer_addr) {
+            Some(peer) => peer,
+            None => return Some(packet),
+        };
+
+        match peer.packets.try_send(packet) {
+            Ok(()) => {
+                peer.last_seen = Instant::now();
+                None
+            }
+
+            Err(err) if err.is_full() => {
+                warn!(
+                    "Dropping UDP packet from {} that is not being read",
+                    peer_addr
+                );
+                peer.last_seen = Instant::now();
+                None
+            }
+
+            Err(err) => {
+                debug!("Connection with UDP peer {} was dropped", peer_addr);
+                self.peers.remove(&peer_addr);
+                Some(err.into_inner())
+            }
+        }
+    }
+
+    /// Creates a [`Connection`] for a new peer, whose first packet is read by the connection.
+    fn connect_peer(&mut self, packet: Vec<u8>, peer_addr: SocketAddr) -> Connection {
+        debug!("Received first packet from UDP peer {}", peer_addr);
+
+        let (mut sender, receiver) = mpsc::channel(PEER_BUFFER_SIZE);
+        sender
+            .try_send(packet)
+            .expect("new channel has capacity for a packet");
+
+        self.peers.insert(
+            peer_addr,
+            Peer {
+                packets: sender,
+                last_seen: Instant::now(),
+            },
+        );
+
+        let (read_order, write_order) = (SharedByteOrder::default(), SharedByteOrder::default());
+
+        Connection::new(
+            self.local_addr,
+            peer_addr,
+            Box::pin(UdpReadStream::new(
+                PacketSource::Listener(receiver),
+                self.options,
+                read_order.clone(),
+            )),
+            Box::pin(UdpWriteStream::new(
+                self.socket.clone(),
+                Some(peer_addr),
+                write_order.clone(),
+            )),
+        )
+        .with_framing_byte_order(read_order, write_order)
+    }
+}
+
+impl Stream for UdpListener {
+    type Item = Connection;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        while self.expiry.poll_next_unpin(cx).is_ready() {
+            self.expire_peers();
+        }
+
+        loop {
+            let socket = self.socket.clone();
+            let recv = self.recv.get_or_insert_with(|| {
+                Box::pin(async move {
+                    let mut buffer = vec![0; MAX_PACKET_SIZE];
+                    let (bytes_read, peer_addr) = socket.recv_from(&mut buffer).await?;
+
+                    buffer.truncate(bytes_read);
+                    Ok((buffer, peer_addr))
+                })
+            });
+
+            let res = match recv.as_mut().poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(res) => res,
+            };
+            self.recv.take();
+
+            match res {
+                Ok((packet, peer_addr)) => {
+                    if let Some(packet) = self.forward_packet(packet, peer_addr) {
+                        // the byte order of the connection is only set once it is accepted, so
+                        // a new peer may frame its datagrams in either
+                        if starts_datagram(&packet, ByteOrder::BigEndian, &self.options)
+                            || starts_datagram(&packet, ByteOrder::LittleEndian, &self.options)
+                        {
+                            return Poll::Ready(Some(self.connect_peer(packet, peer_addr)));
+                        }
+
+                        warn!("Could not deserialize message from UDP peer {}", peer_addr);
+                    }
+                }
+
+                Err(err) => {
+                    error!("Encountered error when receiving UDP packet: {}", err);
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UdpListener;
+    use crate::{ConnectDatagram, Connection};
+    use async_std::net::UdpSocket;
+    use futures::{SinkExt, StreamExt};
+    use std::convert::TryFrom;
+    use std::time::Duration;
+
+    async fn client_of(server: &UdpListener) -> anyhow::Result<Connection> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        socket.connect(server.local_addr()).await?;
+        Connection::try_from(socket)
+    }
+
+    #[async_std::test]
+    async fn connection_per_peer() -> anyhow::Result<()> {
+        let mut server = UdpListener::bind("127.0.0.1:0").await?;
+        let mut a = client_of(&server).await?;
+        let mut b = client_of(&server).await?;
+
+        a.writer()
+            .send(ConnectDatagram::with_tag(1, vec![1])?)
+            .await?;
+        let mut conn_a = server.next().await.expect("listener closed");
+        assert_eq!(a.local_addr(), conn_a.peer_addr());
+
+        b.writer()
+            .send(ConnectDatagram::with_tag(2, vec![2])?)
+            .await?;
+        let mut conn_b = server.next().await.expect("listener closed");
+        assert_eq!(b.local_addr(), conn_b.peer_addr());
+
+        // keep receiving packets for the existing peers in the background
+        async_std::task::spawn(async move { while server.next().await.is_some() {} });
+
+        a.writer()
+            .send(ConnectDatagram::with_tag(3, vec![3])?)
+            .await?;
+        assert_eq!(1, conn_a.reader().next().await.unwrap().tag());
+        assert_eq!(3, conn_a.reader().next().await.unwrap().tag());
+        assert_eq!(2, conn_b.reader().next().await.unwrap().tag());
+
+        conn_b
+            .writer()
+            .send(ConnectDatagram::with_tag(4, vec![4])?)
+            .await?;
+        assert_eq!(4, b.reader().next().await.unwrap().tag());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn idle_peer_expires() -> anyhow::Result<()> {
+        let mut server = UdpListener::bind("127.0.0.1:0")
+            .await?
+            .with_idle_timeout(Duration::from_millis(50));
+        let mut client = client_of(&server).await?;
+
+        client
+            .writer()
+            .send(ConnectDatagram::with_tag(1, vec![1])?)
+            .await?;
+        let mut conn = server.next().await.expect("listener closed");
+        async_std::task::spawn(async move { while server.next().await.is_some() {} });
+
+        assert_eq!(1, conn.reader().next().await.unwrap().tag());
+        let next = async_std::future::timeout(Duration::from_secs(5), conn.reader().next()).await?;
+        assert!(next.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,614 @@
+//! UDP transport implementations.
+//!
+//! <br/>
+//!
+//! This module primarily exposes the conversion of a connected [`UdpSocket`] into a
+//! [`Connection`], and the UDP listener implementation as [`UdpListener`].
+//!
+//! Each serialized datagram is sent as a single UDP packet if it fits within the maximum UDP
+//! payload of 65507 bytes. Larger datagrams are split into consecutive fragments of that size and
+//! reassembled by the receiver using the size-prefix of the datagram. Fragments carry no sequence
+//! numbers, so a datagram with a lost or reordered fragment cannot be reassembled and is dropped;
+//! see [`UdpOptions`] for how incomplete datagrams are discarded.
+//!
+//! An error when sending a UDP packet only drops the datagram being sent. The error is returned
+//! when flushing the [`ConnectionWriter`](`crate::ConnectionWriter`), which remains usable to send
+//! later datagrams.
+
+pub(crate) mod listener;
+
+pub use listener::*;
+
+use crate::logging::*;
+use crate::protocol::SharedByteOrder;
+use crate::writer::DEFAULT_BUFFER_LIMIT;
+use crate::{ByteOrder, ConnectDatagram, Connection, SIZE_PREFIX_BYTE_SIZE};
+use async_std::net::{SocketAddr, UdpSocket};
+use async_std::pin::Pin;
+use async_std::task::{Context, Poll};
+use futures::channel::mpsc;
+use futures::{AsyncRead, AsyncWrite, Future, Stream};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+type RecvFuture = Pin<Box<dyn Future<Output = std::io::Result<Vec<u8>>> + Send + Sync>>;
+type SendFuture = Pin<Box<dyn Future<Output = std::io::Result<usize>> + Send + Sync>>;
+
+/// The maximum payload of a UDP packet, which is also the size of the fragments that larger
+/// serialized datagrams are split into.
+pub(crate) const MAX_PACKET_SIZE: usize = 65507;
+
+/// Configures how datagrams split across multiple UDP packets are reassembled.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let conn = Connection::udp_with_options(socket, UdpOptions {
+///     max_message_size: 16 * 1024 * 1024,
+///     ..UdpOptions::default()
+/// })?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpOptions {
+    /// Maximum size of a serialized datagram that is reassembled from multiple UDP packets,
+    /// including its header. Larger datagrams are dropped. Defaults to 4MB.
+    pub max_message_size: usize,
+
+    /// Time allowed for all fragments of a datagram to arrive. A datagram that is still
+    /// incomplete when the next packet arrives after this time is dropped. Defaults to 5 seconds.
+    pub reassembly_timeout: Duration,
+}
+
+impl Default for UdpOptions {
+    fn default() -> Self {
+        Self {
+            max_message_size: DEFAULT_BUFFER_LIMIT,
+            reassembly_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Reads the size-prefix at the start of a UDP packet framed in `byte_order`, returning the full
+/// serialized size of the datagram it starts.
+fn datagram_size(packet: &[u8], byte_order: ByteOrder) -> Option<usize> {
+    let size = byte_order.read_size_prefix(packet)? as usize;
+
+    Some(SIZE_PREFIX_BYTE_SIZE + size)
+}
+
+/// Checks whether a UDP packet holds exactly one well-formed serialized datagram framed in
+/// `byte_order`.
+pub(crate) fn is_datagram_packet(packet: &[u8], byte_order: ByteOrder) -> bool {
+    let mut packet = Cow::Borrowed(packet);
+    if byte_order != ByteOrder::BigEndian {
+        byte_order.convert_header(packet.to_mut());
+    }
+
+    ConnectDatagram::from_bytes(&packet).is_ok()
+}
+
+/// Checks whether a UDP packet is the first fragment of a serialized datagram framed in
+/// `byte_order` that is small enough to be reassembled.
+fn is_first_fragment(packet: &[u8], byte_order: ByteOrder, options: &UdpOptions) -> bool {
+    datagram_size(packet, byte_order).is_some_and(|size| {
+        packet.len() == MAX_PACKET_SIZE && size > packet.len() && size <= options.max_message_size
+    })
+}
+
+/// Checks whether a UDP packet starts a new serialized datagram framed in `byte_order`, either in
+/// full or as its first fragment.
+pub(crate) fn starts_datagram(packet: &[u8], byte_order: ByteOrder, options: &UdpOptions) -> bool {
+    is_datagram_packet(packet, byte_order) || is_first_fragment(packet, byte_order, options)
+}
+
+/// Where a [`UdpReadStream`] receives its UDP packets from.
+pub(crate) enum PacketSource {
+    /// Packets are received directly from a connected socket.
+    Socket {
+        socket: Arc<UdpSocket>,
+        recv: Option<RecvFuture>,
+    },
+
+    /// Packets from a single peer are forwarded by a [`UdpListener`].
+    Listener(mpsc::Receiver<Vec<u8>>),
+}
+
+impl PacketSource {
+    /// Receives the next UDP packet, returning `None` once no more packets can be received.
+    fn poll_packet(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<Option<Vec<u8>>>> {
+        match self {
+            PacketSource::Socket { socket, recv } => {
+                let socket = socket.clone();
+                let fut = recv.get_or_insert_with(|| {
+                    Box::pin(async move {
+                        let mut buffer = vec![0; MAX_PACKET_SIZE];
+                        let bytes_read = socket.recv(&mut buffer).await?;
+
+                        buffer.truncate(bytes_read);
+                        Ok(buffer)
+                    })
+                });
+
+                let res = futures::ready!(fut.as_mut().poll(cx));
+                recv.take();
+                Poll::Ready(res.map(Some))
+            }
+
+            PacketSource::Listener(packets) => Pin::new(packets).poll_next(cx).map(Ok),
+        }
+    }
+}
+
+/// A serialized datagram whose fragments are still being received.
+struct PartialDatagram {
+    buffer: Vec<u8>,
+    size: usize,
+    started: Instant,
+}
+
+/// Exposes the datagrams received over UDP as a byte stream, so that they can be read by a
+/// [`ConnectionReader`](`crate::ConnectionReader`).
+///
+/// Each UDP packet is expected to hold either one serialized datagram or a fragment of one.
+/// Packets that cannot be deserialized or reassembled are dropped so that they cannot corrupt the
+/// framing of the byte stream.
+pub(crate) struct UdpReadStream {
+    source: PacketSource,
+    options: UdpOptions,
+    byte_order: SharedByteOrder,
+    partial: Option<PartialDatagram>,
+    packet: Vec<u8>,
+    offset: usize,
+}
+
+impl UdpReadStream {
+    pub(crate) fn new(
+        source: PacketSource,
+        options: UdpOptions,
+        byte_order: SharedByteOrder,
+    ) -> Self {
+        Self {
+            source,
+            options,
+            byte_order,
+            partial: None,
+            packet: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// Handles a received UDP packet, returning a serialized datagram once one is complete.
+    fn receive_packet(&mut self, packet: Vec<u8>) -> Option<Vec<u8>> {
+        let byte_order = self.byte_order.get();
+
+        if let Some(mut partial) = self.partial.take() {
+            if partial.started.elapsed() > self.options.reassembly_timeout {
+                warn!("Dropping UDP datagram that was not reassembled in time");
+            } else if is_datagram_packet(&packet, byte_order) {
+                warn!("Dropping incomplete UDP datagram interrupted by another datagram");
+            } else {
+                partial.buffer.extend_from_slice(&packet);
+
+                if partial.buffer.len() < partial.size {
+                    self.partial.replace(partial);
+                    return None;
+                }
+
+                if partial.buffer.len() == partial.size
+                    && is_datagram_packet(&partial.buffer, byte_order)
+                {
+                    return Some(partial.buffer);
+                }
+
+                warn!("Could not reassemble message from UDP fragments");
+                return None;
+            }
+        }
+
+        if is_datagram_packet(&packet, byte_order) {
+            Some(packet)
+        } else if is_first_fragment(&packet, byte_order, &self.options) {
+            let size =
+                datagram_size(&packet, byte_order).expect("fragment starts with a size-prefix");
+            trace!("reassembling UDP datagram of size {} bytes", size);
+
+            self.partial.replace(PartialDatagram {
+                buffer: packet,
+                size,
+                started: Instant::now(),
+            });
+            None
+        } else {
+            warn!("Could not deserialize message from UDP message");
+            None
+        }
+    }
+}
+
+impl AsyncRead for UdpReadStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        loop {
+            if self.offset < self.packet.len() {
+                let offset = self.offset;
+                let len = buf.len().min(self.packet.len() - offset);
+
+                buf[..len].copy_from_slice(&self.packet[offset..offset + len]);
+                self.offset += len;
+
+                return Poll::Ready(Ok(len));
+            }
+
+            match self.source.poll_packet(cx) {
+                Poll::Pending => return Poll::Pending,
+
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+
+                Poll::Ready(Ok(None)) => return Poll::Ready(Ok(0)),
+
+                Poll::Ready(Ok(Some(packet))) => {
+                    if let Some(packet) = self.receive_packet(packet) {
+                        self.packet = packet;
+                        self.offset = 0;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sends the byte stream written by a [`ConnectionWriter`](`crate::ConnectionWriter`) over UDP,
+/// one serialized datagram per UDP packet, or one fragment per UDP packet for datagrams larger than
+/// the maximum UDP payload.
+///
+/// Packets are sent to `peer_addr` if it is set, or else to the address the socket is connected to.
+///
+/// Since UDP packets are sent independently of each other, an error when sending a packet only
+/// drops the datagram it belongs to. The remaining datagrams are still sent, and the first error
+/// is returned once the flush completes, leaving the stream usable for later writes.
+pub(crate) struct UdpWriteStream {
+    socket: Arc<UdpSocket>,
+    peer_addr: Option<SocketAddr>,
+    byte_order: SharedByteOrder,
+    send: Option<SendFuture>,
+    buffer: Vec<u8>,
+    packets: VecDeque<Vec<u8>>,
+    error: Option<std::io::Error>,
+}
+
+impl UdpWriteStream {
+    pub(crate) fn new(
+        socket: Arc<UdpSocket>,
+        peer_addr: Option<SocketAddr>,
+        byte_order: SharedByteOrder,
+    ) -> Self {
+        Self {
+            socket,
+            peer_addr,
+            byte_order,
+            send: None,
+            buffer: Vec::new(),
+            packets: VecDeque::new(),
+            error: None,
+        }
+    }
+
+    /// Removes the next complete serialized datagram from the buffered bytes, if there is one.
+    fn next_datagram(&mut self) -> Option<Vec<u8>> {
+        let size = datagram_size(&self.buffer, self.byte_order.get())?;
+
+        if self.buffer.len() < size {
+            return None;
+        }
+
+        let remaining = self.buffer.split_off(size);
+        Some(std::mem::replace(&mut self.buffer, remaining))
+    }
+
+    /// Gets the next UDP packet to send, splitting the next serialized datagram into fragments if
+    /// it is larger than the maximum UDP payload.
+    fn next_packet(&mut self) -> Option<Vec<u8>> {
+        if self.packets.is_empty() {
+            let datagram = self.next_datagram()?;
+
+            if datagram.len() <= MAX_PACKET_SIZE {
+                return Some(datagram);
+            }
+
+            trace!(
+                "splitting datagram of size {} bytes into fragments",
+                datagram.len()
+            );
+            self.packets
+                .extend(datagram.chunks(MAX_PACKET_SIZE).map(|c| c.to_vec()));
+        }
+
+        self.packets.pop_front()
+    }
+}
+
+impl AsyncWrite for UdpWriteStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.buffer.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        loop {
+            if let Some(send) = self.send.as_mut() {
+                let res = match send.as_mut().poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(res) => res,
+                };
+
+                self.send.take();
+                if let Err(err) = res {
+                    warn!("Dropping datagram that could not be sent over UDP: {}", err);
+
+                    // the remaining fragments cannot be reassembled without the one that failed
+                    self.packets.clear();
+                    self.error.get_or_insert(err);
+                }
+            }
+
+            match self.next_packet() {
+                Some(packet) => {
+                    let socket = self.socket.clone();
+                    let peer_addr = self.peer_addr;
+
+                    self.send.replace(Box::pin(async move {
+                        match peer_addr {
+                            Some(peer_addr) => socket.send_to(&packet, peer_addr).await,
+                            None => socket.send(&packet).await,
+                        }
+                    }));
+                }
+
+                None => {
+                    return match self.error.take() {
+                        Some(err) => Poll::Ready(Err(err)),
+                        None => Poll::Ready(Ok(())),
+                    };
+                }
+            }
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl Connection {
+    /// Creates a [`Connection`] that sends and receives datagrams on a connected [`UdpSocket`],
+    /// reassembling datagrams split across multiple UDP packets according to `options`.
+    ///
+    /// Converting the socket with [`TryFrom`] uses the default [`UdpOptions`].
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    /// socket.connect("127.0.0.1:3456").await?;
+    ///
+    /// let conn = Connection::udp_with_options(socket, UdpOptions::default())?;
+    /// ```
+    pub fn udp_with_options(socket: UdpSocket, options: UdpOptions) -> anyhow::Result<Self> {
+        let local_addr = socket.local_addr()?;
+        let peer_addr = socket.peer_addr()?;
+
+        let socket = Arc::new(socket);
+        let (read_order, write_order) = (SharedByteOrder::default(), SharedByteOrder::default());
+
+        let read_stream = UdpReadStream::new(
+            PacketSource::Socket {
+                socket: socket.clone(),
+                recv: None,
+            },
+            options,
+            read_order.clone(),
+        );
+        let write_stream = UdpWriteStream::new(socket, None, write_order.clone());
+
+        Ok(Self::new(
+            local_addr,
+            peer_addr,
+            Box::pin(read_stream),
+            Box::pin(write_stream),
+        )
+        .with_framing_byte_order(read_order, write_order))
+    }
+}
+
+impl TryFrom<UdpSocket> for Connection {
+    type Error = anyhow::Error;
+
+    fn try_from(socket: UdpSocket) -> Result<Self, Self::Error> {
+        Self::udp_with_options(socket, UdpOptions::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PacketSource, UdpOptions, UdpReadStream, MAX_PACKET_SIZE};
+    use crate::protocol::SharedByteOrder;
+    use crate::{ByteOrder, ConnectDatagram, Connection, ConnectionReader};
+    use async_std::net::UdpSocket;
+    use futures::channel::mpsc;
+    use futures::{SinkExt, StreamExt};
+    use std::convert::TryFrom;
+
+    #[async_std::test]
+    async fn datagrams_over_udp() -> anyhow::Result<()> {
+        let a = UdpSocket::bind("127.0.0.1:0").await?;
+        let b = UdpSocket::bind("127.0.0.1:0").await?;
+        a.connect(b.local_addr()?).await?;
+        b.connect(a.local_addr()?).await?;
+
+        // a malformed packet is dropped without corrupting the datagrams that follow it
+        a.send(b"not a datagram").await?;
+
+        let mut sender = Connection::try_from(a)?;
+        let mut receiver = Connection::try_from(b)?;
+
+        for tag in 0..3 {
+            let datagram = ConnectDatagram::with_tag(tag, vec![tag as u8; 100])?;
+            sender.writer().feed(datagram).await?;
+        }
+        sender.writer().flush().await?;
+
+        for tag in 0..3 {
+            let received = receiver.reader().next().await.unwrap();
+            assert_eq!(tag, received.tag());
+            assert_eq!(&vec![tag as u8; 100][..], received.data());
+        }
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn writer_survives_send_error() -> anyhow::Result<()> {
+        let a = UdpSocket::bind("127.0.0.1:0").await?;
+        let peer_addr = UdpSocket::bind("127.0.0.1:0").await?.local_addr()?;
+        a.connect(peer_addr).await?;
+        let local_addr = a.local_addr()?;
+
+        // nothing is listening at the peer address, so sends are eventually refused
+        let mut sender = Connection::try_from(a)?;
+        let mut refused = false;
+        for _ in 0..10 {
+            if sender
+                .writer()
+                .send(ConnectDatagram::with_tag(1, vec![1])?)
+                .await
+                .is_err()
+            {
+                refused = true;
+                break;
+            }
+
+            async_std::task::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(refused);
+
+        let b = UdpSocket::bind(peer_addr).await?;
+        b.connect(local_addr).await?;
+        let mut receiver = Connection::try_from(b)?;
+
+        sender
+            .writer()
+            .send(ConnectDatagram::with_tag(2, vec![2])?)
+            .await?;
+        assert_eq!(2, receiver.reader().next().await.unwrap().tag());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn large_datagram_over_udp() -> anyhow::Result<()> {
+        let a = UdpSocket::bind("127.0.0.1:0").await?;
+        let b = UdpSocket::bind("127.0.0.1:0").await?;
+        a.connect(b.local_addr()?).await?;
+        b.connect(a.local_addr()?).await?;
+
+        let mut sender = Connection::try_from(a)?;
+        let mut receiver = Connection::try_from(b)?;
+
+        let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let receiving = async_std::task::spawn(async move { receiver.reader().next().await });
+        sender
+            .writer()
+            .send(ConnectDatagram::with_tag(1, data.clone())?)
+            .await?;
+
+        let received = receiving.await.unwrap();
+        assert_eq!(1, received.tag());
+        assert_eq!(data.as_slice(), received.data());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn little_endian_over_udp() -> anyhow::Result<()> {
+        let a = UdpSocket::bind("127.0.0.1:0").await?;
+        let b = UdpSocket::bind("127.0.0.1:0").await?;
+        a.connect(b.local_addr()?).await?;
+        b.connect(a.local_addr()?).await?;
+
+        let mut sender = Connection::try_from(a)?;
+        let mut receiver = Connection::try_from(b)?;
+        sender.set_byte_order(ByteOrder::LittleEndian);
+        receiver.set_byte_order(ByteOrder::LittleEndian);
+
+        // both a datagram sent in a single packet and one split into fragments
+        let small = ConnectDatagram::with_tag(1, vec![1; 100])?;
+        let large = ConnectDatagram::with_tag(2, (0..100_000).map(|i| i as u8).collect())?;
+
+        let receiving = async_std::task::spawn(async move {
+            let small = receiver.reader().next().await;
+            let large = receiver.reader().next().await;
+            (small, large)
+        });
+        sender.writer().send(small.clone()).await?;
+        sender.writer().send(large.clone()).await?;
+
+        let (received_small, received_large) =
+            async_std::future::timeout(std::time::Duration::from_secs(5), receiving).await?;
+        assert_eq!(Some(small), received_small);
+        assert_eq!(Some(large), received_large);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn reassemble_fragments() -> anyhow::Result<()> {
+        let (mut packets, receiver) = mpsc::channel(16);
+        let read_stream = UdpReadStream::new(
+            PacketSource::Listener(receiver),
+            UdpOptions::default(),
+            SharedByteOrder::default(),
+        );
+        let addr = "127.0.0.1:0".parse()?;
+        let reader = ConnectionReader::new(addr, addr, Box::pin(read_stream));
+
+        let small = ConnectDatagram::with_tag(1, vec![1; 10])?;
+        let large = ConnectDatagram::with_tag(2, vec![2; 2 * MAX_PACKET_SIZE])?;
+        let fragments: Vec<Vec<u8>> = large
+            .clone()
+            .into_bytes()
+            .chunks(MAX_PACKET_SIZE)
+            .map(|c| c.to_vec())
+            .collect();
+        assert_eq!(3, fragments.len());
+
+        // a datagram interrupted by another one is dropped
+        packets.try_send(fragments[0].clone())?;
+        packets.try_send(small.clone().into_bytes())?;
+        packets.try_send(fragments[1].clone())?;
+        packets.try_send(fragments[2].clone())?;
+
+        for fragment in fragments {
+            packets.try_send(fragment)?;
+        }
+        drop(packets);
+
+        let received: Vec<ConnectDatagram> = reader.collect().await;
+        assert_eq!(vec![small, large], received);
+
+        Ok(())
+    }
+}
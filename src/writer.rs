@@ -1,15 +1,50 @@
-use crate::protocol::ConnectDatagram;
-use async_std::net::SocketAddr;
-use async_std::pin::Pin;
+use crate::conn_limit::ConnectionSlot;
+use crate::heartbeat::WriterHeartbeat;
+use crate::idle::WriterIdle;
+use crate::logging::*;
+use crate::observer::ConnectionObserver;
+use crate::protocol::{
+    serialized_tag, ByteOrder, ConnectDatagram, DatagramError, SegmentedDatagram, SharedByteOrder,
+    MAX_DATA_BYTE_SIZE,
+};
+use crate::rate_limit::TokenBucket;
+use crate::rt::{self, Instant, Timer};
+use crate::sequence;
+use crate::stats::ConnectionStats;
+use crate::DATAGRAM_HEADER_BYTE_SIZE;
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::future::{join_all, poll_fn};
 use futures::io::IoSlice;
-use futures::task::{Context, Poll};
-use futures::{AsyncWrite, Sink};
-use log::*;
+use futures::task::{noop_waker, Context, Poll};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, Future, Sink, SinkExt};
 use std::error::Error;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 
-pub use futures::{SinkExt, StreamExt};
+pub use futures::StreamExt;
 use std::fmt::Debug;
 
+/// The default number of buffered bytes at which a [`ConnectionWriter`] stops accepting new
+/// messages until pending writes are drained.
+pub(crate) const DEFAULT_BUFFER_LIMIT: usize = 4 * 1024 * 1024;
+
+/// The maximum number of buffers handed to a single vectored write, kept within the `IOV_MAX`
+/// limit that operating systems impose on `writev`.
+pub(crate) const MAX_IO_SLICES: usize = 1024;
+
+/// The priority of datagrams sent without an explicit priority.
+pub(crate) const DEFAULT_PRIORITY: u8 = 0;
+
+/// The number of pending bytes at which a flush is no longer delayed by the flush delay.
+pub(crate) const FLUSH_DELAY_THRESHOLD: usize = 64 * 1024;
+
+/// The boxed network stream that a [`ConnectionWriter`] writes to by default, which erases the
+/// type of the transport so that writers of different transports share a single type.
+pub type BoxedWriteStream = Pin<Box<dyn AsyncWrite + Send + Sync>>;
+
 /// Encountered when there is an issue with writing messages on the network stream.
 ///
 #[derive(Debug)]
@@ -34,6 +69,35 @@ impl std::fmt::Display for ConnectionWriteError {
     }
 }
 
+/// Encountered when sending bytes through the [`tagged_sink`](ConnectionWriter::tagged_sink) of a
+/// [`ConnectionWriter`].
+///
+#[derive(Debug)]
+pub enum TaggedSinkError {
+    /// Wraps a [`DatagramError`] encountered when wrapping the bytes in a datagram.
+    Datagram(DatagramError),
+
+    /// Wraps a [`ConnectionWriteError`] encountered when sending the datagram.
+    Write(ConnectionWriteError),
+}
+
+impl Error for TaggedSinkError {}
+
+impl std::fmt::Display for TaggedSinkError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TaggedSinkError::Datagram(err) => std::fmt::Display::fmt(err, formatter),
+            TaggedSinkError::Write(err) => std::fmt::Display::fmt(err, formatter),
+        }
+    }
+}
+
+impl From<ConnectionWriteError> for TaggedSinkError {
+    fn from(err: ConnectionWriteError) -> Self {
+        TaggedSinkError::Write(err)
+    }
+}
+
 /// An interface to write messages to the network connection.
 ///
 /// Implements the `Sink` trait to asynchronously write messages to the network connection.
@@ -46,15 +110,161 @@ impl std::fmt::Display for ConnectionWriteError {
 /// writer.send(msg).await?;
 /// ```
 ///
+/// # Flushing
+///
+/// Messages accepted by the writer are queued and only written to the network stream when the
+/// writer is flushed. Of the [`SinkExt`] combinators, `send`, `send_all`, `flush` and `close` flush
+/// the writer before completing, and `forward` flushes once the forwarded stream ends or has no
+/// message ready. `feed` and a bare `start_send` only queue the message, which lets many messages
+/// be batched into fewer writes. Enable [`set_auto_flush`](ConnectionWriter::set_auto_flush) to
+/// write every message as soon as it is accepted instead.
+///
+/// Always `flush().await` or `close().await` the writer before dropping it: dropping a writer only
+/// makes a best-effort attempt to write pending messages without waiting, and logs a warning for
+/// any that are lost.
+///
+/// # Cancellation safety
+///
+/// Dropping a `send`, `feed`, `flush` or `close` future, such as in a losing branch of
+/// `select!`, never loses or duplicates bytes on the network stream. A datagram that is dropped
+/// before the writer is ready to accept it is discarded without being queued or stamped with a
+/// sequence number. Once accepted, a datagram is queued as a whole and stays queued until it has
+/// been completely written by a later flush, continuing from where any partial write left off,
+/// and a datagram that was partially written is always finished before any other is started.
+/// The same holds for [`send_with_priority`](ConnectionWriter::send_with_priority),
+/// [`send_with_ack`](ConnectionWriter::send_with_ack) and
+/// [`send_segmented`](ConnectionWriter::send_segmented), whose datagram is still sent after the
+/// future is dropped but without waiting for it. The exception is
+/// [`send_stream`](ConnectionWriter::send_stream), see its documentation.
+///
+/// # Stream type
+///
+/// By default the writer writes to a [`BoxedWriteStream`], so that every [`ConnectionWriter`] has
+/// the same type regardless of the transport. A writer constructed with
+/// [`ConnectionWriter::from_stream`] writes to a concrete stream type `W` instead, which avoids
+/// the allocation and the dynamic dispatch of each write.
+///
 /// Please see the [tcp-client](https://github.com/sachanganesh/connect-rs/blob/main/examples/tcp-client/)
 /// example program or other client example programs for a more thorough showcase.
 ///
-pub struct ConnectionWriter {
+pub struct ConnectionWriter<W: AsyncWrite + Unpin = BoxedWriteStream> {
     local_addr: SocketAddr,
     peer_addr: SocketAddr,
-    write_stream: Pin<Box<dyn AsyncWrite + Send + Sync>>,
-    pending_writes: Vec<Vec<u8>>,
+    write_stream: W,
+    pending_writes: Vec<PendingWrite>,
+    pending_offset: usize,
+    next_write_id: u64,
+    pending_bytes: usize,
+    buffer_limit: usize,
+    stats: ConnectionStats,
     closed: bool,
+    slot: Option<Arc<ConnectionSlot>>,
+    heartbeat: Option<WriterHeartbeat>,
+    idle: Option<WriterIdle>,
+    rate_limiter: Option<TokenBucket>,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+    flush_delay: Option<Duration>,
+    flush_deadline: Option<(Instant, Timer)>,
+    auto_flush: bool,
+    next_sequence: Option<u64>,
+
+    /// The sequence number of the next pending datagram to be stamped, which trails
+    /// `next_sequence` by the number of queued datagrams that are not stamped yet.
+    next_stamp: u64,
+    byte_order: ByteOrder,
+    framing_byte_order: Option<SharedByteOrder>,
+    label: Option<String>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+/// A serialized datagram queued for sending, along with its priority and an identifier to track
+/// when it is written.
+struct PendingWrite {
+    id: u64,
+    priority: u8,
+    buffer: Bytes,
+
+    /// Further segments of the datagram that follow `buffer`, for datagrams sent without
+    /// concatenating their message body.
+    segments: Vec<Bytes>,
+
+    /// A sequenced datagram and the byte order to frame it in, which is only stamped with its
+    /// sequence number and serialized into `buffer` once it is about to be written, so that
+    /// sequence numbers follow the order in which datagrams are written rather than queued.
+    unstamped: Option<(ConnectDatagram, ByteOrder)>,
+
+    /// Whether the datagram was stamped with a sequence number, after which no other datagram may
+    /// overtake it.
+    stamped: bool,
+}
+
+impl PendingWrite {
+    fn len(&self) -> usize {
+        match self.unstamped.as_ref() {
+            Some((datagram, _)) => datagram.serialized_size(),
+            None => self.buffer.len() + self.segments.iter().map(Bytes::len).sum::<usize>(),
+        }
+    }
+
+    /// Stamps an unstamped datagram with its sequence number and serializes it.
+    fn stamp(&mut self, sequence: u64) -> Result<(), DatagramError> {
+        let (mut datagram, byte_order) = match self.unstamped.take() {
+            Some(unstamped) => unstamped,
+            None => return Ok(()),
+        };
+
+        // the datagram was stamped with a placeholder when queued, so restamping it cannot change
+        // its size
+        let res = sequence::stamp(&mut datagram, sequence);
+        self.buffer = convert_byte_order(
+            byte_order,
+            Bytes::from(datagram.into_bytes()),
+            &mut self.segments,
+        );
+        self.stamped = true;
+
+        res
+    }
+
+    /// Iterates over the serialized bytes of the datagram in order.
+    fn slices(&self) -> impl Iterator<Item = &[u8]> {
+        std::iter::once(&self.buffer[..]).chain(self.segments.iter().map(|segment| &segment[..]))
+    }
+
+    /// Concatenates the serialized bytes of the datagram into a single buffer.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn into_bytes(self) -> Bytes {
+        if self.segments.is_empty() {
+            return self.buffer;
+        }
+
+        let mut buffer = Vec::with_capacity(self.len());
+        for slice in self.slices() {
+            buffer.extend_from_slice(slice);
+        }
+
+        Bytes::from(buffer)
+    }
+}
+
+/// Converts the header of a serialized big-endian datagram to `byte_order`.
+///
+/// Only the header is copied, with the rest of the buffer moved to the front of `segments`, so
+/// that a large or shared buffer is not copied in full.
+fn convert_byte_order(byte_order: ByteOrder, buffer: Bytes, segments: &mut Vec<Bytes>) -> Bytes {
+    if byte_order == ByteOrder::BigEndian || buffer.len() < DATAGRAM_HEADER_BYTE_SIZE {
+        return buffer;
+    }
+
+    let mut header = buffer[..DATAGRAM_HEADER_BYTE_SIZE].to_vec();
+    byte_order.convert_header(&mut header);
+
+    if buffer.len() > DATAGRAM_HEADER_BYTE_SIZE {
+        segments.insert(0, buffer.slice(DATAGRAM_HEADER_BYTE_SIZE..));
+    }
+
+    Bytes::from(header)
 }
 
 impl ConnectionWriter {
@@ -63,128 +273,1887 @@ impl ConnectionWriter {
     pub fn new(
         local_addr: SocketAddr,
         peer_addr: SocketAddr,
-        write_stream: Pin<Box<dyn AsyncWrite + Send + Sync>>,
+        write_stream: BoxedWriteStream,
     ) -> Self {
+        Self::from_stream(local_addr, peer_addr, write_stream)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> ConnectionWriter<W> {
+    /// Creates a new [`ConnectionWriter`] that writes to a concrete stream type and the local and
+    /// peer socket metadata, without boxing the stream.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let writer = ConnectionWriter::from_stream(local_addr, peer_addr, stream);
+    /// ```
+    pub fn from_stream(local_addr: SocketAddr, peer_addr: SocketAddr, write_stream: W) -> Self {
         Self {
             local_addr,
             peer_addr,
             write_stream,
             pending_writes: Vec::new(),
+            pending_offset: 0,
+            next_write_id: 0,
+            pending_bytes: 0,
+            buffer_limit: DEFAULT_BUFFER_LIMIT,
+            stats: ConnectionStats::default(),
             closed: false,
+            slot: None,
+            heartbeat: None,
+            idle: None,
+            rate_limiter: None,
+            observer: None,
+            flush_delay: None,
+            flush_deadline: None,
+            auto_flush: false,
+            next_sequence: None,
+            next_stamp: 0,
+            byte_order: ByteOrder::default(),
+            framing_byte_order: None,
+            label: None,
+            #[cfg(feature = "tracing")]
+            span: crate::logging::connection_span(local_addr, peer_addr),
         }
     }
 
     /// Get the local IP address and port.
     pub fn local_addr(&self) -> SocketAddr {
-        self.local_addr.clone()
+        self.local_addr
     }
 
     /// Get the peer IP address and port.
     pub fn peer_addr(&self) -> SocketAddr {
-        self.peer_addr.clone()
+        self.peer_addr
+    }
+
+    /// Sets a label that identifies the connection in log lines alongside the peer address, such
+    /// as the authenticated user or a request ID, to tell apart connections that share a peer
+    /// address. See [`Connection::set_label`](`crate::Connection::set_label`).
+    pub fn set_label(&mut self, label: String) {
+        #[cfg(feature = "tracing")]
+        self.span.record("label", label.as_str());
+
+        self.label = Some(label);
+    }
+
+    /// Get the label that identifies the connection in log lines, if it has one.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Identifies the peer of the connection in log lines.
+    fn peer(&self) -> Peer<'_> {
+        Peer {
+            label: self.label.as_deref(),
+            addr: self.peer_addr,
+        }
     }
 
     /// Check if the `Sink` of messages to the network is closed.
+    ///
+    /// A connection with a heartbeat enabled is also closed once the peer stops responding, and a
+    /// connection with an idle timeout once it has been idle for too long.
     pub fn is_closed(&self) -> bool {
         self.closed
+            || self.slot.as_ref().is_some_and(|s| s.is_closed())
+            || self.heartbeat.as_ref().is_some_and(|h| h.is_dead())
+            || self.idle.as_ref().is_some_and(|i| i.is_expired())
     }
 
-    pub(crate) fn write_pending_bytes(
+    /// Get a [`Sink`] of raw bytes that wraps each item in a datagram with the provided tag before
+    /// sending it through this writer.
+    ///
+    /// This saves constructing the datagrams by hand when forwarding a stream of bytes, with any
+    /// [`DatagramError`], such as for an empty item, surfacing as an error of the sink. Closing
+    /// the sink, as `forward` does once its stream ends, also closes this writer.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// chunks.map(Ok).forward(writer.tagged_sink(7)).await?;
+    /// ```
+    pub fn tagged_sink(
         &mut self,
-        cx: &mut Context<'_>,
-    ) -> Poll<Result<(), ConnectionWriteError>> {
-        if self.pending_writes.len() > 0 {
-            let stream = self.write_stream.as_mut();
+        tag: u16,
+    ) -> impl Sink<Vec<u8>, Error = TaggedSinkError> + Unpin + '_ {
+        self.with(move |data: Vec<u8>| {
+            futures::future::ready(
+                ConnectDatagram::with_tag(tag, data).map_err(TaggedSinkError::Datagram),
+            )
+        })
+    }
 
-            match stream.poll_flush(cx) {
-                Poll::Pending => Poll::Pending,
+    /// Half-closes the connection by writing the pending datagrams and then closing only the
+    /// writing direction of the network stream, signalling to the peer that no more datagrams will
+    /// be sent.
+    ///
+    /// The paired [`ConnectionReader`](crate::ConnectionReader) is left untouched, so replies from
+    /// the peer can still be drained until it closes its own end, which the reader observes as the
+    /// end of its stream. For TCP this shuts down the write half of the socket, sending a `FIN`.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// writer.send(last_request).await?;
+    /// writer.shutdown().await?;
+    ///
+    /// while let Some(reply) = reader.next().await {
+    ///     // handle the remaining replies
+    /// }
+    /// ```
+    pub async fn shutdown(&mut self) -> Result<(), ConnectionWriteError> {
+        self.close().await
+    }
 
-                Poll::Ready(Ok(_)) => {
-                    let stream = self.write_stream.as_mut();
-
-                    let split = self.pending_writes.split_off(0);
-                    let pending: Vec<IoSlice> = split.iter().map(|p| IoSlice::new(p)).collect();
-
-                    trace!("sending pending bytes to network stream");
-                    match stream.poll_write_vectored(cx, pending.as_slice()) {
-                        Poll::Pending => {
-                            self.pending_writes = split;
-                            Poll::Pending
-                        }
-
-                        Poll::Ready(Ok(bytes_written)) => {
-                            trace!("wrote {} bytes to network stream", bytes_written);
-                            Poll::Ready(Ok(()))
-                        }
-
-                        Poll::Ready(Err(err)) => {
-                            error!("Encountered error when writing to network stream");
-                            self.pending_writes = split;
-                            Poll::Ready(Err(ConnectionWriteError::IoError(err)))
-                        }
-                    }
+    /// Waits until a heartbeat datagram is due, then writes it to the network stream along with any
+    /// other pending messages.
+    ///
+    /// Heartbeats are otherwise only sent while the writer is being used to send messages, so a
+    /// task that may not send anything for longer than the heartbeat interval should drive this
+    /// alongside its own messages. Never completes if no heartbeat is enabled.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// loop {
+    ///     futures::select! {
+    ///         msg = outgoing.next() => writer.send(msg.unwrap()).await?,
+    ///         res = writer.heartbeat().fuse() => res?,
+    ///     }
+    /// }
+    /// ```
+    pub async fn heartbeat(&mut self) -> Result<(), ConnectionWriteError> {
+        poll_fn(|cx| {
+            if self.is_closed() {
+                return Poll::Ready(Err(ConnectionWriteError::ConnectionClosed));
+            }
+
+            if !self.queue_heartbeats(cx) {
+                return Poll::Pending;
+            }
+
+            self.write_pending_bytes(cx)
+        })
+        .await
+    }
+
+    /// Sends everything read from `reader` as a stream of chunk datagrams of up to `chunk_size`
+    /// bytes with the provided tag, followed by an empty chunk that marks the end of the stream.
+    /// Returns the number of bytes sent once they have all been written to the network stream.
+    ///
+    /// This is not cancellation safe: if the future is dropped before completing, the chunks
+    /// already queued are still sent but the end of the stream never is, so the peer's
+    /// [`ChunkReader`](`crate::ChunkReader`) would take the chunks of the next stream with the same
+    /// tag as a continuation. Close the connection instead of sending anything else after
+    /// abandoning a stream.
+    ///
+    /// Only one chunk is held in memory at a time on top of the buffered writes, so streams may be
+    /// far larger than the 100MB limit of a single datagram. The peer reads the stream back with
+    /// [`ConnectionReader::recv_stream`](`crate::ConnectionReader::recv_stream`).
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let file = async_std::fs::File::open(path).await?;
+    /// writer.send_stream(FILE_TAG, file, 64 * 1024).await?;
+    /// ```
+    pub async fn send_stream<R: AsyncRead + Unpin>(
+        &mut self,
+        tag: u16,
+        mut reader: R,
+        chunk_size: usize,
+    ) -> Result<u64, ConnectionWriteError> {
+        let chunk_size = chunk_size.clamp(1, MAX_DATA_BYTE_SIZE);
+        let mut bytes_sent = 0;
+
+        loop {
+            let mut buffer = vec![0; DATAGRAM_HEADER_BYTE_SIZE + chunk_size];
+            let mut filled = DATAGRAM_HEADER_BYTE_SIZE;
+
+            // fill the chunk as far as possible so that short reads do not produce tiny chunks
+            while filled < buffer.len() {
+                match reader.read(&mut buffer[filled..]).await {
+                    Ok(0) => break,
+                    Ok(bytes_read) => filled += bytes_read,
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(ConnectionWriteError::IoError(err)),
+                }
+            }
+            buffer.truncate(filled);
+
+            let chunk_len = filled - DATAGRAM_HEADER_BYTE_SIZE;
+            let chunk =
+                ConnectDatagram::stream_chunk(tag, buffer).expect("chunk is never too large");
+            self.feed(chunk).await?;
+
+            if chunk_len == 0 {
+                break;
+            }
+
+            bytes_sent += chunk_len as u64;
+        }
+
+        trace!("queued stream of {} bytes for sending", bytes_sent);
+        self.flush().await?;
+
+        Ok(bytes_sent)
+    }
+
+    /// Sends a datagram ahead of any pending datagrams with a lower priority, and waits until it
+    /// has been written to the network stream.
+    ///
+    /// Datagrams sent through the `Sink` trait have the lowest priority of `0`. Datagrams with the
+    /// same priority are always written in the order they were sent, but a datagram may overtake
+    /// lower-priority datagrams that were sent before it and are still pending, so delivery is
+    /// only ordered within each priority. A datagram that has been partially written is always
+    /// finished first.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// writer.send_with_priority(ConnectDatagram::control(CANCEL_TAG), 255).await?;
+    /// ```
+    pub async fn send_with_priority(
+        &mut self,
+        datagram: ConnectDatagram,
+        priority: u8,
+    ) -> Result<(), ConnectionWriteError> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_ready(cx)).await?;
+        self.queue_datagram(datagram, priority);
+
+        poll_fn(|cx| Pin::new(&mut *self).poll_flush(cx)).await
+    }
+
+    /// Sends a datagram and waits until its bytes have been written to the network stream and the
+    /// stream has been flushed, acknowledging that this particular datagram was transmitted.
+    ///
+    /// Unlike `send().await`, which completes once every pending datagram has been flushed, this
+    /// completes as soon as the datagram itself is written, even if datagrams queued after it are
+    /// still pending. It also bypasses the delay set with
+    /// [`set_flush_delay`](ConnectionWriter::set_flush_delay). The datagram is sent with the
+    /// default priority of `0`, so the datagrams queued before it are written first.
+    ///
+    /// An acknowledgement only means that the bytes were handed to the network stream, such as the
+    /// kernel send buffer of a TCP socket, and not that the peer has received them.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// writer.send_with_ack(entry.to_datagram()?).await?;
+    /// log.checkpoint(entry.offset).await?;
+    /// ```
+    pub async fn send_with_ack(
+        &mut self,
+        datagram: ConnectDatagram,
+    ) -> Result<(), ConnectionWriteError> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_ready(cx)).await?;
+        let id = self.queue_datagram(datagram, DEFAULT_PRIORITY);
+
+        poll_fn(|cx| {
+            self.queue_heartbeats(cx);
+
+            if self.is_write_pending(id) {
+                match self.write_pending_bytes(cx) {
+                    Poll::Pending if self.is_write_pending(id) => return Poll::Pending,
+                    Poll::Pending => {}
+                    res => return res,
                 }
+            }
 
+            // the datagram is written while later datagrams are still pending, so only the
+            // network stream is left to flush
+            match Pin::new(&mut self.write_stream).poll_flush(cx) {
+                Poll::Pending => Poll::Pending,
+                Poll::Ready(Ok(())) => Poll::Ready(Ok(())),
                 Poll::Ready(Err(err)) => {
-                    error!("Encountered error when flushing network stream");
-                    Poll::Ready(Err(ConnectionWriteError::IoError(err)))
+                    Poll::Ready(Err(self.write_error(ConnectionWriteError::IoError(err))))
                 }
             }
-        } else {
-            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+
+    /// Sends a [`SegmentedDatagram`], writing the segments of its message body to the network
+    /// stream as separate [`IoSlice`]s so that they are never concatenated.
+    ///
+    /// Like `send().await`, this waits until the writer has room for the datagram and then flushes
+    /// the writer. The datagram is sent with the default priority of `0`, and is not stamped with a
+    /// sequence number even if [`set_next_sequence`](ConnectionWriter::set_next_sequence) enabled
+    /// sequencing.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let msg = SegmentedDatagram::with_tag(tag, vec![header, body, trailer])?;
+    /// writer.send_segmented(msg).await?;
+    /// ```
+    pub async fn send_segmented(
+        &mut self,
+        datagram: SegmentedDatagram,
+    ) -> Result<(), ConnectionWriteError> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_ready(cx)).await?;
+
+        let (header, segments) = datagram.into_parts();
+        self.queue_segments(header, segments, DEFAULT_PRIORITY);
+
+        poll_fn(|cx| Pin::new(&mut *self).poll_flush(cx)).await
+    }
+
+    pub(crate) fn set_heartbeat(&mut self, heartbeat: WriterHeartbeat) {
+        self.heartbeat = Some(heartbeat);
+    }
+
+    pub(crate) fn set_idle(&mut self, idle: WriterIdle) {
+        self.idle = Some(idle);
+    }
+
+    pub(crate) fn set_observer(&mut self, observer: Arc<dyn ConnectionObserver>) {
+        self.observer = Some(observer);
+    }
+
+    /// Queues any heartbeat datagrams that are due, returning whether any were queued.
+    fn queue_heartbeats(&mut self, cx: &mut Context<'_>) -> bool {
+        let due = match self.heartbeat.as_mut() {
+            Some(heartbeat) => heartbeat.poll_due(cx),
+            None => return false,
+        };
+
+        let queued = !due.is_empty();
+        for datagram in due {
+            trace!("queueing heartbeat for {}", self.peer());
+            self.queue_write(Bytes::from(datagram.into_bytes()), DEFAULT_PRIORITY);
         }
+
+        queued
     }
-}
 
-impl Sink<ConnectDatagram> for ConnectionWriter {
-    type Error = ConnectionWriteError;
+    /// Sets the number of buffered bytes at which the writer stops accepting new messages.
+    ///
+    /// Once the bytes queued for sending reach this limit, `poll_ready` returns `Poll::Pending`
+    /// while it tries to write the backlog to the network stream, so `send().await` blocks the
+    /// producer until the peer catches up. A single message larger than the limit is still accepted
+    /// when the writer has drained below the limit.
+    ///
+    /// Defaults to 4MB.
+    pub fn set_buffer_limit(&mut self, bytes: usize) {
+        self.buffer_limit = bytes;
+    }
 
-    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        if self.is_closed() {
-            trace!("connection is closed - cannot send message");
-            Poll::Ready(Err(ConnectionWriteError::ConnectionClosed))
-        } else {
-            trace!("connection ready to send message");
-            Poll::Ready(Ok(()))
+    /// Limits the bytes written to the network stream to `bytes_per_sec`, including datagram
+    /// headers.
+    ///
+    /// Once the limit is reached, flushing returns `Poll::Pending` until more bytes may be written,
+    /// so `send().await` is delayed rather than the application having to pace its sends. The limit
+    /// is enforced with a token bucket, so a burst of up to `bytes_per_sec` bytes can still be
+    /// written at once after a quiet period.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// // cap the connection at 1MB/s
+    /// writer.set_rate_limit(1_000_000);
+    /// ```
+    pub fn set_rate_limit(&mut self, bytes_per_sec: u64) {
+        self.rate_limiter = Some(TokenBucket::new(bytes_per_sec));
+    }
+
+    /// Delays flushing by up to `delay`, so that datagrams queued within that window are coalesced
+    /// into fewer writes to the network stream.
+    ///
+    /// When a flush is requested, including the one at the end of `send().await`, the pending
+    /// datagrams are only written once `delay` has passed since the first flush that found them
+    /// pending, or as soon as 64KB are pending. This trades up to `delay` of added latency per
+    /// datagram for fewer, larger writes, which pays off when many small datagrams are sent in
+    /// quick succession, e.g. by concurrent producers through
+    /// [`into_channel`](ConnectionWriter::into_channel). A single producer awaiting each `send`
+    /// in turn should instead `feed` its datagrams and flush once, since each `send` waits out the
+    /// delay.
+    ///
+    /// Since the [`Sink`] implementation cannot tell an explicit `flush` apart from the one in
+    /// `send`, use [`flush_now`](ConnectionWriter::flush_now) to write the pending datagrams
+    /// without waiting for the delay. A zero `delay`, the default, disables coalescing.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// writer.set_flush_delay(Duration::from_millis(2));
+    /// ```
+    pub fn set_flush_delay(&mut self, delay: Duration) {
+        self.flush_delay = Some(delay).filter(|delay| !delay.is_zero());
+        self.flush_deadline = None;
+    }
+
+    /// Writes all pending datagrams to the network stream and flushes it, bypassing the delay set
+    /// with [`set_flush_delay`](ConnectionWriter::set_flush_delay).
+    pub async fn flush_now(&mut self) -> Result<(), ConnectionWriteError> {
+        poll_fn(|cx| {
+            self.queue_heartbeats(cx);
+            self.poll_flush_now(cx)
+        })
+        .await
+    }
+
+    /// Writes every datagram to the network stream as soon as it is accepted, rather than waiting
+    /// for the writer to be flushed.
+    ///
+    /// With auto-flush enabled, `start_send` and `feed` immediately write as much of the datagram
+    /// as the network stream accepts, and the writer only accepts the next datagram once the
+    /// previous one is fully written and flushed. This makes a custom [`Sink`] pipeline behave like
+    /// a "write now" API, at the cost of the batching that deferred flushes allow. Auto-flush also
+    /// bypasses the delay set with [`set_flush_delay`](ConnectionWriter::set_flush_delay). It is
+    /// disabled by default.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// writer.set_auto_flush(true);
+    ///
+    /// // written to the network stream without an explicit flush
+    /// writer.feed(msg).await?;
+    /// ```
+    pub fn set_auto_flush(&mut self, auto_flush: bool) {
+        self.auto_flush = auto_flush;
+    }
+
+    /// Stamps every datagram sent from now on with a sequence number, starting at `sequence` and
+    /// increasing by one with each datagram, so that the peer's reader can detect datagrams that
+    /// were lost or repeated, such as across a reconnect.
+    ///
+    /// Sequence numbers are carried in the extension field with the reserved
+    /// [`SEQUENCE_EXTENSION_KEY`](`crate::SEQUENCE_EXTENSION_KEY`), replacing any sequence number
+    /// the datagram already had. Heartbeats, [`SegmentedDatagram`]s and datagrams sent with
+    /// [`broadcast`] are not stamped. A datagram whose extension fields leave no room for the
+    /// sequence number is sent without one, and does not use up a sequence number.
+    ///
+    /// Calling this again resets the sequence, such as to resume from a checkpoint after
+    /// reconnecting. See [`ConnectionReader::set_expected_sequence`](`crate::ConnectionReader::set_expected_sequence`)
+    /// for validating the sequence numbers on the receiving end.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut conn = Connection::tcp_client(ip_address).await?;
+    /// conn.writer().set_next_sequence(checkpoint.next_sequence);
+    /// ```
+    pub fn set_next_sequence(&mut self, sequence: u64) {
+        // datagrams that were already queued keep the sequence numbers they were sent with
+        self.stamp_pending_writes(usize::MAX, usize::MAX);
+        self.next_sequence = Some(sequence);
+        self.next_stamp = sequence;
+    }
+
+    /// Get the sequence number that the next datagram will be stamped with, or `None` if
+    /// sequencing is disabled.
+    pub fn next_sequence(&self) -> Option<u64> {
+        self.next_sequence
+    }
+
+    /// Stops stamping datagrams with the sequence numbers enabled by
+    /// [`set_next_sequence`](ConnectionWriter::set_next_sequence).
+    pub fn remove_sequencing(&mut self) {
+        self.stamp_pending_writes(usize::MAX, usize::MAX);
+        self.next_sequence = None;
+    }
+
+    /// Sets the byte order in which the header fields of sent datagrams are framed, which must
+    /// match the [`ByteOrder`] the peer reads in.
+    ///
+    /// Only datagrams queued after this call are affected, including those sent with
+    /// [`broadcast`]. Big-endian by default.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// writer.set_byte_order(ByteOrder::LittleEndian);
+    /// ```
+    pub fn set_byte_order(&mut self, byte_order: ByteOrder) {
+        self.byte_order = byte_order;
+
+        if let Some(framing) = self.framing_byte_order.as_ref() {
+            framing.set(byte_order);
         }
     }
 
-    fn start_send(mut self: Pin<&mut Self>, item: ConnectDatagram) -> Result<(), Self::Error> {
-        trace!("preparing datagram to be queued for sending");
+    /// Shares the byte order with the transport that frames the datagrams written to the network
+    /// stream.
+    pub(crate) fn share_byte_order(&mut self, framing: SharedByteOrder) {
+        framing.set(self.byte_order);
+        self.framing_byte_order = Some(framing);
+    }
 
-        let buffer = item.into_bytes();
-        let msg_size = buffer.len();
-        trace!("serialized pending message into {} bytes", msg_size);
+    /// Get the byte order in which the header fields of sent datagrams are framed.
+    pub fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
 
-        self.pending_writes.push(buffer);
+    /// Queues a datagram, reserving the next sequence number for it if sequencing is enabled.
+    ///
+    /// A sequenced datagram is only stamped once it is about to be written, see
+    /// [`stamp_pending_writes`](ConnectionWriter::stamp_pending_writes), since datagrams sent with
+    /// a higher priority may still overtake it until then.
+    fn queue_datagram(&mut self, mut datagram: ConnectDatagram, priority: u8) -> u64 {
+        if let Some(next) = self.next_sequence {
+            // stamp a placeholder so that the datagram already has its final size
+            match sequence::stamp(&mut datagram, 0) {
+                Ok(()) => {
+                    self.next_sequence = Some(next.wrapping_add(1));
 
-        Ok(())
+                    let pending = PendingWrite {
+                        id: self.next_write_id(),
+                        priority,
+                        buffer: Bytes::new(),
+                        segments: Vec::new(),
+                        unstamped: Some((datagram, self.byte_order)),
+                        stamped: false,
+                    };
+                    return self.queue_pending_write(pending);
+                }
+
+                Err(err) => warn!(
+                    "Sending datagram to {} without a sequence number: {}",
+                    self.peer(),
+                    err
+                ),
+            }
+        }
+
+        self.queue_write(Bytes::from(datagram.into_bytes()), priority)
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.write_pending_bytes(cx)
+    /// Stamps the unstamped datagrams at the front of the pending writes with their sequence
+    /// numbers, until the stamped datagrams fill `max_slices` slices or `max_bytes` bytes.
+    fn stamp_pending_writes(&mut self, max_slices: usize, max_bytes: usize) {
+        let mut slices = 0;
+        let mut bytes = 0;
+        let mut offset = self.pending_offset;
+
+        for index in 0..self.pending_writes.len() {
+            if slices >= max_slices || bytes >= max_bytes {
+                break;
+            }
+
+            if self.pending_writes[index].unstamped.is_some() {
+                let sequence = self.next_stamp;
+                self.next_stamp = sequence.wrapping_add(1);
+
+                if let Err(err) = self.pending_writes[index].stamp(sequence) {
+                    warn!(
+                        "Sending datagram to {} without a sequence number: {}",
+                        self.peer(),
+                        err
+                    );
+                }
+            }
+
+            let pending = &self.pending_writes[index];
+            slices += 1 + pending.segments.len();
+            bytes += pending.len() - offset;
+            offset = 0;
+        }
     }
 
-    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.closed = true;
-        debug!("Closing the sink for connection with {}", self.peer_addr);
+    /// Removes the limit set by [`set_rate_limit`](ConnectionWriter::set_rate_limit), so that
+    /// bytes are written as fast as the network stream accepts them.
+    pub fn remove_rate_limit(&mut self) {
+        self.rate_limiter = None;
+    }
 
-        match self.write_pending_bytes(cx) {
-            Poll::Pending => Poll::Pending,
+    /// Get the total number of bytes written to the network stream, including datagram headers.
+    pub fn bytes_written(&self) -> u64 {
+        self.stats.bytes_written
+    }
 
-            Poll::Ready(Ok(_)) => {
-                let stream = self.write_stream.as_mut();
+    /// Get a snapshot of the messages and bytes written so far.
+    ///
+    /// The read counters are always zero; see [`Connection::stats`](`crate::Connection::stats`)
+    /// for the statistics of both halves.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats
+    }
 
-                match stream.poll_close(cx) {
-                    Poll::Pending => Poll::Pending,
+    /// Get the number of bytes queued for sending that have not yet been written to the network
+    /// stream.
+    pub fn pending_bytes(&self) -> usize {
+        self.pending_bytes
+    }
 
-                    Poll::Ready(Ok(_)) => Poll::Ready(Ok(())),
+    /// Consumes the [`ConnectionWriter`] to spawn a task that sends the datagrams queued in a
+    /// bounded channel of `capacity` datagrams, decoupling producing messages from writing them.
+    ///
+    /// The channel is a runtime-agnostic [`futures::channel::mpsc`] channel, while the task is
+    /// spawned on the `async-std` runtime. While the network stream is not accepting more bytes the
+    /// channel fills up, so sending into a full channel waits and applies backpressure to the
+    /// producers. Once every [`mpsc::Sender`] is dropped, the task writes the remaining datagrams
+    /// and closes the writer. If writing fails, the channel is closed and further sends fail.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut outbox = writer.into_channel(64);
+    ///
+    /// outbox.send(msg).await?;
+    /// ```
+    pub fn into_channel(mut self, capacity: usize) -> mpsc::Sender<ConnectDatagram>
+    where
+        W: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(capacity);
+
+        rt::spawn(async move {
+            if let Err(err) = self.send_all(&mut receiver.map(Ok)).await {
+                error!(
+                    "Stopped sending datagrams from the channel to {}: {}",
+                    self.peer(),
+                    err
+                );
+                return;
+            }
+
+            if let Err(err) = self.close().await {
+                warn!("Could not close writer for {}: {}", self.peer(), err);
+            }
+        });
+
+        sender
+    }
+
+    pub(crate) fn set_slot(&mut self, slot: Arc<ConnectionSlot>) {
+        self.slot = Some(slot);
+    }
+
+    /// Queues a serialized datagram behind the pending datagrams of the same or a higher priority,
+    /// returning the identifier of the pending write.
+    fn queue_write(&mut self, buffer: Bytes, priority: u8) -> u64 {
+        self.queue_segments(buffer, Vec::new(), priority)
+    }
+
+    /// Queues a serialized datagram made up of `buffer` followed by `segments`, see
+    /// [`queue_write`](ConnectionWriter::queue_write).
+    fn queue_segments(&mut self, buffer: Bytes, mut segments: Vec<Bytes>, priority: u8) -> u64 {
+        let buffer = convert_byte_order(self.byte_order, buffer, &mut segments);
+
+        let pending = PendingWrite {
+            id: self.next_write_id(),
+            priority,
+            buffer,
+            segments,
+            unstamped: None,
+            stamped: false,
+        };
+        self.queue_pending_write(pending)
+    }
+
+    /// Queues a pending write by its priority, see [`queue_write`](ConnectionWriter::queue_write).
+    fn queue_pending_write(&mut self, pending: PendingWrite) -> u64 {
+        self.pending_bytes += pending.len();
+        let id = pending.id;
+        let priority = pending.priority;
+
+        // pending writes are ordered by descending priority, except for a partially written
+        // datagram at the front which must be finished before anything else is written, and for
+        // datagrams already stamped with a sequence number which must be written in that order
+        let partial = if self.pending_offset > 0 { 1 } else { 0 };
+        let stamped = self
+            .pending_writes
+            .iter()
+            .rposition(|pending| pending.stamped)
+            .map_or(0, |index| index + 1);
+        let first = partial.max(stamped);
+        let index = first
+            + self.pending_writes[first..].partition_point(|pending| pending.priority >= priority);
+
+        self.pending_writes.insert(index, pending);
+
+        id
+    }
 
-                    Poll::Ready(Err(err)) => Poll::Ready(Err(ConnectionWriteError::IoError(err))),
+    fn next_write_id(&mut self) -> u64 {
+        let id = self.next_write_id;
+        self.next_write_id += 1;
+        id
+    }
+
+    /// Checks whether the datagram queued with the identifier has not been completely written to
+    /// the network stream.
+    fn is_write_pending(&self, id: u64) -> bool {
+        self.pending_writes.iter().any(|pending| pending.id == id)
+    }
+
+    /// Removes the serialized datagrams that have not been completely written to the network
+    /// stream, including any partially written datagram in full.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn take_pending_writes(&mut self) -> Vec<Bytes> {
+        self.stamp_pending_writes(usize::MAX, usize::MAX);
+        self.pending_offset = 0;
+        self.pending_bytes = 0;
+        std::mem::take(&mut self.pending_writes)
+            .into_iter()
+            .map(PendingWrite::into_bytes)
+            .collect()
+    }
+
+    /// Queues previously taken serialized datagrams ahead of any datagrams already pending,
+    /// regardless of their priority.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn restore_pending_writes(&mut self, buffers: Vec<Bytes>) {
+        if buffers.is_empty() {
+            return;
+        }
+
+        self.pending_bytes += buffers.iter().map(|b| b.len()).sum::<usize>();
+        let mut buffers: Vec<PendingWrite> = buffers
+            .into_iter()
+            .map(|buffer| PendingWrite {
+                id: self.next_write_id(),
+                priority: u8::MAX,
+                buffer,
+                segments: Vec::new(),
+                unstamped: None,
+                stamped: false,
+            })
+            .collect();
+
+        if self.pending_offset == 0 {
+            buffers.append(&mut self.pending_writes);
+            self.pending_writes = buffers;
+        } else {
+            // keep the partially written datagram at the front so its remaining bytes are written
+            // before anything else
+            let tail = self.pending_writes.split_off(1);
+            self.pending_writes.extend(buffers);
+            self.pending_writes.extend(tail);
+        }
+    }
+
+    /// Discards the first `bytes_written` bytes of the pending writes, keeping any unwritten tail
+    /// of a partially written buffer queued for the next write attempt.
+    fn advance_pending_writes(&mut self, mut bytes_written: usize) {
+        let mut written_buffers = 0;
+        self.pending_bytes -= bytes_written;
+
+        for pending in self.pending_writes.iter() {
+            let remaining = pending.len() - self.pending_offset;
+
+            if bytes_written >= remaining {
+                bytes_written -= remaining;
+                self.pending_offset = 0;
+                written_buffers += 1;
+
+                if let Some(observer) = self.observer.as_ref() {
+                    let tag = self
+                        .byte_order
+                        .convert_field(serialized_tag(&pending.buffer));
+                    observer.on_message_written(tag, pending.len());
                 }
+            } else {
+                self.pending_offset += bytes_written;
+                break;
             }
+        }
 
-            err => err,
+        self.pending_writes.drain(..written_buffers);
+        self.stats.messages_written += written_buffers as u64;
+
+        if written_buffers > 0 {
+            if let Some(idle) = self.idle.as_ref() {
+                idle.touch();
+            }
+        }
+    }
+
+    /// Checks whether a flush must wait for the flush delay to pass before writing, registering to
+    /// be woken once it does.
+    fn poll_flush_delay(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let delay = match self.flush_delay {
+            Some(delay) => delay,
+            None => return Poll::Ready(()),
+        };
+
+        if self.pending_writes.is_empty() || self.pending_bytes >= FLUSH_DELAY_THRESHOLD {
+            return Poll::Ready(());
+        }
+
+        let (deadline, timer) = self.flush_deadline.get_or_insert_with(|| {
+            let deadline = Instant::now() + delay;
+            (deadline, Timer::at(deadline))
+        });
+
+        if Instant::now() >= *deadline {
+            return Poll::Ready(());
+        }
+
+        trace!("delaying flush to coalesce pending datagrams");
+        match Pin::new(timer).poll(cx) {
+            Poll::Ready(_) => Poll::Ready(()),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Writes all pending datagrams and flushes the network stream, ending any flush delay once
+    /// everything is written.
+    fn poll_flush_now(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), ConnectionWriteError>> {
+        let res = self.write_pending_bytes(cx);
+
+        if res.is_ready() {
+            self.flush_deadline = None;
+        }
+
+        res
+    }
+
+    pub(crate) fn write_pending_bytes(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), ConnectionWriteError>> {
+        #[cfg(feature = "tracing")]
+        let _span = self.span.clone().entered();
+
+        while !self.pending_writes.is_empty() {
+            let mut allowance = match self.rate_limiter.as_mut() {
+                Some(limiter) => {
+                    if limiter.poll_ready(cx, 1).is_pending() {
+                        trace!("rate limit reached, waiting to write pending bytes");
+                        return Poll::Pending;
+                    }
+
+                    limiter.available() as usize
+                }
+
+                None => usize::MAX,
+            };
+
+            self.stamp_pending_writes(MAX_IO_SLICES, allowance);
+
+            let mut offset = self.pending_offset;
+            let pending: Vec<IoSlice> = self
+                .pending_writes
+                .iter()
+                .take_while(|pending| pending.unstamped.is_none())
+                .flat_map(PendingWrite::slices)
+                .filter_map(|buf| {
+                    // skip the bytes of a partially written datagram that were already written
+                    if offset >= buf.len() {
+                        offset -= buf.len();
+                        None
+                    } else {
+                        let buf = &buf[offset..];
+                        offset = 0;
+                        Some(buf)
+                    }
+                })
+                .take(MAX_IO_SLICES)
+                .map_while(|buf| {
+                    if allowance == 0 {
+                        return None;
+                    }
+
+                    let len = buf.len().min(allowance);
+                    allowance -= len;
+                    Some(IoSlice::new(&buf[..len]))
+                })
+                .collect();
+
+            trace!("sending pending bytes to network stream");
+            match Pin::new(&mut self.write_stream).poll_write_vectored(cx, pending.as_slice()) {
+                Poll::Pending => return Poll::Pending,
+
+                Poll::Ready(Ok(0)) => {
+                    error!("Network stream accepted no bytes when writing pending bytes");
+                    return Poll::Ready(Err(self.write_error(ConnectionWriteError::IoError(
+                        std::io::ErrorKind::WriteZero.into(),
+                    ))));
+                }
+
+                Poll::Ready(Ok(bytes_written)) => {
+                    trace!("wrote {} bytes to network stream", bytes_written);
+                    self.stats.bytes_written += bytes_written as u64;
+                    self.advance_pending_writes(bytes_written);
+
+                    if let Some(limiter) = self.rate_limiter.as_mut() {
+                        limiter.take(bytes_written as u64);
+                    }
+                }
+
+                Poll::Ready(Err(err)) => {
+                    error!("Encountered error when writing to network stream");
+                    return Poll::Ready(Err(self.write_error(ConnectionWriteError::IoError(err))));
+                }
+            }
         }
+
+        match Pin::new(&mut self.write_stream).poll_flush(cx) {
+            Poll::Pending => Poll::Pending,
+
+            Poll::Ready(Ok(_)) => Poll::Ready(Ok(())),
+
+            Poll::Ready(Err(err)) => {
+                error!("Encountered error when flushing network stream");
+                Poll::Ready(Err(self.write_error(ConnectionWriteError::IoError(err))))
+            }
+        }
+    }
+
+    /// Reports an error writing to the network stream to the observer, if any, before it is
+    /// returned.
+    fn write_error(&self, err: ConnectionWriteError) -> ConnectionWriteError {
+        if let Some(observer) = self.observer.as_ref() {
+            observer.on_write_error(&err);
+        }
+
+        err
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Drop for ConnectionWriter<W> {
+    fn drop(&mut self) {
+        if self.pending_writes.is_empty() {
+            return;
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = self.span.clone().entered();
+
+        // write whatever the network stream accepts right away, since dropping cannot wait
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let _ = self.write_pending_bytes(&mut cx);
+
+        if !self.pending_writes.is_empty() {
+            warn!(
+                "Dropped writer for connection with {} while {} messages ({} bytes) were not written, flush the writer before dropping it",
+                self.peer(),
+                self.pending_writes.len(),
+                self.pending_bytes
+            );
+        }
+    }
+}
+
+/// Sends one datagram to many connections, serializing it only once and sharing the serialized
+/// bytes between all of the `writers`.
+///
+/// Writers are flushed concurrently, so a slow peer does not hold up the others. A writer that
+/// fails, such as because its connection is closed, is skipped without affecting the rest of the
+/// broadcast. Returns the index in `writers` and the error of each writer that failed.
+///
+/// Since the bytes are shared, the datagram is not stamped with a sequence number by writers that
+/// have sequencing enabled.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// for (index, err) in broadcast(&msg, &mut subscribers).await {
+///     warn!("Could not send to subscriber {}: {}", index, err);
+/// }
+/// ```
+pub async fn broadcast<W: AsyncWrite + Unpin>(
+    datagram: &ConnectDatagram,
+    writers: &mut [ConnectionWriter<W>],
+) -> Vec<(usize, ConnectionWriteError)> {
+    let buffer = Bytes::copy_from_slice(datagram.as_bytes());
+
+    let results = join_all(writers.iter_mut().map(|writer| {
+        let buffer = buffer.clone();
+
+        async move {
+            poll_fn(|cx| Pin::new(&mut *writer).poll_ready(cx)).await?;
+            writer.queue_write(buffer, DEFAULT_PRIORITY);
+
+            poll_fn(|cx| Pin::new(&mut *writer).poll_flush(cx)).await
+        }
+    }))
+    .await;
+
+    results
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, res)| res.err().map(|err| (index, err)))
+        .collect()
+}
+
+impl<W: AsyncWrite + Unpin> Sink<ConnectDatagram> for ConnectionWriter<W> {
+    type Error = ConnectionWriteError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        #[cfg(feature = "tracing")]
+        let _span = self.span.clone().entered();
+
+        if self.is_closed() {
+            trace!("connection is closed - cannot send message");
+            return Poll::Ready(Err(ConnectionWriteError::ConnectionClosed));
+        }
+
+        self.queue_heartbeats(cx);
+
+        if self.pending_bytes >= self.buffer_limit {
+            trace!(
+                "{} pending bytes exceeds buffer limit, writing to network stream before accepting more",
+                self.pending_bytes
+            );
+
+            if let Poll::Ready(Err(err)) = self.write_pending_bytes(cx) {
+                return Poll::Ready(Err(err));
+            }
+
+            if self.pending_bytes >= self.buffer_limit {
+                return Poll::Pending;
+            }
+        }
+
+        if self.auto_flush && !self.pending_writes.is_empty() {
+            trace!("auto-flushing previous datagram before accepting more");
+
+            match self.poll_flush_now(cx) {
+                Poll::Ready(Ok(())) => {}
+                poll => return poll,
+            }
+        }
+
+        trace!("connection ready to send message");
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: ConnectDatagram) -> Result<(), Self::Error> {
+        #[cfg(feature = "tracing")]
+        let _span = self.span.clone().entered();
+
+        trace!("preparing datagram to be queued for sending");
+
+        trace!(
+            "queueing pending message of {} bytes",
+            item.serialized_size()
+        );
+
+        self.queue_datagram(item, DEFAULT_PRIORITY);
+
+        if self.auto_flush {
+            // write whatever the network stream accepts right away, poll_ready finishes the rest
+            let waker = noop_waker();
+            if let Poll::Ready(Err(err)) = self.poll_flush_now(&mut Context::from_waker(&waker)) {
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.slot.as_ref().is_some_and(|s| s.poll_writer_closed(cx)) {
+            trace!("connection was closed by its listener - cannot flush messages");
+            return Poll::Ready(Err(ConnectionWriteError::ConnectionClosed));
+        }
+
+        self.queue_heartbeats(cx);
+
+        if !self.auto_flush && self.poll_flush_delay(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        self.poll_flush_now(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        #[cfg(feature = "tracing")]
+        let _span = self.span.clone().entered();
+
+        self.closed = true;
+        debug!("Closing the sink for connection with {}", self.peer());
+
+        match self.write_pending_bytes(cx) {
+            Poll::Pending => Poll::Pending,
+
+            Poll::Ready(Ok(_)) => {
+                let stream = Pin::new(&mut self.write_stream);
+
+                match stream.poll_close(cx) {
+                    Poll::Pending => Poll::Pending,
+
+                    Poll::Ready(Ok(_)) => Poll::Ready(Ok(())),
+
+                    Poll::Ready(Err(err)) => {
+                        Poll::Ready(Err(self.write_error(ConnectionWriteError::IoError(err))))
+                    }
+                }
+            }
+
+            err => err,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_IO_SLICES;
+    use crate::{
+        broadcast, ByteOrder, Bytes, ConnectDatagram, ConnectionReader, ConnectionWriteError,
+        ConnectionWriter, DatagramError, SegmentedDatagram, TaggedSinkError,
+    };
+    use async_std::net::SocketAddr;
+    use async_std::pin::Pin;
+    use futures::io::{Cursor, IoSlice};
+    use futures::task::{Context, Poll, Waker};
+    use futures::{AsyncRead, AsyncWrite, FutureExt, SinkExt, StreamExt};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    /// An [`AsyncWrite`] that only accepts up to `chunk_size` bytes per write call.
+    struct ShortWriter {
+        written: Arc<Mutex<Vec<u8>>>,
+        chunk_size: usize,
+    }
+
+    impl AsyncWrite for ShortWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let len = buf.len().min(self.chunk_size);
+            self.written.lock().unwrap().extend_from_slice(&buf[..len]);
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_write_vectored(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            bufs: &[IoSlice<'_>],
+        ) -> Poll<std::io::Result<usize>> {
+            assert!(bufs.len() <= MAX_IO_SLICES);
+
+            let mut written = self.written.lock().unwrap();
+            let mut remaining = self.chunk_size;
+
+            for buf in bufs {
+                let len = buf.len().min(remaining);
+                written.extend_from_slice(&buf[..len]);
+                remaining -= len;
+
+                if remaining == 0 {
+                    break;
+                }
+            }
+
+            Poll::Ready(Ok(self.chunk_size - remaining))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// An [`AsyncWrite`] that never accepts any bytes.
+    struct StalledWriter;
+
+    impl AsyncWrite for StalledWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Poll::Pending
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Pending
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Pending
+        }
+    }
+
+    /// An [`AsyncWrite`] that accepts up to `budget` bytes in total and is pending after that,
+    /// until the budget is raised.
+    struct ThrottledWriter(Arc<Mutex<Throttle>>);
+
+    struct Throttle {
+        written: Vec<u8>,
+        budget: usize,
+    }
+
+    impl AsyncWrite for ThrottledWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let mut throttle = self.0.lock().unwrap();
+            if throttle.budget == 0 {
+                return Poll::Pending;
+            }
+
+            let len = buf.len().min(throttle.budget);
+            throttle.written.extend_from_slice(&buf[..len]);
+            throttle.budget -= len;
+
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// An in-memory byte pipe shared between a [`PipeWriter`] and a [`PipeReader`].
+    #[derive(Default)]
+    struct Pipe {
+        buffer: Vec<u8>,
+        closed: bool,
+        waker: Option<Waker>,
+    }
+
+    struct PipeWriter(Arc<Mutex<Pipe>>);
+
+    struct PipeReader(Arc<Mutex<Pipe>>);
+
+    /// Creates one direction of an in-memory duplex stream.
+    fn pipe() -> (PipeWriter, PipeReader) {
+        let pipe = Arc::new(Mutex::new(Pipe::default()));
+        (PipeWriter(pipe.clone()), PipeReader(pipe))
+    }
+
+    impl AsyncWrite for PipeWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let mut pipe = self.0.lock().unwrap();
+            if pipe.closed {
+                return Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()));
+            }
+
+            pipe.buffer.extend_from_slice(buf);
+            if let Some(waker) = pipe.waker.take() {
+                waker.wake();
+            }
+
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            let mut pipe = self.0.lock().unwrap();
+            pipe.closed = true;
+            if let Some(waker) = pipe.waker.take() {
+                waker.wake();
+            }
+
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncRead for PipeReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let mut pipe = self.0.lock().unwrap();
+            if pipe.buffer.is_empty() {
+                if pipe.closed {
+                    return Poll::Ready(Ok(0));
+                }
+
+                pipe.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            let len = buf.len().min(pipe.buffer.len());
+            buf[..len].copy_from_slice(&pipe.buffer[..len]);
+            pipe.buffer.drain(..len);
+
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    fn test_addr() -> SocketAddr {
+        "127.0.0.1:0".parse().unwrap()
+    }
+
+    #[async_std::test]
+    async fn partial_vectored_writes() -> anyhow::Result<()> {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = ConnectionWriter::new(
+            test_addr(),
+            test_addr(),
+            Box::pin(ShortWriter {
+                written: written.clone(),
+                chunk_size: 3,
+            }),
+        );
+
+        let messages: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; i as usize + 1]).collect();
+        for (tag, data) in messages.iter().enumerate() {
+            writer
+                .feed(ConnectDatagram::with_tag(tag as u16, data.clone())?)
+                .await?;
+        }
+        writer.flush().await?;
+
+        let bytes = written.lock().unwrap().clone();
+        assert_eq!(bytes.len() as u64, writer.bytes_written());
+
+        let mut reader =
+            ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)));
+        let received: Vec<ConnectDatagram> = reader.by_ref().collect().await;
+        assert_eq!(writer.bytes_written(), reader.bytes_read());
+
+        assert_eq!(messages.len(), received.len());
+        for (tag, (data, datagram)) in messages.iter().zip(received.iter()).enumerate() {
+            assert_eq!(tag as u16, datagram.tag());
+            assert_eq!(data.as_slice(), datagram.data());
+        }
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn large_backlog_vectored_writes() -> anyhow::Result<()> {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = ConnectionWriter::new(
+            test_addr(),
+            test_addr(),
+            Box::pin(ShortWriter {
+                written: written.clone(),
+                chunk_size: usize::MAX,
+            }),
+        );
+
+        let count = 5000;
+        for i in 0..count {
+            writer
+                .feed(ConnectDatagram::new((i as u16).to_be_bytes().to_vec())?)
+                .await?;
+        }
+        writer.flush().await?;
+
+        let bytes = written.lock().unwrap().clone();
+        let reader = ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)));
+        let received: Vec<ConnectDatagram> = reader.collect().await;
+
+        assert_eq!(count, received.len());
+        for (i, datagram) in received.iter().enumerate() {
+            assert_eq!((i as u16).to_be_bytes(), datagram.data());
+        }
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn priority_overtakes_pending_writes() -> anyhow::Result<()> {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = ConnectionWriter::new(
+            test_addr(),
+            test_addr(),
+            Box::pin(ShortWriter {
+                written: written.clone(),
+                chunk_size: 5,
+            }),
+        );
+
+        for tag in 0..3 {
+            writer
+                .feed(ConnectDatagram::with_tag(tag, vec![0; 20])?)
+                .await?;
+        }
+        writer
+            .send_with_priority(ConnectDatagram::with_tag(10, vec![1])?, 1)
+            .await?;
+
+        let bytes = written.lock().unwrap().clone();
+        let reader = ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)));
+        let tags: Vec<u16> = reader.map(|datagram| datagram.tag()).collect().await;
+        assert_eq!(vec![10, 0, 1, 2], tags);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn sequence_follows_priority_order() -> anyhow::Result<()> {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = ConnectionWriter::new(
+            test_addr(),
+            test_addr(),
+            Box::pin(ShortWriter {
+                written: written.clone(),
+                chunk_size: 5,
+            }),
+        );
+        writer.set_next_sequence(0);
+
+        for tag in 0..3 {
+            writer
+                .feed(ConnectDatagram::with_tag(tag, vec![0; 20])?)
+                .await?;
+        }
+        writer
+            .send_with_priority(ConnectDatagram::with_tag(10, vec![1])?, 1)
+            .await?;
+        assert_eq!(Some(4), writer.next_sequence());
+
+        let bytes = written.lock().unwrap().clone();
+        let reader = ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)));
+        let received: Vec<(u16, Option<u64>)> = reader
+            .map(|datagram| (datagram.tag(), datagram.sequence()))
+            .collect()
+            .await;
+        assert_eq!(
+            vec![(10, Some(0)), (0, Some(1)), (1, Some(2)), (2, Some(3))],
+            received
+        );
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn rate_limit_shapes_writes() -> anyhow::Result<()> {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = ConnectionWriter::new(
+            test_addr(),
+            test_addr(),
+            Box::pin(ShortWriter {
+                written: written.clone(),
+                chunk_size: usize::MAX,
+            }),
+        );
+        writer.set_rate_limit(1_000_000);
+
+        // the first 1MB is sent as a burst, and the rest at 1MB/s
+        let start = Instant::now();
+        for _ in 0..15 {
+            writer.send(ConnectDatagram::new(vec![0; 100_000])?).await?;
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert_eq!(writer.bytes_written(), written.lock().unwrap().len() as u64);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn broadcast_skips_closed_writers() -> anyhow::Result<()> {
+        let outputs: Vec<Arc<Mutex<Vec<u8>>>> =
+            (0..3).map(|_| Arc::new(Mutex::new(Vec::new()))).collect();
+        let mut writers: Vec<ConnectionWriter> = outputs
+            .iter()
+            .map(|written| {
+                ConnectionWriter::new(
+                    test_addr(),
+                    test_addr(),
+                    Box::pin(ShortWriter {
+                        written: written.clone(),
+                        chunk_size: 7,
+                    }),
+                )
+            })
+            .collect();
+        writers[1].close().await?;
+
+        let datagram = ConnectDatagram::with_tag(1, vec![1, 2, 3])?;
+        let failed = broadcast(&datagram, &mut writers).await;
+
+        assert_eq!(1, failed.len());
+        assert_eq!(1, failed[0].0);
+        assert!(matches!(
+            failed[0].1,
+            ConnectionWriteError::ConnectionClosed
+        ));
+
+        assert_eq!(datagram.as_bytes(), outputs[0].lock().unwrap().as_slice());
+        assert!(outputs[1].lock().unwrap().is_empty());
+        assert_eq!(datagram.as_bytes(), outputs[2].lock().unwrap().as_slice());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn drop_writes_pending_bytes() -> anyhow::Result<()> {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = ConnectionWriter::new(
+            test_addr(),
+            test_addr(),
+            Box::pin(ShortWriter {
+                written: written.clone(),
+                chunk_size: 7,
+            }),
+        );
+
+        let datagram = ConnectDatagram::with_tag(1, vec![1, 2, 3])?;
+        writer.feed(datagram.clone()).await?;
+        assert!(written.lock().unwrap().is_empty());
+
+        drop(writer);
+        assert_eq!(datagram.as_bytes(), written.lock().unwrap().as_slice());
+
+        // dropping a writer that cannot make progress does not block
+        let mut stalled = ConnectionWriter::new(test_addr(), test_addr(), Box::pin(StalledWriter));
+        stalled.feed(datagram).await?;
+        drop(stalled);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn buffer_limit_backpressure() -> anyhow::Result<()> {
+        let mut writer = ConnectionWriter::new(test_addr(), test_addr(), Box::pin(StalledWriter));
+        writer.set_buffer_limit(100);
+
+        writer.feed(ConnectDatagram::new(vec![0; 50])?).await?;
+        writer.feed(ConnectDatagram::new(vec![1; 50])?).await?;
+        assert!(writer.pending_bytes() >= 100);
+
+        let blocked = writer
+            .feed(ConnectDatagram::new(vec![2; 50])?)
+            .now_or_never();
+        assert!(blocked.is_none());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn cancelled_send_is_written_once() -> anyhow::Result<()> {
+        let first = ConnectDatagram::with_tag(1, vec![1; 16])?;
+        let second = ConnectDatagram::with_tag(2, vec![2; 16])?;
+        let mut expected = first.clone().into_bytes();
+        expected.extend(second.clone().into_bytes());
+
+        // cancel the first send before anything, some, or all but one byte has been written
+        for cancel_at in [0, 3, 9, first.serialized_size() - 1] {
+            let throttle = Arc::new(Mutex::new(Throttle {
+                written: Vec::new(),
+                budget: cancel_at,
+            }));
+            let mut writer = ConnectionWriter::new(
+                test_addr(),
+                test_addr(),
+                Box::pin(ThrottledWriter(throttle.clone())),
+            );
+
+            assert!(writer.send(first.clone()).now_or_never().is_none());
+            assert_eq!(cancel_at, throttle.lock().unwrap().written.len());
+
+            throttle.lock().unwrap().budget = usize::MAX;
+            writer.send(second.clone()).await?;
+
+            assert_eq!(expected, throttle.lock().unwrap().written);
+            assert_eq!(0, writer.pending_bytes());
+        }
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn send_cancelled_before_ready_is_not_sent() -> anyhow::Result<()> {
+        let throttle = Arc::new(Mutex::new(Throttle {
+            written: Vec::new(),
+            budget: 0,
+        }));
+        let mut writer = ConnectionWriter::new(
+            test_addr(),
+            test_addr(),
+            Box::pin(ThrottledWriter(throttle.clone())),
+        );
+        writer.set_buffer_limit(1);
+        writer.set_next_sequence(0);
+
+        let first = ConnectDatagram::with_tag(1, vec![1])?;
+        writer.feed(first).await?;
+        let queued = writer.pending_bytes();
+
+        // the writer is over its buffer limit, so the datagram is dropped without being queued
+        let blocked = ConnectDatagram::with_tag(2, vec![2])?;
+        assert!(writer.send(blocked).now_or_never().is_none());
+        assert_eq!(queued, writer.pending_bytes());
+        assert_eq!(Some(1), writer.next_sequence());
+
+        throttle.lock().unwrap().budget = usize::MAX;
+        writer.flush().await?;
+
+        let bytes = throttle.lock().unwrap().written.clone();
+        let mut reader =
+            ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)));
+        let tags: Vec<u16> = reader
+            .by_ref()
+            .map(|datagram| datagram.tag())
+            .collect()
+            .await;
+        assert_eq!(vec![1], tags);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn flush_delay_coalesces_writes() -> anyhow::Result<()> {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = ConnectionWriter::new(
+            test_addr(),
+            test_addr(),
+            Box::pin(ShortWriter {
+                written: written.clone(),
+                chunk_size: usize::MAX,
+            }),
+        );
+        writer.set_flush_delay(Duration::from_millis(100));
+
+        let start = Instant::now();
+        let mut send = writer.send(ConnectDatagram::with_tag(1, vec![1])?);
+        assert!((&mut send).now_or_never().is_none());
+        assert!(written.lock().unwrap().is_empty());
+
+        send.await?;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        let flushed = written.lock().unwrap().len();
+        assert!(flushed > 0);
+
+        let start = Instant::now();
+        writer.feed(ConnectDatagram::with_tag(2, vec![2])?).await?;
+        writer.flush_now().await?;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert!(written.lock().unwrap().len() > flushed);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn little_endian_byte_order() -> anyhow::Result<()> {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = ConnectionWriter::new(
+            test_addr(),
+            test_addr(),
+            Box::pin(ShortWriter {
+                written: written.clone(),
+                chunk_size: 64,
+            }),
+        );
+        writer.set_byte_order(ByteOrder::LittleEndian);
+
+        writer
+            .send(ConnectDatagram::with_tag(0x0102, vec![9, 9])?)
+            .await?;
+        writer
+            .send_segmented(SegmentedDatagram::with_tag(
+                3,
+                vec![Bytes::from_static(b"ab"), Bytes::from_static(b"c")],
+            )?)
+            .await?;
+
+        let bytes = written.lock().unwrap().clone();
+        assert_eq!(&[6, 0, 0, 0, 1, 0, 2, 1, 9, 9], &bytes[..10]);
+        assert_eq!(&[7, 0, 0, 0, 1, 0, 3, 0], &bytes[10..18]);
+
+        let mut reader =
+            ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)));
+        reader.set_byte_order(ByteOrder::LittleEndian);
+
+        let received: Vec<ConnectDatagram> = reader.collect().await;
+        assert_eq!(2, received.len());
+        assert_eq!(0x0102, received[0].tag());
+        assert_eq!(&[9, 9], received[0].data());
+        assert_eq!(3, received[1].tag());
+        assert_eq!(b"abc", received[1].data());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn auto_flush_writes_on_feed() -> anyhow::Result<()> {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = ConnectionWriter::new(
+            test_addr(),
+            test_addr(),
+            Box::pin(ShortWriter {
+                written: written.clone(),
+                chunk_size: 4,
+            }),
+        );
+
+        writer.feed(ConnectDatagram::with_tag(1, vec![1])?).await?;
+        assert!(written.lock().unwrap().is_empty());
+        writer.flush().await?;
+        written.lock().unwrap().clear();
+
+        writer.set_auto_flush(true);
+        let datagram = ConnectDatagram::with_tag(2, vec![2; 64])?;
+        writer.feed(datagram.clone()).await?;
+
+        assert_eq!(datagram.into_bytes(), *written.lock().unwrap());
+        assert_eq!(0, writer.pending_bytes());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn send_with_ack_bypasses_flush_delay() -> anyhow::Result<()> {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = ConnectionWriter::new(
+            test_addr(),
+            test_addr(),
+            Box::pin(ShortWriter {
+                written: written.clone(),
+                chunk_size: usize::MAX,
+            }),
+        );
+        writer.set_flush_delay(Duration::from_secs(60));
+
+        let datagram = ConnectDatagram::with_tag(1, vec![1])?;
+        async_std::future::timeout(
+            Duration::from_secs(1),
+            writer.send_with_ack(datagram.clone()),
+        )
+        .await??;
+
+        assert_eq!(datagram.into_bytes(), *written.lock().unwrap());
+        assert_eq!(0, writer.pending_bytes());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn segmented_datagram_written_in_order() -> anyhow::Result<()> {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = ConnectionWriter::new(
+            test_addr(),
+            test_addr(),
+            Box::pin(ShortWriter {
+                written: written.clone(),
+                chunk_size: 5,
+            }),
+        );
+
+        let segments = vec![
+            Bytes::from_static(b"head"),
+            Bytes::new(),
+            Bytes::from_static(b"body of the message"),
+            Bytes::from_static(b"tail"),
+        ];
+        let datagram = SegmentedDatagram::with_tag(3, segments)?;
+        assert_eq!(3, datagram.tag());
+        assert_eq!(27, datagram.data_size());
+
+        writer.send(ConnectDatagram::with_tag(1, vec![1])?).await?;
+        writer.send_segmented(datagram).await?;
+
+        let mut expected = ConnectDatagram::with_tag(1, vec![1])?.into_bytes();
+        expected.extend(
+            ConnectDatagram::with_tag(3, b"headbody of the messagetail".to_vec())?.into_bytes(),
+        );
+        assert_eq!(expected, *written.lock().unwrap());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn shutdown_keeps_reading() -> anyhow::Result<()> {
+        let (client_tx, server_rx) = pipe();
+        let (server_tx, client_rx) = pipe();
+
+        let mut client_reader =
+            ConnectionReader::new(test_addr(), test_addr(), Box::pin(client_rx));
+        let mut client_writer =
+            ConnectionWriter::new(test_addr(), test_addr(), Box::pin(client_tx));
+        let mut server_reader =
+            ConnectionReader::new(test_addr(), test_addr(), Box::pin(server_rx));
+        let mut server_writer =
+            ConnectionWriter::new(test_addr(), test_addr(), Box::pin(server_tx));
+
+        client_writer
+            .send(ConnectDatagram::with_tag(1, vec![1])?)
+            .await?;
+        client_writer.shutdown().await?;
+        assert!(client_writer.is_closed());
+
+        // the server sees the end of the requests, and keeps replying afterwards
+        assert_eq!(1, server_reader.next().await.expect("stream ended").tag());
+        assert!(server_reader.next().await.is_none());
+
+        for tag in 2..=3 {
+            server_writer
+                .send(ConnectDatagram::with_tag(tag, vec![tag as u8])?)
+                .await?;
+        }
+        server_writer.shutdown().await?;
+
+        let replies: Vec<u16> = client_reader.by_ref().map(|msg| msg.tag()).collect().await;
+        assert_eq!(vec![2, 3], replies);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn tagged_sink_wraps_bytes() -> anyhow::Result<()> {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = ConnectionWriter::new(
+            test_addr(),
+            test_addr(),
+            Box::pin(ShortWriter {
+                written: written.clone(),
+                chunk_size: usize::MAX,
+            }),
+        );
+
+        let res = writer.tagged_sink(7).send(Vec::new()).await;
+        assert!(matches!(
+            res,
+            Err(TaggedSinkError::Datagram(DatagramError::EmptyMessage))
+        ));
+
+        // forwarding closes the sink, and with it the writer, once the stream ends
+        let chunks = futures::stream::iter(vec![vec![1], vec![2, 2]]).map(Ok);
+        chunks.forward(writer.tagged_sink(7)).await?;
+        assert!(writer.is_closed());
+
+        let bytes = written.lock().unwrap().clone();
+        let reader = ConnectionReader::new(test_addr(), test_addr(), Box::pin(Cursor::new(bytes)));
+        let received: Vec<ConnectDatagram> = reader.collect().await;
+
+        assert_eq!(2, received.len());
+        assert!(received.iter().all(|datagram| datagram.tag() == 7));
+        assert_eq!(&[2, 2], received[1].data());
+
+        Ok(())
     }
 }
@@ -0,0 +1,90 @@
+use crate::logging::*;
+use crate::Connection;
+use futures::task::{Context, Poll};
+use futures::{Sink, Stream};
+use send_wrapper::SendWrapper;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use ws_stream_wasm::{WsMessage, WsMeta, WsStream};
+
+/// The payloads of the binary messages of a browser `WebSocket`.
+///
+/// The browser's `WebSocket` can only be used from the thread that created it, which is always
+/// the case on `wasm32-unknown-unknown` since it has no threads, so it is wrapped to be `Send` like
+/// every other network stream of a [`Connection`].
+struct BrowserMessages(SendWrapper<WsStream>);
+
+impl Stream for BrowserMessages {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match futures::ready!(Pin::new(&mut *self.0).poll_next(cx)) {
+                Some(WsMessage::Binary(data)) => return Poll::Ready(Some(Ok(data))),
+
+                Some(WsMessage::Text(_)) => trace!("ignoring non-binary WebSocket message"),
+
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+impl Sink<Vec<u8>> for BrowserMessages {
+    type Error = std::io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut *self.0)
+            .poll_ready(cx)
+            .map_err(std::io::Error::other)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Vec<u8>) -> Result<(), Self::Error> {
+        Pin::new(&mut *self.0)
+            .start_send(WsMessage::Binary(item))
+            .map_err(std::io::Error::other)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut *self.0)
+            .poll_flush(cx)
+            .map_err(std::io::Error::other)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut *self.0)
+            .poll_close(cx)
+            .map_err(std::io::Error::other)
+    }
+}
+
+impl Connection {
+    /// Creates a [`Connection`] that uses the browser's WebSocket transport, connecting to a
+    /// `ws://` or `wss://` URL.
+    ///
+    /// The browser does not expose the socket addresses of a WebSocket, so the
+    /// [`local_addr`](Connection::local_addr) and [`peer_addr`](Connection::peer_addr) of the
+    /// connection are both the unspecified address `0.0.0.0:0`.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut conn = Connection::ws_client("wss://example.com/connect").await?;
+    /// ```
+    pub async fn ws_client(url: &str) -> anyhow::Result<Self> {
+        let (_, stream) = WsMeta::connect(url, None)
+            .await
+            .map_err(|err| anyhow::anyhow!("could not connect to {}: {}", url, err))?;
+        info!("Established client WebSocket connection to {}", url);
+
+        let unspecified = SocketAddr::from(([0, 0, 0, 0], 0));
+
+        Ok(Self::from_ws_messages(
+            unspecified,
+            unspecified,
+            BrowserMessages(SendWrapper::new(stream)),
+        ))
+    }
+}
@@ -0,0 +1,111 @@
+use crate::logging::*;
+use async_std::net::{SocketAddr, TcpStream};
+use async_tungstenite::tungstenite::client::IntoClientRequest;
+use async_tungstenite::tungstenite::Message;
+use async_tungstenite::WebSocketStream;
+use futures::task::{Context, Poll};
+use futures::{Sink, Stream};
+use std::pin::Pin;
+
+use crate::Connection;
+
+/// The payloads of the binary messages of a WebSocket established with `async-tungstenite`.
+struct TungsteniteMessages(WebSocketStream<TcpStream>);
+
+impl Stream for TungsteniteMessages {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match futures::ready!(Pin::new(&mut self.0).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => return Poll::Ready(Some(Ok(data))),
+
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(None),
+
+                Some(Ok(_)) => trace!("ignoring non-binary WebSocket message"),
+
+                Some(Err(err)) => return Poll::Ready(Some(Err(std::io::Error::other(err)))),
+            }
+        }
+    }
+}
+
+impl Sink<Vec<u8>> for TungsteniteMessages {
+    type Error = std::io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0)
+            .poll_ready(cx)
+            .map_err(std::io::Error::other)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Vec<u8>) -> Result<(), Self::Error> {
+        Pin::new(&mut self.0)
+            .start_send(Message::Binary(item))
+            .map_err(std::io::Error::other)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0)
+            .poll_flush(cx)
+            .map_err(std::io::Error::other)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0)
+            .poll_close(cx)
+            .map_err(std::io::Error::other)
+    }
+}
+
+impl Connection {
+    /// Creates a [`Connection`] that uses a WebSocket transport, connecting to a `ws://` URL.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut conn = Connection::ws_client("ws://127.0.0.1:3456").await?;
+    /// ```
+    pub async fn ws_client(url: &str) -> anyhow::Result<Self> {
+        let request = url.into_client_request()?;
+        let uri = request.uri();
+
+        if uri.scheme_str() != Some("ws") {
+            return Err(anyhow::anyhow!(
+                "unsupported WebSocket URL {}, only ws:// URLs are supported",
+                url
+            ));
+        }
+
+        let host = uri
+            .host()
+            .ok_or_else(|| anyhow::anyhow!("WebSocket URL {} has no host", url))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = uri.port_u16().unwrap_or(80);
+
+        let stream = TcpStream::connect((host.as_str(), port)).await?;
+        info!("Established client TCP connection to {}", url);
+        stream.set_nodelay(true)?;
+
+        let local_addr = stream.local_addr()?;
+        let peer_addr = stream.peer_addr()?;
+
+        let (stream, _) = async_tungstenite::client_async(request, stream).await?;
+        debug!("Completed WebSocket handshake with {}", peer_addr);
+
+        Ok(Self::from_ws(local_addr, peer_addr, stream))
+    }
+
+    /// Creates a [`Connection`] over an established WebSocket.
+    pub(crate) fn from_ws(
+        local_addr: SocketAddr,
+        peer_addr: SocketAddr,
+        stream: WebSocketStream<TcpStream>,
+    ) -> Self {
+        Self::from_ws_messages(local_addr, peer_addr, TungsteniteMessages(stream))
+    }
+}
@@ -0,0 +1,202 @@
+use crate::logging::*;
+use crate::Connection;
+use async_std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use async_std::pin::Pin;
+use async_std::task::{Context, Poll};
+use async_stream::stream;
+use async_tungstenite::tungstenite::Error as WsError;
+use async_tungstenite::WebSocketStream;
+use futures::{Future, Stream};
+use futures_lite::StreamExt;
+
+type AcceptStream =
+    Pin<Box<dyn Stream<Item = Option<Result<TcpStream, std::io::Error>>> + Send + Sync>>;
+
+type HandshakeFuture =
+    Pin<Box<dyn Future<Output = Result<WebSocketStream<TcpStream>, WsError>> + Send>>;
+
+/// Listens on a bound socket for incoming WebSocket connections to be handled as independent
+/// [`Connection`]s.
+///
+/// Implements the [`Stream`] trait to asynchronously accept incoming WebSocket connections.
+/// Connections whose WebSocket handshake fails are logged and skipped.
+///
+/// # Example
+///
+/// Basic usage:
+///
+/// ```ignore
+/// let mut server = WsListener::bind(ip_address).await?;
+///
+/// // wait for a connection to come in and be accepted
+/// while let Some(mut conn) = server.next().await {
+///     // do something with connection
+/// }
+/// ```
+pub struct WsListener {
+    local_addrs: SocketAddr,
+    conn_stream: AcceptStream,
+    handshake: Option<(SocketAddr, HandshakeFuture)>,
+}
+
+impl WsListener {
+    /// Creates a [`WsListener`] by binding to an IP address and port and listens for incoming
+    /// WebSocket connections.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let mut server = WsListener::bind("127.0.0.1:3456").await?;
+    /// ```
+    pub async fn bind<A: ToSocketAddrs + std::fmt::Display>(ip_addrs: A) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(&ip_addrs).await?;
+        info!("Started WebSocket server at {}", &ip_addrs);
+
+        let local_addrs = listener.local_addr()?;
+
+        let stream = Box::pin(stream! {
+            loop {
+                yield listener.incoming().next().await;
+            }
+        });
+
+        Ok(Self {
+            local_addrs,
+            conn_stream: stream,
+            handshake: None,
+        })
+    }
+
+    /// Get the local IP address and port that the listener is bound to.
+    ///
+    /// When binding to port `0`, this is the port that was assigned by the operating system, such
+    /// as to tell clients where to connect to.
+    ///
+    /// # Example
+    ///
+    /// Basic usage:
+    ///
+    /// ```ignore
+    /// let server = WsListener::bind("127.0.0.1:0").await?;
+    /// let port = server.local_addr().port();
+    /// ```
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs
+    }
+}
+
+impl Stream for WsListener {
+    type Item = Connection;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some((peer_addr, handshake)) = self.handshake.as_mut() {
+                let peer_addr = *peer_addr;
+
+                let res = match handshake.as_mut().poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(res) => res,
+                };
+                self.handshake.take();
+
+                match res {
+                    Ok(stream) => {
+                        debug!("Completed WebSocket handshake with {}", peer_addr);
+                        return Poll::Ready(Some(Connection::from_ws(
+                            self.local_addrs,
+                            peer_addr,
+                            stream,
+                        )));
+                    }
+
+                    Err(err) => {
+                        warn!("WebSocket handshake with {} failed: {}", peer_addr, err);
+                        continue;
+                    }
+                }
+            }
+
+            match self.conn_stream.poll_next(cx) {
+                Poll::Ready(Some(Some(Ok(tcp_stream)))) => {
+                    let peer_addr = match tcp_stream.peer_addr() {
+                        Ok(peer_addr) => peer_addr,
+                        Err(err) => {
+                            error!("Could not get address of accepted connection: {}", err);
+                            continue;
+                        }
+                    };
+                    debug!("Received connection attempt from {}", peer_addr);
+
+                    self.handshake.replace((
+                        peer_addr,
+                        Box::pin(async_tungstenite::accept_async(tcp_stream)),
+                    ));
+                }
+
+                Poll::Ready(Some(Some(Err(err)))) => {
+                    error!(
+                        "Encountered error when trying to accept new connection {}",
+                        err
+                    );
+
+                    // the error was not caused by a pending operation, so make sure to be polled again
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+
+                Poll::Ready(Some(None)) => return Poll::Ready(None),
+
+                Poll::Ready(None) => return Poll::Ready(None),
+
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WsListener;
+    use crate::{ConnectDatagram, Connection};
+    use async_std::io::WriteExt;
+    use async_std::net::TcpStream;
+    use futures::{SinkExt, StreamExt};
+
+    #[async_std::test]
+    async fn datagrams_over_websocket() -> anyhow::Result<()> {
+        let mut server = WsListener::bind("127.0.0.1:0").await?;
+        let url = format!("ws://{}", server.local_addr());
+
+        // a connection that fails the handshake is skipped
+        let mut garbage = TcpStream::connect(server.local_addrs).await?;
+        garbage
+            .write_all(b"not a websocket handshake\r\n\r\n")
+            .await?;
+
+        let client = async_std::task::spawn(async move {
+            let mut conn = Connection::ws_client(&url).await?;
+            conn.writer()
+                .send(ConnectDatagram::with_tag(1, vec![1; 100_000])?)
+                .await?;
+
+            let reply = conn.reader().next().await.expect("connection closed");
+            anyhow::Ok(reply)
+        });
+
+        let mut conn = server.next().await.expect("listener closed");
+        let msg = conn.reader().next().await.expect("connection closed");
+        assert_eq!(1, msg.tag());
+        assert_eq!(&vec![1; 100_000][..], msg.data());
+
+        conn.writer()
+            .send(ConnectDatagram::with_tag(2, b"hello".to_vec())?)
+            .await?;
+        let reply = client.await?;
+        assert_eq!(2, reply.tag());
+        assert_eq!(b"hello", reply.data());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+//! WebSocket transport client and listener implementations.
+//!
+//! <br/>
+//!
+//! This module primarily exposes the WebSocket client implementation over a [`Connection`] type
+//! and the WebSocket listener implementation as [`WsListener`].
+//!
+//! Each datagram is sent as a single binary WebSocket message. Since WebSocket messages are
+//! already delimited, the size-prefix of the datagram is not sent on this transport. Text messages
+//! are ignored.
+//!
+//! On `wasm32`, the client is backed by the browser's `WebSocket` API instead, so that code
+//! handling messages can be shared between a native server and a browser client. The listener is
+//! not available in the browser.
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) mod browser;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod client;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod listener;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use listener::*;
+
+use crate::protocol::SharedByteOrder;
+use crate::{Connection, DatagramError, SIZE_PREFIX_BYTE_SIZE};
+use futures::task::{Context, Poll};
+use futures::{AsyncRead, AsyncWrite, Sink, Stream, StreamExt};
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+/// Exposes the binary messages received on a WebSocket as a byte stream of size-prefixed
+/// datagrams, so that they can be read by a [`ConnectionReader`](`crate::ConnectionReader`).
+///
+/// The size-prefix is framed in the byte order of the reader, like the rest of the header that the
+/// peer sent.
+struct WsReadStream<S> {
+    messages: S,
+    byte_order: SharedByteOrder,
+    packet: Vec<u8>,
+    offset: usize,
+}
+
+impl<S> AsyncRead for WsReadStream<S>
+where
+    S: Stream<Item = std::io::Result<Vec<u8>>> + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        loop {
+            if self.offset < self.packet.len() {
+                let offset = self.offset;
+                let len = buf.len().min(self.packet.len() - offset);
+
+                buf[..len].copy_from_slice(&self.packet[offset..offset + len]);
+                self.offset += len;
+
+                return Poll::Ready(Ok(len));
+            }
+
+            match futures::ready!(Pin::new(&mut self.messages).poll_next(cx)) {
+                Some(Ok(data)) => {
+                    // a message too large for a size-prefix must not wrap around into a smaller one
+                    let size = u32::try_from(data.len()).map_err(|_| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            DatagramError::SizePrefixOverflow,
+                        )
+                    })?;
+
+                    let mut packet = Vec::with_capacity(SIZE_PREFIX_BYTE_SIZE + data.len());
+                    packet.extend(self.byte_order.get().size_prefix(size));
+                    packet.extend(data);
+
+                    self.packet = packet;
+                    self.offset = 0;
+                }
+
+                None => return Poll::Ready(Ok(0)),
+
+                Some(Err(err)) => return Poll::Ready(Err(err)),
+            }
+        }
+    }
+}
+
+/// Sends the byte stream written by a [`ConnectionWriter`](`crate::ConnectionWriter`) on a
+/// WebSocket, one datagram without its size-prefix per binary message.
+struct WsWriteStream<K> {
+    messages: K,
+    byte_order: SharedByteOrder,
+    buffer: Vec<u8>,
+}
+
+impl<K> WsWriteStream<K> {
+    /// Removes the next complete serialized datagram from the buffered bytes, if there is one.
+    fn next_datagram(&mut self) -> Option<Vec<u8>> {
+        let size = self.byte_order.get().read_size_prefix(&self.buffer)? as usize;
+        let datagram_size = SIZE_PREFIX_BYTE_SIZE + size;
+
+        if self.buffer.len() < datagram_size {
+            return None;
+        }
+
+        let remaining = self.buffer.split_off(datagram_size);
+        Some(std::mem::replace(&mut self.buffer, remaining))
+    }
+}
+
+impl<K> AsyncWrite for WsWriteStream<K>
+where
+    K: Sink<Vec<u8>, Error = std::io::Error> + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.buffer.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        loop {
+            futures::ready!(Pin::new(&mut self.messages).poll_ready(cx))?;
+
+            match self.next_datagram() {
+                Some(mut datagram) => {
+                    let data = datagram.split_off(SIZE_PREFIX_BYTE_SIZE);
+                    Pin::new(&mut self.messages).start_send(data)?;
+                }
+
+                None => return Pin::new(&mut self.messages).poll_flush(cx),
+            }
+        }
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        futures::ready!(self.as_mut().poll_flush(cx))?;
+
+        Pin::new(&mut self.messages).poll_close(cx)
+    }
+}
+
+impl Connection {
+    /// Creates a [`Connection`] over an established WebSocket, given as a stream of the payloads
+    /// of the binary messages it receives and a sink of the payloads of binary messages to send.
+    fn from_ws_messages<M>(local_addr: SocketAddr, peer_addr: SocketAddr, messages: M) -> Self
+    where
+        M: Stream<Item = std::io::Result<Vec<u8>>>
+            + Sink<Vec<u8>, Error = std::io::Error>
+            + Send
+            + 'static,
+    {
+        let (write_messages, read_messages) = messages.split();
+        let (read_order, write_order) = (SharedByteOrder::default(), SharedByteOrder::default());
+
+        let read_stream = WsReadStream {
+            messages: read_messages,
+            byte_order: read_order.clone(),
+            packet: Vec::new(),
+            offset: 0,
+        };
+
+        let write_stream = WsWriteStream {
+            messages: write_messages,
+            byte_order: write_order.clone(),
+            buffer: Vec::new(),
+        };
+
+        Self::new(
+            local_addr,
+            peer_addr,
+            Box::pin(read_stream),
+            Box::pin(write_stream),
+        )
+        .with_framing_byte_order(read_order, write_order)
+    }
+}